@@ -10,8 +10,8 @@ use tabled::{style::Style as TableStyle, Table, Tabled};
 
 const PM_IMPL_DIR: &str = "src/pm/";
 const METHODS: &[&str] = &[
-    "q", "qc", "qe", "qi", "qk", "ql", "qm", "qo", "qp", "qs", "qu", "r", "rn", "rns", "rs", "rss",
-    "s", "sc", "scc", "sccc", "sg", "si", "sii", "sl", "ss", "su", "suy", "sw", "sy", "u",
+    "q", "qc", "qdt", "qe", "qi", "qk", "ql", "qm", "qo", "qp", "qs", "qu", "r", "rn", "rns", "rs",
+    "rss", "s", "sc", "scc", "sccc", "sg", "si", "sii", "sl", "ss", "su", "suy", "sw", "sy", "u",
 ];
 
 /// Checks the implementation status of `pacman` commands in a specific file
@@ -59,7 +59,7 @@ fn make_table() -> anyhow::Result<String> {
         .map(|entry| entry.context("Error while reading path"))
         .try_collect()?;
 
-    let excluded_names = ["mod.rs", "unknown.rs"];
+    let excluded_names = ["custom.rs", "mod.rs", "op.rs", "registry.rs", "unknown.rs"];
     let impls: BTreeMap<OsString, BTreeMap<String, bool>> = paths
         .iter()
         .filter(|entry| !excluded_names.iter().any(|&ex| ex == entry.file_name()))
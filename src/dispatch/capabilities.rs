@@ -0,0 +1,58 @@
+//! The matrix of the operations supported by each package manager, as printed
+//! by `pacaptr --capabilities`.
+
+use std::iter;
+
+use itertools::Itertools;
+
+use crate::pm::Op;
+
+/// Renders the matrix of the operations supported by each package manager in
+/// `rows`, given by its name and its
+/// [`Pm::supported_ops`](crate::pm::Pm::supported_ops), with one column per
+/// [`Op`] where the supported ones are marked with `*`.
+#[must_use]
+pub(super) fn render(rows: &[(&str, &[Op])]) -> String {
+    let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let headers = Op::ALL.iter().map(ToString::to_string).collect_vec();
+    let line = |name: &str, cells: Vec<&str>| {
+        let cells = cells
+            .iter()
+            .zip(&headers)
+            .map(|(cell, header)| format!("{:w$}", cell, w = header.len()))
+            .join(" ");
+        format!("{:w$} {}", name, cells, w = name_width)
+            .trim_end()
+            .to_owned()
+    };
+    let header = line("", headers.iter().map(String::as_str).collect());
+    let rows = rows.iter().map(|(name, ops)| {
+        let cells = Op::ALL
+            .iter()
+            .map(|op| if ops.contains(op) { "*" } else { "" })
+            .collect();
+        line(name, cells)
+    });
+    iter::once(header)
+        .chain(rows)
+        .map(|line| line + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_matrix() {
+        let text = render(&[("apk", &[Op::Q, Op::Qc]), ("tlmgr", &[Op::Qc, Op::U])]);
+        let lines = text.lines().collect_vec();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("      -Q -Qc -Qdt -Qe "));
+        assert!(lines[0].ends_with(" -Sy -U"));
+        assert_eq!(lines[1], "apk   *  *");
+        assert!(lines[2].starts_with("tlmgr    *   "));
+        assert!(lines[2].ends_with(" *"));
+        assert_eq!(lines[2].rfind('*'), lines[0].rfind("-U"));
+    }
+}
@@ -1,18 +1,33 @@
 //! Definitions for command line argument mapping and dispatching.
 
-use std::iter::FromIterator;
+use std::{
+    env, fs, io,
+    iter::{self, FromIterator},
+    path::Path,
+    time::Duration,
+};
 
-use clap::{self, AppSettings, Clap};
-use itertools::Itertools;
+use clap::{self, AppSettings, ArgEnum, Clap, IntoApp};
+use clap_generate::{generate, generators};
+use is_root::is_root;
+use itertools::{chain, Itertools};
 use tap::prelude::*;
 use tokio::task;
-use tt_call::tt_call;
 
+use super::{capabilities, diff, doctor, export, help, hooks, man, pick};
 use crate::{
-    dispatch::Config,
+    dispatch::{Config, Proxy},
+    elevate,
     error::{Error, Result},
-    methods,
-    pm::Pm,
+    exec, history,
+    json::{self, Report},
+    lock,
+    pm::{self, registry, Op, Pm},
+    print::{
+        self, print_info, print_msg, print_warning, ColorMode, EventFormat, Verbosity, PROMPT_INFO,
+        PROMPT_WARNING,
+    },
+    search_cache, self_update, stat, sudo,
 };
 
 /// The command line options to be collected.
@@ -21,6 +36,8 @@ use crate::{
     version = clap::crate_version!(),
     author = clap::crate_authors!(),
     global_setting = AppSettings::ColoredHelp,
+    setting = AppSettings::DisableHelpSubcommand,
+    setting = AppSettings::DisableVersionFlag,
     setting = AppSettings::SubcommandRequiredElseHelp,
 )]
 #[allow(clippy::struct_excessive_bools)]
@@ -39,6 +56,16 @@ pub struct Pacaptr {
     )]
     using: Option<String>,
 
+    /// Force the package manager to be invoked for this run only, overriding
+    /// both `--using` and the config.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "backend",
+        value_name = "name"
+    )]
+    backend: Option<String>,
+
     /// Perform a dry run.
     #[clap(global = true, long = "dry-run", visible_alias = "dryrun")]
     dry_run: bool,
@@ -56,10 +83,354 @@ pub struct Pacaptr {
     )]
     no_confirm: bool,
 
+    /// Answer no to every question.
+    #[clap(global = true, long = "assume-no", conflicts_with = "no-confirm")]
+    assume_no: bool,
+
+    /// Answer yes to every question, with the native flag meant for it if any.
+    #[clap(
+        global = true,
+        long = "assume-yes",
+        conflicts_with_all = &["assume-no", "confirm"]
+    )]
+    assume_yes: bool,
+
+    /// Ask for confirmation, even if `no_confirm` is set in the config.
+    #[clap(global = true, long = "confirm", conflicts_with = "no-confirm")]
+    confirm: bool,
+
+    /// Accepted for compatibility with `pacman`, but ignored.
+    #[clap(global = true, long = "asdeps")]
+    as_deps: bool,
+
     /// Remove cache after installation.
     #[clap(global = true, long = "no-cache", visible_alias = "nocache")]
     no_cache: bool,
 
+    /// Suppress the messages of `pacaptr` itself, except for errors, leaving
+    /// only the output of the package manager.
+    #[clap(global = true, short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Print every command executed along with its duration and exit status,
+    /// or even more details if given twice.
+    #[clap(
+        global = true,
+        short = 'v',
+        long = "verbose",
+        parse(from_occurrences),
+        conflicts_with = "quiet"
+    )]
+    verbose: u32,
+
+    /// Target casks instead of formulae (`brew` only).
+    #[clap(global = true, long = "cask")]
+    cask: bool,
+
+    /// Apply the patches instead of updating the packages in `-Su`, or list
+    /// them in `-Qu` (`zypper` only).
+    #[clap(global = true, long = "patch")]
+    patch: bool,
+
+    /// Install the packages under the given virtual package in `-S`, or remove
+    /// it in `-R` (`apk` only).
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "virtual",
+        value_name = "name"
+    )]
+    virtual_pkg: Option<String>,
+
+    /// Exclude the given package(s) from upgrades, separated by commas.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        multiple_occurrences = true,
+        long = "ignore",
+        value_name = "pkg"
+    )]
+    ignore: Vec<String>,
+
+    /// Download the packages to the given directory in `-Sw`, creating it if
+    /// missing.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "download-dir",
+        value_name = "dir"
+    )]
+    download_dir: Option<String>,
+
+    /// Retry a failed network-bound command (eg. in `-Sy`) the given number of
+    /// times.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "retries",
+        value_name = "n"
+    )]
+    retries: Option<u32>,
+
+    /// Download (or build) the given number of packages in parallel in `-S`,
+    /// `-Su` and `-Suy`, if the package manager supports it, or search for as
+    /// many keywords at once in `-Ss`.
+    #[clap(global = true, number_of_values = 1, long = "jobs", value_name = "n")]
+    jobs: Option<u32>,
+
+    /// Don't install the weak dependencies (eg. `Recommends` for `apt`) along
+    /// with the packages in `-S`, `-Su` and `-Suy`, if the package manager
+    /// supports it.
+    #[clap(global = true, long = "no-recommends")]
+    no_recommends: bool,
+
+    /// Install the weak dependencies along with the packages in `-S`, `-Su`
+    /// and `-Suy`, even if `recommends = false` is set in the config.
+    #[clap(
+        global = true,
+        long = "recommends",
+        visible_alias = "with-recommends",
+        conflicts_with = "no-recommends"
+    )]
+    recommends: bool,
+
+    /// Restrict `-S`, `-Ss`, `-Su` and `-Suy` to the given repository (or
+    /// release for `apt`, tap for `brew`, or CTAN mirror URL for `tlmgr`), if
+    /// the package manager supports it.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        multiple_occurrences = true,
+        long = "repo",
+        value_name = "name"
+    )]
+    repos: Vec<String>,
+
+    /// Use the given package index (or channel for `conda`) in `-S`, `-Ss` and
+    /// `-Si`, if the package manager supports it.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        multiple_occurrences = true,
+        long = "index-url",
+        value_name = "url"
+    )]
+    index_urls: Vec<String>,
+
+    /// Manage the packages in the given root directory instead of `/`, eg. of
+    /// a chroot, if the package manager supports it.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "root",
+        value_name = "path"
+    )]
+    root: Option<String>,
+
+    /// Install or look up the packages for the given architecture, eg. `arm64`
+    /// on a multi-arch system, if the package manager supports it.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "arch",
+        value_name = "arch"
+    )]
+    arch: Option<String>,
+
+    /// Print only the paths listed by `-Ql` which match the given glob (eg.
+    /// `*/bin/*`), or which contain the given string otherwise.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "filter",
+        value_name = "glob"
+    )]
+    filter: Option<String>,
+
+    /// Print only the first given number of lines of the output of the
+    /// queries (eg. `-Sl`), followed by a `... (truncated)` notice.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "max-lines",
+        visible_alias = "head",
+        value_name = "n"
+    )]
+    max_lines: Option<usize>,
+
+    /// Print the packages listed by `-Q` and `-Ss` with the given template
+    /// (eg. `{name} {version}`) if the package manager's output can be parsed.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "print-format",
+        value_name = "template"
+    )]
+    print_format: Option<String>,
+
+    /// Refuse the operations which need the network (eg. `-Sy`), or run them
+    /// from the local cache instead if the package manager supports it.
+    #[clap(global = true, long = "offline")]
+    offline: bool,
+
+    /// Preview the transactions of `-S`, `-Su` and `-R` by running the
+    /// package manager in its own simulation mode (eg. `dnf --assumeno`),
+    /// showing the dependency changes without committing them.
+    #[clap(global = true, long = "simulate")]
+    simulate: bool,
+
+    /// Overwrite the files of other packages conflicting with `-S`, `-Su` and
+    /// `-Suy` at the paths matching the given glob (eg. `*`), like `pacman
+    /// --overwrite`, if the package manager supports it; this is dangerous.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "overwrite",
+        value_name = "glob"
+    )]
+    overwrite: Option<String>,
+
+    /// Neither read nor update the cache of `-Ss` and `-Si`, as enabled by
+    /// `search_cache_ttl` in the config.
+    #[clap(global = true, long = "no-search-cache")]
+    no_search_cache: bool,
+
+    /// Fail on the output of the package manager which is not valid UTF-8,
+    /// instead of replacing the invalid bytes.
+    #[clap(global = true, long = "strict-utf8")]
+    strict_utf8: bool,
+
+    /// Print the output of the queries directly, even if `pager` is set in the
+    /// config.
+    #[clap(global = true, long = "no-pager")]
+    no_pager: bool,
+
+    /// When to use colors in the messages of `pacaptr` itself.
+    #[clap(
+        global = true,
+        long = "color",
+        arg_enum,
+        default_value = "auto",
+        value_name = "when"
+    )]
+    color: ColorMode,
+
+    /// Kill any command running longer than the given number of seconds.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "timeout",
+        value_name = "secs"
+    )]
+    timeout: Option<u64>,
+
+    /// Fail instead of waiting when another `pacaptr` is running on the same
+    /// package manager.
+    #[clap(global = true, long = "no-wait")]
+    no_wait: bool,
+
+    /// Run the package managers behind the given proxy (eg.
+    /// `http://proxy.example.com:3128`) for both HTTP and HTTPS, instead of
+    /// that set in the config.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "proxy",
+        value_name = "url"
+    )]
+    proxy: Option<String>,
+
+    /// Wait for at most the given number of seconds when another `pacaptr` is
+    /// running on the same package manager, then fail.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "wait-lock",
+        value_name = "secs"
+    )]
+    wait_lock: Option<u64>,
+
+    /// Exit with the code of the failed command as is, instead of one of the
+    /// codes which are the same across package managers.
+    #[clap(global = true, long = "raw-exit-code")]
+    raw_exit_code: bool,
+
+    /// Skip checking that the executable of the package manager exists before
+    /// running an operation.
+    #[clap(global = true, long = "no-preflight")]
+    no_preflight: bool,
+
+    /// Run the package managers which refuse to run as `root` (eg. `brew`)
+    /// anyway.
+    #[clap(global = true, long = "allow-root")]
+    allow_root: bool,
+
+    /// Print the packages added, removed or upgraded by `-Su` and `-Suy`.
+    #[clap(global = true, long = "show-diff")]
+    show_diff: bool,
+
+    /// Print a summary of the commands run after an operation which might
+    /// modify the package database (eg. `-Syu`).
+    #[clap(global = true, long = "stat")]
+    stat: bool,
+
+    /// Emit machine-readable JSON on `stdout` instead of the normal output.
+    #[clap(global = true, long = "json")]
+    json: bool,
+
+    /// Emit the progress as a stream of events on `stderr`, for automation.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "events",
+        arg_enum,
+        value_name = "format"
+    )]
+    events: Option<EventFormat>,
+
+    /// Hold the given package(s), preventing them from being upgraded (with
+    /// `-S` or `-R`), or list the held packages (with `-Q`).
+    #[clap(global = true, long = "hold", conflicts_with = "unhold")]
+    hold: bool,
+
+    /// Release the hold on the given package(s) (with `-S` or `-R`).
+    #[clap(global = true, long = "unhold")]
+    unhold: bool,
+
+    /// Add (with `-S`) or remove (with `-R`) the given third-party
+    /// repositories, eg. Homebrew taps, or list them (with `-Q`).
+    #[clap(global = true, long = "tap")]
+    tap: bool,
+
+    /// Pick the package(s) to install among the search results of the given
+    /// keywords (with `-S` or `-Ss`).
+    #[clap(global = true, long = "pick")]
+    pick: bool,
+
+    /// Remove the orphans, as listed by `-Qdt` (with `-R` and no package).
+    #[clap(global = true, long = "orphans")]
+    orphans: bool,
+
+    /// Remove the orphans left behind along with the given package(s) (with
+    /// `-R`), if the package manager supports it.
+    #[clap(global = true, long = "autoremove")]
+    autoremove: bool,
+
+    /// Read more package(s) from the given file, as written by `pacaptr
+    /// export`.
+    #[clap(
+        global = true,
+        number_of_values = 1,
+        long = "from-file",
+        value_name = "file"
+    )]
+    from_file: Option<String>,
+
+    /// Keep running the operations chained after `-- --` when one of them
+    /// fails, eg. `-S bar` in `pacaptr -R foo -- -- -S bar`.
+    #[clap(global = true, long = "keep-going")]
+    keep_going: bool,
+
     /// Package name or (sometimes) regex.
     #[clap(global = true, name = "KEYWORDS")]
     keywords: Vec<String>,
@@ -69,6 +440,46 @@ pub struct Pacaptr {
     extra_flags: Vec<String>,
 }
 
+/// Checks if `arg` starts an operation chained after the current one, eg.
+/// `-S` in `pacaptr -R foo -- -- -S bar`.
+#[must_use]
+fn starts_op(arg: &str) -> bool {
+    let mut chars = arg.chars();
+    chars.next() == Some('-')
+        && matches!(chars.next(), Some('Q' | 'R' | 'S' | 'U'))
+        && chars.all(|c| c.is_ascii_lowercase())
+}
+
+/// Splits `extra_flags` into the ones of the current operation, and the
+/// arguments of each operation chained after it.
+///
+/// An operation is chained after another `--` within the extra flags followed
+/// by a `pacman` operation, eg. `-S bar` in `pacaptr -R foo -- -- -S bar`, so
+/// that the extra flags right after the first `--` (eg. `-U` in `pacaptr -S
+/// foo -- -U`) are always passed to the backend. Any other `--` is kept along
+/// with the flags after it.
+#[must_use]
+fn split_chain(extra_flags: &[String]) -> (Vec<&str>, Vec<Vec<&str>>) {
+    let mut own = vec![];
+    let mut chain: Vec<Vec<&str>> = vec![];
+    for (i, seg) in extra_flags.split(|flag| flag == "--").enumerate() {
+        if i > 0 && seg.first().map_or(false, |arg| starts_op(arg)) {
+            chain.push(seg.iter().map(String::as_str).collect());
+            continue;
+        }
+        let args = chain.last_mut().unwrap_or(&mut own);
+        if i > 0 {
+            args.push("--");
+        }
+        args.extend(seg.iter().map(String::as_str));
+    }
+    (own, chain)
+}
+
+/// The long flags of `pacman` that are recognized even when given as extra
+/// flags, instead of being passed to the backend.
+const PACMAN_FLAGS: &[&str] = &["--noconfirm", "--needed", "--asdeps", "--confirm"];
+
 // For details on operations, flags and flagcounters, see: https://www.archlinux.org/pacman/pacman.8.html
 #[derive(Debug, Clap)]
 #[clap(about = clap::crate_description!())]
@@ -80,6 +491,10 @@ enum Operations {
         #[clap(short, long = "changelog")]
         c: bool,
 
+        /// Restrict or filter output to packages installed as dependencies.
+        #[clap(short, long = "deps")]
+        d: bool,
+
         /// Restrict or filter output to explicitly installed packages.
         #[clap(short, long = "explicit")]
         e: bool,
@@ -116,6 +531,11 @@ enum Operations {
         #[clap(short, long = "search")]
         s: bool,
 
+        /// Restrict output to packages not required or optionally required by
+        /// any currently installed package.
+        #[clap(short, long = "unrequired")]
+        t: bool,
+
         /// Restrict or filter output to packages that are out-of-date on the
         /// local system.
         #[clap(short, long = "upgrades")]
@@ -186,35 +606,372 @@ enum Operations {
 
     /// Upgrade or add package(s) to the system and install the required
     /// dependencies from sync repositories.
-    #[clap(short_flag = 'U', long_flag = "update")]
+    // `update` is a verb-style operation, so this can only be given as a flag
+    // or under another name.
+    #[clap(name = "install-file", short_flag = 'U', long_flag = "update")]
     Update {
         /// Only print the targets instead of performing the actual operation.
         #[clap(short, long = "print")]
         p: bool,
     },
+
+    /// Install package(s), as in `-S`.
+    Install,
+
+    /// Remove package(s) along with their configuration files and unneeded
+    /// dependencies, as in `-Rns`.
+    Purge,
+
+    /// Search for package(s) in the sync databases, as in `-Ss`.
+    Search,
+
+    /// Display information on the given package(s), as in `-Si`.
+    Info,
+
+    /// List the installed packages, as in `-Q`.
+    List,
+
+    /// Refresh the package databases, as in `-Sy`.
+    #[clap(name = "update")]
+    UpdateDb,
+
+    /// Refresh the package databases, then upgrade all packages, as in `-Syu`.
+    Upgrade,
+
+    /// Clean the package cache, as in `-Sc`.
+    Clean,
+
+    /// List the files owned by the given package(s), as in `-Ql`.
+    Files,
+
+    /// Search for the packages owning the given file(s), as in `-Qo`.
+    Owns,
+
+    /// List the orphans, as in `-Qdt`.
+    Orphans {
+        /// Remove the orphans instead, as in `-R --orphans`.
+        #[clap(long = "remove")]
+        remove: bool,
+    },
+
+    /// List all the supported package managers.
+    #[clap(long_flag = "list-pms")]
+    ListPms,
+
+    /// Print the operations supported by the package manager given by
+    /// `--using`, or by all the supported ones otherwise.
+    #[clap(long_flag = "capabilities")]
+    Capabilities,
+
+    /// Generate the completion script for the given shell.
+    Completions {
+        /// The shell to generate the completion script for.
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+
+    /// Describe a `pacman`-style (or verb-style) operation and print the
+    /// native command(s) it stands for, without running anything.
+    // `clap` treats any subcommand named `help` specially, so that name can
+    // only be given as an alias.
+    #[clap(name = "describe", visible_alias = "help")]
+    Help {
+        /// The operation to be described, eg. `Syu` or `upgrade`.
+        #[clap(name = "OPERATION", allow_hyphen_values = true)]
+        op: String,
+    },
+
+    /// Print the last commands run, as logged to the `log_file` set in the
+    /// config.
+    History {
+        /// The number of commands to be printed.
+        #[clap(short = 'n', long = "lines", default_value = "10", value_name = "n")]
+        lines: usize,
+
+        /// Only print the commands run by the given operation, eg. `Syu`.
+        #[clap(long = "op", value_name = "op")]
+        op: Option<String>,
+    },
+
+    /// Print the list of explicitly installed packages, which can be installed
+    /// again with `-S --from-file`.
+    Export {
+        /// Write the list to the given file instead of `stdout`.
+        #[clap(short = 'o', long = "output", value_name = "file")]
+        output: Option<String>,
+    },
+
+    /// Install the packages listed in a bundle file (a `Brewfile` for `brew`),
+    /// or write the installed packages to it with `--dump`.
+    Bundle {
+        /// The bundle file, which defaults to the one of the package manager
+        /// (eg. `./Brewfile`), or to `stdout` with `--dump`.
+        #[clap(long = "file", value_name = "file")]
+        file: Option<String>,
+
+        /// Write the installed packages to the bundle file instead, as in
+        /// `pacaptr export`.
+        #[clap(long = "dump")]
+        dump: bool,
+    },
+
+    /// Remove the cached results of `-Ss` and `-Si`.
+    #[clap(name = "clean-cache")]
+    CleanCache,
+
+    /// Set the default package manager in the config file, creating the
+    /// latter if missing.
+    #[clap(name = "set-default")]
+    SetDefault {
+        /// The name of the package manager, eg. `dnf`.
+        #[clap(value_name = "name")]
+        pm: String,
+    },
+
+    /// Diagnose the environment, eg. the config file and the package managers
+    /// detected.
+    Doctor,
+
+    /// Print the version, or also the package manager which would be selected
+    /// and its own version if `--verbose` is given.
+    #[clap(short_flag = 'V', long_flag = "version")]
+    Version,
+
+    /// Replace `pacaptr` with its latest release on GitHub, if it has been
+    /// installed from there.
+    #[clap(name = "self-update")]
+    SelfUpdate,
+
+    /// Generate the man page and print it to `stdout`.
+    #[clap(long_flag = "generate-man", setting = AppSettings::Hidden)]
+    GenerateMan,
+}
+
+impl Operations {
+    /// Gets the name of the verb-style operation, eg. `install`, if this is
+    /// one of them.
+    #[must_use]
+    fn verb(&self) -> Option<&'static str> {
+        Some(match self {
+            Operations::Install => "install",
+            Operations::Purge => "purge",
+            Operations::Search => "search",
+            Operations::Info => "info",
+            Operations::List => "list",
+            Operations::UpdateDb => "update",
+            Operations::Upgrade => "upgrade",
+            Operations::Clean => "clean",
+            Operations::Files => "files",
+            Operations::Owns => "owns",
+            Operations::Orphans { .. } => "orphans",
+            _ => return None,
+        })
+    }
+}
+
+/// The shells supported by `pacaptr completions`.
+#[derive(Copy, Clone, Debug, ArgEnum)]
+enum Shell {
+    Bash,
+    Elvish,
+    Fish,
+    Powershell,
+    Zsh,
+}
+
+impl Shell {
+    /// Writes the completion script for this shell to `stdout`.
+    ///
+    /// The names (and aliases) of the supported package managers are offered
+    /// as the possible values of `--using`, while remaining unrestricted
+    /// during the actual argument parsing.
+    fn print_completions(self) {
+        let pm_names = registry::REGISTRY
+            .iter()
+            .flat_map(|entry| iter::once(&entry.name).chain(entry.aliases))
+            .copied()
+            .collect_vec();
+        let mut app = Pacaptr::into_app().mut_arg("using", |arg| arg.possible_values(&pm_names));
+        let bin_name = clap::crate_name!();
+        let mut stdout = io::stdout();
+        match self {
+            Shell::Bash => generate::<generators::Bash, _>(&mut app, bin_name, &mut stdout),
+            Shell::Elvish => generate::<generators::Elvish, _>(&mut app, bin_name, &mut stdout),
+            Shell::Fish => generate::<generators::Fish, _>(&mut app, bin_name, &mut stdout),
+            Shell::Powershell => {
+                generate::<generators::PowerShell, _>(&mut app, bin_name, &mut stdout);
+            }
+            Shell::Zsh => generate::<generators::Zsh, _>(&mut app, bin_name, &mut stdout),
+        }
+    }
 }
 
 impl Pacaptr {
+    /// Checks if the orphans are to be removed, as in `-R --orphans` or
+    /// `orphans --remove`.
+    fn removes_orphans(&self) -> bool {
+        self.orphans || matches!(self.ops, Operations::Orphans { remove: true })
+    }
+
+    /// Checks if the `pacman` long flag `flag` is given among the extra flags.
+    fn has_pacman_flag(&self, flag: &str) -> bool {
+        split_chain(&self.extra_flags).0.contains(&flag)
+    }
+
+    /// Returns the extra flags to be passed to the backend, that is, without
+    /// the ones in [`PACMAN_FLAGS`] and the operations chained after `--`.
+    fn backend_flags(&self) -> Vec<&str> {
+        split_chain(&self.extra_flags)
+            .0
+            .into_iter()
+            .filter(|f| !PACMAN_FLAGS.contains(f))
+            .collect()
+    }
+
+    /// Parses the operations chained after `--`, eg. `-S bar` in `pacaptr -R
+    /// foo -- -- -S bar`, each of which takes its own flags.
+    ///
+    /// # Errors
+    /// Returns an [`Error::ArgParseError`] when an operation is malformed.
+    fn chained_ops(&self) -> Result<Vec<Pacaptr>> {
+        split_chain(&self.extra_flags)
+            .1
+            .into_iter()
+            .map(|args| {
+                Pacaptr::try_parse_from(iter::once(clap::crate_name!()).chain(args))
+                    .map_err(|e| Error::ArgParseError { msg: e.to_string() })
+            })
+            .collect()
+    }
+
     /// Generates current [`Config`] by merging current command line arguments
     /// and options obtained with [`clap`] with the dotfile [`Config`], which
     /// has a lower precedence.
+    ///
+    /// The dotfile might also be the [`Config`] of the operation this one is
+    /// chained after, whose options set on the command line are kept as well.
     fn merge_cfg(&self, dotfile: Config) -> Config {
         Config {
             dry_run: self.dry_run || dotfile.dry_run,
-            needed: self.needed || dotfile.dry_run,
-            no_confirm: self.no_confirm || dotfile.no_confirm,
+            needed: self.needed || self.has_pacman_flag("--needed") || dotfile.needed,
+            autoremove: self.autoremove || dotfile.autoremove,
+            no_confirm: self.assume_yes
+                || (self.no_confirm || self.has_pacman_flag("--noconfirm") || dotfile.no_confirm)
+                    && !(self.confirm || self.has_pacman_flag("--confirm")),
+            assume_no: self.assume_no || dotfile.assume_no,
+            assume_yes: self.assume_yes,
             no_cache: self.no_cache || dotfile.no_cache,
-            default_pm: self.using.clone().or(dotfile.default_pm),
+            quiet: self.quiet || dotfile.quiet,
+            verbosity: match self.verbose {
+                _ if self.quiet || dotfile.quiet => Verbosity::Quiet,
+                0 => dotfile.verbosity,
+                1 => Verbosity::Verbose,
+                _ => Verbosity::Debug,
+            },
+            cask: self.cask || dotfile.cask,
+            patch: self.patch || dotfile.patch,
+            virtual_pkg: self.virtual_pkg.clone().or(dotfile.virtual_pkg),
+            default_pm: self
+                .backend
+                .clone()
+                .or_else(|| self.using.clone())
+                .or(dotfile.default_pm),
+            conda_tool: dotfile.conda_tool,
+            no_self_upgrade: dotfile.no_self_upgrade,
+            no_wait: self.no_wait || dotfile.no_wait,
+            wait_lock: self.wait_lock.or(dotfile.wait_lock),
+            raw_exit_code: self.raw_exit_code || dotfile.raw_exit_code,
+            no_preflight: self.no_preflight || dotfile.no_preflight,
+            allow_root: self.allow_root || dotfile.allow_root,
+            sudo_keepalive: dotfile.sudo_keepalive,
+            elevation: dotfile.elevation,
+            ignore: dotfile
+                .ignore
+                .into_iter()
+                .chain(
+                    self.ignore
+                        .iter()
+                        .flat_map(|pkgs| pkgs.split(','))
+                        .map(Into::into),
+                )
+                .collect(),
+            log_file: dotfile.log_file,
+            download_dir: self.download_dir.clone().or(dotfile.download_dir),
+            retries: self.retries.unwrap_or(dotfile.retries),
+            jobs: self.jobs.or(dotfile.jobs),
+            recommends: if self.recommends {
+                Some(true)
+            } else if self.no_recommends {
+                Some(false)
+            } else {
+                dotfile.recommends
+            },
+            repos: if self.repos.is_empty() {
+                dotfile.repos
+            } else {
+                self.repos.clone()
+            },
+            index_urls: if self.index_urls.is_empty() {
+                dotfile.index_urls
+            } else {
+                self.index_urls.clone()
+            },
+            root: self.root.clone().or(dotfile.root),
+            arch: self.arch.clone().or(dotfile.arch),
+            filter: self.filter.clone().or(dotfile.filter),
+            max_lines: self.max_lines.or(dotfile.max_lines),
+            print_format: self.print_format.clone().or(dotfile.print_format),
+            offline: self.offline || dotfile.offline,
+            simulate: self.simulate || dotfile.simulate,
+            overwrite: self.overwrite.clone().or(dotfile.overwrite),
+            search_cache_ttl: dotfile.search_cache_ttl,
+            no_search_cache: self.no_search_cache || dotfile.no_search_cache,
+            strict_utf8: self.strict_utf8 || dotfile.strict_utf8,
+            timeout: self.timeout.or(dotfile.timeout),
+            output_limit: dotfile.output_limit,
+            check_self_update: dotfile.check_self_update,
+            proxy: match &self.proxy {
+                Some(url) => Proxy {
+                    http: Some(url.clone()),
+                    https: Some(url.clone()),
+                    no_proxy: dotfile.proxy.no_proxy,
+                },
+                None => dotfile.proxy,
+            },
+            pre_hooks: dotfile.pre_hooks,
+            hooks: dotfile.hooks,
+            show_diff: self.show_diff || dotfile.show_diff,
+            stats: self.stat || dotfile.stats,
+            prefix_stages: dotfile.prefix_stages,
+            no_partial_upgrade_warning: dotfile.no_partial_upgrade_warning,
+            pager: dotfile.pager && !self.no_pager,
+            json: self.json || dotfile.json,
+            events: self.events.or(dotfile.events),
+            // The CLI itself never follows the events of the commands.
+            event_sender: None,
+            printer: dotfile.printer,
+            describe: false,
+            custom_pms: dotfile.custom_pms,
+            pms: dotfile.pms,
         }
     }
 
+    /// Executes the job according to the flags received and the package manager
+    /// detected, with the messages printed by [`Config::printer`] if set.
+    ///
+    /// # Errors
+    /// See [`Error`](crate::error::Error) for a list of possible errors.
+    async fn dispatch_from(&self, cfg: Config) -> Result<()> {
+        print::scoped(cfg.printer.clone(), Box::pin(self.dispatch_with(cfg))).await
+    }
+
     /// Executes the job according to the flags received and the package manager
     /// detected.
     ///
     /// # Errors
-    /// See [`Error`](crate::error::Error) for a  list of possible errors.
+    /// See [`Error`](crate::error::Error) for a list of possible errors.
     #[allow(trivial_numeric_casts)]
-    async fn dispatch_from(&self, mut cfg: Config) -> Result<()> {
+    async fn dispatch_with(&self, mut cfg: Config) -> Result<()> {
         // Collect options as a `String`, eg. `-S -y -u => "Suy"`.
         // ! HACK: In `Pm` we ensure the Pacman methods are all named with flags in
         // ! ASCII order, ! eg. `Suy` instead of `Syu`.
@@ -240,97 +997,897 @@ impl Pacaptr {
                         options.push_str(stringify!($flag));
                     })* )?
                 } )*
+                _ => unreachable!("only `pacman` operations should reach `collect_options`"),
             }
             options.chars().sorted_unstable().pipe(String::from_iter)
         }};}
 
-        let options = collect_options! {
-            Query {
-                flags: [c, e, i, k, l, m, o, p, s, u],
-            },
-            Remove {
-                mappings: [p -> dry_run],
-                flags: [n, s],
-            },
-            Sync {
-                mappings: [p -> dry_run],
-                flags: [c, g, i, l, s, u, w, y],
-            },
-            Update {
-                mappings: [p -> dry_run],
+        let verb_options = if self.removes_orphans() {
+            Some("R")
+        } else if let Operations::Bundle { .. } = self.ops {
+            // `bundle` installs the packages listed in the bundle file, as `-S`
+            // does with the ones given.
+            Some("S")
+        } else if matches!(self.ops, Operations::UpdateDb)
+            && !(self.keywords.is_empty() && self.from_file.is_none())
+        {
+            // `update` used to install package files, as in `-U`, which is kept
+            // for a while when it is given any.
+            print_warning(
+                "`update` with package files is deprecated, \
+                use `install-file` (or `-U`) instead",
+                PROMPT_WARNING,
+            );
+            Some("U")
+        } else {
+            self.ops.verb().and_then(help::verb_options)
+        };
+        let options = verb_options.map_or_else(
+            || {
+                collect_options! {
+                    Query {
+                        flags: [c, d, e, i, k, l, m, o, p, s, t, u],
+                    },
+                    Remove {
+                        mappings: [p -> dry_run],
+                        flags: [n, s],
+                    },
+                    Sync {
+                        mappings: [p -> dry_run],
+                        flags: [c, g, i, l, s, u, w, y],
+                    },
+                    Update {
+                        mappings: [p -> dry_run],
+                    },
+                }
             },
+            Into::into,
+        );
+
+        // Nothing is worth checking or locking without a package manager.
+        let pm = cfg.try_conv::<Box<dyn Pm>>()?;
+
+        let imported = match (&self.ops, &self.from_file) {
+            (Operations::Bundle { .. }, _) if !self.keywords.is_empty() => {
+                return Err(Error::ArgParseError {
+                    msg: "`bundle` installs the packages listed in the bundle file, \
+                        and can't be given any package"
+                        .into(),
+                })
+            }
+            // The bundle file is given to the package manager as is.
+            (Operations::Bundle { file, .. }, _) => file.iter().cloned().collect(),
+            (_, Some(path)) => export::import(Path::new(path))?,
+            (_, None) => vec![],
         };
+        let kws = chain!(&self.keywords, &imported)
+            .map(|s| s as &str)
+            .collect_vec();
+        let flags = self.backend_flags();
+        if self.as_deps || self.has_pacman_flag("--asdeps") {
+            print_info(
+                "`--asdeps` is not supported, ignoring it",
+                PROMPT_INFO,
+                Verbosity::Normal,
+            );
+        }
 
-        let pm = cfg.conv::<Box<dyn Pm>>();
+        let op = options.to_lowercase();
+        let run = Box::pin(history::with_op(
+            &op,
+            self.dispatch_pm(&*pm, &options, &kws, &flags),
+        ));
+        let cfg = pm.cfg();
+        let locking = options.parse::<Op>().map_or(false, pm::is_locking_op);
+        let res = if cfg.stats && locking && !cfg.json && !cfg.describe {
+            let (res, stats) = stat::collect(run).await;
+            stat::print(&stats, &res);
+            res
+        } else {
+            run.await
+        };
+        if !pm.cfg().json {
+            return res;
+        }
+
+        // Under `--json`, report what has been recorded during the operation.
+        let records = json::take_records();
+        if json::is_query(&op) {
+            for record in records {
+                Report::from_record(pm.name(), &op, record).print()?;
+            }
+        } else {
+            Report::from_result(pm.name(), &op, &res).print()?;
+        }
+        res
+    }
 
-        let kws = self.keywords.iter().map(|s| s as &str).collect_vec();
-        let flags = self.extra_flags.iter().map(|s| s as &str).collect_vec();
-
-        // Call the method indicated by `options` on `pm`. That is:
-        // ```rust
-        // match &options.to_lowercase() as _ {
-        //     "q" => pm.q(&kws, &flags).await,
-        //     ..
-        // }
-        // ```
-        macro_rules! dispatch_match {(
-            methods = [{ $(
-                $( #[$meta:meta] )*
-                async fn $method:ident;
-            )* }]
-        ) => {
-            match &options.to_lowercase() as _ {
-                $(stringify!($method) => pm.$method(&kws, &flags).await,)*
+    /// Calls the [`Pm`] method indicated by the flags taking over the operation
+    /// (eg. `--hold`), returning its result, or [`None`] if no such flag is
+    /// set.
+    async fn dispatch_takeover(
+        &self,
+        pm: &dyn Pm,
+        op: Op,
+        kws: &[&str],
+        flags: &[&str],
+    ) -> Option<Result<()>> {
+        // Holding is not a `pacman` concept, so `--hold`/`--unhold` take over the
+        // operation instead of being mapped to a method name.
+        let operation = op.method().chars().next();
+        if self.hold || self.unhold {
+            return Some(match operation {
+                Some('s' | 'r') if self.hold => pm.hold(kws, flags).await,
+                Some('s' | 'r') => pm.unhold(kws, flags).await,
+                Some('q') if self.hold => pm.held(kws, flags).await,
                 _ => Err(Error::ArgParseError {
-                    msg: format!("Invalid flag combination `-{}`", &options),
+                    msg: "`--hold` can only be used along with `-S`, `-R` or `-Q`, \
+                          and `--unhold` with `-S` or `-R`"
+                        .into(),
                 }),
-            }
-        };}
+            });
+        }
+
+        // Same for `--tap`, which manages the third-party repositories.
+        if self.tap {
+            return Some(match operation {
+                Some('s') => pm.tap(kws, flags).await,
+                Some('r') => pm.untap(kws, flags).await,
+                Some('q') => pm.taps(kws, flags).await,
+                _ => Err(Error::ArgParseError {
+                    msg: "`--tap` can only be used along with `-S`, `-R` or `-Q`".into(),
+                }),
+            });
+        }
 
-        // Send `methods!()` to `dispatch_match`. That is,
-        // `dispatch_match!( methods = [{ q qc qe .. }] )`.
-        tt_call! {
-            macro = [{ methods }]
-            ~~> dispatch_match
+        // Same for `--orphans`, which removes the packages listed by `-Qdt`.
+        if self.removes_orphans() {
+            return Some(match operation {
+                Some('r') if !kws.is_empty() => Err(Error::ArgParseError {
+                    msg: "`--orphans` removes the packages listed by `-Qdt`, \
+                        and can't be given any package"
+                        .into(),
+                }),
+                Some('r') => pm.remove_orphans(kws, flags).await,
+                _ => Err(Error::ArgParseError {
+                    msg: "`--orphans` can only be used along with `-R`".into(),
+                }),
+            });
+        }
+
+        // Same for `--pick`, which installs the packages picked among the search
+        // results.
+        if self.pick {
+            return Some(match op {
+                Op::S | Op::Ss => pick::pick(pm, kws, flags).await,
+                _ => Err(Error::ArgParseError {
+                    msg: "`--pick` can only be used along with `-S` or `-Ss`".into(),
+                }),
+            });
         }
+
+        None
     }
 
-    /// Runs [`dispatch_from`](Pacaptr::dispatch_from) with automatically
-    /// detected [`Config`].
+    /// Calls the [`Pm`] method indicated by `options`, eg. `Suy`.
     ///
     /// # Errors
     /// See [`Error`](crate::error::Error) for a  list of possible errors.
-    #[allow(trivial_numeric_casts)]
-    pub async fn dispatch(&self) -> Result<()> {
-        let dotfile = task::block_in_place(Config::try_load);
-        let cfg = self.merge_cfg(dotfile?);
-        self.dispatch_from(cfg).await
-    }
-}
-
-#[cfg(test)]
-pub(super) mod tests {
-    use async_trait::async_trait;
-    use once_cell::sync::Lazy;
-    use tokio::test;
-    use tt_call::tt_call;
+    async fn dispatch_pm(
+        &self,
+        pm: &dyn Pm,
+        options: &str,
+        kws: &[&str],
+        flags: &[&str],
+    ) -> Result<()> {
+        let op = options.parse::<Op>()?;
+        let method = op.method();
+        let cfg = pm.cfg();
+        check_root(pm)?;
+        check_privilege(pm, is_root())?;
+        check_simulate(pm, op)?;
+        if !cfg.no_preflight && !cfg.dry_run && !cfg.describe {
+            registry::preflight(pm)?;
+        }
 
-    use super::*;
+        // Overlapping runs which modify the package database might corrupt it.
+        let _lock = if (self.hold
+            || self.unhold
+            || self.tap
+            || self.removes_orphans()
+            || pm::is_locking_op(op))
+            && !cfg.dry_run
+            && !cfg.describe
+        {
+            Some(lock::acquire(&lock::dir(), pm.name(), cfg.lock_wait()).await?)
+        } else {
+            None
+        };
+        // The password of `sudo` is asked for up front, if at all, rather than
+        // in the middle of the operation.
+        let _keepalive =
+            if cfg!(unix) && _lock.is_some() && sudo::decide(cfg, pm.uses_sudo(), is_root()) {
+                Some(sudo::keep_alive().await?)
+            } else {
+                None
+            };
+        let elevation = if cfg!(windows) && _lock.is_some() {
+            elevate::decide(cfg, pm.name(), pm.requires_elevation(), is_root())?
+        } else {
+            None
+        };
 
-    pub(crate) struct MockPm {
-        pub cfg: Config,
-    }
+        if let Some(res) = self.dispatch_takeover(pm, op, kws, flags).await {
+            return res;
+        }
 
-    macro_rules! make_mock_op_body {
-        ($self:ident, $kws:ident, $flags:ident, $method:ident) => {{
-            let kws: Vec<_> = $kws.iter().chain($flags).collect();
-            panic!("should run: {} {:?}", stringify!($method), &kws)
-        }};
-    }
+        let offline_op = offline_op(pm, op, kws)?;
+        warn_options(pm, op);
 
-    macro_rules! impl_pm_mock {(
-        methods = [{ $(
-            $( #[$meta:meta] )*
+        // The latest release is looked up while the operation runs.
+        let self_update_check = if cfg.dry_run || cfg.describe || cfg.json {
+            None
+        } else {
+            self_update::spawn_check(cfg)
+        };
+        hooks::run_pre_hook(cfg, method, kws).await?;
+
+        let run = pm::offline(offline_op, async {
+            if let Operations::Bundle { .. } = self.ops {
+                pm.bundle(kws, flags).await
+            } else if is_staged(pm, options, kws, flags).await {
+                pm::staged(call_method(pm, options, kws, flags)).await
+            } else {
+                call_method(pm, options, kws, flags).await
+            }
+        });
+        let run = pm::elevated(elevation, run);
+        let run = async {
+            match &cfg.filter {
+                Some(pattern) if op == Op::Ql && !cfg.dry_run && !cfg.describe => {
+                    pm::filtered(pattern, run).await
+                }
+                _ => run.await,
+            }
+        };
+        let print_format = cfg
+            .print_format
+            .as_deref()
+            .filter(|_| matches!(op, Op::Q | Op::Ss) && !cfg.dry_run && !cfg.describe && !cfg.json);
+        let run = async {
+            match print_format {
+                Some(template) => pm::formatted(pm, method, template, run).await,
+                None => run.await,
+            }
+        };
+        // Only the queries, whose output is not interactive, are truncated.
+        let max_lines = cfg
+            .max_lines
+            .filter(|_| json::is_query(method) && !cfg.dry_run && !cfg.describe && !cfg.json);
+        let run = async {
+            match max_lines {
+                Some(max_lines) => pm::truncated(max_lines, run).await,
+                None => run.await,
+            }
+        };
+        // The packages can't be compared when the upgrade is not actually run,
+        // or when nothing but JSON should be printed.
+        let show_diff = cfg.show_diff && !cfg.dry_run && !cfg.describe && !cfg.json;
+        // A truncated or formatted output must not be cached in place of the
+        // whole one.
+        let search_cache_ttl = cfg.search_cache_ttl.filter(|_| {
+            !cfg.no_search_cache
+                && !cfg.dry_run
+                && !cfg.describe
+                && !cfg.json
+                && max_lines.is_none()
+                && print_format.is_none()
+        });
+        let pager = pager(cfg, method, atty::is(atty::Stream::Stdout));
+        let res = match (search_cache_ttl, pager) {
+            _ if show_diff && matches!(op, Op::Su | Op::Suy) => diff::show_diff(pm, run).await,
+            (Some(ttl), _) if search_cache::is_cached_op(method) => {
+                let key = search_cache::key(cfg, method, kws, flags);
+                let key = key.iter().map(String::as_str).collect_vec();
+                let run = pm::network_bound(run);
+                search_cache::cached(pm, &key, Duration::from_secs(ttl), run).await
+            }
+            (_, Some(pager)) => {
+                let run = async {
+                    if pm::is_network_bound_op(method) {
+                        pm::network_bound(run).await
+                    } else {
+                        run.await
+                    }
+                };
+                // The output is paged as a whole, but the result is still that
+                // of the query rather than that of the pager.
+                let (res, out) = pm::capture(run).await;
+                let printed = exec::print_paged(&out, &pager).await;
+                res.and(printed)
+            }
+            _ if pm::is_network_bound_op(method) => pm::network_bound(run).await,
+            _ => run.await,
+        };
+        // Other package managers on this system might support the operation.
+        let res = res.map_err(|e| {
+            let detected = registry::REGISTRY
+                .iter()
+                .filter(|entry| entry.is_detected());
+            registry::suggest_using(e, detected)
+        });
+        if res.is_ok() {
+            if search_cache::is_invalidating_op(method) && !cfg.dry_run && !cfg.describe {
+                if let Err(e) = search_cache::invalidate(&search_cache::dir(), pm.name()) {
+                    print_warning(
+                        format!("Failed to invalidate the search cache: {}", e),
+                        PROMPT_WARNING,
+                    );
+                }
+            }
+            hooks::run_post_hook(cfg, method, kws).await?;
+            if let Some(check) = self_update_check {
+                self_update::notify(check).await;
+            }
+        }
+        res
+    }
+
+    /// Runs [`dispatch_from`](Pacaptr::dispatch_from) with automatically
+    /// detected [`Config`].
+    ///
+    /// # Errors
+    /// See [`Error`](crate::error::Error) for a  list of possible errors.
+    #[allow(trivial_numeric_casts)]
+    pub async fn dispatch(&self) -> Result<()> {
+        print::set_color(self.color);
+
+        // Operations that don't require any configured package manager.
+        if let Operations::ListPms = self.ops {
+            for entry in registry::REGISTRY {
+                if entry.aliases.is_empty() {
+                    println!("{}", entry.name);
+                } else {
+                    println!("{} ({})", entry.name, entry.aliases.join(", "));
+                }
+            }
+            return Ok(());
+        }
+        if let Operations::Completions { shell } = self.ops {
+            shell.print_completions();
+            return Ok(());
+        }
+        if let Operations::CleanCache = self.ops {
+            return search_cache::clean(&search_cache::dir()).map_err(Into::into);
+        }
+        if let Operations::SelfUpdate = self.ops {
+            return self_update::self_update().await;
+        }
+        if let Operations::GenerateMan = self.ops {
+            print!("{}", man::render(&Pacaptr::into_app()));
+            return Ok(());
+        }
+
+        if let Some(backend) = &self.backend {
+            check_backend(backend)?;
+        }
+        if let Operations::SetDefault { pm } = &self.ops {
+            check_backend(pm)?;
+            let path = Config::path()?;
+            Config::store_default_pm(&path, pm)?;
+            print_msg(
+                &format!("Set `default_pm` to `{}` in `{}`", pm, path.display()),
+                PROMPT_INFO,
+            );
+            return Ok(());
+        }
+
+        let dotfile = task::block_in_place(Config::try_load);
+        if let Operations::Doctor = self.ops {
+            return self.dispatch_doctor(dotfile).await;
+        }
+        if let Operations::Version = self.ops {
+            if self.verbose == 0 {
+                println!("{} {}", clap::crate_name!(), clap::crate_version!());
+                return Ok(());
+            }
+            // A broken config shouldn't prevent from getting the version.
+            let cfg = self.merge_cfg(dotfile.unwrap_or_default());
+            let pm = cfg.try_conv::<Box<dyn Pm>>().ok();
+            println!("{}", doctor::version_info(pm.as_deref()).await);
+            return Ok(());
+        }
+        let cfg = self.merge_cfg(dotfile?);
+        print::set_verbosity(cfg.verbosity);
+        print_info(format!("{:#?}", cfg), PROMPT_INFO, Verbosity::Debug);
+        if let Operations::Capabilities = self.ops {
+            let pms = if self.backend.is_some() || self.using.is_some() {
+                let pm = cfg.conv::<Box<dyn Pm>>();
+                vec![(pm.name().to_owned(), pm)]
+            } else {
+                registry::REGISTRY
+                    .iter()
+                    .map(|entry| (entry.name.to_owned(), (entry.constructor)(cfg.clone())))
+                    .collect()
+            };
+            let rows = pms
+                .iter()
+                .map(|(name, pm)| (name.as_str(), pm.supported_ops()))
+                .collect_vec();
+            print!("{}", capabilities::render(&rows));
+            return Ok(());
+        }
+        if let Operations::History { lines, op } = &self.ops {
+            let path = cfg.log_file.as_ref().ok_or_else(|| Error::ConfigError {
+                msg: "`log_file` is not set in the config".into(),
+            })?;
+            let op = op.as_deref().map(help::method_name);
+            for entry in history::read_last(Path::new(path), *lines, op.as_deref())? {
+                println!("{}", entry.render());
+            }
+            return Ok(());
+        }
+        let export = match &self.ops {
+            Operations::Export { output } => Some((output, false)),
+            Operations::Bundle { file, dump: true } => Some((file, true)),
+            _ => None,
+        };
+        if let Some((output, bundle)) = export {
+            let pm = cfg.try_conv::<Box<dyn Pm>>()?;
+            let cfg = pm.cfg();
+            if !cfg.no_preflight && !cfg.dry_run {
+                registry::preflight(&*pm)?;
+            }
+            let list = if bundle {
+                export::bundle(&*pm, &self.backend_flags()).await?
+            } else {
+                export::export(&*pm).await?
+            };
+            // Nothing has been listed in a dry run.
+            if cfg.dry_run {
+                return Ok(());
+            }
+            return match output {
+                Some(path) => fs::write(path, list).map_err(Into::into),
+                None => {
+                    print!("{}", list);
+                    Ok(())
+                }
+            };
+        }
+        if let Operations::Help { op } = &self.ops {
+            let pm = Config {
+                describe: true,
+                ..cfg
+            }
+            .conv::<Box<dyn Pm>>();
+            let kws = self.keywords.iter().map(|s| s as &str).collect_vec();
+            let flags = self.backend_flags();
+            return help::describe(&*pm, op, &kws, &flags).await;
+        }
+        self.dispatch_chain(cfg).await
+    }
+
+    /// Runs [`dispatch_from`](Pacaptr::dispatch_from) with `cfg`, followed by
+    /// the operations chained after it, each of which takes `cfg` (as merged
+    /// with the command line) as the base of its own [`Config`].
+    ///
+    /// The chain is aborted on the first failure, unless `--keep-going` is
+    /// set, in which case the failures are printed as they come.
+    ///
+    /// # Errors
+    /// See [`Error`](crate::error::Error) for a  list of possible errors.
+    async fn dispatch_chain(&self, cfg: Config) -> Result<()> {
+        let chain = self.chained_ops()?;
+        if chain.is_empty() {
+            return self.dispatch_from(cfg).await;
+        }
+        let total = chain.len() + 1;
+        let cfgs = chain
+            .iter()
+            .map(|op| op.merge_cfg(cfg.clone()))
+            .collect_vec();
+        let ops = iter::once((self, cfg)).chain(chain.iter().zip(cfgs));
+        let mut failures = vec![];
+        for (op, cfg) in ops {
+            match Box::pin(op.dispatch_from(cfg)).await {
+                Err(e) if self.keep_going => {
+                    print::print_err(&e, print::PROMPT_ERROR);
+                    if let Some(output) = e.output() {
+                        print::print_err_output(output);
+                    }
+                    failures.push(e);
+                }
+                res => res?,
+            }
+        }
+        let failed = failures.len();
+        failures.into_iter().next().map_or(Ok(()), |first| {
+            Err(Error::ChainError {
+                failed,
+                total,
+                first: Box::new(first),
+            })
+        })
+    }
+
+    /// Runs `pacaptr doctor` with the dotfile [`Config`] loaded as `dotfile`,
+    /// which is reported instead of being required.
+    ///
+    /// # Errors
+    /// Returns an [`Error::JsonError`] if the report can't be serialized.
+    async fn dispatch_doctor(&self, dotfile: Result<Config>) -> Result<()> {
+        let selected_by = if self.backend.is_some() {
+            "--backend"
+        } else if self.using.is_some() {
+            "--using"
+        } else if matches!(
+            &dotfile,
+            Ok(Config {
+                default_pm: Some(_),
+                ..
+            })
+        ) {
+            "config"
+        } else {
+            "detection"
+        };
+        let config = doctor::ConfigCheck::new(Config::path(), &dotfile);
+        let cfg = self.merge_cfg(dotfile.unwrap_or_default());
+        print::set_verbosity(cfg.verbosity);
+        let pm = cfg.conv::<Box<dyn Pm>>();
+        doctor::doctor(&*pm, config, selected_by).await
+    }
+}
+
+/// Checks if the output of the [`Pm`] method indicated by `options` (eg.
+/// `Suy`) should be prefixed with the commands it comes from, that is, if
+/// [`Config::prefix_stages`] is set and the method runs several commands,
+/// whose output is printed to a terminal.
+async fn is_staged(pm: &dyn Pm, options: &str, kws: &[&str], flags: &[&str]) -> bool {
+    let cfg = pm.cfg();
+    if !cfg.prefix_stages
+        || cfg.quiet
+        || cfg.json
+        || cfg.dry_run
+        || cfg.describe
+        || !atty::is(atty::Stream::Stdout)
+    {
+        return false;
+    }
+    let describing = Config {
+        describe: true,
+        ..cfg.clone()
+    }
+    .conv::<Box<dyn Pm>>();
+    pm::count_cmds(call_method(&*describing, options, kws, flags)).await > 1
+}
+
+/// Checks that `name` is the name of a supported package manager, of one
+/// registered with [`register`](crate::pm::register), or of one defined in
+/// the config.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when it is not, or an
+/// [`Error::ConfigError`] when the config has to be read but can't be.
+fn check_backend(name: &str) -> Result<()> {
+    if registry::find(name).is_some() || registry::find_custom(name).is_some() {
+        return Ok(());
+    }
+    // The config is only read when needed.
+    if !Config::try_load()?.custom_pms.contains_key(name) {
+        return Err(Error::ArgParseError {
+            msg: format!(
+                "Unknown backend `{}`, see `pacaptr --list-pms` for the supported ones",
+                name
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that the root directory set by `--root`, if any, exists and is
+/// supported by `pm`.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when the directory doesn't exist, or an
+/// [`Error::OperationUnimplementedError`] when `pm` doesn't support `--root`.
+fn check_root(pm: &dyn Pm) -> Result<()> {
+    let cfg = pm.cfg();
+    let root = match &cfg.root {
+        Some(root) => root,
+        None => return Ok(()),
+    };
+    cfg.check_root()?;
+    if pm.root_flags(pm.executable(), root).is_none() {
+        return Err(Error::OperationUnimplementedError {
+            op: "--root".into(),
+            pm: pm.name().into(),
+            alternatives: vec![],
+            lacking: false,
+            using: vec![],
+        });
+    }
+    Ok(())
+}
+
+/// Checks that `pm` can be run with the privilege of `pacaptr` itself, ie. as
+/// `root` if `as_root` is set.
+///
+/// Nothing is checked in a dry run, or if it is allowed by `--allow-root`.
+///
+/// # Errors
+/// Returns an [`Error::RootNotAllowedError`] when `pm` refuses to run as
+/// `root`, as told by [`Pm::allows_root`].
+fn check_privilege(pm: &dyn Pm, as_root: bool) -> Result<()> {
+    let cfg = pm.cfg();
+    if !as_root || cfg.allow_root || cfg.dry_run || cfg.describe || pm.allows_root() {
+        return Ok(());
+    }
+    Err(Error::RootNotAllowedError {
+        pm: pm.name().into(),
+    })
+}
+
+/// The operations which can be previewed under `--simulate`.
+const SIMULATED_OPS: &[Op] = &[Op::S, Op::Su, Op::Suy, Op::R];
+
+/// Checks that the operation `op` (eg. [`Op::Suy`]) can be previewed by `pm`
+/// under `--simulate`, if set.
+///
+/// The operations which might modify the system and are not in
+/// [`SIMULATED_OPS`] are rejected, instead of being run for real.
+///
+/// # Errors
+/// Returns an [`Error::OperationUnimplementedError`] when the operation can't
+/// be previewed by `pm`.
+fn check_simulate(pm: &dyn Pm, op: Op) -> Result<()> {
+    if !pm.cfg().simulate || !pm::is_locking_op(op) {
+        return Ok(());
+    }
+    if SIMULATED_OPS.contains(&op) && pm.simulate_flags().is_some() {
+        return Ok(());
+    }
+    Err(Error::OperationUnimplementedError {
+        op: format!("{} --simulate", op),
+        pm: pm.name().into(),
+        alternatives: vec![],
+        lacking: false,
+        using: vec![],
+    })
+}
+
+/// Gets the operation `op` (eg. [`Op::Suy`]) back if, with the keywords `kws`
+/// on `pm`, it should run from the local cache under `--offline`, as given by
+/// [`Pm::offline_flags`], or [`None`] if it can run as usual.
+///
+/// # Errors
+/// Returns [`Error::OfflineError`] when `op` needs the network and `pm` can't
+/// run it offline.
+fn offline_op(pm: &dyn Pm, op: Op, kws: &[&str]) -> Result<Option<Op>> {
+    let cfg = pm.cfg();
+    if !cfg.offline || cfg.describe || !pm::needs_network(op.method(), kws) {
+        return Ok(None);
+    }
+    pm.offline_flags(pm.executable(), op)
+        .map(|_| Some(op))
+        .ok_or_else(|| Error::OfflineError {
+            op: op.to_string(),
+            pm: pm.name().into(),
+        })
+}
+
+/// Warns about the options which might not do what is expected in the
+/// operation `op` (eg. [`Op::Suy`]) on `pm`, eg. those it doesn't support.
+fn warn_options(pm: &dyn Pm, op: Op) {
+    let cfg = pm.cfg();
+    if warns_partial_upgrade(cfg, op.method()) {
+        print_warning(
+            "`-Sy` only refreshes the package database without upgrading any package, \
+             use `-Syu` to upgrade them as well",
+            PROMPT_WARNING,
+        );
+    }
+
+    if let Some(jobs) = cfg.jobs {
+        if matches!(op, Op::S | Op::Su | Op::Suy) && pm.jobs_flags(jobs).is_none() {
+            print_warning(
+                format!("`--jobs` is not supported by `{}`, ignoring it", pm.name()),
+                PROMPT_WARNING,
+            );
+        }
+    }
+    if let Some(recommends) = cfg.recommends {
+        if matches!(op, Op::S | Op::Su | Op::Suy) && pm.recommends_flags(recommends).is_none() {
+            print_warning(
+                format!(
+                    "`--{}recommends` is not supported by `{}`, ignoring it",
+                    if recommends { "" } else { "no-" },
+                    pm.name()
+                ),
+                PROMPT_WARNING,
+            );
+        }
+    }
+    if !cfg.repos.is_empty()
+        && matches!(op, Op::S | Op::Ss | Op::Su | Op::Suy)
+        && pm.repo_flags(&cfg.repos).is_none()
+    {
+        print_warning(
+            format!(
+                "`--repo {}` is not supported by `{}`, ignoring it",
+                cfg.repos.join(" --repo "),
+                pm.name()
+            ),
+            PROMPT_WARNING,
+        );
+    }
+    if !cfg.index_urls.is_empty()
+        && matches!(op, Op::S | Op::Ss | Op::Si)
+        && pm.index_url_flags(op, &cfg.index_urls).is_none()
+    {
+        print_warning(
+            format!(
+                "`--index-url` is not supported by `{}` in `{}`, ignoring it",
+                pm.name(),
+                op
+            ),
+            PROMPT_WARNING,
+        );
+    }
+    if let Some(glob) = &cfg.overwrite {
+        if matches!(op, Op::S | Op::Su | Op::Suy) && pm.overwrite_flags(glob).is_none() {
+            print_warning(
+                format!(
+                    "`--overwrite` is not supported by `{}`, ignoring it",
+                    pm.name()
+                ),
+                PROMPT_WARNING,
+            );
+        }
+    }
+    if cfg.patch && matches!(op, Op::Qu | Op::Su | Op::Suy) && !pm.supports_patches() {
+        print_warning(
+            format!("`--patch` is not supported by `{}`, ignoring it", pm.name()),
+            PROMPT_WARNING,
+        );
+    }
+    if let Some(name) = &cfg.virtual_pkg {
+        if !matches!(op, Op::S | Op::R) {
+            print_warning(
+                "`--virtual` is only supported in `-S` and `-R`, ignoring it",
+                PROMPT_WARNING,
+            );
+        } else if pm.virtual_flags(name).is_none() {
+            print_warning(
+                format!(
+                    "`--virtual` is not supported by `{}`, ignoring it",
+                    pm.name()
+                ),
+                PROMPT_WARNING,
+            );
+        }
+    }
+    if cfg.autoremove {
+        if op != Op::R {
+            print_warning(
+                "`--autoremove` is only supported in `-R`, ignoring it",
+                PROMPT_WARNING,
+            );
+        } else if !pm::can_autoremove(pm) {
+            print_warning(
+                format!(
+                    "`--autoremove` is not supported by `{}`, ignoring it",
+                    pm.name()
+                ),
+                PROMPT_WARNING,
+            );
+        }
+    }
+    if cfg.filter.is_some() && op != Op::Ql {
+        print_warning(
+            "`--filter` is only supported in `-Ql`, ignoring it",
+            PROMPT_WARNING,
+        );
+    }
+    if cfg.print_format.is_some() && !matches!(op, Op::Q | Op::Ss) {
+        print_warning(
+            "`--print-format` is only supported in `-Q` and `-Ss`, ignoring it",
+            PROMPT_WARNING,
+        );
+    }
+    if cfg.max_lines.is_some() && !json::is_query(op.method()) {
+        print_warning(
+            "`--max-lines` is only supported in queries, ignoring it",
+            PROMPT_WARNING,
+        );
+    }
+    if let Some(arch) = &cfg.arch {
+        if matches!(op, Op::S | Op::Q | Op::Ss) && pm.arch_args(op, arch).is_none() {
+            print_warning(
+                format!(
+                    "`--arch` is not supported by `{}` in `{}`, ignoring it",
+                    pm.name(),
+                    op
+                ),
+                PROMPT_WARNING,
+            );
+        }
+    }
+}
+
+/// Checks if a warning should be printed about the operation indicated by `op`
+/// leading to a partial upgrade, that is, if it is `sy`, which refreshes the
+/// package database without upgrading any package, unless
+/// [`Config::no_partial_upgrade_warning`] is set.
+#[must_use]
+fn warns_partial_upgrade(cfg: &Config, op: &str) -> bool {
+    op == "sy" && !cfg.no_partial_upgrade_warning && !cfg.describe
+}
+
+/// Gets the pager command (eg. `["less", "-FRX"]`) through which the output of
+/// the [`Pm`] method indicated by `op` (eg. `ql`) should be printed, that is,
+/// if [`Config::pager`] is set and `op` is a query, whose output is printed to
+/// a terminal as indicated by `is_tty`.
+#[must_use]
+fn pager(cfg: &Config, op: &str, is_tty: bool) -> Option<Vec<String>> {
+    if !cfg.pager || !is_tty || cfg.json || cfg.dry_run || cfg.describe || !json::is_query(op) {
+        return None;
+    }
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| exec::DEFAULT_PAGER.into());
+    Some(pager.split_whitespace().map(Into::into).collect())
+}
+
+/// Calls the [`Pm`] method indicated by `options` (eg. `Suy`) on `pm`.
+///
+/// # Errors
+/// See [`Error`](crate::error::Error) for a  list of possible errors.
+pub(super) async fn call_method(
+    pm: &dyn Pm,
+    options: &str,
+    kws: &[&str],
+    flags: &[&str],
+) -> Result<()> {
+    let op = options.parse::<Op>()?;
+    if op == Op::R && pm.cfg().autoremove && pm::can_autoremove(pm) {
+        return pm::autoremoved(pm, kws, flags).await;
+    }
+    op.call(pm, kws, flags).await
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use async_trait::async_trait;
+    use once_cell::sync::Lazy;
+    use tempfile::TempDir;
+    use tokio::test;
+    use tt_call::tt_call;
+
+    use super::*;
+    use crate::{
+        dispatch::{CustomPmConfig, Hook, OnFailure},
+        methods,
+        print::BufPrinter,
+        test_util::COLOR_LOCK,
+    };
+
+    pub(crate) struct MockPm {
+        pub cfg: Config,
+    }
+
+    macro_rules! make_mock_op_body {
+        ($self:ident, $kws:ident, $flags:ident, $method:ident) => {{
+            let kws: Vec<_> = $kws.iter().chain($flags).collect();
+            panic!("should run: {} {:?}", stringify!($method), &kws)
+        }};
+    }
+
+    macro_rules! impl_pm_mock {(
+        methods = [{ $(
+            $( #[$meta:meta] )*
             async fn $method:ident;
         )* }]
     ) => {
@@ -345,6 +1902,42 @@ pub(super) mod tests {
                 &self.cfg
             }
 
+            async fn hold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, hold)
+            }
+
+            async fn unhold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, unhold)
+            }
+
+            async fn held(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, held)
+            }
+
+            async fn remove_orphans(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, remove_orphans)
+            }
+
+            async fn bundle(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, bundle)
+            }
+
+            async fn bundle_dump(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, bundle_dump)
+            }
+
+            async fn tap(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, tap)
+            }
+
+            async fn untap(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, untap)
+            }
+
+            async fn taps(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                make_mock_op_body!(self, kws, flags, taps)
+            }
+
             // * Automatically generated methods below... *
             $( async fn $method(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
                     make_mock_op_body!(self, kws, flags, $method)
@@ -393,6 +1986,71 @@ pub(super) mod tests {
         opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
     }
 
+    #[test]
+    async fn pm_not_found_unlocked() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-S", "curl"]));
+        let cfg = Config {
+            default_pm: Some("pacaptr-missing".into()),
+            ..Config::default()
+        };
+        let err = opt.dispatch_from(cfg).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::PmNotFoundError { tried, .. } if tried == &["pacaptr-missing"])
+        );
+        let lock = crate::lock::path(&crate::lock::dir(), "pacaptr-missing");
+        assert!(!lock.exists());
+    }
+
+    /// Runs `pacaptr` with the arguments `args` on the mock package manager,
+    /// returning the method it should run along with the arguments.
+    async fn mock_run(args: &[&str]) -> String {
+        use std::panic::AssertUnwindSafe;
+
+        use futures::FutureExt;
+
+        let opt = dbg!(Pacaptr::parse_from(args));
+        let err = AssertUnwindSafe(opt.dispatch_from(MOCK_CFG.clone()))
+            .catch_unwind()
+            .await
+            .unwrap_err();
+        *err.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    async fn verbs() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["install", "curl"], &["-S", "curl"]),
+            (&["remove", "curl"], &["-R", "curl"]),
+            (&["purge", "curl"], &["-Rns", "curl"]),
+            (&["search", "curl"], &["-Ss", "curl"]),
+            (&["info", "curl"], &["-Si", "curl"]),
+            (&["list"], &["-Q"]),
+            (&["update"], &["-Sy"]),
+            (&["upgrade"], &["-Syu"]),
+            (&["clean"], &["-Sc"]),
+            (&["files", "curl"], &["-Ql", "curl"]),
+            (&["owns", "/usr/bin/curl"], &["-Qo", "/usr/bin/curl"]),
+            (&["orphans"], &["-Qdt"]),
+            (&["orphans", "--remove"], &["-R", "--orphans"]),
+            // The deprecated `update` with package files, as in `-U`.
+            (&["update", "./curl.deb"], &["-U", "./curl.deb"]),
+            (
+                &["install", "--yes", "docker", "--", "--proxy=localhost:1234"],
+                &["-S", "--yes", "docker", "--", "--proxy=localhost:1234"],
+            ),
+        ];
+        for &(verb, flag) in cases {
+            let verb = mock_run(&[&["pacaptr"], verb].concat()).await;
+            let flag = mock_run(&[&["pacaptr"], flag].concat()).await;
+            assert!(verb.starts_with("should run: "));
+            assert_eq!(verb, flag);
+        }
+        assert_eq!(
+            mock_run(&["pacaptr", "install", "--hold", "curl"]).await,
+            r#"should run: hold ["curl"]"#
+        );
+    }
+
     #[test]
     #[should_panic(expected = r#"should run: sw ["curl", "wget"]"#)]
     #[allow(clippy::semicolon_if_nothing_returned)]
@@ -445,6 +2103,153 @@ pub(super) mod tests {
         opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
     }
 
+    #[test]
+    async fn pacman_flags() {
+        let opt = Pacaptr::parse_from(&[
+            "pacaptr",
+            "-S",
+            "docker",
+            "--",
+            "--noconfirm",
+            "--proxy=localhost:1234",
+            "--needed",
+            "--asdeps",
+            "--overwrite",
+        ]);
+        let cfg = opt.merge_cfg(Config::default());
+        assert!(cfg.no_confirm);
+        assert!(cfg.needed);
+        assert_eq!(
+            opt.backend_flags(),
+            ["--proxy=localhost:1234", "--overwrite"]
+        );
+        assert_eq!(
+            mock_run(&[
+                "pacaptr",
+                "-S",
+                "docker",
+                "--",
+                "--noconfirm",
+                "--needed",
+                "--foo"
+            ])
+            .await,
+            r#"should run: s ["docker", "--foo"]"#,
+        );
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "docker", "--", "--confirm"]);
+        let dotfile = Config {
+            no_confirm: true,
+            ..Config::default()
+        };
+        assert!(!opt.merge_cfg(dotfile.clone()).no_confirm);
+        assert!(opt.backend_flags().is_empty());
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "--confirm", "--asdeps", "docker"]);
+        assert!(opt.as_deps);
+        assert!(!opt.merge_cfg(dotfile).no_confirm);
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "docker"]);
+        let needed = |dotfile: Config| opt.merge_cfg(dotfile).needed;
+        assert!(!needed(Config {
+            dry_run: true,
+            ..Config::default()
+        }));
+        assert!(needed(Config {
+            needed: true,
+            ..Config::default()
+        }));
+
+        let dotfile = Config {
+            recommends: Some(false),
+            ..Config::default()
+        };
+        let recommends = |args: &[&str]| Pacaptr::parse_from(args).merge_cfg(dotfile.clone());
+        assert_eq!(
+            recommends(&["pacaptr", "-S", "curl"]).recommends,
+            Some(false)
+        );
+        assert_eq!(
+            recommends(&["pacaptr", "-S", "--with-recommends", "curl"]).recommends,
+            Some(true)
+        );
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "--no-recommends", "curl"]);
+        assert_eq!(opt.merge_cfg(Config::default()).recommends, Some(false));
+        assert_eq!(
+            mock_run(&["pacaptr", "-S", "--asdeps", "docker"]).await,
+            r#"should run: s ["docker"]"#,
+        );
+    }
+
+    #[test]
+    async fn chained_ops() {
+        let opt = Pacaptr::parse_from(&[
+            "pacaptr", "-R", "foo", "--", "--bar", "--", "-Sy", "--yes", "baz", "--", "--qux",
+        ]);
+        assert_eq!(opt.backend_flags(), ["--bar"]);
+        let chain = opt.chained_ops().unwrap();
+        assert_eq!(chain.len(), 1);
+        assert!(matches!(chain[0].ops, Operations::Sync { y: true, .. }));
+        assert!(chain[0].no_confirm);
+        assert_eq!(chain[0].keywords, ["baz"]);
+        assert_eq!(chain[0].backend_flags(), ["--qux"]);
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "-R", "foo", "--", "--", "-Sx"]);
+        assert!(matches!(
+            opt.chained_ops(),
+            Err(Error::ArgParseError { .. })
+        ));
+
+        // The extra flags right after the first `--` are never chained.
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "foo", "--", "-U"]);
+        assert_eq!(opt.backend_flags(), ["-U"]);
+        assert!(opt.chained_ops().unwrap().is_empty());
+
+        // The options of the first operation are kept along the chain.
+        let opt = Pacaptr::parse_from(&[
+            "pacaptr",
+            "-S",
+            "-v",
+            "foo",
+            "--overwrite",
+            "*",
+            "--",
+            "--",
+            "-R",
+            "bar",
+        ]);
+        let chain = opt.chained_ops().unwrap();
+        let cfg = chain[0].merge_cfg(opt.merge_cfg(Config::default()));
+        assert_eq!(cfg.overwrite.as_deref(), Some("*"));
+        assert_eq!(cfg.verbosity, Verbosity::Verbose);
+    }
+
+    #[test]
+    async fn keep_going() {
+        // `-Qcu` fails before reaching the mock package manager, which would
+        // panic if `-S bar` were run.
+        let opt = Pacaptr::parse_from(&["pacaptr", "-Qcu", "--", "--", "-S", "bar"]);
+        let err = opt.dispatch_chain(MOCK_CFG.clone()).await.unwrap_err();
+        assert!(matches!(err, Error::ArgParseError { .. }));
+
+        let opt = Pacaptr::parse_from(&[
+            "pacaptr",
+            "-Qcu",
+            "--keep-going",
+            "--",
+            "--",
+            "-Qcu",
+            "--",
+            "-Qcu",
+        ]);
+        let err = opt.dispatch_chain(MOCK_CFG.clone()).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ChainError { failed: 3, total: 3, first } if matches!(**first, Error::ArgParseError { .. })
+        ));
+        assert_eq!(err.exit_code(), 1);
+    }
+
     #[test]
     #[should_panic(expected = r#"should run: si ["docker", "--proxy=localhost:1234"]"#)]
     #[allow(clippy::semicolon_if_nothing_returned)]
@@ -468,4 +2273,446 @@ pub(super) mod tests {
 
         opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
     }
+
+    #[test]
+    #[should_panic(expected = r#"should run: s ["curl", "wget", "vim"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn from_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("pkgs.txt");
+        fs::write(&path, "# Exported by `pacaptr` from `mockpm`\nwget\nvim\n").unwrap();
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "-S",
+            "curl",
+            "--from-file",
+            path.to_str().unwrap()
+        ]));
+        assert_eq!(opt.keywords, &["curl"]);
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = r#"should run: hold ["curl"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn hold() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-S", "--hold", "curl"]));
+
+        assert!(opt.hold);
+        assert!(matches!(opt.ops, Operations::Sync { .. }));
+        assert_eq!(opt.keywords, &["curl"]);
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = r#"should run: unhold ["curl"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn unhold() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-R", "--unhold", "curl"]));
+
+        assert!(opt.unhold);
+        assert!(matches!(opt.ops, Operations::Remove { .. }));
+        assert_eq!(opt.keywords, &["curl"]);
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "should run: held []")]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn held() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-Q", "--hold"]));
+
+        assert!(opt.hold);
+        assert!(matches!(opt.ops, Operations::Query { .. }));
+        assert!(opt.keywords.is_empty());
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = r#"should run: tap ["user/repo"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn tap() {
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "-S",
+            "--tap",
+            "user/repo"
+        ]));
+
+        assert!(opt.tap);
+        assert!(matches!(opt.ops, Operations::Sync { .. }));
+        assert_eq!(opt.keywords, &["user/repo"]);
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = r#"should run: untap ["user/repo"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn untap() {
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "-R",
+            "--tap",
+            "user/repo"
+        ]));
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "should run: taps []")]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn taps() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-Q", "--tap"]));
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    async fn pick_invalid_op() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-Q", "--pick", "curl"]));
+        assert!(opt.pick);
+
+        let err = opt.dispatch_from(MOCK_CFG.clone()).await.unwrap_err();
+        assert!(matches!(err, Error::ArgParseError { msg } if msg.contains("`--pick`")));
+    }
+
+    #[test]
+    #[should_panic(expected = "should run: qdt []")]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn orphans() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-Qdt"]));
+
+        assert!(matches!(opt.ops, Operations::Query { d, t, .. } if d && t));
+        assert!(opt.keywords.is_empty());
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    async fn orphans_with_packages() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-R", "--orphans", "curl"]));
+        let err = opt.dispatch_from(MOCK_CFG.clone()).await.unwrap_err();
+        assert!(matches!(err, Error::ArgParseError { msg } if msg.contains("`--orphans`")));
+    }
+
+    #[test]
+    #[should_panic(expected = r#"should run: bundle ["Brewfile", "--verbose"]"#)]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn bundle() {
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "bundle",
+            "--file",
+            "Brewfile",
+            "--",
+            "--verbose",
+        ]));
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    async fn bundle_unsupported() {
+        // Only `brew` supports bundle files.
+        let opt = Pacaptr::parse_from(&["pacaptr", "bundle"]);
+        let cfg = Config {
+            default_pm: Some("apt".into()),
+            dry_run: true,
+            ..Config::default()
+        };
+        let err = opt.dispatch_from(cfg).await.unwrap_err();
+        assert!(matches!(err, Error::OperationUnimplementedError { op, .. } if op == "bundle"));
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "bundle", "curl"]);
+        let err = opt.dispatch_from(MOCK_CFG.clone()).await.unwrap_err();
+        assert!(matches!(err, Error::ArgParseError { msg } if msg.contains("`bundle`")));
+    }
+
+    #[test]
+    #[should_panic(expected = "should run: remove_orphans []")]
+    #[allow(clippy::semicolon_if_nothing_returned)]
+    async fn remove_orphans() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-R", "--orphans"]));
+
+        assert!(opt.orphans);
+        assert!(matches!(opt.ops, Operations::Remove { .. }));
+
+        opt.dispatch_from(MOCK_CFG.clone()).await.unwrap();
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    async fn pre_hook_abort() {
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-R", "curl"]));
+        let cfg = Config {
+            hooks: [("pre_r".to_owned(), vec!["exit 1".into()])]
+                .iter()
+                .cloned()
+                .collect(),
+            ..MOCK_CFG.clone()
+        };
+
+        // `MockPm` would have panicked if `-R` were run.
+        let err = opt.dispatch_from(cfg).await.unwrap_err();
+        assert!(matches!(err, Error::HookError { op, .. } if op == "r"));
+    }
+
+    #[test]
+    async fn printer_messages() {
+        let _lock = COLOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        colored::control::set_override(false);
+        let printer = std::sync::Arc::new(BufPrinter::default());
+        let cfg = Config {
+            default_pm: Some("mytool".into()),
+            dry_run: true,
+            jobs: Some(4),
+            virtual_pkg: Some("build-deps".into()),
+            custom_pms: [(
+                "mytool".to_owned(),
+                CustomPmConfig {
+                    ops: [("S".to_owned(), "mytool add {kws}".to_owned())]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    ..CustomPmConfig::default()
+                },
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            printer: Some(printer.clone()),
+            ..Config::default()
+        };
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "curl"]);
+        opt.dispatch_from(cfg).await.unwrap();
+        colored::control::unset_override();
+
+        assert_eq!(
+            printer.lines(),
+            [
+                "  Warning `--jobs` is not supported by `mytool`, ignoring it",
+                "  Warning `--virtual` is not supported by `mytool`, ignoring it",
+                " Canceled `mytool add curl`",
+            ]
+        );
+    }
+
+    #[test]
+    async fn privilege_checked() {
+        let brew = |cfg: Config| registry::find("brew").map(|entry| (entry.constructor)(cfg));
+        let pm = brew(Config::default()).unwrap();
+        let err = check_privilege(&*pm, true).unwrap_err();
+        assert!(matches!(err, Error::RootNotAllowedError { .. }));
+        assert_eq!(
+            err.to_string(),
+            "`brew` refuses to run as root; run `pacaptr` as a normal user instead, or use \
+             `--allow-root` to run it anyway"
+        );
+        assert!(check_privilege(&*pm, false).is_ok());
+        let pm = brew(Config {
+            allow_root: true,
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(check_privilege(&*pm, true).is_ok());
+        let pm = registry::find("apt").map(|entry| (entry.constructor)(Config::default()));
+        assert!(check_privilege(&*pm.unwrap(), true).is_ok());
+    }
+
+    #[test]
+    async fn simulate_rejects_other_ops() {
+        let dispatch = |pm: &str, args: &[&str]| {
+            let cfg = Config {
+                default_pm: Some(pm.into()),
+                simulate: true,
+                printer: Some(std::sync::Arc::new(BufPrinter::default())),
+                ..Config::default()
+            };
+            let opt = Pacaptr::parse_from(iter::once("pacaptr").chain(args.iter().copied()));
+            async move { opt.dispatch_from(cfg).await }
+        };
+        let err = dispatch("dnf", &["-Rns", "curl"]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operation `-Rns --simulate` is unimplemented for `dnf`"
+        );
+        let err = dispatch("brew", &["-S", "curl"]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::OperationUnimplementedError { op, .. } if op == "-S --simulate"
+        ));
+    }
+
+    #[test]
+    async fn offline_rejects_network_ops() {
+        let cfg = Config {
+            default_pm: Some("mytool".into()),
+            dry_run: true,
+            offline: true,
+            custom_pms: [(
+                "mytool".to_owned(),
+                CustomPmConfig {
+                    ops: [
+                        ("S".to_owned(), "mytool add {kws}".to_owned()),
+                        ("Q".to_owned(), "mytool list {kws}".to_owned()),
+                    ]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                    ..CustomPmConfig::default()
+                },
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            printer: Some(std::sync::Arc::new(BufPrinter::default())),
+            ..Config::default()
+        };
+        let opt = Pacaptr::parse_from(&["pacaptr", "-S", "curl"]);
+        let err = opt.dispatch_from(cfg.clone()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operation `-S` of `mytool` needs the network, which is disabled by `--offline`"
+        );
+        assert_eq!(err.exit_code(), 102);
+
+        let opt = Pacaptr::parse_from(&["pacaptr", "-Q", "curl"]);
+        opt.dispatch_from(cfg).await.unwrap();
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    async fn hooks_around_op() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("log");
+        let log = |msg: &str| format!("echo {} >> '{}'", msg, path.display());
+        let cfg = |pre_hook: Hook| Config {
+            default_pm: Some("mytool".into()),
+            custom_pms: [(
+                "mytool".to_owned(),
+                CustomPmConfig {
+                    ops: [("Qi".to_owned(), format!(r#"sh -c "{}" {{kws}}"#, log("op")))]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    ..CustomPmConfig::default()
+                },
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            hooks: [
+                ("pre_qi".to_owned(), vec![pre_hook]),
+                ("post_qi".to_owned(), vec![log("post").as_str().into()]),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+        let opt = || Pacaptr::parse_from(&["pacaptr", "-Qi", "curl"]);
+
+        opt()
+            .dispatch_from(cfg(log("pre").as_str().into()))
+            .await
+            .unwrap();
+        let lines = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines, "pre\nop\npost\n");
+
+        let failing = |on_failure| Hook {
+            run: "exit 1".into(),
+            on_failure: Some(on_failure),
+        };
+        opt()
+            .dispatch_from(cfg(failing(OnFailure::Warn)))
+            .await
+            .unwrap();
+        let lines = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines, "op\npost\n");
+
+        let err = opt()
+            .dispatch_from(cfg(failing(OnFailure::Abort)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::HookError { op, .. } if op == "qi"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    async fn backend_override() {
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "-S",
+            "--using",
+            "apt",
+            "--backend",
+            "dnf",
+            "curl"
+        ]));
+        let cfg = opt.merge_cfg(Config {
+            default_pm: Some("brew".into()),
+            ..Config::default()
+        });
+
+        assert_eq!(cfg.default_pm.as_deref(), Some("dnf"));
+        assert_eq!(cfg.conv::<Box<dyn Pm>>().name(), "dnf");
+    }
+
+    #[test]
+    async fn backend_unknown() {
+        // `dispatch` decides whether colors are in use.
+        let _lock = COLOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let opt = dbg!(Pacaptr::parse_from(&["pacaptr", "-S", "--backend", "foo"]));
+        let err = opt.dispatch().await.unwrap_err();
+
+        assert!(matches!(err, Error::ArgParseError { msg } if msg.contains("`foo`")));
+    }
+
+    #[test]
+    async fn pager_bypass() {
+        let cfg = Config {
+            pager: true,
+            ..Config::default()
+        };
+        assert!(pager(&cfg, "ql", true).is_some());
+        assert!(pager(&cfg, "ss", true).is_some());
+        // The output is never paged when it is not printed to a terminal.
+        assert_eq!(pager(&cfg, "ql", false), None);
+        // Nor is the output of the operations which might ask for confirmation.
+        assert_eq!(pager(&cfg, "s", true), None);
+        assert_eq!(pager(&cfg, "suy", true), None);
+        let dry_run = Config {
+            dry_run: true,
+            ..cfg.clone()
+        };
+        assert_eq!(pager(&dry_run, "ql", true), None);
+
+        let opt = dbg!(Pacaptr::parse_from(&[
+            "pacaptr",
+            "-Ql",
+            "--no-pager",
+            "glibc"
+        ]));
+        let cfg = opt.merge_cfg(cfg);
+        assert_eq!(pager(&cfg, "ql", true), None);
+    }
+
+    #[test]
+    async fn partial_upgrade_warning() {
+        let cfg = Config::default();
+        assert!(warns_partial_upgrade(&cfg, "sy"));
+        assert!(!warns_partial_upgrade(&cfg, "suy"));
+        assert!(!warns_partial_upgrade(&cfg, "syy"));
+        assert!(!warns_partial_upgrade(&cfg, "s"));
+        let cfg = Config {
+            no_partial_upgrade_warning: true,
+            ..cfg
+        };
+        assert!(!warns_partial_upgrade(&cfg, "sy"));
+    }
 }
@@ -0,0 +1,291 @@
+//! The changes made to the installed packages by an upgrade, as shown by
+//! `--show-diff`.
+//!
+//! The package listing of `-Q` is taken before and after the upgrade, and
+//! compared line by line. Parsing these listings is best-effort: each line is
+//! read as `name version ...` (with any `/suffix` of the name dropped, as in
+//! the output of `apt list`), so the upgrades can only be detected on backends
+//! reporting the versions in this form, and the package names are compared as
+//! they are listed. The lines which can't be a package (eg. the `Listing...`
+//! header of `apt list`) are skipped.
+
+use std::{collections::BTreeMap, fmt};
+
+use futures::prelude::*;
+use itertools::Itertools;
+
+use crate::{
+    error::Result,
+    json::Package,
+    pm::{self, Pm},
+    print,
+};
+
+/// The packages in a listing, mapped to their versions if known.
+type Listing = BTreeMap<String, Option<String>>;
+
+/// A change made to an installed package.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Change {
+    /// A new package has been installed.
+    Added {
+        name: String,
+        version: Option<String>,
+    },
+
+    /// A package has been removed.
+    Removed {
+        name: String,
+        version: Option<String>,
+    },
+
+    /// The version of a package has changed.
+    Upgraded {
+        name: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ver = |v: &Option<String>| v.as_deref().unwrap_or("?").to_owned();
+        match self {
+            Change::Added { name, version } => write!(f, "+ {} {}", name, ver(version)),
+            Change::Removed { name, version } => write!(f, "- {} {}", name, ver(version)),
+            Change::Upgraded { name, from, to } => {
+                write!(f, "~ {} {} -> {}", name, ver(from), ver(to))
+            }
+        }
+    }
+}
+
+/// Checks if `pkg` parsed from a line of a listing can be a package, that is,
+/// if its name starts with an alphanumeric character without ending with `.`
+/// or `:` as a header would, and its version (if any) has a digit in it.
+#[must_use]
+fn is_package(pkg: &Package) -> bool {
+    let name_ok = pkg.name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && !pkg.name.ends_with(&['.', ':'][..]);
+    let version_ok = pkg
+        .version
+        .as_ref()
+        .map_or(true, |v| v.contains(|c: char| c.is_ascii_digit()));
+    name_ok && version_ok
+}
+
+/// Parses the package listing `text` printed by `-Q`.
+#[must_use]
+fn parse_listing(text: &str) -> Listing {
+    text.lines()
+        .filter_map(Package::from_line)
+        .filter(is_package)
+        .map(|Package { name, version }| {
+            let name = name.split('/').next().unwrap_or_default().to_owned();
+            (name, version)
+        })
+        .collect()
+}
+
+/// Compares the package listings `before` and `after` an upgrade.
+#[must_use]
+fn diff(before: &Listing, after: &Listing) -> Vec<Change> {
+    let removed = before
+        .iter()
+        .filter(|(name, _)| !after.contains_key(*name))
+        .map(|(name, version)| Change::Removed {
+            name: name.clone(),
+            version: version.clone(),
+        });
+    let changed = after
+        .iter()
+        .filter_map(|(name, to)| match before.get(name) {
+            None => Some(Change::Added {
+                name: name.clone(),
+                version: to.clone(),
+            }),
+            Some(from) if from != to => Some(Change::Upgraded {
+                name: name.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            Some(_) => None,
+        });
+    removed.chain(changed).collect_vec()
+}
+
+/// Gets the package listing of `pm` without printing it.
+async fn snapshot(pm: &dyn Pm) -> Result<Listing> {
+    let (res, out) = pm::capture(pm.q(&[], &[])).await;
+    res?;
+    Ok(parse_listing(&String::from_utf8_lossy(&out)))
+}
+
+/// Runs the upgrade `run`, returning the changes it has made to the packages
+/// installed by `pm`.
+///
+/// # Errors
+/// Returns the error of the upgrade, or that of `pm.q()` if the packages can't
+/// be listed.
+pub(super) async fn changes<F>(pm: &dyn Pm, run: F) -> Result<Vec<Change>>
+where
+    F: Future<Output = Result<()>>,
+{
+    let before = snapshot(pm).await?;
+    run.await?;
+    let after = snapshot(pm).await?;
+    Ok(diff(&before, &after))
+}
+
+/// Runs the upgrade `run`, then prints the changes it has made to the
+/// packages installed by `pm`.
+///
+/// # Errors
+/// See [`changes`].
+pub(super) async fn show_diff<F>(pm: &dyn Pm, run: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let changes = changes(pm, run).await?;
+    let printer = print::printer();
+    if changes.is_empty() {
+        printer.out("No packages have changed.");
+    }
+    for change in changes {
+        printer.out(&change.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+    use crate::dispatch::Config;
+
+    fn change(sign: char, name: &str, from: Option<&str>, to: Option<&str>) -> Change {
+        let (name, from, to) = (name.into(), from.map(Into::into), to.map(Into::into));
+        match sign {
+            '+' => Change::Added { name, version: to },
+            '-' => Change::Removed {
+                name,
+                version: from,
+            },
+            _ => Change::Upgraded { name, from, to },
+        }
+    }
+
+    #[test]
+    fn listing_apt() {
+        let listing = parse_listing(
+            "Listing... Done\n\
+             curl/jammy,now 7.81.0 amd64 [installed]\n\
+             \n\
+             wget/jammy,now 1.21.2 amd64 [installed]\n",
+        );
+        assert_eq!(
+            listing.into_iter().collect_vec(),
+            [
+                ("curl".into(), Some("7.81.0".into())),
+                ("wget".into(), Some("1.21.2".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_headers_skipped() {
+        let listing = parse_listing(
+            "Package    Version\n\
+             ---------- -------\n\
+             requests   2.26.0\n\
+             ==> Formulae\n\
+             Installed packages:\n\
+             2 packages installed.\n\
+             tzdata     2021a\n\
+             d\n",
+        );
+        assert_eq!(
+            listing.into_iter().collect_vec(),
+            [
+                ("d".into(), None),
+                ("requests".into(), Some("2.26.0".into())),
+                ("tzdata".into(), Some("2021a".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_listings() {
+        let before = parse_listing("a 1.0\nb 2.0\nc 3.0\nd\n");
+        let after = parse_listing("a 1.0\nb 2.1\nd\ne 5.0\n");
+        assert_eq!(
+            diff(&before, &after),
+            [
+                change('-', "c", Some("3.0"), None),
+                change('~', "b", Some("2.0"), Some("2.1")),
+                change('+', "e", None, Some("5.0")),
+            ]
+        );
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn change_display() {
+        assert_eq!(
+            change('~', "b", Some("2.0"), Some("2.1")).to_string(),
+            "~ b 2.0 -> 2.1"
+        );
+        assert_eq!(change('+', "e", None, None).to_string(), "+ e ?");
+    }
+
+    static MOCK_CFG: Lazy<Config> = Lazy::new(Config::default);
+
+    /// A package manager whose listing changes after each upgrade.
+    struct MockPm {
+        listings: &'static [&'static str],
+        upgrades: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Pm for MockPm {
+        fn name(&self) -> &str {
+            "mockpm"
+        }
+
+        fn cfg(&self) -> &Config {
+            &MOCK_CFG
+        }
+
+        async fn q(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+            let listing = self.listings[self.upgrades.load(Ordering::SeqCst)];
+            pm::capture_output(listing.as_bytes());
+            Ok(())
+        }
+
+        async fn su(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+            self.upgrades.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_mockpm() {
+        let pm = MockPm {
+            listings: &["a 1.0\nb 2.0\nc 3.0\n", "a 1.0\nb 2.1\nd 4.0\n"],
+            upgrades: AtomicUsize::new(0),
+        };
+        let changes = changes(&pm, pm.su(&[], &[])).await.unwrap();
+        assert_eq!(
+            changes,
+            [
+                change('-', "c", Some("3.0"), None),
+                change('~', "b", Some("2.0"), Some("2.1")),
+                change('+', "d", None, Some("4.0")),
+            ]
+        );
+    }
+}
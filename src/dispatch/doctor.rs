@@ -0,0 +1,507 @@
+//! The environment diagnostics reported by `pacaptr doctor`, eg. to find out
+//! why a package manager has been selected over another.
+//!
+//! Every probe is failure-tolerant: a missing config file or a broken package
+//! manager is reported as such, without aborting the rest of the report.
+//!
+//! The version of the package manager is also shown by `pacaptr --version
+//! --verbose`.
+
+use std::{collections::HashMap, env, path::PathBuf, sync::Mutex, time::Duration};
+
+use is_root::is_root;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use super::Config;
+use crate::{
+    error::{Error, Result},
+    exec::{self, Cmd, Mode, StatusCode},
+    history,
+    json::Report,
+    pm::{registry, Pm},
+    print::{self, PROMPT_INFO, PROMPT_WARNING},
+};
+
+/// How long the version probe of the package manager may run.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The state of the config file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct ConfigCheck {
+    /// The path of the config file, if it can be resolved.
+    pub path: Option<PathBuf>,
+
+    /// Whether the config file exists. Otherwise, the defaults are used.
+    pub exists: bool,
+
+    /// Why the config file can't be resolved or parsed, if that is the case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A package manager tried in automatic detection.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Candidate {
+    /// The name of the package manager, eg. `apt`.
+    pub name: String,
+
+    /// Whether the package manager is found on the current system.
+    pub detected: bool,
+}
+
+/// Whether the standard streams are connected to a terminal.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Tty {
+    pub stdin: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+}
+
+/// The results of a command run to probe the package manager.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Probe {
+    /// The program and the arguments of the command.
+    pub cmd: Vec<String>,
+
+    /// The combined `stdout`/`stderr` of the command, if it has been run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+
+    /// The exit code of the command, if it has exited normally.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusCode>,
+
+    /// Why the command has failed, if that is the case.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The report of `pacaptr doctor`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Diagnosis {
+    /// The state of the config file.
+    pub config: ConfigCheck,
+
+    /// The package managers tried in automatic detection on the current
+    /// system, in order of precedence.
+    pub candidates: Vec<Candidate>,
+
+    /// How the package manager has been selected, eg. `--using`.
+    pub selected_by: String,
+
+    /// The command prefixed to the commands requiring elevated privileges, eg.
+    /// `sudo -S`, if any.
+    pub elevation: Option<String>,
+
+    /// Whether [`pacaptr`](crate) is run as root.
+    pub root: bool,
+
+    /// Whether the standard streams are connected to a terminal.
+    pub tty: Tty,
+
+    /// The version probe of the selected package manager.
+    pub version: Probe,
+}
+
+impl ConfigCheck {
+    /// Checks the config file at `path`, which has been loaded as `loaded`.
+    #[must_use]
+    pub(crate) fn new(path: Result<PathBuf>, loaded: &Result<Config>) -> Self {
+        match path {
+            Ok(path) => ConfigCheck {
+                exists: path.exists(),
+                path: Some(path),
+                error: loaded.as_ref().err().map(ToString::to_string),
+            },
+            Err(e) => ConfigCheck {
+                path: None,
+                exists: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Gets the package managers tried in automatic detection on the current
+/// system, in order of precedence.
+#[must_use]
+fn candidates() -> Vec<Candidate> {
+    registry::REGISTRY
+        .iter()
+        .filter(|entry| {
+            entry
+                .detect_paths
+                .iter()
+                .any(|&(os, _)| os == env::consts::OS)
+        })
+        .map(|entry| Candidate {
+            name: entry.name.into(),
+            detected: entry.is_detected(),
+        })
+        .collect()
+}
+
+/// Gets the command prefixed to the commands of `pm` requiring elevated
+/// privileges, eg. `sudo -S`, if any.
+#[must_use]
+fn elevation(pm: &dyn Pm) -> Option<String> {
+    let argv = Cmd::with_sudo(&[pm.executable()]).argv();
+    let prefix = &argv[..argv.len() - 1];
+    (!prefix.is_empty()).then(|| exec::shell_join(prefix))
+}
+
+/// Runs `cmd`, returning its results whether it succeeds or not.
+async fn probe(cmd: Cmd) -> Probe {
+    let argv = cmd.argv();
+    let res = cmd.timeout(PROBE_TIMEOUT).mode(Mode::Mute).exec().await;
+    let status = history::status_code(&res);
+    let lossy = |out: &[u8]| String::from_utf8_lossy(out).into_owned();
+    match res {
+        Ok(out) => Probe {
+            cmd: argv,
+            stdout: Some(lossy(&out)),
+            status,
+            error: None,
+        },
+        Err(e) => Probe {
+            cmd: argv,
+            stdout: match &e {
+                Error::CmdStatusCodeError { output, .. } => Some(lossy(output)),
+                _ => None,
+            },
+            status,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// The version probes run so far, indexed by the executable, so that each of
+/// them is run at most once per invocation.
+static VERSIONS: Lazy<Mutex<HashMap<String, Probe>>> = Lazy::new(Mutex::default);
+
+/// Runs the version probe of `pm`, ie. its [`Pm::version_cmd`] (eg. `apt
+/// --version`), or reuses its results if it has already been run.
+pub(crate) async fn version(pm: &dyn Pm) -> Probe {
+    let exe = pm.executable();
+    if let Some(probe) = VERSIONS.lock().unwrap().get(exe) {
+        return probe.clone();
+    }
+    let probe = probe(pm.version_cmd()).await;
+    VERSIONS.lock().unwrap().insert(exe.into(), probe.clone());
+    probe
+}
+
+/// Renders the version of [`pacaptr`](crate), followed by that of the
+/// package manager `pm` which would be selected, if any, ie. the whole output
+/// of its [`Pm::version_cmd`], indented after the first line.
+pub(super) async fn version_info(pm: Option<&dyn Pm>) -> String {
+    let header = format!("{} {}", clap::crate_name!(), clap::crate_version!());
+    let pm = match pm {
+        Some(pm) => pm,
+        None => return format!("{}\nBackend: none detected", header),
+    };
+    let path = registry::exe_path(pm).map_or_else(
+        || format!("`{}` not found", pm.executable()),
+        |path| path.display().to_string(),
+    );
+    let probe = version(pm).await;
+    let version = match &probe {
+        Probe {
+            error: None,
+            stdout: Some(out),
+            ..
+        } => out.trim().lines().collect::<Vec<_>>().join("\n  "),
+        Probe { error, .. } => format!("unknown ({})", error.as_deref().unwrap_or_default()),
+    };
+    format!(
+        "{}\nBackend: {} ({})\nBackend version: {}",
+        header,
+        pm.name(),
+        path,
+        version
+    )
+}
+
+/// Diagnoses the environment of `pm`, whose config file is in the state of
+/// `config`, and which has been selected as indicated by `selected_by`.
+pub(crate) async fn diagnose(pm: &dyn Pm, config: ConfigCheck, selected_by: &str) -> Diagnosis {
+    Diagnosis {
+        config,
+        candidates: candidates(),
+        selected_by: selected_by.into(),
+        elevation: elevation(pm),
+        root: is_root(),
+        tty: Tty {
+            stdin: atty::is(atty::Stream::Stdin),
+            stdout: atty::is(atty::Stream::Stdout),
+            stderr: atty::is(atty::Stream::Stderr),
+        },
+        version: version(pm).await,
+    }
+}
+
+impl Diagnosis {
+    /// Renders the [`Diagnosis`] of the package manager `pm` as `(prompt,
+    /// message)` pairs, where the prompt is [`PROMPT_WARNING`] for the issues
+    /// found.
+    #[must_use]
+    fn render(&self, pm: &str) -> Vec<(&'static str, String)> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let config = match &self.config {
+            ConfigCheck {
+                path: Some(path),
+                error: None,
+                exists,
+            } => (
+                PROMPT_INFO,
+                format!(
+                    "Config `{}` {}",
+                    path.display(),
+                    if *exists {
+                        "is loaded"
+                    } else {
+                        "is not found, using the defaults"
+                    }
+                ),
+            ),
+            ConfigCheck {
+                path,
+                error: Some(e),
+                ..
+            } => (
+                PROMPT_WARNING,
+                match path {
+                    Some(path) => format!("Config `{}` is invalid: {}", path.display(), e),
+                    None => format!("Config path is not found: {}", e),
+                },
+            ),
+            ConfigCheck { .. } => (PROMPT_WARNING, "Config path is not found".into()),
+        };
+        let candidates = self.candidates.iter().map(|c| {
+            let state = if !c.detected {
+                "is not detected"
+            } else if c.name == pm {
+                "is detected (selected)"
+            } else {
+                "is detected"
+            };
+            (PROMPT_INFO, format!("`{}` {}", c.name, state))
+        });
+        let version = &self.version;
+        let probed = match (&version.error, &version.stdout) {
+            (None, out) => (
+                PROMPT_INFO,
+                format!("`{}` prints:", exec::shell_join(&version.cmd)),
+                out,
+            ),
+            (Some(e), out) => (
+                PROMPT_WARNING,
+                format!("`{}` failed: {}", exec::shell_join(&version.cmd), e),
+                out,
+            ),
+        };
+        let output = probed.2.iter().flat_map(|out| {
+            out.trim()
+                .lines()
+                .map(|ln| (PROMPT_INFO, format!("  {}", ln.trim_end())))
+        });
+
+        std::iter::once(config)
+            .chain(candidates)
+            .chain([
+                (
+                    PROMPT_INFO,
+                    format!("Using `{}` (selected by {})", pm, self.selected_by),
+                ),
+                (
+                    PROMPT_INFO,
+                    match &self.elevation {
+                        Some(cmd) => format!("Elevating with `{}`", cmd),
+                        None => "Elevation is not needed".into(),
+                    },
+                ),
+                (
+                    PROMPT_INFO,
+                    format!("Running as root: {}", yes_no(self.root)),
+                ),
+                (
+                    PROMPT_INFO,
+                    format!(
+                        "Terminal: stdin {}, stdout {}, stderr {}",
+                        yes_no(self.tty.stdin),
+                        yes_no(self.tty.stdout),
+                        yes_no(self.tty.stderr),
+                    ),
+                ),
+                (probed.0, probed.1),
+            ])
+            .chain(output)
+            .collect()
+    }
+}
+
+/// Prints the [`Diagnosis`] of `pm`, as a [`Report::Doctor`] under `--json`.
+///
+/// # Errors
+/// Returns an [`Error::JsonError`] if the serialization fails.
+pub(super) async fn doctor(pm: &dyn Pm, config: ConfigCheck, selected_by: &str) -> Result<()> {
+    let diagnosis = diagnose(pm, config, selected_by).await;
+    if pm.cfg().json {
+        return Report::Doctor {
+            pm: pm.name(),
+            op: "doctor",
+            diagnosis: &diagnosis,
+        }
+        .print();
+    }
+    for (prompt, msg) in diagnosis.render(pm.name()) {
+        if prompt == PROMPT_WARNING {
+            print::print_warning(msg, prompt);
+        } else {
+            print::print_msg(&msg, prompt);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tap::prelude::*;
+
+    use super::*;
+    use crate::pm::Unknown;
+
+    fn diagnosis() -> Diagnosis {
+        Diagnosis {
+            config: ConfigCheck {
+                path: Some("/home/u/.config/pacaptr/pacaptr.toml".into()),
+                exists: false,
+                error: None,
+            },
+            candidates: vec![
+                Candidate {
+                    name: "apk".into(),
+                    detected: false,
+                },
+                Candidate {
+                    name: "apt".into(),
+                    detected: true,
+                },
+            ],
+            selected_by: "detection".into(),
+            elevation: Some("sudo -S".into()),
+            root: false,
+            tty: Tty {
+                stdin: true,
+                stdout: true,
+                stderr: false,
+            },
+            version: Probe {
+                cmd: vec!["apt".into(), "--version".into()],
+                stdout: Some("apt 2.4.8 (amd64)\n".into()),
+                status: Some(0),
+                error: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn version_mockpm() {
+        let header = format!("pacaptr {}", clap::crate_version!());
+        assert_eq!(
+            version_info(None).await,
+            format!("{}\nBackend: none detected", header)
+        );
+
+        let pm = Config {
+            default_pm: Some("mockpm".into()),
+            ..Config::default()
+        }
+        .conv::<Box<dyn Pm>>();
+        VERSIONS.lock().unwrap().insert(
+            "mockpm".into(),
+            Probe {
+                cmd: vec!["mockpm".into(), "--version".into()],
+                stdout: Some("mockpm 1.2.3\nCopyright (C) 2021\n".into()),
+                status: Some(0),
+                error: None,
+            },
+        );
+        assert_eq!(
+            version_info(Some(&*pm)).await,
+            format!(
+                "{}\nBackend: mockpm (`mockpm` not found)\n\
+                 Backend version: mockpm 1.2.3\n  Copyright (C) 2021",
+                header
+            )
+        );
+    }
+
+    #[test]
+    fn doctor_render() {
+        let lines = diagnosis().render("apt");
+        let msgs = lines
+            .iter()
+            .map(|(_, msg)| msg.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            msgs,
+            [
+                "Config `/home/u/.config/pacaptr/pacaptr.toml` is not found, using the defaults",
+                "`apk` is not detected",
+                "`apt` is detected (selected)",
+                "Using `apt` (selected by detection)",
+                "Elevating with `sudo -S`",
+                "Running as root: no",
+                "Terminal: stdin yes, stdout yes, stderr no",
+                "`apt --version` prints:",
+                "  apt 2.4.8 (amd64)",
+            ]
+        );
+        assert!(lines.iter().all(|&(prompt, _)| prompt == PROMPT_INFO));
+    }
+
+    #[test]
+    fn doctor_json() {
+        let report = Report::Doctor {
+            pm: "apt",
+            op: "doctor",
+            diagnosis: &diagnosis(),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"pm":"apt","op":"doctor","config":{"path":"/home/u/.config/pacaptr/pacaptr.toml","exists":false},"candidates":[{"name":"apk","detected":false},{"name":"apt","detected":true}],"selected_by":"detection","elevation":"sudo -S","root":false,"tty":{"stdin":true,"stdout":true,"stderr":false},"version":{"cmd":["apt","--version"],"stdout":"apt 2.4.8 (amd64)\n","status":0}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn doctor_broken_pm() {
+        let loaded = Err(Error::ConfigError {
+            msg: "Failed to read config".into(),
+        });
+        let config = ConfigCheck::new(Ok("/nonexistent/pacaptr.toml".into()), &loaded);
+        // Every probe is tolerated, even that of a missing executable.
+        let diagnosis = diagnose(
+            &Unknown::new("pacaptr-missing-exe", vec![]),
+            config,
+            "--using",
+        )
+        .await;
+        assert!(diagnosis.version.error.is_some());
+        assert_eq!(diagnosis.version.status, None);
+
+        let lines = diagnosis.render("unknown");
+        let warnings = lines
+            .iter()
+            .filter(|&&(prompt, _)| prompt == PROMPT_WARNING)
+            .map(|(_, msg)| msg.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(
+            warnings[0],
+            "Config `/nonexistent/pacaptr.toml` is invalid: Failed to handle config: Failed to read config"
+        );
+        assert!(warnings[1].contains("pacaptr-missing-exe' --version` failed: "));
+    }
+}
@@ -0,0 +1,145 @@
+//! The package lists written by `pacaptr export` and read back by
+//! `--from-file`, eg. to migrate the packages installed to another machine.
+//!
+//! A package list holds a package spec per line, as formatted by
+//! [`Pm::format_export`], so that it can be given to `-S` as is. Blank lines
+//! and comments starting with `#` are ignored.
+
+use std::{fs, path::Path};
+
+use crate::{
+    error::Result,
+    pm::{self, Pm},
+};
+
+/// Renders the package `specs` exported from `pm` as a package list.
+#[must_use]
+fn render(pm: &str, specs: &[String]) -> String {
+    let header = format!("# Exported by `pacaptr` from `{}`\n", pm);
+    specs.iter().fold(header, |mut list, spec| {
+        list.push_str(spec);
+        list.push('\n');
+        list
+    })
+}
+
+/// Parses the package list `text`, returning the package specs in it.
+#[must_use]
+fn parse(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|ln| !ln.is_empty() && !ln.starts_with('#'))
+        .map(Into::into)
+        .collect()
+}
+
+/// Lists the packages explicitly installed by `pm` (or all of them if it
+/// can't tell) with [`Pm::export`], returning the package list to be written
+/// by `pacaptr export`.
+///
+/// # Errors
+/// Returns the error of `pm.export()`.
+pub(super) async fn export(pm: &dyn Pm) -> Result<String> {
+    let (res, out) = pm::capture(pm.export(&[])).await;
+    res?;
+    let specs = pm.format_export(&String::from_utf8_lossy(&out));
+    Ok(render(pm.name(), &specs))
+}
+
+/// Lists the packages installed by `pm` in its own bundle file format (eg. a
+/// `Brewfile` for `brew`) with [`Pm::bundle_dump`], returning the bundle file
+/// to be written by `pacaptr bundle --dump`.
+///
+/// # Errors
+/// Returns the error of `pm.bundle_dump()`.
+pub(super) async fn bundle(pm: &dyn Pm, flags: &[&str]) -> Result<String> {
+    let (res, out) = pm::capture(pm.bundle_dump(&[], flags)).await;
+    res?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Reads the package list at `path`, as given by `--from-file`.
+///
+/// # Errors
+/// Returns an [`Error::IoError`](crate::error::Error::IoError) when the file
+/// can't be read.
+pub(super) fn import(path: &Path) -> Result<Vec<String>> {
+    Ok(parse(&fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dispatch::Config,
+        pm::{Apt, Conda, Dnf, Pip},
+    };
+
+    fn round_trip(pm: &dyn Pm, listing: &str) -> Vec<String> {
+        parse(&render(pm.name(), &pm.format_export(listing)))
+    }
+
+    #[test]
+    fn export_apt() {
+        let pm = Apt::new(Config::default());
+        assert_eq!(round_trip(&pm, "curl\nwget\n\n"), ["curl", "wget"]);
+        assert_eq!(
+            round_trip(&pm, "curl/jammy,now 7.81.0 amd64 [installed]\n"),
+            ["curl"]
+        );
+    }
+
+    #[test]
+    fn export_dnf() {
+        let pm = Dnf::new(Config::default());
+        assert_eq!(
+            round_trip(
+                &pm,
+                "curl-0:7.76.1-14.el9.x86_64\nperl-IO-Socket-SSL-0:2.073-1.el9.noarch\n"
+            ),
+            ["curl", "perl-IO-Socket-SSL"]
+        );
+    }
+
+    #[test]
+    fn export_pip() {
+        let pm = Pip::new(Config {
+            default_pm: Some("pip3".into()),
+            ..Config::default()
+        });
+        let listing =
+            "# Editable install\n-e git+https://github.com/u/x.git#egg=x\nwheel==0.37.0\n";
+        assert_eq!(
+            round_trip(&pm, listing),
+            ["-e git+https://github.com/u/x.git#egg=x", "wheel==0.37.0"]
+        );
+    }
+
+    #[test]
+    fn export_conda() {
+        let pm = Conda::new(Config::default());
+        let listing = indoc::indoc! {"
+            name: base
+            channels:
+              - defaults
+            dependencies:
+              - numpy=1.21.2=py39h20f2e39_0
+              - pip=21.2.4=py39hecd8cb5_0
+              - pip:
+                - wheel==0.37.0
+            prefix: /opt/conda
+        "};
+        assert_eq!(
+            round_trip(&pm, listing),
+            ["numpy=1.21.2=py39h20f2e39_0", "pip=21.2.4=py39hecd8cb5_0"]
+        );
+    }
+
+    #[test]
+    fn import_comments() {
+        assert_eq!(
+            parse("# Exported by `pacaptr` from `apt`\n\n  curl  \n# wget\nvim\n"),
+            ["curl", "vim"]
+        );
+    }
+}
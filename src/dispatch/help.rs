@@ -0,0 +1,119 @@
+//! Descriptions of the `pacman`-style operations, as in `pacaptr help Syu`,
+//! and of the verb-style ones standing for them, as in `pacaptr help upgrade`.
+
+use itertools::Itertools;
+use tt_call::tt_call;
+
+use super::cmd::call_method;
+use crate::{
+    error::{Error, Result},
+    methods,
+    pm::Pm,
+};
+
+/// The verb-style operations, eg. `install`, along with the `pacman`-style
+/// operations they stand for, eg. `S`.
+static VERBS: &[(&str, &str)] = &[
+    ("install", "S"),
+    ("remove", "R"),
+    ("purge", "Rns"),
+    ("search", "Ss"),
+    ("info", "Si"),
+    ("list", "Q"),
+    ("update", "Sy"),
+    ("upgrade", "Suy"),
+    ("clean", "Sc"),
+    ("files", "Ql"),
+    ("owns", "Qo"),
+    ("orphans", "Qdt"),
+];
+
+/// Gets the `pacman`-style operation the verb-style operation `verb` stands
+/// for, eg. `Suy` for `upgrade`.
+#[must_use]
+pub(super) fn verb_options(verb: &str) -> Option<&'static str> {
+    VERBS
+        .iter()
+        .find(|&&(name, _)| name == verb)
+        .map(|&(_, options)| options)
+}
+
+/// Converts an operation given by the user, eg. `-Syu` or `upgrade`, to the
+/// name of the corresponding [`Pm`] method, eg. `suy`.
+///
+/// The operation itself (eg. `S`) stays in front, while the rest of the flags
+/// are sorted in ASCII order.
+#[must_use]
+pub(super) fn method_name(op: &str) -> String {
+    let op = verb_options(op).unwrap_or(op);
+    let mut chars = op.trim_start_matches('-').chars();
+    chars
+        .next()
+        .into_iter()
+        .chain(chars.sorted_unstable())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Gets the description of the [`Pm`] method `method`, eg. `suy`.
+#[must_use]
+fn method_doc(method: &str) -> Option<&'static str> {
+    macro_rules! doc_match {(
+        methods = [{ $(
+            $( #[doc = $doc:literal] )*
+            async fn $method:ident;
+        )* }]
+    ) => {
+        match method {
+            $( stringify!($method) => Some(concat!($( $doc ),*).trim()), )*
+            _ => None,
+        }
+    };}
+
+    tt_call! {
+        macro = [{ methods }]
+        ~~> doc_match
+    }
+}
+
+/// Prints the description of the operation `op`, eg. `Syu`, followed by the
+/// native command(s) that `pm` would run for it.
+///
+/// `pm` should be created with [`Config::describe`](super::Config::describe)
+/// set, so that nothing is actually run.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] if `op` is not a supported operation.
+pub(super) async fn describe(pm: &dyn Pm, op: &str, kws: &[&str], flags: &[&str]) -> Result<()> {
+    let method = method_name(op);
+    let doc = method_doc(&method).ok_or_else(|| Error::ArgParseError {
+        msg: format!("Invalid flag combination `-{}`", op.trim_start_matches('-')),
+    })?;
+    println!("{}", doc);
+    call_method(pm, &method, kws, flags).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_names() {
+        assert_eq!(method_name("Syu"), "suy");
+        assert_eq!(method_name("-Syu"), "suy");
+        assert_eq!(method_name("Rns"), "rns");
+        assert_eq!(method_name("Qi"), "qi");
+        assert_eq!(method_name("upgrade"), "suy");
+        assert_eq!(method_name("purge"), "rns");
+        assert_eq!(method_name(""), "");
+    }
+
+    #[test]
+    fn method_docs() {
+        assert_eq!(
+            method_doc("s"),
+            Some("S installs one or more packages by name.")
+        );
+        assert_eq!(method_doc("qz"), None);
+    }
+}
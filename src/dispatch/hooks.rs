@@ -0,0 +1,300 @@
+//! User-defined hooks to be run around an operation.
+//!
+//! A hook is a shell command configured in the `[hooks]` table of the
+//! [`Config`], indexed by the name of the operation prefixed with `pre_` (run
+//! before the operation) or `post_` (run after the operation succeeds), eg.
+//! ```toml
+//! [hooks]
+//! pre_suy = "snapper create -d pacaptr"
+//! pre_su = ["timeshift --create"]
+//! post_suy = { run = "notify-send 'upgrade done'", on_failure = "abort" }
+//! ```
+//! The keywords of the operation are passed to the hook in the
+//! `PACAPTR_PKGS` environment variable, separated by spaces.
+//!
+//! An operation can have several hooks, which are run in order.
+//!
+//! The `[pre_hooks]` table (eg. `suy` for `pre_suy`) and the names without a
+//! prefix in `[hooks]` (eg. `suy` for `post_suy`) are deprecated: they are
+//! only used when the prefixed name isn't set, with a warning either way.
+
+use crate::{
+    dispatch::{Config, Hook, OnFailure},
+    error::{Error, Result},
+    exec::{Cmd, Mode},
+    print::{print_warning, PROMPT_WARNING},
+};
+
+/// The environment variable holding the keywords of the operation.
+const PKGS_ENV_VAR: &str = "PACAPTR_PKGS";
+
+/// Makes the [`Cmd`] running `hook` in the current platform's shell.
+fn hook_cmd(hook: &str, kws: &[&str]) -> Cmd {
+    let shell: &[&str] = if cfg!(windows) {
+        &["powershell", "-NoProfile", "-Command"]
+    } else {
+        &["sh", "-c"]
+    };
+    Cmd::new(shell)
+        .kws(&[hook])
+        .envs(&[(PKGS_ENV_VAR, kws.join(" "))])
+}
+
+/// Runs `hook` for the operation `op`.
+///
+/// # Errors
+/// Returns an [`Error::HookError`] if the hook fails.
+async fn run_hook(cfg: &Config, op: &str, hook: &str, kws: &[&str]) -> Result<()> {
+    let mode = if cfg.dry_run {
+        Mode::PrintCmd
+    } else if cfg.json {
+        Mode::Mute
+    } else {
+        Mode::CheckErr
+    };
+    hook_cmd(hook, kws)
+        .mode(mode)
+        .exec()
+        .await
+        .map(|_| ())
+        .map_err(|e| Error::HookError {
+            op: op.into(),
+            msg: e.to_string(),
+        })
+}
+
+/// Gets the hooks configured for the operation `op` (eg. `suy`), to be run
+/// before it if `pre` is set, or after it otherwise.
+///
+/// A warning is printed if the deprecated name of these hooks is used, which
+/// is ignored when the prefixed name is set as well.
+fn hooks_of<'c>(cfg: &'c Config, op: &str, pre: bool) -> &'c [Hook] {
+    let (key, table, deprecated) = if pre {
+        (format!("pre_{}", op), "pre_hooks", cfg.pre_hooks.get(op))
+    } else {
+        (format!("post_{}", op), "hooks", cfg.hooks.get(op))
+    };
+    let hooks = cfg.hooks.get(&key);
+    if deprecated.is_some() {
+        let advice = if hooks.is_some() {
+            "is ignored in favor of"
+        } else {
+            "should be replaced by"
+        };
+        print_warning(
+            format!(
+                "`{}` in `[{}]` is deprecated and {} `{}` in `[hooks]`",
+                op, table, advice, key
+            ),
+            PROMPT_WARNING,
+        );
+    }
+    hooks.or(deprecated).map_or(&[], Vec::as_slice)
+}
+
+/// Runs the hooks configured for the operation `op` (eg. `suy`), before it
+/// if `pre` is set, or after it otherwise.
+///
+/// # Errors
+/// Returns an [`Error::HookError`] if a hook fails under
+/// [`OnFailure::Abort`], in which case the remaining hooks are not run.
+async fn run_hooks(cfg: &Config, op: &str, kws: &[&str], pre: bool) -> Result<()> {
+    let default = if pre {
+        OnFailure::Abort
+    } else {
+        OnFailure::Warn
+    };
+    for hook in hooks_of(cfg, op, pre) {
+        match run_hook(cfg, op, &hook.run, kws).await {
+            Err(e) if hook.on_failure.unwrap_or(default) == OnFailure::Warn => {
+                print_warning(e, PROMPT_WARNING);
+            }
+            res => res?,
+        }
+    }
+    Ok(())
+}
+
+/// Runs the hooks configured for the operation `op` (eg. `suy`) before it
+/// starts, if any.
+///
+/// # Errors
+/// Returns an [`Error::HookError`] if a hook fails, unless it is set to
+/// [`OnFailure::Warn`]. The operation should then be aborted.
+pub(crate) async fn run_pre_hook(cfg: &Config, op: &str, kws: &[&str]) -> Result<()> {
+    run_hooks(cfg, op, kws, true).await
+}
+
+/// Runs the hooks configured for the operation `op` (eg. `suy`) after it has
+/// succeeded, if any.
+///
+/// A failing hook doesn't affect the result of the operation by default, so a
+/// warning is printed instead.
+///
+/// # Errors
+/// Returns an [`Error::HookError`] if a hook set to [`OnFailure::Abort`]
+/// fails.
+pub(crate) async fn run_post_hook(cfg: &Config, op: &str, kws: &[&str]) -> Result<()> {
+    run_hooks(cfg, op, kws, false).await
+}
+
+#[cfg(test)]
+#[cfg(not(windows))]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn hook_env() {
+        let out = hook_cmd(r#"echo "$PACAPTR_PKGS""#, &["curl", "wget"])
+            .mode(Mode::Mute)
+            .exec()
+            .await
+            .unwrap();
+        assert_eq!(out, b"curl wget\n");
+    }
+
+    #[tokio::test]
+    async fn post_hook_s() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("pkgs");
+        let cfg = Config {
+            hooks: [(
+                "post_s".to_owned(),
+                vec![format!(r#"echo "$PACAPTR_PKGS" > '{}'"#, path.display())
+                    .as_str()
+                    .into()],
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+
+        run_post_hook(&cfg, "su", &["curl"]).await.unwrap();
+        assert!(!path.exists());
+
+        run_post_hook(&cfg, "s", &["curl", "wget"]).await.unwrap();
+        let pkgs = fs::read_to_string(&path).unwrap();
+        assert_eq!(pkgs, "curl wget\n");
+    }
+
+    #[tokio::test]
+    async fn pre_hook() {
+        let cfg = Config {
+            hooks: [
+                (
+                    "pre_s".to_owned(),
+                    vec![r#"test "$PACAPTR_PKGS" = curl"#.into()],
+                ),
+                ("pre_r".to_owned(), vec!["exit 1".into()]),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+
+        run_pre_hook(&cfg, "s", &["curl"]).await.unwrap();
+        run_pre_hook(&cfg, "su", &[]).await.unwrap();
+
+        let err = run_pre_hook(&cfg, "r", &["curl"]).await.unwrap_err();
+        assert!(matches!(err, Error::HookError { op, .. } if op == "r"));
+    }
+
+    #[tokio::test]
+    async fn post_hook_failure() {
+        let cfg = Config {
+            hooks: [
+                ("post_s".to_owned(), vec!["exit 1".into()]),
+                (
+                    "post_r".to_owned(),
+                    vec![Hook {
+                        run: "exit 1".into(),
+                        on_failure: Some(OnFailure::Abort),
+                    }],
+                ),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+        // A failing hook only gives a warning by default.
+        run_post_hook(&cfg, "s", &["curl"]).await.unwrap();
+
+        let err = run_post_hook(&cfg, "r", &["curl"]).await.unwrap_err();
+        assert!(matches!(err, Error::HookError { op, .. } if op == "r"));
+    }
+
+    #[tokio::test]
+    async fn hooks_precedence() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("log");
+        let log = |msg: &str| -> Hook {
+            format!("echo {} >> '{}'", msg, path.display())
+                .as_str()
+                .into()
+        };
+        let cfg = Config {
+            pre_hooks: [
+                ("su".to_owned(), vec![log("a")]),
+                ("s".to_owned(), vec![log("f")]),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            hooks: [
+                ("pre_su".to_owned(), vec![log("b"), log("c")]),
+                ("su".to_owned(), vec![log("d")]),
+                ("post_su".to_owned(), vec![log("e")]),
+                ("s".to_owned(), vec![log("g")]),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+
+        // The prefixed names win over the deprecated ones...
+        run_pre_hook(&cfg, "su", &[]).await.unwrap();
+        run_post_hook(&cfg, "su", &[]).await.unwrap();
+        // ... which are still used on their own.
+        run_pre_hook(&cfg, "s", &[]).await.unwrap();
+        run_post_hook(&cfg, "s", &[]).await.unwrap();
+        let lines = fs::read_to_string(&path).unwrap();
+        assert_eq!(lines, "b\nc\ne\nf\ng\n");
+    }
+
+    #[tokio::test]
+    async fn pre_hook_warn() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("touched");
+        let cfg = Config {
+            hooks: [(
+                "pre_s".to_owned(),
+                vec![
+                    Hook {
+                        run: "exit 1".into(),
+                        on_failure: Some(OnFailure::Warn),
+                    },
+                    format!("touch '{}'", path.display()).as_str().into(),
+                    "exit 1".into(),
+                    format!("rm '{}'", path.display()).as_str().into(),
+                ],
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Config::default()
+        };
+
+        // The hooks after an aborting one are not run.
+        let err = run_pre_hook(&cfg, "s", &[]).await.unwrap_err();
+        assert!(matches!(err, Error::HookError { op, .. } if op == "s"));
+        assert!(path.exists());
+    }
+}
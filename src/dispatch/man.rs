@@ -0,0 +1,187 @@
+//! Man page generation from the [`clap`] definition of
+//! [`Pacaptr`](super::Pacaptr).
+//!
+//! The page is rendered in `roff` directly from the [`App`] instance, with an
+//! extra section listing all the `pacman`-style operations recognized by
+//! [`pacaptr`](crate), as those are not visible in the [`clap`] definition.
+
+use clap::{App, AppSettings, Arg, ArgSettings};
+use itertools::Itertools;
+use tt_call::tt_call;
+
+use crate::methods;
+
+/// Escapes `text` so that it can be inserted verbatim in a `roff` document.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', r"\\").replace('-', r"\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!(r"\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Formats the short and long flags (along with their visible aliases) of an
+/// [`Arg`], eg. `-c, --changelog`.
+fn arg_flags(arg: &Arg) -> String {
+    let shorts = arg
+        .get_short_and_visible_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| format!(r"\fB\-{}\fR", c));
+    let longs = arg
+        .get_long_and_visible_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|s| format!(r"\fB\-\-{}\fR", escape(s)));
+    let value = arg
+        .is_set(ArgSettings::TakesValue)
+        .then(|| format!(r" <\fI{}\fR>", arg.get_name()));
+    shorts.chain(longs).join(", ") + &value.unwrap_or_default()
+}
+
+/// Renders the non-positional, visible arguments of `app` as a list of
+/// tagged paragraphs.
+fn render_args(app: &App) -> Vec<String> {
+    app.get_arguments()
+        .filter(|arg| !arg.is_set(ArgSettings::Hidden))
+        .filter(|arg| arg.get_short().is_some() || arg.get_long().is_some())
+        .flat_map(|arg| {
+            vec![
+                ".TP".into(),
+                arg_flags(arg),
+                escape(arg.get_about().unwrap_or_default()),
+            ]
+        })
+        .collect()
+}
+
+/// Renders the visible subcommands of `app`, each in its own subsection.
+fn render_subcommands(app: &App) -> Vec<String> {
+    app.get_subcommands()
+        .filter(|sub| !sub.is_set(AppSettings::Hidden))
+        .flat_map(|sub| {
+            let short = sub.get_short_flag().map(|c| format!(r"\-{}", c));
+            let long = sub.get_long_flag().map(|s| format!(r"\-\-{}", escape(s)));
+            let positionals = sub
+                .get_positionals()
+                .map(|arg| format!(r" <\fI{}\fR>", arg.get_name()))
+                .join("");
+            let heading = match (short, long) {
+                (None, None) => escape(sub.get_name()),
+                (short, long) => short.into_iter().chain(long).join(", "),
+            };
+            let mut lines = vec![
+                format!(".SS {}{}", heading, positionals),
+                escape(sub.get_about().unwrap_or_default()),
+            ];
+            for arg in sub.get_positionals() {
+                if let Some(vals) = arg.get_possible_values() {
+                    lines.push(".PP".into());
+                    lines.push(format!(
+                        r"Possible values for \fI{}\fR: {}.",
+                        arg.get_name(),
+                        vals.iter().join(", ")
+                    ));
+                }
+            }
+            lines.extend(render_args(sub));
+            lines
+        })
+        .collect()
+}
+
+/// Renders the `pacman`-style operations supported by [`pacaptr`](crate),
+/// eg. `-Qi`, along with their descriptions.
+fn render_methods() -> Vec<String> {
+    macro_rules! method_lines {(
+        methods = [{ $(
+            $( #[doc = $doc:literal] )*
+            async fn $method:ident;
+        )* }]
+    ) => {
+        vec![ $( {
+            let method = stringify!($method);
+            let flag = method[0..1].to_uppercase() + &method[1..];
+            [
+                ".TP".to_owned(),
+                format!(r"\fB\-{}\fR", flag),
+                escape(concat!($( $doc ),*).trim()),
+            ]
+        }, )* ]
+        .concat()
+    };}
+
+    tt_call! {
+        macro = [{ methods }]
+        ~~> method_lines
+    }
+}
+
+/// Renders the man page of `app` in `roff`.
+#[must_use]
+pub(crate) fn render(app: &App) -> String {
+    let name = app.get_name();
+    let about = app.get_about().unwrap_or(clap::crate_description!());
+    [
+        vec![
+            format!(
+                r#".TH {} 1 "" "{} {}""#,
+                name.to_uppercase(),
+                name,
+                clap::crate_version!()
+            ),
+            ".SH NAME".into(),
+            format!(r"{} \- {}", name, escape(about)),
+            ".SH SYNOPSIS".into(),
+            format!(
+                r"\fB{}\fR <\fIoperation\fR> [\fIoptions\fR] [\fIKEYWORDS\fR]... [\-\- \fIEXTRA_FLAGS\fR...]",
+                name
+            ),
+            ".SH OPTIONS".into(),
+        ],
+        render_args(app),
+        vec![".SH COMMANDS".into()],
+        render_subcommands(app),
+        vec![
+            ".SH OPERATIONS".into(),
+            "The following pacman-style operations are recognized. \
+             Whether each one is supported depends on the package manager in use."
+                .into(),
+        ],
+        render_methods(),
+    ]
+    .concat()
+    .join("\n")
+        + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::IntoApp;
+
+    use super::*;
+    use crate::dispatch::Pacaptr;
+
+    #[test]
+    fn man_sections() {
+        let page = render(&Pacaptr::into_app());
+        assert!(page.starts_with(".TH PACAPTR 1"));
+        for section in &[".SH NAME", ".SH SYNOPSIS", ".SH OPTIONS", ".SH OPERATIONS"] {
+            assert!(page.contains(section), "missing section `{}`", section);
+        }
+        assert!(page.contains(r"\fB\-\-using\fR"));
+        assert!(page.contains(r".SS \-S, \-\-sync"));
+        assert!(!page.contains(r"generate\-man"));
+    }
+
+    #[test]
+    fn man_operations() {
+        let page = render(&Pacaptr::into_app());
+        assert!(page.contains(
+            "\\fB\\-Qi\\fR\nQi displays local package information: name, version, description, etc."
+        ));
+        assert!(page.contains("\\fB\\-Rns\\fR\n"));
+        assert!(page.contains("\\fB\\-Suy\\fR\n"));
+    }
+}
@@ -12,104 +12,150 @@
 //!   `.suy()`, according to the combination of flags and options obtained
 //!   above.
 
+mod capabilities;
 mod cmd;
 mod config;
+mod diff;
+mod doctor;
+mod export;
+mod help;
+mod hooks;
+mod man;
+mod pick;
+
+pub(crate) use self::doctor::Diagnosis;
+pub use self::{
+    cmd::Pacaptr,
+    config::{Config, CustomPmConfig, Elevation, Hook, OnFailure, Proxy},
+};
+use std::convert::TryFrom;
 
-pub use self::cmd::Pacaptr;
-pub(crate) use self::config::Config;
 use crate::{
-    exec::is_exe,
-    pm::{Apk, Apt, Brew, Choco, Conda, Dnf, Emerge, Pip, Pm, Port, Scoop, Tlmgr, Unknown, Zypper},
+    error::{Error, Result},
+    pm::{
+        registry::{self, PmEntry},
+        CustomPm, Pm, Unknown,
+    },
+    print::{print_info, Verbosity, PROMPT_INFO},
 };
 
-/// Detects the name of the package manager to be used in auto dispatch.
+/// Detects the name of the package manager to be used in auto dispatch, along
+/// with the names of the candidates probed until then.
 #[must_use]
-fn detect_pm_str<'s>() -> &'s str {
-    let pairs: &[(&str, &str)] = match () {
-        _ if cfg!(target_os = "windows") => &[("scoop", ""), ("choco", "")],
-
-        _ if cfg!(target_os = "macos") => &[
-            ("brew", "/usr/local/bin/brew"),
-            ("port", "/opt/local/bin/port"),
-            ("apt", "/opt/procursus/bin/apt"),
-        ],
-
-        _ if cfg!(target_os = "ios") => &[("apt", "/usr/bin/apt")],
-
-        _ if cfg!(target_os = "linux") => &[
-            ("apk", "/sbin/apk"),
-            ("apt", "/usr/bin/apt"),
-            ("emerge", "/usr/bin/emerge"),
-            ("dnf", "/usr/bin/dnf"),
-            ("zypper", "/usr/bin/zypper"),
-        ],
-
-        _ => &[],
-    };
+fn detect_pm_str() -> (&'static str, Vec<String>) {
+    detect_pm_with(PmEntry::is_detected)
+}
 
-    pairs
+/// Detects the package manager as in [`detect_pm_str`], where each candidate
+/// is probed with `is_detected`.
+#[must_use]
+fn detect_pm_with(is_detected: impl Fn(&PmEntry) -> bool) -> (&'static str, Vec<String>) {
+    let mut tried = vec![];
+    let pm = registry::REGISTRY
         .iter()
-        .find_map(|(name, path)| is_exe(name, path).then(|| *name))
-        .unwrap_or("unknown")
+        .filter(|entry| entry.is_candidate())
+        .find(|entry| {
+            tried.push(entry.name.to_owned());
+            let detected = is_detected(entry);
+            print_info(
+                format!(
+                    "`{}` is {}",
+                    entry.name,
+                    if detected { "detected" } else { "not detected" }
+                ),
+                PROMPT_INFO,
+                Verbosity::Debug,
+            );
+            detected
+        })
+        .map_or("unknown", |entry| entry.name);
+    (pm, tried)
 }
 
-impl From<Config> for Box<dyn Pm> {
-    /// Generates the `Pm` instance according it's name, feeding it with the
-    /// current `Config`.
-    fn from(mut cfg: Config) -> Self {
-        // If the `Pm` to be used is not stated in any config,
-        // we should fall back to automatic detection and overwrite `cfg`.
-        let pm = cfg.default_pm.get_or_insert_with(|| detect_pm_str().into());
-
-        #[allow(clippy::match_single_binding)]
-        match pm as _ {
-            // Chocolatey
-            "choco" => Choco::new(cfg).boxed(),
-
-            // Scoop
-            "scoop" => Scoop::new(cfg).boxed(),
-
-            // Homebrew/Linuxbrew
-            "brew" => Brew::new(cfg).boxed(),
-
-            // Macports
-            "port" if cfg!(target_os = "macos") => Port::new(cfg).boxed(),
-
-            // Portage for Gentoo
-            "emerge" => Emerge::new(cfg).boxed(),
-
-            // Apk for Alpine
-            "apk" => Apk::new(cfg).boxed(),
-
-            // Apt for Debian/Ubuntu/Termux (new versions)
-            "apt" => Apt::new(cfg).boxed(),
+/// Generates the `Pm` instance according it's name, feeding it with the
+/// current `Config`.
+///
+/// The package managers registered with [`register`](crate::pm::register), and
+/// then those defined in the config, take precedence over the built-in ones.
+///
+/// Returns the name of the package manager along with the names of the
+/// candidates probed instead when none is found under that name, or by
+/// automatic detection.
+fn find_pm(mut cfg: Config) -> std::result::Result<Box<dyn Pm>, (String, Vec<String>)> {
+    // If the `Pm` to be used is not stated in any config,
+    // we should fall back to automatic detection and overwrite `cfg`.
+    let (pm, tried) = match &cfg.default_pm {
+        Some(pm) => (pm.clone(), vec![pm.clone()]),
+        None => {
+            let (pm, tried) = detect_pm_str();
+            cfg.default_pm = Some(pm.into());
+            (pm.into(), tried)
+        }
+    };
 
-            // Dnf for RedHat
-            "dnf" => Dnf::new(cfg).boxed(),
+    // Test-only mock package manager
+    #[cfg(test)]
+    if pm == "mockpm" {
+        use self::cmd::tests::MockPm;
+        return Ok(MockPm { cfg }.boxed());
+    }
 
-            // Zypper for SUSE
-            "zypper" => Zypper::new(cfg).boxed(),
+    // Package managers registered at runtime
+    if let Some(constructor) = registry::find_custom(&pm) {
+        return Ok(constructor(cfg));
+    }
 
-            // -- External Package Managers --
+    // Package managers defined in the config
+    if let Some(def) = cfg.custom_pms.get(&pm).cloned() {
+        return Ok(CustomPm::new(&pm, def, cfg).boxed());
+    }
 
-            // Conda
-            "conda" => Conda::new(cfg).boxed(),
+    registry::find(&pm)
+        .map(|entry| (entry.constructor)(cfg))
+        .ok_or((pm, tried))
+}
 
-            // Pip
-            "pip" | "pip3" => Pip::new(cfg).boxed(),
+impl TryFrom<Config> for Box<dyn Pm> {
+    type Error = Error;
 
-            // Tlmgr
-            "tlmgr" => Tlmgr::new(cfg).boxed(),
+    /// Generates the `Pm` instance according it's name, as [`From`] does.
+    ///
+    /// # Errors
+    /// Returns an [`Error::PmNotFoundError`] when no package manager is found.
+    fn try_from(cfg: Config) -> Result<Self> {
+        find_pm(cfg).map_err(|(_, tried)| registry::pm_not_found(tried))
+    }
+}
 
-            // Test-only mock package manager
-            #[cfg(test)]
-            "mockpm" => {
-                use self::cmd::tests::MockPm;
-                MockPm { cfg }.boxed()
-            }
+impl From<Config> for Box<dyn Pm> {
+    /// Generates the `Pm` instance according it's name, falling back to
+    /// [`Unknown`], which fails every operation, when none is found.
+    fn from(cfg: Config) -> Self {
+        find_pm(cfg).unwrap_or_else(|(pm, tried)| Unknown::new(&pm, tried).boxed())
+    }
+}
 
-            // Unknown package manager X
-            x => Unknown::new(x).boxed(),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn no_pm_detected() {
+        // As if nothing could be found in `$PATH`.
+        let (pm, tried) = detect_pm_with(|_| false);
+        assert_eq!(pm, "unknown");
+        let candidates = registry::REGISTRY
+            .iter()
+            .filter(|entry| entry.is_candidate())
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(tried, candidates);
+
+        let err = Unknown::new(pm, tried).s(&["curl"], &[]).await.unwrap_err();
+        assert!(matches!(&err, Error::PmNotFoundError { tried, .. } if tried == &candidates));
+        assert_eq!(err.exit_code(), 127);
+        assert!(err
+            .to_string()
+            .starts_with("No supported package manager is found (tried: "));
     }
 }
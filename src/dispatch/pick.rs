@@ -0,0 +1,344 @@
+//! The interactive picker of `--pick`, selecting the packages to be installed
+//! among the results of `-Ss`.
+//!
+//! The picker is a list drawn on `stderr`, where the packages are selected
+//! with the arrow keys and `Space`, then installed with `Enter`, or none of
+//! them with `Esc`.
+
+use std::{
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    queue,
+    terminal::{self, ClearType},
+};
+use itertools::Itertools;
+use tokio::task;
+
+use crate::{
+    error::{Error, Result},
+    pm::{self, Pm},
+    print::{print_warning, PROMPT_WARNING},
+};
+
+/// The maximum number of packages shown at once in the picker.
+const PICKER_HEIGHT: usize = 10;
+
+/// A key pressed in the picker.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Key {
+    /// Moves the cursor up.
+    Up,
+
+    /// Moves the cursor down.
+    Down,
+
+    /// Selects or deselects the package under the cursor.
+    Toggle,
+
+    /// Installs the selected packages, or the one under the cursor if none is
+    /// selected.
+    Confirm,
+
+    /// Installs nothing.
+    Cancel,
+
+    /// Any other key, which is ignored.
+    Other,
+}
+
+/// A source of [`Key`]s, eg. the terminal.
+trait Input {
+    /// Waits for the next [`Key`] to be pressed.
+    fn next_key(&mut self) -> io::Result<Key>;
+}
+
+/// The [`Key`]s pressed in the terminal, which must be in raw mode.
+#[derive(Copy, Clone, Debug)]
+struct TermInput;
+
+impl Input for TermInput {
+    fn next_key(&mut self) -> io::Result<Key> {
+        loop {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            return Ok(match key.code {
+                KeyCode::Up | KeyCode::Char('k') => Key::Up,
+                KeyCode::Down | KeyCode::Char('j') => Key::Down,
+                KeyCode::Char(' ') | KeyCode::Tab => Key::Toggle,
+                KeyCode::Enter => Key::Confirm,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Cancel,
+                KeyCode::Esc | KeyCode::Char('q') => Key::Cancel,
+                _ => Key::Other,
+            });
+        }
+    }
+}
+
+/// The state of the picker.
+#[derive(Clone, Debug)]
+struct Picker<'a> {
+    /// The packages to pick from.
+    items: &'a [String],
+
+    /// Whether each package is selected.
+    selected: Vec<bool>,
+
+    /// The index of the package under the cursor.
+    cursor: usize,
+
+    /// The index of the first package shown.
+    offset: usize,
+
+    /// The maximum number of packages shown at once.
+    height: usize,
+}
+
+impl<'a> Picker<'a> {
+    /// Makes a new [`Picker`] of `items`, showing at most `height` of them at
+    /// once.
+    #[must_use]
+    fn new(items: &'a [String], height: usize) -> Self {
+        Picker {
+            items,
+            selected: vec![false; items.len()],
+            cursor: 0,
+            offset: 0,
+            height: height.max(1),
+        }
+    }
+
+    /// Handles `key`, returning the packages picked if it ends the picking.
+    #[must_use]
+    fn handle(&mut self, key: Key) -> Option<Vec<String>> {
+        match key {
+            Key::Up => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down => self.cursor = (self.cursor + 1).min(self.items.len().saturating_sub(1)),
+            Key::Toggle => {
+                if let Some(sel) = self.selected.get_mut(self.cursor) {
+                    *sel = !*sel;
+                }
+            }
+            Key::Confirm if !self.selected.contains(&true) => {
+                return Some(self.items.get(self.cursor).cloned().into_iter().collect())
+            }
+            Key::Confirm => {
+                return Some(
+                    self.items
+                        .iter()
+                        .zip(&self.selected)
+                        .filter(|(_, &sel)| sel)
+                        .map(|(item, _)| item.clone())
+                        .collect(),
+                )
+            }
+            Key::Cancel => return Some(vec![]),
+            Key::Other => (),
+        }
+        // Scroll to keep the cursor in sight.
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + self.height {
+            self.offset = self.cursor + 1 - self.height;
+        }
+        None
+    }
+
+    /// Renders the lines of the picker, with the cursor marked by `>` and the
+    /// selected packages by `[x]`.
+    #[must_use]
+    fn render(&self) -> Vec<String> {
+        let header = "Pick the packages to install \
+                      (Up/Down: move, Space: select, Enter: install, Esc: cancel)"
+            .to_owned();
+        let items = self
+            .items
+            .iter()
+            .zip(&self.selected)
+            .enumerate()
+            .skip(self.offset)
+            .take(self.height)
+            .map(|(i, (item, &sel))| {
+                let cursor = if i == self.cursor { '>' } else { ' ' };
+                let check = if sel { 'x' } else { ' ' };
+                format!("{} [{}] {}", cursor, check, item)
+            });
+        std::iter::once(header).chain(items).collect()
+    }
+}
+
+/// Lets the user pick among `items` with the keys from `input`, drawing the
+/// picker on `out`, which is cleared afterwards.
+///
+/// Returns the packages picked, which might be none.
+fn select(
+    items: &[String],
+    height: usize,
+    input: &mut impl Input,
+    out: &mut impl Write,
+) -> io::Result<Vec<String>> {
+    /// Clears the `drawn` lines above the cursor.
+    fn clear(out: &mut impl Write, drawn: usize) -> io::Result<()> {
+        if drawn > 0 {
+            let up = u16::try_from(drawn).unwrap_or(u16::MAX);
+            queue!(out, cursor::MoveUp(up), cursor::MoveToColumn(0))?;
+        }
+        queue!(out, terminal::Clear(ClearType::FromCursorDown))
+    }
+
+    let mut picker = Picker::new(items, height);
+    let mut drawn = 0;
+    loop {
+        clear(out, drawn)?;
+        let lines = picker.render();
+        for line in &lines {
+            // In raw mode, a newline doesn't return to the first column.
+            write!(out, "{}\r\n", line)?;
+        }
+        out.flush()?;
+        drawn = lines.len();
+        if let Some(picked) = picker.handle(input.next_key()?) {
+            clear(out, drawn)?;
+            out.flush()?;
+            return Ok(picked);
+        }
+    }
+}
+
+/// Lets the user pick among `items` in the terminal.
+fn select_in_terminal(items: &[String]) -> io::Result<Vec<String>> {
+    terminal::enable_raw_mode()?;
+    let res = select(items, PICKER_HEIGHT, &mut TermInput, &mut io::stderr());
+    terminal::disable_raw_mode()?;
+    res
+}
+
+/// Checks if the packages can be picked, that is, if the picker can be drawn
+/// in a terminal as indicated by `is_tty`, and nothing but JSON is to be
+/// printed under `json`.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] if this is not the case.
+fn check_pickable(is_tty: bool, json: bool) -> Result<()> {
+    if !is_tty || json {
+        return Err(Error::ArgParseError {
+            msg: "`--pick` can only be used in a terminal, and not along with `--json`".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Searches for the packages matching `kws` with `pm`, then installs those
+/// picked by the user in the terminal.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when not run in a terminal, an
+/// [`Error::OperationUnimplementedError`] when the search results of `pm` can't
+/// be parsed, or the error of `pm.ss()` or `pm.s()`.
+pub(super) async fn pick(pm: &dyn Pm, kws: &[&str], flags: &[&str]) -> Result<()> {
+    let is_tty = atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr);
+    check_pickable(is_tty, pm.cfg().json)?;
+    let (res, out) = pm::capture(pm::network_bound(pm.ss(kws, flags))).await;
+    res?;
+    let names = pm
+        .parse_search(&String::from_utf8_lossy(&out))
+        .ok_or_else(|| Error::OperationUnimplementedError {
+            op: "ss --pick".into(),
+            pm: pm.name().into(),
+            alternatives: vec![],
+            lacking: false,
+            using: vec![],
+        })?;
+    if names.is_empty() {
+        print_warning("No packages found to pick from", PROMPT_WARNING);
+        return Ok(());
+    }
+    let picked = task::block_in_place(|| select_in_terminal(&names))?;
+    if picked.is_empty() {
+        return Ok(());
+    }
+    pm.s(&picked.iter().map(String::as_str).collect_vec(), flags)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The [`Key`]s pressed in a test.
+    struct Keys(std::vec::IntoIter<Key>);
+
+    impl Input for Keys {
+        fn next_key(&mut self) -> io::Result<Key> {
+            Ok(self.0.next().expect("the picker should have returned"))
+        }
+    }
+
+    fn items(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&s| s.into()).collect()
+    }
+
+    fn select_with(items: &[String], height: usize, keys: &[Key]) -> Vec<String> {
+        let mut out = vec![];
+        select(
+            items,
+            height,
+            &mut Keys(keys.to_vec().into_iter()),
+            &mut out,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn picker_select() {
+        use Key::*;
+
+        let pkgs = items(&["curl", "wget", "aria2"]);
+        assert_eq!(select_with(&pkgs, 10, &[Confirm]), ["curl"]);
+        assert_eq!(select_with(&pkgs, 10, &[Down, Other, Confirm]), ["wget"]);
+        assert_eq!(
+            select_with(&pkgs, 10, &[Toggle, Down, Down, Down, Toggle, Confirm]),
+            ["curl", "aria2"]
+        );
+        // A package is deselected when toggled twice.
+        assert_eq!(
+            select_with(&pkgs, 10, &[Toggle, Down, Toggle, Up, Toggle, Confirm]),
+            ["wget"]
+        );
+        assert!(select_with(&pkgs, 10, &[Toggle, Cancel]).is_empty());
+        assert!(select_with(&[], 10, &[Up, Toggle, Confirm]).is_empty());
+    }
+
+    #[test]
+    fn pick_not_in_terminal() {
+        check_pickable(true, false).unwrap();
+        for &(is_tty, json) in &[(false, false), (true, true)] {
+            assert!(matches!(
+                check_pickable(is_tty, json),
+                Err(Error::ArgParseError { msg }) if msg.contains("terminal")
+            ));
+        }
+    }
+
+    #[test]
+    fn picker_render() {
+        let pkgs = items(&["a", "b", "c", "d"]);
+        let mut picker = Picker::new(&pkgs, 2);
+        assert_eq!(picker.render()[1..], ["> [ ] a", "  [ ] b"]);
+        assert_eq!(picker.handle(Key::Toggle), None);
+        assert_eq!(picker.handle(Key::Down), None);
+        assert_eq!(picker.handle(Key::Down), None);
+        // The list scrolls along with the cursor.
+        assert_eq!(picker.render()[1..], ["  [ ] b", "> [ ] c"]);
+        for _ in 0..3 {
+            assert_eq!(picker.handle(Key::Up), None);
+        }
+        assert_eq!(picker.render()[1..], ["> [x] a", "  [ ] b"]);
+    }
+}
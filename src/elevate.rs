@@ -0,0 +1,244 @@
+//! Elevating the commands of the package managers which need an elevated
+//! shell on Windows (eg. `choco`), when [`pacaptr`](crate) is not elevated
+//! itself, as selected by [`Config::elevation`].
+//!
+//! With [`Elevation::Uac`], each command is relaunched by `powershell` with
+//! `Start-Process -Verb RunAs`, which shows the UAC prompt. The elevated
+//! process can't share the console, so its output is relayed through a
+//! temporary file which is printed once it has finished.
+
+use std::fs;
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    dispatch::{Config, Elevation},
+    error::{Error, Result},
+    exec::{self, Cmd, Output},
+};
+
+/// Decides how the commands of `pm` are to be elevated, if they need to be,
+/// given whether [`pacaptr`](crate) is `elevated` itself.
+///
+/// Nothing needs to be elevated in a dry run, or if the package manager is
+/// user-scoped (eg. `scoop`).
+///
+/// # Errors
+/// Returns an [`Error::ElevationRequiredError`] when the commands need to be
+/// elevated but [`Config::elevation`] is [`Elevation::Error`], or is
+/// [`Elevation::Gsudo`] without `gsudo` installed.
+pub(crate) fn decide(
+    cfg: &Config,
+    pm: &str,
+    requires_elevation: bool,
+    elevated: bool,
+) -> Result<Option<Elevation>> {
+    if elevated || !requires_elevation || cfg.dry_run || cfg.describe {
+        return Ok(None);
+    }
+    match cfg.elevation {
+        Elevation::Gsudo if exec::is_exe("gsudo", "") => Ok(Some(Elevation::Gsudo)),
+        Elevation::Uac => Ok(Some(Elevation::Uac)),
+        _ => Err(Error::ElevationRequiredError { pm: pm.into() }),
+    }
+}
+
+/// Prefixes `cmd` with `gsudo`, which runs it elevated in the same console.
+#[must_use]
+pub(crate) fn gsudo(mut cmd: Cmd) -> Cmd {
+    cmd.cmd.insert(0, "gsudo".into());
+    cmd
+}
+
+/// The temporary file the output of a command relaunched through the UAC
+/// prompt is relayed through.
+#[derive(Debug)]
+pub(crate) struct Relay {
+    file: NamedTempFile,
+}
+
+impl Relay {
+    /// Makes a new [`Relay`] in the temporary directory, under a random name
+    /// which can't be taken over beforehand by another user.
+    ///
+    /// # Errors
+    /// Returns an [`Error::IoError`] when the file can't be created.
+    pub(crate) fn new() -> Result<Self> {
+        let file = tempfile::Builder::new()
+            .prefix("pacaptr-")
+            .suffix(".log")
+            .tempfile()?;
+        Ok(Relay { file })
+    }
+
+    /// Wraps `cmd` into a `powershell` command relaunching it through the UAC
+    /// prompt, with its output redirected to this [`Relay`] and its exit code
+    /// passed through.
+    ///
+    /// The extra environment variables of `cmd` and the answer to be fed to its
+    /// `stdin` are not passed to the elevated process.
+    #[must_use]
+    pub(crate) fn wrap(&self, cmd: &Cmd) -> Cmd {
+        let argv = cmd.argv();
+        let script = format!(
+            "& {} *> {}; exit $LASTEXITCODE",
+            argv.iter()
+                .map(|arg| quote(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            quote(&self.file.path().to_string_lossy())
+        );
+        let launcher = format!(
+            "$p = Start-Process -FilePath powershell \
+             -ArgumentList '-NoProfile','-EncodedCommand','{}' \
+             -Verb RunAs -WindowStyle Hidden -Wait -PassThru; exit $p.ExitCode",
+            encode_command(&script)
+        );
+        Cmd {
+            sudo: false,
+            cmd: vec![
+                "powershell".into(),
+                "-NoProfile".into(),
+                "-Command".into(),
+                launcher,
+            ],
+            flags: vec![],
+            kws: vec![],
+            envs: vec![],
+            answer: None,
+            ..cmd.clone()
+        }
+    }
+
+    /// Takes the output relayed by the command, removing the file.
+    ///
+    /// Under Windows PowerShell, the redirected output is written in UTF-16,
+    /// which is converted back to UTF-8.
+    #[must_use]
+    pub(crate) fn take(self) -> Output {
+        let out = fs::read(self.file.path()).unwrap_or_default();
+        let _ = self.file.close();
+        match out.strip_prefix(&[0xFF, 0xFE]) {
+            Some(utf16) => {
+                let units = utf16
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>();
+                String::from_utf16_lossy(&units).into_bytes()
+            }
+            None => out,
+        }
+    }
+}
+
+/// Quotes `arg` as a literal string for `powershell`.
+#[must_use]
+fn quote(arg: &str) -> String {
+    exec::quote_powershell(arg).into_owned()
+}
+
+/// Encodes `script` for `powershell -EncodedCommand`, ie. as the Base64 of
+/// its UTF-16LE bytes, so that it needs no quoting at all.
+#[must_use]
+fn encode_command(script: &str) -> String {
+    let bytes = script
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    base64::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_base64() {
+        // `[Convert]::ToBase64String([Text.Encoding]::Unicode.GetBytes('dir'))`
+        assert_eq!(encode_command("dir"), "ZABpAHIA");
+        assert_eq!(encode_command("a"), "YQA=");
+        assert_eq!(encode_command(""), "");
+    }
+
+    #[test]
+    fn elevation_decided() {
+        let cfg = |elevation| Config {
+            elevation,
+            ..Config::default()
+        };
+        let check = |cfg: &Config, requires, elevated| decide(cfg, "choco", requires, elevated);
+        assert!(matches!(
+            check(&cfg(Elevation::Error), true, false),
+            Err(Error::ElevationRequiredError { pm }) if pm == "choco"
+        ));
+        assert_eq!(
+            check(&cfg(Elevation::Uac), true, false).unwrap(),
+            Some(Elevation::Uac)
+        );
+        for &(requires, elevated) in &[(true, true), (false, false)] {
+            assert_eq!(
+                check(&cfg(Elevation::Error), requires, elevated).unwrap(),
+                None
+            );
+        }
+        let dry_run = Config {
+            dry_run: true,
+            ..cfg(Elevation::Error)
+        };
+        assert_eq!(check(&dry_run, true, false).unwrap(), None);
+    }
+
+    #[test]
+    fn relay_wrapped() {
+        let relay = Relay::new().unwrap();
+        let cmd = relay.wrap(&Cmd::new(&["choco", "install"]).kws(&["git"]));
+        assert_eq!(cmd.cmd[..3], ["powershell", "-NoProfile", "-Command"]);
+        assert!(cmd.cmd[3].starts_with(
+            "$p = Start-Process -FilePath powershell -ArgumentList '-NoProfile','-EncodedCommand','"
+        ));
+        let script = format!(
+            "& choco install git *> {}; exit $LASTEXITCODE",
+            quote(&relay.file.path().to_string_lossy())
+        );
+        assert!(cmd.cmd[3].contains(&encode_command(&script)));
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    #[ignore]
+    async fn relay_elevated() {
+        use crate::exec::Mode;
+
+        // The CI runners are elevated already, so no UAC prompt shows up.
+        let relay = Relay::new().unwrap();
+        let cmd = relay.wrap(&Cmd::new(&[
+            "powershell",
+            "-NoProfile",
+            "-Command",
+            "Write-Output relayed; exit 3",
+        ]));
+        let err = cmd.mode(Mode::Mute).exec().await.unwrap_err();
+        assert!(matches!(err, Error::CmdStatusCodeError { code: 3, .. }));
+        assert_eq!(String::from_utf8_lossy(&relay.take()).trim(), "relayed");
+    }
+
+    #[test]
+    fn relay_utf16() {
+        let relay = Relay::new().unwrap();
+        let utf16 = "Installed 1/1 ✓\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes);
+        fs::write(
+            relay.file.path(),
+            [0xFF, 0xFE]
+                .iter()
+                .copied()
+                .chain(utf16)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let path = relay.file.path().to_owned();
+        assert_eq!(relay.take(), "Installed 1/1 ✓\n".as_bytes());
+        assert!(!path.exists());
+    }
+}
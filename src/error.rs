@@ -1,9 +1,14 @@
 //! Basic error definitions specific to this crate.
 
+use std::env;
+
 use thiserror::Error;
 use tokio::{io, task::JoinError};
 
-use crate::exec::{Output, StatusCode};
+use crate::{
+    exec::{Output, StatusCode},
+    pm::Op,
+};
 
 /// A specialized [`Result`](std::result::Result) type used by
 /// [`pacaptr`](crate).
@@ -41,29 +46,324 @@ pub enum Error {
     #[error("Subprocess failed while running: {0}")]
     CmdWaitError(io::Error),
 
-    /// An [`Cmd`](crate::exec::Cmd) exits with an error.
+    /// An [`Cmd`](crate::exec::Cmd) exits with an error, possibly classified
+    /// as a [`FailureKind`] by the package manager.
+    ///
+    /// The `output` of a command run by a package manager is only kept if it
+    /// hasn't been printed, eg. when the command is muted.
     #[error("Subprocess exited with code {code}")]
     #[allow(missing_docs)]
-    CmdStatusCodeError { code: StatusCode, output: Output },
+    CmdStatusCodeError {
+        code: StatusCode,
+        output: Output,
+        kind: Option<FailureKind>,
+        /// Whether `code` is passed through as the exit code of
+        /// [`pacaptr`](crate), as set by `--raw-exit-code`.
+        raw: bool,
+    },
 
     /// An [`Cmd`](crate::exec::Cmd) gets interrupted by a signal.
     #[error("Subprocess interrupted by signal")]
     CmdInterruptedError,
 
+    /// An [`Cmd`](crate::exec::Cmd) gets killed for running longer than its
+    /// timeout, leaving a partial `output` (kept as in
+    /// [`Error::CmdStatusCodeError`]).
+    #[error("Subprocess timed out after {secs}s")]
+    #[allow(missing_docs)]
+    CmdTimeoutError { secs: u64, output: Output },
+
     /// Error while converting a [`Vec<u8>`] to a [`String`].
     #[error(transparent)]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
 
+    /// Error while serializing the `--json` output.
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
     /// An unmentioned case of [`io::Error`].
     #[error(transparent)]
     IoError(#[from] io::Error),
 
-    /// A [`Pm`](crate::pm::Pm) operation is not implemented.
-    #[error("Operation `{op}` is unimplemented for `{pm}`")]
+    /// A hook configured for an operation fails.
+    #[error("Hook for `{op}` failed: {msg}")]
+    #[allow(missing_docs)]
+    HookError { op: String, msg: String },
+
+    /// The lock on a package manager is held by another run of
+    /// [`pacaptr`](crate).
+    #[error("Another `pacaptr` is running on `{pm}`")]
+    #[allow(missing_docs)]
+    PmLockedError { pm: String },
+
+    /// Another [`pacaptr`](crate) is still running on the same package manager
+    /// after waiting for it as long as allowed by `--wait-lock`.
+    #[error("Timed out after {secs}s waiting for another `pacaptr` running on `{pm}`")]
+    #[allow(missing_docs)]
+    PmLockTimeoutError { pm: String, secs: u64 },
+
+    /// [`pacaptr`](crate) can't be updated by `pacaptr self-update`.
+    #[error("Failed to update `pacaptr`: {msg}")]
+    #[allow(missing_docs)]
+    SelfUpdateError { msg: String },
+
+    /// The credentials of `sudo` can't be validated under `sudo_keepalive`,
+    /// eg. as the password can't be asked for without a terminal.
+    #[error(
+        "Failed to validate the credentials of `sudo`; without a terminal, run `sudo -v` \
+         beforehand or allow `sudo` without a password"
+    )]
+    SudoPasswordError,
+
+    /// The executable of the selected package manager is not found, along
+    /// with the package managers detected on this system.
+    #[error(
+        "Executable `{exe}` of `{pm}` is not found{}",
+        detected_hint(.detected)
+    )]
+    #[allow(missing_docs)]
+    ExeNotFoundError {
+        pm: String,
+        exe: String,
+        detected: Vec<String>,
+    },
+
+    /// A [`Pm`](crate::pm::Pm) operation is not implemented, either because
+    /// the package manager has no equivalent of it (`lacking`) or because it
+    /// is not mapped yet, along with the nearest operations supported instead,
+    /// eg. `-Qi`, and the package managers on this system which support it to
+    /// be suggested with `--using`.
+    #[error(
+        "{}",
+        unimplemented_msg(.op, .pm, *.lacking, .alternatives, .using)
+    )]
+    #[allow(missing_docs)]
+    OperationUnimplementedError {
+        op: String,
+        pm: String,
+        alternatives: Vec<String>,
+        lacking: bool,
+        using: Vec<String>,
+    },
+
+    /// No supported package manager is found, either by automatic detection
+    /// or under the name given in the config, where `tried` holds the names of
+    /// the package managers probed, and `supported` those supported on the
+    /// current system.
+    #[error("{}", pm_not_found_msg(.tried, .supported))]
+    #[allow(missing_docs)]
+    PmNotFoundError {
+        tried: Vec<String>,
+        supported: Vec<String>,
+    },
+
+    /// The package manager needs an elevated shell on Windows, which is not
+    /// the case of `pacaptr` itself, and can't be elevated as selected by
+    /// [`Config::elevation`](crate::dispatch::Config::elevation).
+    #[error(
+        "`{pm}` needs an elevated shell; run `pacaptr` as administrator, or set `elevation` \
+         to `\"gsudo\"` (with `gsudo` installed) or `\"uac\"` in the config"
+    )]
+    #[allow(missing_docs)]
+    ElevationRequiredError { pm: String },
+
+    /// The package manager refuses to run as `root`, which is the case of
+    /// `pacaptr` itself.
+    #[error(
+        "`{pm}` refuses to run as root; run `pacaptr` as a normal user instead, or use \
+         `--allow-root` to run it anyway"
+    )]
     #[allow(missing_docs)]
-    OperationUnimplementedError { op: String, pm: String },
+    RootNotAllowedError { pm: String },
+
+    /// Error when an operation needing the network is run under `--offline`,
+    /// and can't be run from the local cache instead.
+    #[error("Operation `{op}` of `{pm}` needs the network, which is disabled by `--offline`")]
+    #[allow(missing_docs)]
+    OfflineError { op: String, pm: String },
+
+    /// Some of the operations chained in one run have failed under
+    /// `--keep-going`, the first of which is kept.
+    #[error("{failed} of {total} chained operations failed")]
+    #[allow(missing_docs)]
+    ChainError {
+        failed: usize,
+        total: usize,
+        first: Box<Error>,
+    },
 
     /// Miscellaneous other error.
     #[error("{0}")]
     OtherError(String),
 }
+
+/// Suggests the package managers `detected` on this system in the message of
+/// [`Error::ExeNotFoundError`].
+#[must_use]
+fn detected_hint(detected: &[String]) -> String {
+    if detected.is_empty() {
+        return ", and no other package manager is detected on this system".into();
+    }
+    let detected = detected
+        .iter()
+        .map(|pm| format!("`{}`", pm))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        " (detected on this system: {}; try `--using` with one of them)",
+        detected
+    )
+}
+
+/// The compatibility table of the operations supported by each package
+/// manager, as linked in the message of [`Error::OperationUnimplementedError`].
+const COMPAT_TABLE_URL: &str = "https://rami3l.github.io/pacaptr/pacaptr/#compatibility-table";
+
+/// Makes the message of [`Error::OperationUnimplementedError`].
+///
+/// The operations of [`Op`] are given in their `pacman` spelling, eg. `-Qk`,
+/// along with the reason why they are unimplemented and the link to the
+/// compatibility table.
+#[must_use]
+fn unimplemented_msg(
+    op: &str,
+    pm: &str,
+    lacking: bool,
+    alternatives: &[String],
+    using: &[String],
+) -> String {
+    let quote = |items: &[String], prefix: &str| {
+        items
+            .iter()
+            .map(|item| format!("`{}{}`", prefix, item))
+            .collect::<Vec<_>>()
+    };
+    let parsed = match op.parse::<Op>() {
+        Ok(parsed) => parsed,
+        Err(_) => return format!("Operation `{}` is unimplemented for `{}`", op, pm),
+    };
+    let mut msg = format!("Operation `{}` is unimplemented for `{}`", parsed, pm);
+    msg += if lacking {
+        ", which has no equivalent of it"
+    } else {
+        ", as it is not mapped by `pacaptr` yet"
+    };
+    if !alternatives.is_empty() {
+        msg += &format!(
+            " (supported alternatives: {})",
+            quote(alternatives, "").join(", ")
+        );
+    }
+    if !using.is_empty() {
+        msg += &format!("; try {} instead", quote(using, "--using ").join(" or "));
+    }
+    msg + &format!("; see {}", COMPAT_TABLE_URL)
+}
+
+/// Makes the message of [`Error::PmNotFoundError`], listing the package
+/// managers supported on the current system to be selected instead.
+#[must_use]
+fn pm_not_found_msg(tried: &[String], supported: &[String]) -> String {
+    let quote = |names: &[String]| {
+        names
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let tried = match quote(tried) {
+        tried if tried.is_empty() => "none".to_owned(),
+        tried => tried,
+    };
+    let supported = quote(supported);
+    format!(
+        "No supported package manager is found (tried: {}); select one of those supported on \
+         `{}` with `--backend <name>` or `default_pm` in the config: {}",
+        tried,
+        env::consts::OS,
+        supported
+    )
+}
+
+/// The category of a failed command, which is mapped to an exit code of
+/// [`pacaptr`](crate) that is the same across package managers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The package (or something else asked for) is not found.
+    NotFound,
+
+    /// The package manager fails to talk to the network.
+    NetworkError,
+
+    /// The user aborts the operation, eg. by answering `no` to a prompt.
+    Aborted,
+
+    /// Any other failure.
+    Other,
+}
+
+impl FailureKind {
+    /// Gets the exit code of [`pacaptr`](crate) for this kind of failure.
+    #[must_use]
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::NotFound => 100,
+            FailureKind::NetworkError => 102,
+            FailureKind::Aborted => 130,
+            FailureKind::Other => 1,
+        }
+    }
+}
+
+impl Error {
+    /// Gets the exit code of [`pacaptr`](crate) for this error.
+    ///
+    /// The exit code of a failed command is only passed through under
+    /// `--raw-exit-code`, or else it is that of its [`FailureKind`], which is
+    /// [`FailureKind::Other`] unless classified otherwise.
+    #[must_use]
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::CmdStatusCodeError {
+                code, raw: true, ..
+            } => *code,
+            Error::CmdStatusCodeError { kind, .. } => {
+                kind.unwrap_or(FailureKind::Other).exit_code()
+            }
+            Error::CmdInterruptedError => FailureKind::Aborted.exit_code(),
+            Error::ChainError { first, .. } => first.exit_code(),
+            Error::OfflineError { .. } => FailureKind::NetworkError.exit_code(),
+            // The same as `timeout(1)`.
+            Error::CmdTimeoutError { .. } => 124,
+            // The same as a command not found in the shell.
+            Error::PmNotFoundError { .. } => 127,
+            _ => 1,
+        }
+    }
+
+    /// Gets the output of the failed [`Cmd`](crate::exec::Cmd) which hasn't
+    /// been printed yet, if any.
+    #[must_use]
+    pub fn output(&self) -> Option<&[u8]> {
+        match self {
+            Error::CmdStatusCodeError { output, .. } | Error::CmdTimeoutError { output, .. }
+                if !output.is_empty() =>
+            {
+                Some(output)
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops the output of the failed [`Cmd`](crate::exec::Cmd), eg. when it
+    /// has already been printed.
+    #[must_use]
+    pub(crate) fn without_output(mut self) -> Self {
+        if let Error::CmdStatusCodeError { output, .. } | Error::CmdTimeoutError { output, .. } =
+            &mut self
+        {
+            output.clear();
+        }
+        self
+    }
+}
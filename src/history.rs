@@ -0,0 +1,335 @@
+//! The history of the commands run by [`pacaptr`](crate), enabled by
+//! [`Config::log_file`].
+//!
+//! Every command run on behalf of an operation is appended to the log file as
+//! an [`Entry`] per line, which can then be read back with `pacaptr history`.
+
+use std::{
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fs2::FileExt;
+use futures::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+    exec::{self, Cmd, Output, StatusCode},
+    print::{print_warning, PROMPT_WARNING},
+};
+
+/// A command run by [`pacaptr`](crate), as recorded in the log file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    /// When the command was started, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// The name of the package manager, eg. `apt`.
+    pub pm: String,
+
+    /// The name of the operation, eg. `suy` for `-Syu`.
+    pub op: String,
+
+    /// The program and the arguments of the command.
+    pub argv: Vec<String>,
+
+    /// The exit code of the command, if it has exited normally.
+    pub code: Option<StatusCode>,
+
+    /// How long the command has run, in milliseconds.
+    pub duration_ms: u64,
+
+    /// Whether the command has been run (or only printed) in a dry run.
+    pub dry_run: bool,
+}
+
+tokio::task_local! {
+    /// The name of the operation the commands being run belong to.
+    static OP: String;
+}
+
+/// Runs `fut` with all of its commands recorded as part of the operation `op`,
+/// eg. `suy`.
+pub(crate) async fn with_op<F: Future>(op: &str, fut: F) -> F::Output {
+    OP.scope(op.into(), fut).await
+}
+
+/// Whether a warning about the log file has been printed.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Gets the exit code of a command from its result, if it has exited
+/// normally.
+#[must_use]
+pub(crate) fn status_code(res: &Result<Output>) -> Option<StatusCode> {
+    match res {
+        Ok(_) => Some(0),
+        Err(Error::CmdStatusCodeError { code, .. }) => Some(*code),
+        Err(_) => None,
+    }
+}
+
+/// Records the run of `cmd` from `started` until now in the log file, if there
+/// is one in `cfg`, where `code` is [`None`] if it has not exited normally, or
+/// has not been run at all in a dry run.
+///
+/// This never fails: instead, a single warning is printed for the whole run.
+pub(crate) fn record(
+    cfg: &Config,
+    pm: &str,
+    cmd: &Cmd,
+    code: Option<StatusCode>,
+    started: SystemTime,
+) {
+    let path = match &cfg.log_file {
+        Some(path) => path,
+        None => return,
+    };
+    let entry = Entry {
+        timestamp: started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        pm: pm.into(),
+        op: OP.try_with(Clone::clone).unwrap_or_default(),
+        // The credentials of a proxy shouldn't end up in the log.
+        argv: cmd.redacted().argv(),
+        code,
+        duration_ms: started
+            .elapsed()
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+        dry_run: cfg.dry_run,
+    };
+    if let Err(e) = append(Path::new(path), &entry) {
+        if !WARNED.swap(true, Ordering::SeqCst) {
+            print_warning(
+                format!("Failed to write to log file `{}`: {}", path, e),
+                PROMPT_WARNING,
+            );
+        }
+    }
+}
+
+/// Opens the log file at `path` for appending, creating it if missing.
+///
+/// The file is only readable and writable by the current user, since it might
+/// reveal what is installed on the system.
+fn open(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Appends `entry` to the log file at `path` as a line of JSON.
+///
+/// The file is locked during the write, so that the lines of concurrent runs
+/// are never interleaved.
+fn append(path: &Path, entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = open(path)?;
+    file.lock_exclusive()?;
+    // The lock is released as soon as the file is closed.
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the last `count` entries of the log file at `path`, keeping only
+/// those of the operation `op` (eg. `suy`) if it is given.
+///
+/// The lines that can't be parsed are skipped.
+///
+/// # Errors
+/// Returns an [`Error::IoError`] when the log file can't be read.
+pub(crate) fn read_last(path: &Path, count: usize, op: Option<&str>) -> Result<Vec<Entry>> {
+    let content = fs::read_to_string(path)?;
+    let entries = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+        .filter(|entry| op.map_or(true, |op| entry.op == op))
+        .collect_vec();
+    let skipped = entries.len().saturating_sub(count);
+    Ok(entries.into_iter().skip(skipped).collect())
+}
+
+/// Formats the Unix timestamp `secs` as a UTC date and time, eg.
+/// `2021-09-18 13:42:05`.
+#[must_use]
+fn format_timestamp(secs: u64) -> String {
+    // See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let secs = i64::try_from(secs).unwrap_or(i64::MAX);
+    let (days, time) = (secs / 86400, secs % 86400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+impl Entry {
+    /// Renders the [`Entry`] as a line of `pacaptr history`, eg.
+    /// `2021-09-18 13:42:05  apt -S  exited with 0 in 1.20s  apt install curl`.
+    #[must_use]
+    pub(crate) fn render(&self) -> String {
+        let op = if self.op.is_empty() {
+            "-".into()
+        } else {
+            format!("-{}{}", self.op[0..1].to_uppercase(), &self.op[1..])
+        };
+        let duration = Duration::from_millis(self.duration_ms);
+        let status = match self.code {
+            // The command has only been printed.
+            None if self.dry_run => "dry run".into(),
+            None => format!("interrupted in {:.2?}", duration),
+            Some(code) if self.dry_run => {
+                format!("exited with {} in {:.2?} (dry run)", code, duration)
+            }
+            Some(code) => format!("exited with {} in {:.2?}", code, duration),
+        };
+        format!(
+            "{}  {} {}  {}  {}",
+            format_timestamp(self.timestamp),
+            self.pm,
+            op,
+            status,
+            exec::shell_join(&self.argv),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, thread};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn entry(op: &str, code: Option<StatusCode>) -> Entry {
+        Entry {
+            timestamp: 1_631_972_525,
+            pm: "apt".into(),
+            op: op.into(),
+            argv: vec!["apt".into(), "install".into(), "a b".into()],
+            code,
+            duration_ms: 1200,
+            dry_run: false,
+        }
+    }
+
+    /// Makes a scratch directory, removed when dropped, with the path of a log
+    /// file inside.
+    fn temp_log() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history.jsonl");
+        (dir, path)
+    }
+
+    #[test]
+    fn entry_schema() {
+        assert_eq!(
+            serde_json::to_string(&entry("s", Some(0))).unwrap(),
+            r#"{"timestamp":1631972525,"pm":"apt","op":"s","argv":["apt","install","a b"],"code":0,"duration_ms":1200,"dry_run":false}"#
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1_631_972_525), "2021-09-18 13:42:05");
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn entry_render() {
+        assert_eq!(
+            entry("suy", Some(100)).render(),
+            "2021-09-18 13:42:05  apt -Suy  exited with 100 in 1.20s  apt install 'a b'"
+        );
+        let dry = Entry {
+            dry_run: true,
+            ..entry("s", None)
+        };
+        assert_eq!(
+            dry.render(),
+            "2021-09-18 13:42:05  apt -S  dry run  apt install 'a b'"
+        );
+    }
+
+    #[test]
+    fn append_read_last() {
+        let (_dir, path) = temp_log();
+        for (op, code) in &[("s", 0), ("r", 1), ("s", 2), ("suy", 3)] {
+            append(&path, &entry(op, Some(*code))).unwrap();
+        }
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+
+        let codes =
+            |entries: Vec<Entry>| entries.into_iter().map(|e| e.code.unwrap()).collect_vec();
+        assert_eq!(codes(read_last(&path, 2, None).unwrap()), [2, 3]);
+        assert_eq!(codes(read_last(&path, 10, Some("s")).unwrap()), [0, 2]);
+        assert_eq!(codes(read_last(&path, 1, Some("s")).unwrap()), [2]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn append_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_dir, path) = temp_log();
+        append(&path, &entry("s", Some(0))).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn append_concurrently() {
+        let (_dir, path) = temp_log();
+        // Long lines make interleaved writes more likely without a lock.
+        let long = Entry {
+            argv: vec!["x".repeat(1 << 16)],
+            ..entry("s", Some(0))
+        };
+        let writers = (0..8)
+            .map(|_| {
+                let (path, long) = (path.clone(), long.clone());
+                thread::spawn(move || {
+                    for _ in 0..16 {
+                        append(&path, &long).unwrap();
+                    }
+                })
+            })
+            .collect_vec();
+        writers.into_iter().for_each(|w| w.join().unwrap());
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 8 * 16);
+        assert!(content
+            .lines()
+            .all(|line| serde_json::from_str::<Entry>(line).unwrap() == long));
+    }
+}
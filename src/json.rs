@@ -0,0 +1,328 @@
+//! The schema of the machine-readable output enabled by `--json`.
+//!
+//! Under `--json`, every command run on behalf of the user is muted and
+//! recorded instead of being printed. Once the operation finishes, one
+//! [`Report`] per line is written to `stdout`:
+//! - Query operations (eg. `-Q`, `-Qi`, `-Ss`) emit a [`Report::Output`] for
+//!   each command run, or a [`Report::Packages`] if the backend parses the
+//!   output itself.
+//! - Other operations emit a single [`Report::Status`].
+//! - `pacaptr doctor` emits a single [`Report::Doctor`].
+
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+use crate::{
+    dispatch::Diagnosis,
+    error::{Error, Result},
+    exec::{Cmd, Output, StatusCode},
+    print,
+};
+
+/// A package as reported by a backend which parses its own output.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Package {
+    /// The name of the package.
+    pub name: String,
+
+    /// The version of the package, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Package {
+    /// Parses a line of a package listing in the common `name version ...`
+    /// form, or in the `name|version|...` form of `choco --limit-output`,
+    /// returning [`None`] for blank lines.
+    #[must_use]
+    pub(crate) fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line
+            .split(|c: char| c.is_whitespace() || c == '|')
+            .filter(|field| !field.is_empty());
+        Some(Package {
+            name: fields.next()?.into(),
+            version: fields.next().map(Into::into),
+        })
+    }
+
+    /// Renders the package with a `--print-format` template, eg. `{name}
+    /// {version}`, where each `{field}` is replaced by the field of the same
+    /// name, or by nothing if it is missing or unknown.
+    #[must_use]
+    pub(crate) fn render(&self, template: &str) -> String {
+        static FIELD: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?-u)\{(\w*)\}").unwrap());
+        FIELD
+            .replace_all(template, |caps: &Captures<'_>| match &caps[1] {
+                "name" => self.name.clone(),
+                "version" => self.version.clone().unwrap_or_default(),
+                _ => String::new(),
+            })
+            .into_owned()
+    }
+}
+
+impl From<String> for Package {
+    /// Makes a [`Package`] of the given name, without a version.
+    fn from(name: String) -> Self {
+        Package {
+            name,
+            version: None,
+        }
+    }
+}
+
+/// Something recorded during an operation run under `--json`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Record {
+    /// A command run in passthrough mode, along with its results.
+    ///
+    /// Here, `stdout` holds the combined `stdout`/`stderr` of the command.
+    Cmd {
+        cmd: Vec<String>,
+        stdout: String,
+        status: StatusCode,
+    },
+
+    /// A list of packages parsed by the backend.
+    Packages(Vec<Package>),
+}
+
+/// A line of output emitted on `stdout` under `--json`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Report<'a> {
+    /// The results of a command run by a query operation.
+    Output {
+        pm: &'a str,
+        op: &'a str,
+        cmd: Vec<String>,
+        stdout: String,
+        status: StatusCode,
+    },
+
+    /// The packages found by a query operation.
+    Packages {
+        pm: &'a str,
+        op: &'a str,
+        packages: Vec<Package>,
+    },
+
+    /// The final status of a (potentially) mutating operation.
+    Status {
+        pm: &'a str,
+        op: &'a str,
+        status: StatusCode,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// The environment diagnostics of `pacaptr doctor`.
+    Doctor {
+        pm: &'a str,
+        op: &'a str,
+        #[serde(flatten)]
+        diagnosis: &'a Diagnosis,
+    },
+}
+
+impl<'a> Report<'a> {
+    /// Wraps a [`Record`] into a [`Report`] of a query operation.
+    #[must_use]
+    pub(crate) fn from_record(pm: &'a str, op: &'a str, record: Record) -> Self {
+        match record {
+            Record::Cmd {
+                cmd,
+                stdout,
+                status,
+            } => Report::Output {
+                pm,
+                op,
+                cmd,
+                stdout,
+                status,
+            },
+            Record::Packages(packages) => Report::Packages { pm, op, packages },
+        }
+    }
+
+    /// Generates the final [`Report::Status`] of an operation according to its
+    /// result.
+    #[must_use]
+    pub(crate) fn from_result(pm: &'a str, op: &'a str, res: &Result<()>) -> Self {
+        let (status, error) = match res {
+            Ok(()) => (0, None),
+            Err(e @ Error::CmdStatusCodeError { .. }) => (e.exit_code(), Some(e.to_string())),
+            Err(e) => (1, Some(e.to_string())),
+        };
+        Report::Status {
+            pm,
+            op,
+            status,
+            error,
+        }
+    }
+
+    /// Prints the [`Report`] as a single line of JSON to `stdout`, through the
+    /// current [`Printer`](print::Printer).
+    ///
+    /// # Errors
+    /// Returns an [`Error::JsonError`] if the serialization fails.
+    pub(crate) fn print(&self) -> Result<()> {
+        print::printer().out(&serde_json::to_string(self)?);
+        Ok(())
+    }
+}
+
+/// Checks if the operation indicated by `op` (eg. `qi`) only queries the
+/// package database without mutating it.
+#[must_use]
+pub(crate) fn is_query(op: &str) -> bool {
+    op.starts_with('q') || ["sg", "si", "sii", "sl", "ss"].contains(&op)
+}
+
+/// All the [`Record`]s collected so far.
+static RECORDS: Lazy<Mutex<Vec<Record>>> = Lazy::new(Mutex::default);
+
+/// Records the results of a [`Cmd`] run in passthrough mode.
+pub(crate) fn record_cmd(cmd: &Cmd, res: &Result<Output>) {
+    let (stdout, status) = match res {
+        Ok(out) => (out, 0),
+        Err(Error::CmdStatusCodeError { code, output, .. }) => (output, *code),
+        Err(_) => return,
+    };
+    record(Record::Cmd {
+        cmd: print::event_cmd(cmd),
+        stdout: String::from_utf8_lossy(stdout).into(),
+        status,
+    });
+}
+
+/// Records the lines of a package listing.
+pub(crate) fn record_packages(lines: &[&str]) {
+    record(Record::Packages(
+        lines
+            .iter()
+            .filter_map(|ln| Package::from_line(ln))
+            .collect(),
+    ));
+}
+
+/// Records a package listing parsed by the backend itself.
+pub(crate) fn record_parsed(packages: Vec<Package>) {
+    record(Record::Packages(packages));
+}
+
+/// Adds a [`Record`] to the collection.
+fn record(record: Record) {
+    RECORDS
+        .lock()
+        .expect("`--json` records have been poisoned")
+        .push(record);
+}
+
+/// Takes all the [`Record`]s collected so far.
+#[must_use]
+pub(crate) fn take_records() -> Vec<Record> {
+    RECORDS
+        .lock()
+        .expect("`--json` records have been poisoned")
+        .drain(..)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn report_output() {
+        let record = Record::Cmd {
+            cmd: vec!["brew".into(), "list".into()],
+            stdout: "curl\n".into(),
+            status: 0,
+        };
+        let report = Report::from_record("brew", "q", record);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "pm": "brew",
+                "op": "q",
+                "cmd": ["brew", "list"],
+                "stdout": "curl\n",
+                "status": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn report_packages() {
+        let packages = ["curl 7.79.1", "", "  wget", "git|2.33.1|2.34.0|false"]
+            .iter()
+            .filter_map(|ln| Package::from_line(ln))
+            .collect();
+        let report = Report::from_record("pip", "qs", Record::Packages(packages));
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "pm": "pip",
+                "op": "qs",
+                "packages": [
+                    { "name": "curl", "version": "7.79.1" },
+                    { "name": "wget" },
+                    { "name": "git", "version": "2.33.1" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn render_template() {
+        let pkg = Package::from_line("curl 7.79.1").unwrap();
+        assert_eq!(pkg.render("{name} {version}"), "curl 7.79.1");
+        assert_eq!(pkg.render("{name}=={version}"), "curl==7.79.1");
+        assert_eq!(pkg.render("name: {name}"), "name: curl");
+        // The missing or unknown fields are rendered empty.
+        let pkg = Package::from_line("wget").unwrap();
+        assert_eq!(pkg.render("{name}@{version}"), "wget@");
+        assert_eq!(pkg.render("{name} [{repo}]{}"), "wget []");
+    }
+
+    #[tokio::test]
+    async fn report_printed() {
+        let report = Report::from_result("apt", "s", &Ok(()));
+        let (res, lines) = print::collect_lines(async { report.print() }).await;
+        res.unwrap();
+        assert_eq!(lines, [r#"{"pm":"apt","op":"s","status":0}"#]);
+    }
+
+    #[test]
+    fn report_status() {
+        let report = Report::from_result("apt", "s", &Ok(()));
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({ "pm": "apt", "op": "s", "status": 0 })
+        );
+
+        let res = Err(Error::CmdStatusCodeError {
+            code: 100,
+            output: vec![],
+            kind: None,
+            raw: false,
+        });
+        let report = Report::from_result("apt", "s", &res);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "pm": "apt",
+                "op": "s",
+                "status": 1,
+                "error": "Subprocess exited with code 100",
+            })
+        );
+    }
+}
@@ -17,10 +17,39 @@
 #![doc = pacaptr_macros::compat_table!()]
 //! Note: Some flags are "translated" so are not shown in this table, eg. `-p`
 //! in `-Sp`.
+//!
+//! # Library Usage
+//!
+//! Besides the command line interface, the operations above can be run on a
+//! [`Pm`](pm::Pm) built from a [`Config`](dispatch::Config) with
+//! [`Pm::run_op`](pm::Pm::run_op), which returns the output of the commands
+//! run instead of printing it.
+//!
+//! The `sudo`-aware and prompt-aware process runner behind them is also
+//! available on its own as [`Cmd`](exec::Cmd), and backends of your own can
+//! be plugged in with [`register`](pm::register).
+//!
+//! To follow the progress of an operation as it runs, eg. in a GUI, set
+//! [`Config::event_sender`](dispatch::Config::event_sender) to receive an
+//! [`Event`](print::Event) whenever a command starts, prints a line or
+//! finishes.
+//! Likewise, the messages of `pacaptr` itself (eg. the commands echoed after
+//! their prompts, the warnings and the errors) can be redirected with
+//! [`Config::printer`](dispatch::Config::printer), eg. to a
+//! [`BufPrinter`](print::BufPrinter).
 
 pub mod dispatch;
+mod elevate;
 pub mod error;
-mod exec;
-
-mod pm;
+pub mod exec;
+mod history;
+mod json;
+mod lock;
+pub mod pm;
 pub mod print;
+mod search_cache;
+mod self_update;
+mod stat;
+mod sudo;
+#[cfg(test)]
+mod test_util;
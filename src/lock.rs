@@ -0,0 +1,370 @@
+//! The advisory lock preventing overlapping runs of [`pacaptr`](crate) on the
+//! same package manager, which might otherwise corrupt its database.
+//!
+//! The lock is a file per package manager in a directory shared by all the
+//! users, eg. `/run/lock/pacaptr-apt.lock`, since `pacaptr` and `sudo pacaptr`
+//! run against the same package database. It is locked for the whole operation
+//! and released as soon as it is closed, even if [`pacaptr`](crate) crashes.
+//! This is a `flock` on Unix, and a `LockFileEx` on Windows.
+//!
+//! The process holding the lock records its pid next to it, eg. in
+//! `/run/lock/pacaptr-apt.pid`, so that it can be named to those waiting for
+//! it.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+use tokio::time;
+
+use crate::{
+    error::{Error, Result},
+    print::{print_warning, PROMPT_WARNING},
+};
+
+/// The interval between two attempts to acquire a lock under `--wait-lock`.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The lock held on a package manager, released when dropped.
+#[must_use]
+#[derive(Debug)]
+pub(crate) struct Lock {
+    pid_path: PathBuf,
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the locked `file` of the package manager `pm` in `dir`, recording
+    /// the pid of the current process as that of its holder.
+    fn new(dir: &Path, pm: &str, file: File) -> Self {
+        let pid_path = pid_path(dir, pm);
+        // The pid is only informative, so it is fine if it can't be written,
+        // eg. when the file has been created by another user.
+        let _ = fs::write(&pid_path, process::id().to_string());
+        Lock {
+            pid_path,
+            _file: file,
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // The pid is removed before the lock is released (when the file is
+        // closed), so that it never overwrites that of the next holder.
+        let _ = fs::remove_file(&self.pid_path);
+    }
+}
+
+/// The system directories the lock files are preferably put in, as long as
+/// they can be written to by all the users.
+const SYSTEM_DIRS: &[&str] = &["/run/lock", "/var/lock"];
+
+/// Gets the directory of the lock files, which is the same for all the users,
+/// including `root`.
+///
+/// This is the first of [`SYSTEM_DIRS`] writable by all the users, or else the
+/// temporary directory.
+#[must_use]
+pub(crate) fn dir() -> PathBuf {
+    SYSTEM_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .find(|dir| is_shared(dir))
+        .unwrap_or_else(env::temp_dir)
+}
+
+/// Checks if `dir` is a directory writable by all the users.
+#[cfg(unix)]
+#[must_use]
+fn is_shared(dir: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(dir).map_or(false, |meta| {
+        meta.is_dir() && meta.permissions().mode() & 0o002 != 0
+    })
+}
+
+/// Checks if `dir` is a directory writable by all the users.
+#[cfg(windows)]
+#[must_use]
+fn is_shared(_dir: &Path) -> bool {
+    false
+}
+
+/// Gets the path of the lock file of the package manager `pm` in `dir`.
+#[must_use]
+pub(crate) fn path(dir: &Path, pm: &str) -> PathBuf {
+    dir.join(format!("pacaptr-{}.lock", pm))
+}
+
+/// Gets the path of the file recording the pid of the holder of the lock on
+/// the package manager `pm` in `dir`.
+#[must_use]
+fn pid_path(dir: &Path, pm: &str) -> PathBuf {
+    path(dir, pm).with_extension("pid")
+}
+
+/// Gets the pid of the process holding the lock on the package manager `pm`
+/// in `dir`, if it is known and the process is still alive.
+///
+/// The pid left behind by a crashed [`pacaptr`](crate) is stale, and thus
+/// ignored.
+#[must_use]
+fn holder(dir: &Path, pm: &str) -> Option<u32> {
+    fs::read_to_string(pid_path(dir, pm))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&pid| is_alive(pid))
+}
+
+/// Checks if the process with the given `pid` is alive.
+#[cfg(unix)]
+#[must_use]
+fn is_alive(pid: u32) -> bool {
+    use nix::{
+        errno::Errno,
+        sys::signal::{kill, Signal},
+        unistd::Pid,
+    };
+
+    // No signal is sent, but the existence of the process is still checked.
+    #[allow(clippy::cast_possible_wrap)]
+    let pid = Pid::from_raw(pid as i32);
+    !matches!(kill(pid, None::<Signal>), Err(Errno::ESRCH))
+}
+
+/// Checks if the process with the given `pid` is alive.
+#[cfg(windows)]
+#[must_use]
+fn is_alive(pid: u32) -> bool {
+    process::Command::new("tasklist")
+        .args(&["/fi", &format!("PID eq {}", pid), "/fo", "csv", "/nh"])
+        .output()
+        .map_or(false, |out| {
+            String::from_utf8_lossy(&out.stdout).contains(&format!("\"{}\"", pid))
+        })
+}
+
+/// Tries to lock `file` without blocking, returning whether it succeeded.
+fn try_lock(file: &File) -> io::Result<bool> {
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Acquires the lock on the package manager `pm` in `dir` (ie. [`dir`] outside
+/// of the tests), waiting for any other [`pacaptr`](crate) holding it to
+/// finish for at most `wait`, or for as long as it takes if [`None`].
+///
+/// # Errors
+/// Returns an [`Error::PmLockedError`] when the lock is held and `wait` is
+/// zero, an [`Error::PmLockTimeoutError`] when it is still held after `wait`,
+/// or an [`Error::IoError`] when the lock file can't be opened.
+pub(crate) async fn acquire(dir: &Path, pm: &str, wait: Option<Duration>) -> Result<Lock> {
+    fs::create_dir_all(dir)?;
+    let path = path(dir, pm);
+    // The file is only read once it exists, so that a lock file created by
+    // another user (eg. under `sudo`) can still be locked.
+    let _ = OpenOptions::new().append(true).create(true).open(&path);
+    let file = File::open(&path)?;
+
+    if try_lock(&file)? {
+        return Ok(Lock::new(dir, pm, file));
+    }
+    if wait == Some(Duration::from_secs(0)) {
+        return Err(Error::PmLockedError { pm: pm.into() });
+    }
+    let other = holder(dir, pm).map_or_else(String::new, |pid| format!(" (pid {})", pid));
+    print_warning(
+        format!(
+            "Waiting for another `pacaptr`{} running on `{}` to finish...",
+            other, pm
+        ),
+        PROMPT_WARNING,
+    );
+    let wait = match wait {
+        Some(wait) => wait,
+        None => {
+            return tokio::task::spawn_blocking(move || file.lock_exclusive().map(|()| file))
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                .map(|file| Lock::new(dir, pm, file))
+                .map_err(Into::into)
+        }
+    };
+    let deadline = Instant::now() + wait;
+    loop {
+        time::sleep(POLL_INTERVAL).await;
+        if try_lock(&file)? {
+            return Ok(Lock::new(dir, pm, file));
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::PmLockTimeoutError {
+                pm: pm.into(),
+                secs: wait.as_secs(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read},
+        process::{Child, Command, Stdio},
+        thread,
+    };
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// The environment variables naming the directory and the package manager
+    /// of the lock to be held by [`lock_holder`] in another process.
+    const HOLDER_DIR_VAR: &str = "PACAPTR_TEST_LOCK_DIR";
+    const HOLDER_PM_VAR: &str = "PACAPTR_TEST_LOCK_PM";
+
+    /// The package manager locked by the tests, in a temporary directory
+    /// instead of the shared one.
+    const PM: &str = "test";
+
+    /// Spawns another test process holding the lock on [`PM`] in `dir` until
+    /// its `stdin` is closed, returning once the lock is taken.
+    fn spawn_holder(dir: &Path) -> Child {
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(&[
+                "--ignored",
+                "--exact",
+                "lock::tests::lock_holder",
+                "--nocapture",
+            ])
+            .env(HOLDER_DIR_VAR, dir)
+            .env(HOLDER_PM_VAR, PM)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let locked = lines.any(|line| line.map_or(false, |line| line == "locked"));
+        assert!(locked, "the holder exited without taking the lock");
+        // The rest of the output is drained so that the holder never fails to
+        // print it.
+        thread::spawn(move || lines.for_each(drop));
+        child
+    }
+
+    /// Holds the lock named by [`HOLDER_DIR_VAR`] and [`HOLDER_PM_VAR`], when
+    /// run by [`spawn_holder`].
+    #[tokio::test]
+    #[ignore]
+    async fn lock_holder() {
+        let (dir, pm) = match (env::var_os(HOLDER_DIR_VAR), env::var(HOLDER_PM_VAR)) {
+            (Some(dir), Ok(pm)) => (PathBuf::from(dir), pm),
+            _ => return,
+        };
+        let _lock = acquire(&dir, &pm, Some(Duration::from_secs(0)))
+            .await
+            .unwrap();
+        println!("locked");
+        let _ = std::io::stdin().read_to_end(&mut vec![]);
+    }
+
+    #[test]
+    fn shared_dir() {
+        let dir = dir();
+        assert!(is_shared(&dir) || dir == env::temp_dir(), "{:?}", dir);
+        assert_eq!(path(&dir, "apt"), dir.join("pacaptr-apt.lock"));
+        assert_eq!(pid_path(&dir, "apt"), dir.join("pacaptr-apt.pid"));
+    }
+
+    #[tokio::test]
+    async fn lock_no_wait() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.path();
+        let no_wait = Some(Duration::from_secs(0));
+        let lock = acquire(dir, PM, no_wait).await.unwrap();
+        assert!(matches!(
+            acquire(dir, PM, no_wait).await,
+            Err(Error::PmLockedError { pm: locked }) if locked == PM
+        ));
+        drop(lock);
+        acquire(dir, PM, no_wait).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_wait() {
+        let dir = TempDir::new().unwrap();
+        let lock = acquire(dir.path(), PM, None).await.unwrap();
+        let mut waiting = tokio::spawn({
+            let dir = dir.path().to_owned();
+            async move { acquire(&dir, PM, None).await }
+        });
+        assert!(time::timeout(Duration::from_millis(200), &mut waiting)
+            .await
+            .is_err());
+        drop(lock);
+        time::timeout(Duration::from_secs(10), waiting)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_across_processes() {
+        let dir = TempDir::new().unwrap();
+        let mut child = spawn_holder(dir.path());
+        assert_eq!(holder(dir.path(), PM), Some(child.id()));
+        assert!(matches!(
+            acquire(dir.path(), PM, Some(Duration::from_secs(0))).await,
+            Err(Error::PmLockedError { .. })
+        ));
+        assert!(matches!(
+            acquire(dir.path(), PM, Some(Duration::from_millis(300))).await,
+            Err(Error::PmLockTimeoutError { pm: locked, .. }) if locked == PM
+        ));
+
+        // The lock is released as soon as the holder exits.
+        let waiting = tokio::spawn({
+            let dir = dir.path().to_owned();
+            async move { acquire(&dir, PM, Some(Duration::from_secs(10))).await }
+        });
+        drop(child.stdin.take());
+        assert!(child.wait().unwrap().success());
+        let lock = waiting.await.unwrap().unwrap();
+        assert_eq!(holder(dir.path(), PM), Some(process::id()));
+        drop(lock);
+        assert_eq!(holder(dir.path(), PM), None);
+    }
+
+    #[tokio::test]
+    async fn lock_stale_pid() {
+        let dir = TempDir::new().unwrap();
+        let mut child = spawn_holder(dir.path());
+        let pid = child.id();
+        // A killed holder leaves its pid behind, which is then stale.
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(
+            fs::read_to_string(pid_path(dir.path(), PM)).unwrap(),
+            pid.to_string()
+        );
+        assert_eq!(holder(dir.path(), PM), None);
+        let lock = acquire(dir.path(), PM, Some(Duration::from_secs(0)))
+            .await
+            .unwrap();
+        assert_eq!(holder(dir.path(), PM), Some(process::id()));
+        drop(lock);
+    }
+}
@@ -1,8 +1,7 @@
 use clap::Clap;
 use pacaptr::{
     dispatch::Pacaptr,
-    error::Error,
-    print::{print_err, PROMPT_ERROR},
+    print::{print_err, print_err_output, PROMPT_ERROR},
 };
 
 #[tokio::main]
@@ -11,9 +10,9 @@ async fn main() {
     // TODO: Replace this with `Termination`. Currently blocked by https://github.com/rust-lang/rust/issues/43301.
     if let Err(e) = &res {
         print_err(e, PROMPT_ERROR);
-        std::process::exit(match e {
-            Error::CmdStatusCodeError { code, .. } => *code,
-            _ => 1,
-        })
+        if let Some(output) = e.output() {
+            print_err_output(output);
+        }
+        std::process::exit(e.exit_code())
     }
 }
@@ -1,15 +1,20 @@
 #![doc = docs_self!()]
 
+use std::{fs, path::Path};
+
 use async_trait::async_trait;
 use indoc::indoc;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{
+    classify_output, network_bound, NoCacheStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy,
+    Strategy,
+};
 use crate::{
     dispatch::Config,
-    error::Result,
-    exec::{self, Cmd},
+    error::{FailureKind, Result},
+    exec::{Cmd, StatusCode},
     print::{self, PROMPT_RUN},
 };
 
@@ -38,12 +43,60 @@ static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("unable to select packages", FailureKind::NotFound),
+    (
+        "temporary error (try again later)",
+        FailureKind::NetworkError,
+    ),
+    ("DNS lookup error", FailureKind::NetworkError),
+];
+
+/// Parses the names of the packages explicitly installed from the content of
+/// `/etc/apk/world`, eg. `foo` from `foo>=1.0` or `foo@edge`.
+#[must_use]
+fn parse_world(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .filter(|dep| !dep.starts_with('!'))
+        .filter_map(|dep| dep.split(|c| "<>=~@".contains(c)).next())
+        .collect()
+}
+
+/// Parses the packages which are not required by any other package from the
+/// output of `apk info -r`, eg. `foo` from an empty list under
+/// `foo-1.0-r0 is required by:`.
+#[must_use]
+fn parse_unrequired(text: &str) -> Vec<&str> {
+    text.split("\n\n")
+        .filter_map(|block| {
+            let mut lines = block.lines().filter(|ln| !ln.trim().is_empty());
+            let pkg = lines.next()?.strip_suffix(" is required by:")?;
+            lines.next().is_none().then(|| pkg)
+        })
+        // Drops the version along with the release, eg. `-1.0-r0`.
+        .filter_map(|pkg| pkg.rsplitn(3, '-').nth(2))
+        .collect()
+}
+
 impl Apk {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
         Apk { cfg }
     }
+
+    /// Gets the keywords `kws` along with the virtual package set by
+    /// `--virtual`, if any, so that the latter is removed as a whole.
+    #[must_use]
+    fn with_virtual<'a>(&'a self, kws: &[&'a str]) -> Vec<&'a str> {
+        self.cfg
+            .virtual_pkg
+            .as_deref()
+            .into_iter()
+            .chain(kws.iter().copied())
+            .collect()
+    }
 }
 
 #[async_trait]
@@ -57,6 +110,67 @@ impl Pm for Apk {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qdt,
+            Op::Qi,
+            Op::Ql,
+            Op::Qo,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rn,
+            Op::Rns,
+            Op::Rs,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Si,
+            Op::Sii,
+            Op::Sl,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sw,
+            Op::Sy,
+            Op::U,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Qc, Op::Sg]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Manages the packages of the system under the given root directory.
+    fn root_flags(&self, exe: &str, root: &str) -> Option<Vec<String>> {
+        (exe == "apk").then(|| vec!["--root".into(), root.into()])
+    }
+
+    /// Installs only the packages already in the cache, while `ss` and `si`
+    /// only read the local indexes anyway.
+    fn offline_flags(&self, exe: &str, op: Op) -> Option<Vec<String>> {
+        match (exe, op) {
+            ("apk", Op::S | Op::Su) => Some(vec!["--no-network".into()]),
+            ("apk", Op::Ss | Op::Si) => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    /// Installs the packages under the given virtual package.
+    fn virtual_flags(&self, name: &str) -> Option<Vec<String>> {
+        Some(vec!["--virtual".into(), name.into()])
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
@@ -66,6 +180,45 @@ impl Pm for Apk {
         }
     }
 
+    /// Qdt lists orphans, ie. packages installed as dependencies which are no
+    /// longer required by any other package.
+    async fn qdt(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = Cmd::new(&["apk", "info"]).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out_bytes = self
+            .check_output(cmd, PmMode::Mute, &Strategy::default())
+            .await?;
+        let installed = self.decode(out_bytes)?;
+        if installed.trim().is_empty() {
+            return Ok(());
+        }
+
+        // The packages in the world file are the ones installed explicitly.
+        let world = Path::new(self.cfg.root.as_deref().unwrap_or("/"))
+            .join("etc/apk/world")
+            .pipe(fs::read_to_string)?;
+        let world = parse_world(&world);
+        let deps = installed
+            .split_whitespace()
+            .filter(|pkg| !world.contains(pkg))
+            .collect::<Vec<_>>();
+        if deps.is_empty() {
+            return Ok(());
+        }
+
+        let cmd = Cmd::new(&["apk", "info", "-r"]).kws(&deps).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out_bytes = self
+            .check_output(cmd, PmMode::Mute, &Strategy::default())
+            .await?;
+        let orphans = parse_unrequired(&self.decode(out_bytes)?).join("\n");
+        self.grep_print(&orphans, kws)
+    }
+
     /// Qi displays local package information: name, version, description, etc.
     async fn qi(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.si(kws, flags).await
@@ -92,13 +245,13 @@ impl Pm for Apk {
     // matching ALL of those terms are returned.
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         let cmd = Cmd::new(&["apk", "info", "-d"]).flags(flags);
-        if !self.cfg.dry_run {
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        exec::grep_print(&String::from_utf8(out_bytes)?, kws)
+        self.grep_print(&self.decode(out_bytes)?, kws)
     }
 
     /// Qu lists packages which have an update available.
@@ -111,7 +264,7 @@ impl Pm for Apk {
     /// R removes a single package, leaving all of its dependencies installed.
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["apk", "del"])
-            .kws(kws)
+            .kws(&self.with_virtual(kws))
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
@@ -121,7 +274,7 @@ impl Pm for Apk {
     /// files.
     async fn rn(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["apk", "del", "--purge"])
-            .kws(kws)
+            .kws(&self.with_virtual(kws))
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
@@ -132,7 +285,7 @@ impl Pm for Apk {
     /// backup files.
     async fn rns(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["apk", "del", "--purge", "-r"])
-            .kws(kws)
+            .kws(&self.with_virtual(kws))
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
@@ -146,9 +299,18 @@ impl Pm for Apk {
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let flags = self
+            .cfg
+            .virtual_pkg
+            .as_deref()
+            .and_then(|name| self.virtual_flags(name))
+            .into_iter()
+            .flatten()
+            .chain(flags.iter().map(|&f| f.into()))
+            .collect::<Vec<_>>();
         Cmd::with_sudo(&["apk", "add"])
             .kws(kws)
-            .flags(flags)
+            .flags(&flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -236,8 +398,7 @@ impl Pm for Apk {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::with_sudo(&["apk", "update"]).kws(kws).flags(flags))
-            .await?;
+        network_bound(self.run(Cmd::with_sudo(&["apk", "update"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
@@ -254,3 +415,28 @@ impl Pm for Apk {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_orphans() {
+        let world = "alpine-base curl>=7.79 git@edge !bash .build-deps\n";
+        assert_eq!(
+            parse_world(world),
+            ["alpine-base", "curl", "git", ".build-deps"]
+        );
+
+        let out = indoc! {"
+            libcurl-7.79.1-r0 is required by:
+            curl-7.79.1-r0
+
+            libfoo-1.0-r0 is required by:
+
+            py3-bar-baz-2.3.4-r1 is required by:
+
+        "};
+        assert_eq!(parse_unrequired(out), ["libfoo", "py3-bar-baz"]);
+    }
+}
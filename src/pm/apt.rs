@@ -1,12 +1,24 @@
 #![doc = docs_self!()]
 
+use std::{env, fs, io::Write};
+
 use async_trait::async_trait;
 use indoc::indoc;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
+use tempfile::NamedTempFile;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
-use crate::{dispatch::Config, error::Result, exec::Cmd};
+use super::{
+    check_overwrite_all, classify_output, network_bound, AssumeNoStrategy, AssumeYesStrategy,
+    NoCacheStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy,
+};
+use crate::{
+    dispatch::{Config, Proxy},
+    error::{FailureKind, Result},
+    exec::{Cmd, StatusCode},
+    print::{self, PROMPT_RUN},
+};
 
 macro_rules! docs_self {
     () => {
@@ -24,21 +36,83 @@ pub(crate) struct Apt {
 
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["--yes"]),
+    assume_no: AssumeNoStrategy::with_flags(&["--assume-no"]),
+    assume_yes: AssumeYesStrategy::with_flags(&["--assume-yes"]),
+    ..Strategy::default()
+});
+
+static STRAT_PROMPT_CUSTOM: Lazy<Strategy> = Lazy::new(|| Strategy {
+    prompt: PromptStrategy::CustomPrompt,
     ..Strategy::default()
 });
 
 static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["--yes"]),
+    assume_no: AssumeNoStrategy::with_flags(&["--assume-no"]),
+    assume_yes: AssumeYesStrategy::with_flags(&["--assume-yes"]),
     no_cache: NoCacheStrategy::Scc,
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("Unable to locate package", FailureKind::NotFound),
+    ("has no installation candidate", FailureKind::NotFound),
+    ("Temporary failure resolving", FailureKind::NetworkError),
+    ("Failed to fetch", FailureKind::NetworkError),
+    ("Abort.", FailureKind::Aborted),
+];
+
+/// Parses the packages to be removed from the output of `apt-get autoremove
+/// --dry-run`, eg. `libfoo` from `Remv libfoo [1.0-1]`.
+#[must_use]
+fn parse_autoremove(text: &str) -> Vec<&str> {
+    text.lines()
+        .filter_map(|ln| ln.strip_prefix("Remv "))
+        .filter_map(|ln| ln.split_whitespace().next())
+        .collect()
+}
+
 impl Apt {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
         Apt { cfg }
     }
+
+    /// Upgrades all the outdated packages.
+    async fn upgrade(&self, flags: &[&str]) -> Result<()> {
+        check_overwrite_all(self)?;
+        let flags = self.with_recommends(&self.with_overwrite(&self.with_repos(flags)));
+        Cmd::with_sudo(&["apt", "upgrade"])
+            .flags(&flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await?;
+        Cmd::with_sudo(&["apt", "dist-upgrade"])
+            .flags(&flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
+            .await
+    }
+
+    /// Writes a temporary `apt_preferences(5)` file preventing the packages in
+    /// [`Config::ignore`] from being upgraded, which is removed once dropped.
+    ///
+    /// The installed version of a package is not found in any release, so a
+    /// negative priority on all releases leaves it as the only candidate.
+    fn write_ignore_prefs(&self) -> Result<NamedTempFile> {
+        let prefs = self
+            .cfg
+            .ignore
+            .iter()
+            .map(|pkg| format!("Package: {}\nPin: release *\nPin-Priority: -1\n", pkg))
+            .join("\n");
+        let mut file = tempfile::Builder::new()
+            .prefix("pacaptr-ignore-")
+            .suffix(".pref")
+            .tempfile()?;
+        file.write_all(prefs.as_bytes())?;
+        Ok(file)
+    }
 }
 
 #[async_trait]
@@ -52,9 +126,176 @@ impl Pm for Apt {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qc,
+            Op::Qdt,
+            Op::Qe,
+            Op::Qi,
+            Op::Qo,
+            Op::Qp,
+            Op::Qu,
+            Op::R,
+            Op::Rn,
+            Op::Rns,
+            Op::Rs,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Sg,
+            Op::Si,
+            Op::Sii,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sw,
+            Op::Sy,
+        ]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Targets the given release (eg. `bullseye-backports`), of which there can
+    /// only be one.
+    fn repo_flags(&self, repos: &[String]) -> Option<Vec<String>> {
+        match repos {
+            [release] => Some(vec!["-t".into(), release.clone()]),
+            _ => None,
+        }
+    }
+
+    /// Removes the dependencies which are no longer needed along with the
+    /// packages.
+    fn autoremove_flags(&self) -> Option<Vec<String>> {
+        Some(vec!["--auto-remove".into()])
+    }
+
+    /// Installs the recommended packages or not, overriding
+    /// `APT::Install-Recommends`.
+    fn recommends_flags(&self, recommends: bool) -> Option<Vec<String>> {
+        let flag = if recommends {
+            "--install-recommends"
+        } else {
+            "--no-install-recommends"
+        };
+        Some(vec![flag.into()])
+    }
+
+    /// Lets `dpkg` overwrite the conflicting files, which it can't restrict to
+    /// some paths, so only the glob `*` is accepted.
+    fn overwrite_flags(&self, _glob: &str) -> Option<Vec<String>> {
+        Some(vec![
+            "-o".into(),
+            "Dpkg::Options::=--force-overwrite".into(),
+        ])
+    }
+
+    /// Prefixes all the directories of `apt` with the given root, and points
+    /// `dpkg-query` to the database under it.
+    fn root_flags(&self, exe: &str, root: &str) -> Option<Vec<String>> {
+        match exe {
+            "apt" | "apt-get" | "apt-mark" => Some(vec!["-o".into(), format!("Dir={}", root)]),
+            "dpkg-query" => Some(vec![format!("--admindir={}/var/lib/dpkg", root)]),
+            // `dpkg-deb` only reads the given archives.
+            "dpkg-deb" => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    /// Sets `Acquire::http::Proxy` and `Acquire::https::Proxy` for `apt` and
+    /// `apt-get`, which would otherwise be lost under `sudo`, along with
+    /// `DIRECT` for each host in `no_proxy`.
+    fn proxy_flags(&self, exe: &str, proxy: &Proxy) -> Option<Vec<String>> {
+        if !matches!(exe, "apt" | "apt-get") {
+            return None;
+        }
+        let mut opts = vec![];
+        for (scheme, url) in &[("http", &proxy.http), ("https", &proxy.https)] {
+            if let Some(url) = url {
+                opts.push(format!("Acquire::{}::Proxy={}", scheme, url));
+            }
+            for host in proxy.no_proxy_hosts() {
+                opts.push(format!("Acquire::{}::Proxy::{}=DIRECT", scheme, host));
+            }
+        }
+        Some(
+            opts.into_iter()
+                .flat_map(|opt| vec!["-o".into(), opt])
+                .collect(),
+        )
+    }
+
+    /// Qualifies the package names of `s` and `q` with the architecture, as in
+    /// `curl:arm64`.
+    fn arch_args(&self, op: Op, arch: &str) -> Option<(Vec<String>, String)> {
+        matches!(op, Op::S | Op::Q).then(|| (vec![], format!(":{}", arch)))
+    }
+
+    /// Installs only the packages already downloaded by `apt` or `apt-get`,
+    /// while `ss` and `si` only read the local package lists anyway.
+    fn offline_flags(&self, exe: &str, op: Op) -> Option<Vec<String>> {
+        match (exe, op) {
+            ("apt" | "apt-get", Op::S | Op::Su) => Some(vec!["--no-download".into()]),
+            ("apt" | "apt-get", Op::Ss | Op::Si) => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    /// Takes the package names from the `name/suites version arch` lines of
+    /// `apt search`, skipping the descriptions beneath them.
+    fn parse_search(&self, text: &str) -> Option<Vec<String>> {
+        text.lines()
+            .filter(|ln| !ln.starts_with(char::is_whitespace))
+            .filter_map(|ln| ln.split_once('/'))
+            .map(|(name, _)| name.into())
+            .collect::<Vec<_>>()
+            .pipe(Some)
+    }
+
+    /// Hold prevents one or more packages from being upgraded or removed.
+    async fn hold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["apt-mark", "hold"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM))
+            .await
+    }
+
+    /// Unhold releases one or more packages previously held.
+    async fn unhold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["apt-mark", "unhold"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM))
+            .await
+    }
+
+    /// Held lists the packages that are currently held.
+    async fn held(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&["apt-mark", "showhold"]).kws(kws).flags(flags))
+            .await
+    }
+
+    /// Removes the orphans, as listed by `qdt`.
+    async fn remove_orphans(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["apt", "autoremove"])
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["apt", "list"]).kws(kws).flags(flags))
+        let (kws, flags) = self.with_arch(Op::Q, kws, flags);
+        self.run(Cmd::new(&["apt", "list"]).kws(&kws).flags(&flags))
             .await
     }
 
@@ -64,6 +305,20 @@ impl Pm for Apt {
             .await
     }
 
+    /// Qdt lists orphans, ie. packages installed as dependencies which are no
+    /// longer required by any other package.
+    async fn qdt(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = Cmd::new(&["apt-get", "autoremove", "--dry-run"]).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out_bytes = self
+            .check_output(cmd, PmMode::Mute, &Strategy::default())
+            .await?;
+        let orphans = parse_autoremove(&self.decode(out_bytes)?).join("\n");
+        self.grep_print(&orphans, kws)
+    }
+
     /// Qe lists packages installed explicitly (not as dependencies).
     async fn qe(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.run(Cmd::new(&["apt-mark", "showmanual"]).kws(kws).flags(flags))
@@ -140,13 +395,16 @@ impl Pm for Apt {
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        check_overwrite_all(self)?;
+        let flags = self.with_recommends(&self.with_overwrite(&self.with_repos(flags)));
+        let (kws, flags) = self.with_arch(Op::S, kws, &flags);
         Cmd::with_sudo(if self.cfg.needed {
             &["apt", "install"]
         } else {
             &["apt", "install", "--reinstall"]
         })
-        .kws(kws)
-        .flags(flags)
+        .kws(&kws)
+        .flags(&flags)
         .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
         .await
     }
@@ -199,50 +457,178 @@ impl Pm for Apt {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["apt", "search"]).kws(kws).flags(flags))
-            .await
+        self.run(
+            Cmd::new(&["apt", "search"])
+                .kws(kws)
+                .flags(&self.with_repos(flags)),
+        )
+        .await
     }
 
     /// Su updates outdated packages.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        if kws.is_empty() {
-            Cmd::with_sudo(&["apt", "upgrade"])
-                .flags(flags)
-                .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
-                .await?;
-            Cmd::with_sudo(&["apt", "dist-upgrade"])
-                .flags(flags)
-                .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
-                .await
-        } else {
-            self.s(kws, flags).await
+        if !kws.is_empty() {
+            return self.s(kws, flags).await;
         }
+        if self.cfg.ignore.is_empty() {
+            return self.upgrade(flags).await;
+        }
+        // Nothing is written when the commands are only printed.
+        let prefs = if self.cfg.dry_run || self.cfg.describe {
+            None
+        } else {
+            Some(self.write_ignore_prefs()?)
+        };
+        let path = prefs.as_ref().map_or_else(
+            || env::temp_dir().join("pacaptr-ignore-XXXXXX.pref"),
+            |prefs| prefs.path().to_owned(),
+        );
+        let pref_flag = format!("Dir::Etc::Preferences={}", path.display());
+        // The file is removed once `prefs` is dropped, even if the upgrade fails.
+        self.upgrade(
+            &["-o", &pref_flag]
+                .iter()
+                .chain(flags)
+                .copied()
+                .collect_vec(),
+        )
+        .await
     }
 
     /// Suy refreshes the local package database, then updates outdated
     /// packages.
     async fn suy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.sy(kws, flags).await?;
+        self.sy(&[], flags).await?;
         self.su(kws, flags).await
     }
 
     /// Sw retrieves all packages from the server, but does not install/upgrade
     /// anything.
     async fn sw(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let dir_flags = match self.cfg.ensure_download_dir()? {
+            Some(dir) => {
+                // `apt` refuses to download anything without the `partial` subdirectory.
+                if !self.cfg.dry_run {
+                    fs::create_dir_all(dir.join("partial"))?;
+                }
+                vec![
+                    "-o".into(),
+                    format!("Dir::Cache::archives={}", dir.display()),
+                ]
+            }
+            None => vec![],
+        };
         Cmd::with_sudo(&["apt", "install", "--download-only"])
             .kws(kws)
-            .flags(flags)
+            .flags(
+                &dir_flags
+                    .into_iter()
+                    .chain(flags.iter().map(|&f| f.into()))
+                    .collect_vec(),
+            )
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::with_sudo(&["apt", "update"]).kws(kws).flags(flags))
-            .await?;
+        network_bound(self.run(Cmd::with_sudo(&["apt", "update"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{exec::Mode, pm::prompted};
+
+    #[test]
+    fn autoremove_orphans() {
+        let out = indoc! {"
+            NOTE: This is only a simulation!
+                  apt-get needs root privileges for real execution.
+            Reading package lists... Done
+            The following packages will be REMOVED:
+              libfoo1 libbar2
+            0 upgraded, 0 newly installed, 2 to remove and 0 not upgraded.
+            Remv libfoo1 [1.0-1]
+            Remv libbar2 [2.3-4ubuntu1]
+        "};
+        assert_eq!(parse_autoremove(out), ["libfoo1", "libbar2"]);
+        assert!(parse_autoremove("0 upgraded, 0 newly installed, 0 to remove").is_empty());
+    }
+
+    #[test]
+    fn assume_no() {
+        let cfg = Config {
+            assume_no: true,
+            // `--assume-no` takes precedence over `no_confirm` in the dotfile.
+            no_confirm: true,
+            ..Config::default()
+        };
+        let cmd = Cmd::with_sudo(&["apt", "install"]).kws(&["curl"]);
+        let (cmd, mode) = prompted(&cfg, cmd, PmMode::default(), &STRAT_INSTALL).unwrap();
+        assert_eq!(cmd.flags, ["--assume-no"]);
+        assert!(matches!(mode, Mode::CheckErr));
+
+        // Without a native flag, the command is canceled instead.
+        let cmd = Cmd::with_sudo(&["apt", "upgrade"]);
+        let (cmd, mode) = prompted(&cfg, cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM).unwrap();
+        assert!(cmd.flags.is_empty());
+        assert!(matches!(mode, Mode::PrintCmd));
+
+        let cfg = Config {
+            no_confirm: true,
+            ..Config::default()
+        };
+        let cmd = Cmd::with_sudo(&["apt", "install"]).kws(&["curl"]);
+        let (cmd, _) = prompted(&cfg, cmd, PmMode::default(), &STRAT_INSTALL).unwrap();
+        assert_eq!(cmd.flags, ["--yes"]);
+
+        // `--autoremove` composes with `--yes`.
+        let cmd = Cmd::with_sudo(&["apt", "remove"])
+            .kws(&["curl"])
+            .flags(&Apt::new(Config::default()).autoremove_flags().unwrap());
+        let (cmd, _) = prompted(&cfg, cmd, PmMode::default(), &STRAT_PROMPT).unwrap();
+        assert_eq!(cmd.flags, ["--auto-remove", "--yes"]);
+    }
+
+    #[test]
+    fn assume_yes() {
+        let cfg = Config {
+            assume_yes: true,
+            no_confirm: true,
+            ..Config::default()
+        };
+        let cmd = Cmd::with_sudo(&["apt", "install"]).kws(&["curl"]);
+        let (cmd, mode) = prompted(&cfg, cmd, PmMode::default(), &STRAT_INSTALL).unwrap();
+        assert_eq!(cmd.flags, ["--assume-yes"]);
+        assert!(matches!(mode, Mode::CheckErr));
+
+        // Without a native flag, the prompt is skipped as under `--no-confirm`.
+        let cmd = Cmd::with_sudo(&["apt", "upgrade"]);
+        let (cmd, mode) = prompted(&cfg, cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM).unwrap();
+        assert!(cmd.flags.is_empty());
+        assert!(matches!(mode, Mode::CheckErr));
+    }
+
+    #[test]
+    fn search_names() {
+        let out = indoc! {"
+            WARNING: apt does not have a stable CLI interface. Use with caution in scripts.
+
+            Sorting... Done
+            Full Text Search... Done
+            curl/jammy-updates,jammy-security 7.81.0-1ubuntu1.4 amd64
+              command line tool for transferring data with URL syntax
+
+            libcurl4/jammy-updates,jammy-security,now 7.81.0-1ubuntu1.4 amd64 [installed]
+              easy-to-use client-side URL transfer library (OpenSSL flavour)
+        "};
+        let pm = Apt::new(Config::default());
+        assert_eq!(pm.parse_search(out).unwrap(), ["curl", "libcurl4"]);
+    }
+}
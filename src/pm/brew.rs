@@ -1,15 +1,20 @@
 #![doc = docs_self!()]
 
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
 use indoc::indoc;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{DryRunStrategy, NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{
+    classify_output, network_bound, DryRunStrategy, NoCacheStrategy, Op, Pm, PmHelper, PmMode,
+    PromptStrategy, Strategy,
+};
 use crate::{
     dispatch::Config,
-    error::Result,
-    exec::{self, Cmd},
+    error::{Error, FailureKind, Result},
+    exec::{Cmd, StatusCode},
     print::{self, PROMPT_RUN},
 };
 
@@ -25,6 +30,31 @@ macro_rules! docs_self {
 #[derive(Debug)]
 pub(crate) struct Brew {
     cfg: Config,
+
+    /// The installed formulae and casks, listed once per run when needed.
+    installed: Mutex<Option<Arc<Installed>>>,
+}
+
+/// The names of the installed formulae and casks, as listed by `brew list`.
+#[derive(Debug, Default)]
+struct Installed {
+    formulae: Vec<String>,
+    casks: Vec<String>,
+}
+
+impl Installed {
+    /// Splits `kws` into the formulae and the casks, where a name (maybe
+    /// qualified with a tap) is taken as a cask if it is only found among the
+    /// installed casks.
+    #[must_use]
+    fn split<'k>(&self, kws: &[&'k str]) -> (Vec<&'k str>, Vec<&'k str>) {
+        let (casks, formulae) = kws.iter().partition(|&&kw| {
+            let name = kw.rsplit('/').next().unwrap_or(kw);
+            self.casks.iter().any(|cask| cask == name)
+                && !self.formulae.iter().any(|formula| formula == name)
+        });
+        (formulae, casks)
+    }
 }
 
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
@@ -32,31 +62,180 @@ static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+/// The listings which are run even in a dry run, so that the names are routed
+/// to the right namespace.
+static STRAT_LIST: Lazy<Strategy> = Lazy::new(|| Strategy {
+    dry_run: DryRunStrategy::WithFlags(vec![]),
+    ..Strategy::default()
+});
+
 static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::CustomPrompt,
     no_cache: NoCacheStrategy::Scc,
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("No available formula", FailureKind::NotFound),
+    ("No formulae or casks found", FailureKind::NotFound),
+    ("Failed to download resource", FailureKind::NetworkError),
+    ("Could not resolve host", FailureKind::NetworkError),
+];
+
 impl Brew {
+    /// Makes a new [`Cmd`] instance with the given [`cmd`](Cmd::cmd) part,
+    /// appending `--cask` if casks are targeted.
+    fn cmd(&self, cmd: &[&str]) -> Cmd {
+        Cmd::new(cmd).tap_mut(|cmd| {
+            if self.cfg.cask {
+                cmd.cmd.push("--cask".into());
+            }
+        })
+    }
+
+    /// Qualifies the names in `kws` with the tap set by `--repo`, if any, eg.
+    /// `curl` as `homebrew/core/curl`.
+    #[must_use]
+    fn tapped(&self, kws: &[&str]) -> Vec<String> {
+        match &self.cfg.repos[..] {
+            [tap] => kws
+                .iter()
+                .map(|&kw| {
+                    if kw.contains('/') {
+                        kw.into()
+                    } else {
+                        format!("{}/{}", tap, kw)
+                    }
+                })
+                .collect(),
+            _ => kws.iter().map(|&kw| kw.into()).collect(),
+        }
+    }
+
+    /// Makes a new `brew bundle` command followed by `subcmd`, eg. `dump`, on
+    /// the `Brewfile` given as the only keyword, if any.
+    fn bundle_cmd(subcmd: &[&str], kws: &[&str]) -> Cmd {
+        let file = kws.first().map(|file| format!("--file={}", file));
+        ["brew", "bundle"]
+            .iter()
+            .chain(subcmd)
+            .copied()
+            .chain(file.as_deref())
+            .collect::<Vec<_>>()
+            .pipe(|cmd| Cmd::new(&cmd))
+    }
+
+    /// Lists the installed formulae and casks with `brew list`, which are
+    /// cached for the rest of the run.
+    ///
+    /// The casks are only listed on macOS, where they are supported.
+    async fn installed(&self) -> Result<Arc<Installed>> {
+        let cached = self.installed.lock().unwrap().clone();
+        if let Some(installed) = cached {
+            return Ok(installed);
+        }
+        let list = |kind: &'static str| async move {
+            let cmd = Cmd::new(&["brew", "list", "-1", kind]);
+            let out = self.check_output(cmd, PmMode::Mute, &STRAT_LIST).await?;
+            let names = self
+                .decode(out)?
+                .lines()
+                .map(str::trim)
+                .filter(|ln| !ln.is_empty())
+                .map(Into::into)
+                .collect();
+            Ok::<_, Error>(names)
+        };
+        let installed = Arc::new(Installed {
+            formulae: list("--formula").await?,
+            casks: if cfg!(target_os = "macos") {
+                list("--cask").await?
+            } else {
+                vec![]
+            },
+        });
+        *self.installed.lock().unwrap() = Some(Arc::clone(&installed));
+        Ok(installed)
+    }
+
+    /// Runs `cmd` on the formulae among `kws`, then with `--cask` on the casks,
+    /// as told by the installed ones, avoiding the `No such keg` errors.
+    ///
+    /// All the names are taken as casks under `--cask`.
+    async fn run_routed(
+        &self,
+        cmd: &[&str],
+        kws: &[&str],
+        flags: &[&str],
+        strat: &Strategy,
+    ) -> Result<()> {
+        let (formulae, casks) = if self.cfg.cask || kws.is_empty() {
+            (kws.to_vec(), vec![])
+        } else {
+            self.installed().await?.split(kws)
+        };
+        if !formulae.is_empty() || casks.is_empty() {
+            let cmd = self.cmd(cmd).kws(&formulae).flags(flags);
+            self.run_with(cmd, PmMode::default(), strat).await?;
+        }
+        if !casks.is_empty() {
+            let cmd = Cmd::new(cmd)
+                .tap_mut(|cmd| cmd.cmd.push("--cask".into()))
+                .kws(&casks)
+                .flags(flags);
+            self.run_with(cmd, PmMode::default(), strat).await?;
+        }
+        Ok(())
+    }
+
     async fn search_regex(&self, cmd: &[&str], kws: &[&str], flags: &[&str]) -> Result<()> {
         let cmd = Cmd::new(cmd).flags(flags);
-        if !self.cfg.dry_run {
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        exec::grep_print(&String::from_utf8(out_bytes)?, kws)?;
+        self.grep_print(&self.decode(out_bytes)?, kws)?;
         Ok(())
     }
 }
 
+/// Gets the packages installed from `tap` among the output of `brew list -1
+/// --full-name`, where the names are qualified with their taps.
+#[must_use]
+fn from_tap(text: &str, tap: &str) -> Vec<String> {
+    let prefix = format!("{}/", tap.trim_end_matches('/'));
+    text.lines()
+        .map(str::trim)
+        .filter(|ln| ln.starts_with(&prefix))
+        .map(Into::into)
+        .collect()
+}
+
+/// Parses the formulae to be removed from the output of `brew autoremove
+/// --dry-run`, which are listed one per line after a `==> Would autoremove`
+/// header.
+#[must_use]
+fn parse_autoremove(text: &str) -> Vec<&str> {
+    text.lines()
+        .skip_while(|ln| !ln.starts_with("==> Would autoremove"))
+        .skip(1)
+        .map(str::trim)
+        .take_while(|ln| !ln.starts_with("==>"))
+        .filter(|ln| !ln.is_empty())
+        .collect()
+}
+
 impl Brew {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
-        Brew { cfg }
+        Brew {
+            cfg,
+            installed: Mutex::default(),
+        }
     }
 }
 
@@ -71,10 +250,120 @@ impl Pm for Brew {
         &self.cfg
     }
 
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qc,
+            Op::Qdt,
+            Op::Qi,
+            Op::Ql,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Si,
+            Op::Sii,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sw,
+            Op::Sy,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Rn, Op::Rns, Op::Sg]
+    }
+
+    /// Homebrew bails out when run as `root`, after some time spent on its
+    /// own setup.
+    fn allows_root(&self) -> bool {
+        false
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Qualifies the formulae and casks with the given tap, of which there can
+    /// only be one, instead of adding any flag.
+    fn repo_flags(&self, repos: &[String]) -> Option<Vec<String>> {
+        (repos.len() == 1).then(Vec::new)
+    }
+
+    /// Takes the formulae and casks listed by `brew search` under the `==>
+    /// Formulae` and `==> Casks` headers, dropping the `✔` marking the
+    /// installed ones.
+    fn parse_search(&self, text: &str) -> Option<Vec<String>> {
+        text.lines()
+            .filter(|ln| !ln.starts_with("==>"))
+            .flat_map(str::split_whitespace)
+            .filter(|&word| word != "✔")
+            .map(Into::into)
+            .collect::<Vec<_>>()
+            .pipe(Some)
+    }
+
+    /// Removes the orphans, as listed by `qdt`.
+    async fn remove_orphans(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::new(&["brew", "autoremove"])
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
+    /// Tap adds one or more third-party repositories, eg. `user/repo`, whose
+    /// formulae can then be installed as `user/repo/formula`.
+    async fn tap(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::new(&["brew", "tap"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| network_bound(self.run(cmd)))
+            .await
+    }
+
+    /// Untap removes one or more third-party repositories previously added.
+    async fn untap(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::new(&["brew", "untap"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
+    /// Taps lists the third-party repositories that are currently added.
+    async fn taps(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        if kws.is_empty() {
+            self.run(Cmd::new(&["brew", "tap"]).flags(flags)).await
+        } else {
+            self.search_regex(&["brew", "tap"], kws, flags).await
+        }
+    }
+
+    /// Bundle installs the packages listed in a `Brewfile`, given as the only
+    /// keyword (if any).
+    async fn bundle(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Self::bundle_cmd(&[], kws)
+            .flags(flags)
+            .pipe(|cmd| network_bound(self.run(cmd)))
+            .await
+    }
+
+    /// Bundle dump prints the packages installed in the format of a
+    /// `Brewfile`.
+    async fn bundle_dump(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        Self::bundle_cmd(&["dump"], &["-"])
+            .flags(flags)
+            .pipe(|cmd| self.run(cmd))
+            .await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
-            self.run(Cmd::new(&["brew", "list"]).flags(flags)).await
+            self.run(self.cmd(&["brew", "list"]).flags(flags)).await
         } else {
             self.qs(kws, flags).await
         }
@@ -86,9 +375,24 @@ impl Pm for Brew {
             .await
     }
 
+    /// Qdt lists orphans, ie. packages installed as dependencies which are no
+    /// longer required by any other package.
+    async fn qdt(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = Cmd::new(&["brew", "autoremove", "--dry-run"]).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out_bytes = self
+            .check_output(cmd, PmMode::Mute, &Strategy::default())
+            .await?;
+        let orphans = parse_autoremove(&self.decode(out_bytes)?).join("\n");
+        self.grep_print(&orphans, kws)
+    }
+
     /// Qi displays local package information: name, version, description, etc.
     async fn qi(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.si(kws, flags).await
+        self.run_routed(&["brew", "info"], kws, flags, &Strategy::default())
+            .await
     }
 
     /// Ql displays files provided by local package.
@@ -96,7 +400,7 @@ impl Pm for Brew {
         // TODO: it seems that the output of `brew list python` in fish has a mechanism
         // against duplication: /usr/local/Cellar/python/3.6.0/Frameworks/
         // Python.framework/ (1234 files)
-        self.run(Cmd::new(&["brew", "list"]).kws(kws).flags(flags))
+        self.run_routed(&["brew", "list"], kws, flags, &Strategy::default())
             .await
     }
 
@@ -106,9 +410,11 @@ impl Pm for Brew {
     // matching ALL of those terms are returned.
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         // ! `brew list` lists all formulae and casks only when using tty.
-        self.search_regex(&["brew", "list", "--formula"], kws, flags)
-            .await?;
-        if cfg!(target_os = "macos") {
+        if !self.cfg.cask {
+            self.search_regex(&["brew", "list", "--formula"], kws, flags)
+                .await?;
+        }
+        if self.cfg.cask || cfg!(target_os = "macos") {
             self.search_regex(&["brew", "list", "--cask"], kws, flags)
                 .await?;
         }
@@ -124,16 +430,21 @@ impl Pm for Brew {
 
     /// R removes a single package, leaving all of its dependencies installed.
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["brew", "uninstall"])
-            .kws(kws)
-            .flags(flags)
-            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
-            .await
+        let res = self
+            .run_routed(&["brew", "uninstall"], kws, flags, &STRAT_PROMPT)
+            .await;
+        // The packages removed should no longer be listed.
+        self.installed.lock().unwrap().take();
+        res
     }
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(if self.cfg.needed {
+        // If no formula matches the name given, `brew install` falls back to the
+        // cask with the same name, so cask-only names are handled even without
+        // `--cask`.
+        self.cmd(if self.cfg.needed || self.cfg.cask {
+            // `brew reinstall --cask` fails for casks that are not installed yet.
             &["brew", "install"]
         } else {
             // If the package is not installed, `brew reinstall` behaves just like `brew
@@ -141,7 +452,7 @@ impl Pm for Brew {
             // `pacman -S`.
             &["brew", "reinstall"]
         })
-        .kws(kws)
+        .kws(&self.tapped(kws))
         .flags(flags)
         .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
         .await
@@ -192,14 +503,39 @@ impl Pm for Brew {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["brew", "search"]).kws(kws).flags(flags))
-            .await
+        self.run(
+            self.cmd(&["brew", "search"])
+                .kws(&self.tapped(kws))
+                .flags(flags),
+        )
+        .await
     }
 
     /// Su updates outdated packages.
+    ///
+    /// With a tap set by `--repo`, only the packages installed from it are
+    /// updated.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["brew", "upgrade"])
-            .kws(kws)
+        let kws = match &self.cfg.repos[..] {
+            [tap] if kws.is_empty() => {
+                let cmd = Cmd::new(&["brew", "list", "-1", "--full-name"]);
+                let out = self.check_output(cmd, PmMode::Mute, &STRAT_LIST).await?;
+                let pkgs = from_tap(&self.decode(out)?, tap);
+                if pkgs.is_empty() {
+                    if !self.cfg.describe {
+                        print::print_msg(
+                            &format!("There are no packages installed from `{}`", tap),
+                            print::PROMPT_INFO,
+                        );
+                    }
+                    return Ok(());
+                }
+                pkgs
+            }
+            _ => self.tapped(kws),
+        };
+        self.cmd(&["brew", "upgrade"])
+            .kws(&kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
@@ -224,10 +560,106 @@ impl Pm for Brew {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["brew", "update"]).flags(flags)).await?;
+        network_bound(self.run(Cmd::new(&["brew", "update"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::print::collect_lines;
+
+    #[test]
+    fn tap_packages() {
+        let out = indoc! {"
+            curl
+            homebrew/cask-fonts/font-fira-code
+            user/repo/foo
+            user/repo-extra/bar
+        "};
+        assert_eq!(from_tap(out, "user/repo"), ["user/repo/foo"]);
+        assert_eq!(
+            from_tap(out, "homebrew/cask-fonts/"),
+            ["homebrew/cask-fonts/font-fira-code"]
+        );
+        assert!(from_tap(out, "user/none").is_empty());
+    }
+
+    #[test]
+    fn autoremove_orphans() {
+        let out = indoc! {"
+            ==> Would autoremove 2 unneeded formulae:
+            libfoo
+            libbar
+        "};
+        assert_eq!(parse_autoremove(out), ["libfoo", "libbar"]);
+        assert!(parse_autoremove("").is_empty());
+    }
+
+    #[test]
+    fn bundle_cmds() {
+        assert_eq!(Brew::bundle_cmd(&[], &[]).to_string(), "brew bundle");
+        assert_eq!(
+            Brew::bundle_cmd(&[], &["dotfiles/Brewfile"]).to_string(),
+            "brew bundle --file=dotfiles/Brewfile"
+        );
+        assert_eq!(
+            Brew::bundle_cmd(&["dump"], &["-"]).to_string(),
+            "brew bundle dump --file=-"
+        );
+    }
+
+    #[test]
+    fn search_names() {
+        let out = indoc! {"
+            ==> Formulae
+            curl ✔                     curlie                     curlish
+
+            ==> Casks
+            curl-gui
+        "};
+        let pm = Brew::new(Config::default());
+        assert_eq!(
+            pm.parse_search(out).unwrap(),
+            ["curl", "curlie", "curlish", "curl-gui"]
+        );
+    }
+
+    #[test]
+    fn split_casks() {
+        let installed = Installed {
+            formulae: vec!["curl".into(), "docker".into()],
+            casks: vec!["docker".into(), "firefox".into()],
+        };
+        assert_eq!(
+            installed.split(&["curl", "firefox", "docker", "homebrew/cask/firefox", "wget"]),
+            (
+                vec!["curl", "docker", "wget"],
+                vec!["firefox", "homebrew/cask/firefox"]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn r_routes_casks() {
+        let pm = Brew::new(Config {
+            describe: true,
+            ..Config::default()
+        });
+        *pm.installed.lock().unwrap() = Some(Arc::new(Installed {
+            formulae: vec!["curl".into()],
+            casks: vec!["firefox".into()],
+        }));
+        let (_, lines) = collect_lines(async {
+            pm.r(&["firefox"], &[]).await.unwrap();
+        })
+        .await;
+        assert_eq!(lines, ["   Native `brew uninstall --cask firefox`"]);
+        // The listings are dropped after the removal.
+        assert!(pm.installed.lock().unwrap().is_none());
+    }
+}
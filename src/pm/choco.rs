@@ -5,9 +5,16 @@ use indoc::indoc;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{DryRunStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
-use crate::exec::Cmd;
-use crate::{dispatch::Config, error::Result};
+use super::{
+    classify_output, CaptureStrategy, DryRunStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy,
+    Strategy,
+};
+use crate::{
+    dispatch::Config,
+    error::{FailureKind, Result},
+    exec::{Cmd, StatusCode},
+    print::{self, PROMPT_RUN},
+};
 
 macro_rules! docs_self {
     () => {
@@ -23,17 +30,34 @@ pub(crate) struct Choco {
     cfg: Config,
 }
 
+// The output is limited to the essential information, without the progress,
+// whenever it is collected instead of being printed.
+
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["--yes"]),
     dry_run: DryRunStrategy::with_flags(&["--what-if"]),
+    capture: CaptureStrategy::with_flags(&["--limit-output", "--no-progress"]),
     ..Strategy::default()
 });
 
 static STRAT_CHECK_DRY: Lazy<Strategy> = Lazy::new(|| Strategy {
     dry_run: DryRunStrategy::with_flags(&["--what-if"]),
+    capture: CaptureStrategy::with_flags(&["--limit-output", "--no-progress"]),
+    ..Strategy::default()
+});
+
+static STRAT_CAPTURE: Lazy<Strategy> = Lazy::new(|| Strategy {
+    capture: CaptureStrategy::with_flags(&["--limit-output", "--no-progress"]),
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("not found with the source", FailureKind::NotFound),
+    ("The package was not found", FailureKind::NotFound),
+    ("Unable to connect to source", FailureKind::NetworkError),
+];
+
 impl Choco {
     #[must_use]
     #[allow(missing_docs)]
@@ -59,6 +83,35 @@ impl Pm for Choco {
         &self.cfg
     }
 
+    /// Installs the packages machine-wide, which needs an elevated shell.
+    fn requires_elevation(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qi,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rss,
+            Op::S,
+            Op::Si,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Sg, Op::Sy]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::new(&["choco", "list", "--localonly"])
@@ -73,6 +126,22 @@ impl Pm for Choco {
         self.si(kws, flags).await
     }
 
+    /// Qs searches locally installed package for names or descriptions.
+    // According to https://www.archlinux.org/pacman/pacman.8.html#_query_options_apply_to_em_q_em_a_id_qo_a,
+    // when including multiple search terms, only packages with descriptions
+    // matching ALL of those terms are returned.
+    async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = Cmd::new(&["choco", "list", "--localonly"]).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out = self
+            .check_output(cmd, PmMode::Mute, &STRAT_CAPTURE)
+            .await?
+            .pipe(|out| self.decode(out))?;
+        self.grep_print(&out, kws)
+    }
+
     /// Qu lists packages which have an update available.
     async fn qu(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.check_dry(Cmd::new(&["choco", "outdated"]).kws(kws).flags(flags))
@@ -125,7 +194,16 @@ impl Pm for Choco {
     }
 
     /// Su updates outdated packages.
+    ///
+    /// When updating all of them, `choco` itself is updated first, unless
+    /// `no_self_upgrade` is set.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        if kws.is_empty() && !self.cfg.no_self_upgrade {
+            Cmd::new(&["choco", "upgrade", "chocolatey"])
+                .flags(flags)
+                .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+                .await?;
+        }
         Cmd::new(if kws.is_empty() {
             &["choco", "upgrade", "all"]
         } else {
@@ -1,16 +1,16 @@
 #![doc = docs_self!()]
 
 use async_trait::async_trait;
-use futures::prelude::*;
 use indoc::indoc;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{classify_output, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
 use crate::{
     dispatch::Config,
-    error::Result,
-    exec::{self, Cmd},
+    error::{FailureKind, Result},
+    exec::{Cmd, StatusCode},
     print::{self, PROMPT_RUN},
 };
 
@@ -26,18 +26,43 @@ macro_rules! docs_self {
 #[derive(Debug)]
 pub(crate) struct Conda {
     cfg: Config,
+
+    /// The executable used for installation, removal, update and search, eg.
+    /// `mamba`.
+    tool: String,
 }
 
+/// The names under which [`Conda`] can be selected that also name the
+/// executable to be used, eg. `--using mamba`.
+const TOOL_ALIASES: &[&str] = &["mamba", "micromamba"];
+
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["-y"]),
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("PackagesNotFoundError", FailureKind::NotFound),
+    ("CondaHTTPError", FailureKind::NetworkError),
+    ("CondaSystemExit: Exiting", FailureKind::Aborted),
+];
+
 impl Conda {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
-        Conda { cfg }
+        // An explicit `conda_tool` takes precedence over the name used in `--using`.
+        let tool = cfg
+            .conda_tool
+            .clone()
+            .or_else(|| {
+                cfg.default_pm
+                    .clone()
+                    .filter(|pm| TOOL_ALIASES.contains(&pm.as_str()))
+            })
+            .unwrap_or_else(|| "conda".into());
+        Conda { cfg, tool }
     }
 }
 
@@ -48,10 +73,73 @@ impl Pm for Conda {
         "conda"
     }
 
+    fn executable(&self) -> &str {
+        &self.tool
+    }
+
     fn cfg(&self) -> &Config {
         &self.cfg
     }
 
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qo,
+            Op::Qs,
+            Op::R,
+            Op::S,
+            Op::Sc,
+            Op::Si,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Rn, Op::Rns, Op::Sg, Op::Sy]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Searches the given channels first.
+    fn index_url_flags(&self, _op: Op, urls: &[String]) -> Option<Vec<String>> {
+        Some(
+            urls.iter()
+                .flat_map(|url| ["-c".to_owned(), url.clone()])
+                .collect(),
+        )
+    }
+
+    /// Installs and searches the packages in the local cache only.
+    fn offline_flags(&self, exe: &str, op: Op) -> Option<Vec<String>> {
+        (exe == self.executable() && matches!(op, Op::S | Op::Si | Op::Ss))
+            .then(|| vec!["--offline".into()])
+    }
+
+    /// Takes the `conda` dependencies of the environment file printed by
+    /// `conda env export`, eg. `numpy=1.21.2=py39h20f2e39_0`, skipping those
+    /// installed by `pip`, which `conda` can't install.
+    fn format_export(&self, text: &str) -> Vec<String> {
+        text.lines()
+            .skip_while(|ln| ln.trim_end() != "dependencies:")
+            .skip(1)
+            .take_while(|ln| ln.starts_with(' '))
+            .filter_map(|ln| ln.strip_prefix("  - "))
+            .map(str::trim)
+            .filter(|spec| !spec.ends_with(':'))
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Export prints the current environment as an environment file.
+    async fn export(&self, flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&["conda", "env", "export"]).flags(flags))
+            .await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
@@ -76,19 +164,19 @@ impl Pm for Conda {
     // matching ALL of those terms are returned.
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         let cmd = Cmd::new(&["conda", "list"]).flags(flags);
-        if !self.cfg.dry_run {
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        exec::grep_print(&String::from_utf8(out_bytes)?, kws)?;
+        self.grep_print(&self.decode(out_bytes)?, kws)?;
         Ok(())
     }
 
     /// R removes a single package, leaving all of its dependencies installed.
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["conda", "remove"])
+        Cmd::new(&[&self.tool, "remove"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
@@ -97,9 +185,9 @@ impl Pm for Conda {
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["conda", "install"])
+        Cmd::new(&[&self.tool, "install"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_index_urls(Op::S, flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
     }
@@ -115,9 +203,9 @@ impl Pm for Conda {
 
     /// Si displays remote package information: name, version, description, etc.
     async fn si(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["conda", "search", "--info"])
+        Cmd::new(&[&self.tool, "search", "--info"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_index_urls(Op::Si, flags))
             .pipe(|cmd| self.run(cmd))
             .await
     }
@@ -125,15 +213,21 @@ impl Pm for Conda {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        stream::iter(kws)
-            .map(|&s| Ok(format!("*{}*", s)))
-            .try_for_each(|kw| self.run(Cmd::new(&["conda", "search"]).kws(&[kw]).flags(flags)))
+        kws.iter()
+            .map(|kw| {
+                let cmd = Cmd::new(&[&self.tool, "search"])
+                    .kws(&[format!("*{}*", kw)])
+                    .flags(&self.with_index_urls(Op::Ss, flags));
+                (format!("searching `{}`", kw), cmd)
+            })
+            .collect_vec()
+            .pipe(|cmds| self.run_each(cmds))
             .await
     }
 
     /// Su updates outdated packages.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["conda", "update", "--all"])
+        Cmd::new(&[&self.tool, "update", "--all"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
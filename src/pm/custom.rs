@@ -0,0 +1,486 @@
+#![doc = docs_self!()]
+
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use indoc::indoc;
+use tt_call::tt_call;
+
+use super::{
+    is_locking_op, network_bound, unimplemented, DryRunStrategy, Op, Pm, PmHelper, PmMode,
+    PromptStrategy, Strategy,
+};
+use crate::{
+    dispatch::{Config, CustomPmConfig},
+    error::{Error, Result},
+    exec::Cmd,
+    methods,
+};
+
+macro_rules! docs_self {
+    () => {
+        indoc! {r#"
+            A package manager defined in the `[pm.<name>]` section of the config,
+            with a command template per operation, eg.
+            `s = "mytool add {kws} {flags}"`.
+        "#}
+    };
+}
+
+/// A word of a [`Template`].
+#[derive(Clone, Debug, PartialEq)]
+enum Word {
+    /// A literal argument.
+    Lit(String),
+
+    /// `{kws}`, standing for the keywords, one argument each.
+    Kws,
+
+    /// `{flags}`, standing for the flags, one argument each.
+    Flags,
+}
+
+/// A command template, eg. `mytool add {kws} {flags}`.
+///
+/// The template is split into words as a POSIX shell would do, that is, on
+/// whitespaces outside of the quotes. The placeholders are recognized as
+/// whole unquoted words only, and are substituted with one argument per
+/// keyword or flag, so that the latter never need to be quoted.
+#[derive(Clone, Debug, PartialEq)]
+struct Template(Vec<Word>);
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err = |msg: &str| Error::ConfigError {
+            msg: format!("{} in template `{}`", msg, s),
+        };
+        let mut words = vec![];
+        // The current word, along with whether any part of it is quoted.
+        let mut curr: Option<(String, bool)> = None;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                words.extend(curr.take().map(Word::from));
+                continue;
+            }
+            let (word, quoted) = curr.get_or_insert_with(Default::default);
+            match c {
+                '\'' => {
+                    *quoted = true;
+                    loop {
+                        match chars.next().ok_or_else(|| err("Unterminated quote"))? {
+                            '\'' => break,
+                            c => word.push(c),
+                        }
+                    }
+                }
+                '"' => {
+                    *quoted = true;
+                    loop {
+                        match chars.next().ok_or_else(|| err("Unterminated quote"))? {
+                            '"' => break,
+                            '\\' => match chars.next().ok_or_else(|| err("Unterminated quote"))? {
+                                c @ ('"' | '\\') => word.push(c),
+                                c => word.extend(['\\', c]),
+                            },
+                            c => word.push(c),
+                        }
+                    }
+                }
+                '\\' => {
+                    *quoted = true;
+                    word.push(chars.next().ok_or_else(|| err("Trailing backslash"))?);
+                }
+                c => word.push(c),
+            }
+        }
+        words.extend(curr.map(Word::from));
+
+        if words.iter().filter(|&word| word == &Word::Flags).count() > 1 {
+            return Err(err("More than one `{flags}`"));
+        }
+        match words.first() {
+            Some(Word::Lit(_)) => Ok(Template(words)),
+            _ => Err(err("No command")),
+        }
+    }
+}
+
+impl From<(String, bool)> for Word {
+    fn from((word, quoted): (String, bool)) -> Self {
+        match word.as_str() {
+            "{kws}" if !quoted => Word::Kws,
+            "{flags}" if !quoted => Word::Flags,
+            _ => Word::Lit(word),
+        }
+    }
+}
+
+impl Template {
+    /// Renders the template as a [`Cmd`] with the keywords `kws` and the flags
+    /// `flags`.
+    ///
+    /// The words before `{flags}` become the [`cmd`](field@Cmd::cmd) part and
+    /// those after it the [`kws`](field@Cmd::kws) part, so that the flags
+    /// added by `pacaptr` itself, eg. `--yes`, go in place of `{flags}` as
+    /// well. Without `{flags}` (resp. `{kws}`), the flags (resp. keywords) are
+    /// put at the end. A leading `sudo` is handled as in [`Cmd::sudo`].
+    fn render(&self, kws: &[&str], flags: &[&str]) -> Cmd {
+        let words = if self.is_sudo() {
+            &self.0[1..]
+        } else {
+            &self.0[..]
+        };
+        let expand = |words: &[Word]| -> Vec<String> {
+            words
+                .iter()
+                .flat_map(|word| match word {
+                    Word::Lit(lit) => vec![lit.clone()],
+                    Word::Kws => kws.iter().map(|&kw| kw.into()).collect(),
+                    Word::Flags => vec![],
+                })
+                .collect()
+        };
+        let (head, tail) = words
+            .iter()
+            .position(|word| word == &Word::Flags)
+            .map_or((words, &[][..]), |i| (&words[..i], &words[i + 1..]));
+        let mut tail = expand(tail);
+        if !self.0.contains(&Word::Kws) {
+            tail.extend(kws.iter().map(|&kw| kw.into()));
+        }
+        Cmd::new(&expand(head))
+            .flags(flags)
+            .kws(&tail)
+            .sudo(self.is_sudo())
+    }
+
+    /// Checks if the command starts with a `sudo` to be handled as in
+    /// [`Cmd::sudo`].
+    #[must_use]
+    fn is_sudo(&self) -> bool {
+        matches!(&self.0[..], [Word::Lit(first), _, ..] if first == "sudo")
+    }
+}
+
+/// Splits `flags` into words as in [`Template`], without any placeholder.
+///
+/// # Errors
+/// Returns an [`Error::ConfigError`] when `flags` is ill-formed.
+fn split_flags(flags: &str) -> Result<Vec<String>> {
+    // A dummy command is added so that the template is never empty.
+    let template: Template = format!(": {}", flags).parse()?;
+    Ok(template
+        .0
+        .into_iter()
+        .skip(1)
+        .map(|word| match word {
+            Word::Lit(lit) => lit,
+            Word::Kws => "{kws}".into(),
+            Word::Flags => "{flags}".into(),
+        })
+        .collect())
+}
+
+#[doc = docs_self!()]
+#[derive(Debug)]
+pub(crate) struct CustomPm {
+    name: String,
+    def: CustomPmConfig,
+    cfg: Config,
+
+    /// The operations with a template in `def`.
+    ops: Vec<Op>,
+}
+
+impl CustomPm {
+    #[must_use]
+    /// Creates a new [`CustomPm`] package manager named `name`, as defined by
+    /// `def`.
+    pub(crate) fn new(name: &str, def: CustomPmConfig, cfg: Config) -> Self {
+        let ops = Op::ALL
+            .iter()
+            .copied()
+            .filter(|&op| {
+                def.ops
+                    .keys()
+                    .any(|key| key.parse::<Op>().map_or(false, |k| k == op))
+            })
+            .collect();
+        CustomPm {
+            name: name.into(),
+            def,
+            cfg,
+            ops,
+        }
+    }
+
+    /// Finds the template of the operation indicated by `method` (eg. `suy`),
+    /// whose key might take any spelling of the operation accepted by [`Op`],
+    /// eg. `Syu`.
+    #[must_use]
+    fn template(&self, method: &str) -> Option<&str> {
+        self.def
+            .ops
+            .iter()
+            .find(|(key, _)| key.parse::<Op>().map_or(false, |op| op.method() == method))
+            .map(|(_, template)| template.as_str())
+    }
+
+    /// Makes the [`Strategy`] of the operation indicated by `method`
+    /// according to the `no_confirm` and `dry_run` flags of the definition.
+    ///
+    /// Without `no_confirm`, `pacaptr` asks for confirmation by itself before
+    /// any operation which might modify the package database.
+    ///
+    /// # Errors
+    /// Returns an [`Error::ConfigError`] when the flags are ill-formed.
+    fn strategy(&self, method: &str) -> Result<Strategy> {
+        let prompt = match &self.def.no_confirm {
+            _ if !method.parse::<Op>().map_or(true, is_locking_op) => PromptStrategy::None,
+            Some(flags) => PromptStrategy::native_no_confirm(&split_flags(flags)?),
+            None => PromptStrategy::CustomPrompt,
+        };
+        let dry_run = match &self.def.dry_run {
+            Some(flags) => DryRunStrategy::with_flags(&split_flags(flags)?),
+            None => DryRunStrategy::PrintCmd,
+        };
+        Ok(Strategy {
+            dry_run,
+            prompt,
+            ..Strategy::default()
+        })
+    }
+
+    /// Runs the operation indicated by `method` with its template.
+    ///
+    /// # Errors
+    /// Returns an [`Error::OperationUnimplementedError`] when the operation has
+    /// no template, or an [`Error::ConfigError`] when the template is
+    /// ill-formed.
+    async fn run_template(&self, method: &str, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let template = self
+            .template(method)
+            .ok_or_else(|| unimplemented(self, method))?;
+        let cmd = template.parse::<Template>()?.render(kws, flags);
+        let strat = self.strategy(method)?;
+        let run = self.run_with(cmd, PmMode::default(), &strat);
+        // Only the refresh is worth retrying, as in the built-in backends.
+        if method == "sy" {
+            network_bound(run).await
+        } else {
+            run.await
+        }
+    }
+}
+
+macro_rules! impl_pm_custom {(
+    methods = [{ $(
+        $( #[$meta:meta] )*
+        async fn $method:ident;
+    )* }]
+) => {
+    #[async_trait]
+    impl Pm for CustomPm {
+        /// Gets the name of the package manager.
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn cfg(&self) -> &Config {
+            &self.cfg
+        }
+
+        /// Runs the commands through `sudo` if any template starts with it.
+        fn uses_sudo(&self) -> bool {
+            self.def
+                .ops
+                .values()
+                .any(|template| template.parse::<Template>().map_or(false, |t| t.is_sudo()))
+        }
+
+        fn supported_ops(&self) -> &[Op] {
+            &self.ops
+        }
+
+        $(
+            $( #[$meta] )*
+            async fn $method(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+                self.run_template(stringify!($method), kws, flags).await
+            }
+        )*
+    }
+};}
+
+tt_call! {
+    macro = [{ methods }]
+    ~~> impl_pm_custom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(s: &str) -> Word {
+        Word::Lit(s.into())
+    }
+
+    #[test]
+    fn template_parse() {
+        let parse = |s: &str| s.parse::<Template>().unwrap().0;
+        assert_eq!(
+            parse("mytool  add {kws}\t{flags} "),
+            [lit("mytool"), lit("add"), Word::Kws, Word::Flags]
+        );
+        assert_eq!(
+            parse(r#"mytool 'a b' "c \"d\" \e" f\ g '' --x={kws}"#),
+            [
+                lit("mytool"),
+                lit("a b"),
+                lit(r#"c "d" \e"#),
+                lit("f g"),
+                lit(""),
+                lit("--x={kws}"),
+            ]
+        );
+        // The quoted placeholders are kept as is.
+        assert_eq!(
+            parse(r#"mytool '{kws}' "{flags}" \{kws}"#),
+            [lit("mytool"), lit("{kws}"), lit("{flags}"), lit("{kws}")]
+        );
+    }
+
+    #[test]
+    fn template_parse_errors() {
+        let err = |s: &str| match s.parse::<Template>().unwrap_err() {
+            Error::ConfigError { msg } => msg,
+            e => panic!("unexpected error: {:?}", e),
+        };
+        assert_eq!(
+            err("mytool 'add"),
+            "Unterminated quote in template `mytool 'add`"
+        );
+        assert_eq!(
+            err(r#"mytool "add"#),
+            r#"Unterminated quote in template `mytool "add`"#
+        );
+        assert_eq!(
+            err(r"mytool \"),
+            r"Trailing backslash in template `mytool \`"
+        );
+        assert_eq!(
+            err("mytool {flags} {flags}"),
+            "More than one `{flags}` in template `mytool {flags} {flags}`"
+        );
+        assert_eq!(err(" "), "No command in template ` `");
+        assert_eq!(err("{kws}"), "No command in template `{kws}`");
+    }
+
+    #[test]
+    fn template_render() {
+        let render = |s: &str, kws: &[&str], flags: &[&str]| {
+            let cmd = s.parse::<Template>().unwrap().render(kws, flags);
+            (cmd.sudo, cmd.cmd, cmd.flags, cmd.kws)
+        };
+        assert_eq!(
+            render("mytool add {kws} {flags}", &["a b", "c"], &["-x"]),
+            (
+                false,
+                vec!["mytool".into(), "add".into(), "a b".into(), "c".into()],
+                vec!["-x".into()],
+                vec![],
+            )
+        );
+        assert_eq!(
+            render("sudo mytool {flags} add {kws} --", &["a"], &["-x", "-y"]),
+            (
+                true,
+                vec!["mytool".into()],
+                vec!["-x".into(), "-y".into()],
+                vec!["add".into(), "a".into(), "--".into()],
+            )
+        );
+        // Without the placeholders, the flags and keywords are put at the end.
+        assert_eq!(
+            render("mytool ls", &["a"], &["-x"]),
+            (
+                false,
+                vec!["mytool".into(), "ls".into()],
+                vec!["-x".into()],
+                vec!["a".into()],
+            )
+        );
+        // No keyword at all.
+        assert_eq!(
+            render("mytool ls {kws}", &[], &[]),
+            (false, vec!["mytool".into(), "ls".into()], vec![], vec![])
+        );
+        let cmd = "mytool add {kws}"
+            .parse::<Template>()
+            .unwrap()
+            .render(&["it's"], &[]);
+        assert_eq!(cmd.argv(), ["mytool", "add", "it's"]);
+    }
+
+    #[test]
+    fn flags_split() {
+        assert_eq!(split_flags("-y  --foo='a b'").unwrap(), ["-y", "--foo=a b"]);
+        assert!(split_flags("").unwrap().is_empty());
+        assert!(split_flags("'-y").is_err());
+    }
+
+    fn custom_pm(ops: &[(&str, &str)], cfg: Config) -> CustomPm {
+        let def = CustomPmConfig {
+            no_confirm: Some("--yes".into()),
+            dry_run: Some("--dry".into()),
+            ops: ops
+                .iter()
+                .map(|&(op, template)| (op.into(), template.into()))
+                .collect(),
+        };
+        CustomPm::new("mytool", def, cfg)
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn custom_pm_run() {
+        let pm = custom_pm(
+            &[
+                ("q", r#"printf "[%s]" {kws} {flags}"#),
+                ("Syu", r#"printf "[%s]" {flags} --"#),
+            ],
+            Config {
+                no_confirm: true,
+                ..Config::default()
+            },
+        );
+        assert_eq!(pm.template("suy"), Some(r#"printf "[%s]" {flags} --"#));
+        assert_eq!(pm.supported_ops(), [Op::Q, Op::Suy]);
+        let out = pm.run_op(Op::Q, &["a b", "it's"], &["-x"]).await.unwrap();
+        assert_eq!(out, b"[a b][it's][-x]");
+        let out = pm.run_op(Op::Suy, &["a"], &[]).await.unwrap();
+        assert_eq!(out, b"[--yes][--][a]");
+
+        let err = pm.run_op(Op::R, &["a"], &[]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::OperationUnimplementedError { op, pm, .. } if op == "r" && pm == "mytool"
+        ));
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn custom_pm_dry_run() {
+        let pm = custom_pm(
+            &[("S", r#"printf "[%s]" {flags} {kws}"#)],
+            Config {
+                dry_run: true,
+                ..Config::default()
+            },
+        );
+        let out = pm.run_op(Op::S, &["a"], &[]).await.unwrap();
+        assert_eq!(out, b"[--dry][a]");
+    }
+}
@@ -3,14 +3,18 @@
 use async_trait::async_trait;
 use futures::prelude::*;
 use indoc::indoc;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{
+    classify_output, network_bound, AssumeNoStrategy, AssumeYesStrategy, NoCacheStrategy, Op,
+    Package, Pm, PmHelper, PmMode, PromptStrategy, Strategy,
+};
 use crate::{
-    dispatch::Config,
-    error::Result,
-    exec::{self, Cmd},
+    dispatch::{Config, Proxy},
+    error::{Error, FailureKind, Result},
+    exec::{Cmd, StatusCode},
     print::{self, PROMPT_RUN},
 };
 
@@ -28,8 +32,15 @@ pub(crate) struct Dnf {
     cfg: Config,
 }
 
+/// Answers "no" to the prompt after resolving the transaction, which also
+/// serves as the simulation.
+static ASSUME_NO: Lazy<AssumeNoStrategy> =
+    Lazy::new(|| AssumeNoStrategy::simulating(&["--assumeno"], 1, "Operation aborted"));
+
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["-y"]),
+    assume_no: ASSUME_NO.clone(),
+    assume_yes: AssumeYesStrategy::with_flags(&["--assumeyes"]),
     ..Strategy::default()
 });
 
@@ -40,16 +51,69 @@ static STRAT_PROMPT_CUSTOM: Lazy<Strategy> = Lazy::new(|| Strategy {
 
 static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["-y"]),
+    assume_no: ASSUME_NO.clone(),
+    assume_yes: AssumeYesStrategy::with_flags(&["--assumeyes"]),
     no_cache: NoCacheStrategy::Sccc,
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("No match for argument", FailureKind::NotFound),
+    ("Unable to find a match", FailureKind::NotFound),
+    ("Curl error", FailureKind::NetworkError),
+    ("Failed to download metadata", FailureKind::NetworkError),
+    ("Operation aborted", FailureKind::Aborted),
+];
+
 impl Dnf {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
         Dnf { cfg }
     }
+
+    /// Runs `dnf module <subcmd>` on the modules in `modules`.
+    async fn module(
+        &self,
+        subcmd: &str,
+        modules: &[&str],
+        flags: &[impl AsRef<str> + Sync],
+        strat: &Strategy,
+    ) -> Result<()> {
+        Cmd::with_sudo(&["dnf", "module", subcmd])
+            .kws(modules)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), strat))
+            .await
+    }
+}
+
+/// Gets the name of the module in the keyword `kw` if it is a module spec in
+/// the `name:stream[/profile]` form, eg. `nodejs:18/minimal`, where each part
+/// is made of `[A-Za-z0-9_.+-]`.
+///
+/// Package specs with an epoch (eg. `curl-0:7.76.1-14.el9`), paths and URLs
+/// are not module specs.
+#[must_use]
+fn module_name(kw: &str) -> Option<&str> {
+    let is_part = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"_.+-".contains(&b))
+    };
+    let (name, stream) = kw.split_once(':')?;
+    let (stream, profile) = stream.split_once('/').unwrap_or((stream, "default"));
+    let has_epoch = name.rsplit_once('-').map_or(false, |(_, epoch)| {
+        !epoch.is_empty() && epoch.bytes().all(|b| b.is_ascii_digit())
+    });
+    (is_part(name) && is_part(stream) && is_part(profile) && !has_epoch).then(|| name)
+}
+
+/// Splits the keywords `kws` into the module specs and the others.
+#[must_use]
+fn split_modules<'a>(kws: &[&'a str]) -> (Vec<&'a str>, Vec<&'a str>) {
+    kws.iter().partition(|kw| module_name(kw).is_some())
 }
 
 #[async_trait]
@@ -63,6 +127,168 @@ impl Pm for Dnf {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qc,
+            Op::Qdt,
+            Op::Qe,
+            Op::Qi,
+            Op::Ql,
+            Op::Qm,
+            Op::Qo,
+            Op::Qp,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rn,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Sccc,
+            Op::Sg,
+            Op::Si,
+            Op::Sii,
+            Op::Sl,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sw,
+            Op::Sy,
+            Op::U,
+        ]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Strips the packages listed by `dnf repoquery` in the
+    /// `name-epoch:version-release.arch` form down to their names.
+    fn format_export(&self, text: &str) -> Vec<String> {
+        text.lines()
+            .filter_map(|ln| ln.split_whitespace().next())
+            .map(|nevra| match nevra.rsplitn(3, '-').collect_vec()[..] {
+                [_, evr, name] if evr.contains(':') => name.into(),
+                _ => nevra.into(),
+            })
+            .collect()
+    }
+
+    /// Overrides `max_parallel_downloads` in `dnf.conf`.
+    fn jobs_flags(&self, jobs: u32) -> Option<Vec<String>> {
+        Some(vec![format!("--setopt=max_parallel_downloads={}", jobs)])
+    }
+
+    /// Overrides `install_weak_deps` in `dnf.conf`.
+    fn recommends_flags(&self, recommends: bool) -> Option<Vec<String>> {
+        let value = if recommends { "True" } else { "False" };
+        Some(vec![format!("--setopt=install_weak_deps={}", value)])
+    }
+
+    /// Installs into the given root, as in `--installroot`, which `rpm` reads
+    /// the database under.
+    fn root_flags(&self, exe: &str, root: &str) -> Option<Vec<String>> {
+        match exe {
+            "dnf" => Some(vec![format!("--installroot={}", root)]),
+            "rpm" => Some(vec!["--root".into(), root.into()]),
+            _ => None,
+        }
+    }
+
+    /// Sets the `proxy` option of `dnf`, which is shared by HTTP and HTTPS, so
+    /// the one for HTTPS is preferred.
+    fn proxy_flags(&self, exe: &str, proxy: &Proxy) -> Option<Vec<String>> {
+        if exe != "dnf" {
+            return None;
+        }
+        let url = proxy.https.as_ref().or_else(|| proxy.http.as_ref());
+        Some(
+            url.map(|url| format!("--setopt=proxy={}", url))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// Forces the architecture in `s` and `ss`, since `q` is run by `rpm`.
+    fn arch_args(&self, op: Op, arch: &str) -> Option<(Vec<String>, String)> {
+        matches!(op, Op::S | Op::Ss).then(|| (vec![format!("--forcearch={}", arch)], String::new()))
+    }
+
+    /// Runs entirely from the system cache.
+    fn offline_flags(&self, exe: &str, op: Op) -> Option<Vec<String>> {
+        (exe == "dnf" && matches!(op, Op::S | Op::Si | Op::Ss | Op::Su | Op::Suy))
+            .then(|| vec!["-C".into()])
+    }
+
+    /// Answers "no" to the prompt after resolving the transaction, with the
+    /// flags of `--assume-no` added by the strategies of `s`, `su` and `r`.
+    fn simulate_flags(&self) -> Option<Vec<String>> {
+        Some(vec![])
+    }
+
+    /// Enables only the given repositories.
+    fn repo_flags(&self, repos: &[String]) -> Option<Vec<String>> {
+        Some(
+            repos
+                .iter()
+                .map(|repo| format!("--repo={}", repo))
+                .collect(),
+        )
+    }
+
+    /// Takes the package names from the `name.arch : summary` lines of `dnf
+    /// search`, skipping the headers.
+    fn parse_search(&self, text: &str) -> Option<Vec<String>> {
+        text.lines()
+            .filter_map(|ln| ln.split_once(" : "))
+            .map(|(name_arch, _)| {
+                let name_arch = name_arch.trim();
+                name_arch
+                    .rsplit_once('.')
+                    .map_or(name_arch, |(name, _)| name)
+                    .to_owned()
+            })
+            .unique()
+            .collect_vec()
+            .pipe(Some)
+    }
+
+    /// Takes the `name version` lines of `rpm -qa --qf`, as listed by `q`
+    /// without keywords, or the packages found by `ss`.
+    fn parse_query_output(&self, op: &str, text: &str) -> Option<Vec<Package>> {
+        match op {
+            "q" => text
+                .lines()
+                .filter(|ln| !ln.trim().is_empty())
+                .map(|ln| match ln.split_whitespace().collect_vec()[..] {
+                    [name, version] => Some(Package {
+                        name: name.into(),
+                        version: Some(version.into()),
+                    }),
+                    _ => None,
+                })
+                .collect(),
+            "ss" => self
+                .parse_search(text)
+                .map(|names| names.into_iter().map(Package::from).collect()),
+            _ => None,
+        }
+    }
+
+    /// Removes the orphans, as listed by `qdt`.
+    async fn remove_orphans(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["dnf", "autoremove"])
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
@@ -82,6 +308,16 @@ impl Pm for Dnf {
             .await
     }
 
+    /// Qdt lists orphans, ie. packages installed as dependencies which are no
+    /// longer required by any other package.
+    async fn qdt(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::new(&["dnf", "repoquery", "--unneeded"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run(cmd))
+            .await
+    }
+
     /// Qe lists packages installed explicitly (not as dependencies).
     async fn qe(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::new(&["dnf", "repoquery", "--userinstalled"])
@@ -134,14 +370,14 @@ impl Pm for Dnf {
     // matching ALL of those terms are returned. TODO: Is this right?
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         let cmd = Cmd::new(&["rpm", "-qa"]).flags(flags);
-        if !self.cfg.dry_run {
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?
-            .pipe(String::from_utf8)?;
-        exec::grep_print(&out, kws)
+            .pipe(|out| self.decode(out))?;
+        self.grep_print(&out, kws)
     }
 
     /// Qu lists packages which have an update available.
@@ -152,18 +388,55 @@ impl Pm for Dnf {
 
     /// R removes a single package, leaving all of its dependencies installed.
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let (modules, pkgs) = split_modules(kws);
+        if !modules.is_empty() {
+            self.module("remove", &modules, flags, &STRAT_PROMPT)
+                .await?;
+            if pkgs.is_empty() {
+                return Ok(());
+            }
+        }
         Cmd::with_sudo(&["dnf", "remove"])
-            .kws(kws)
+            .kws(&pkgs)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
     }
 
+    /// Rn removes the modules, then disables their streams.
+    ///
+    /// The other packages are removed as in `r`, as `dnf` keeps no backup of
+    /// their configuration files anyway.
+    async fn rn(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let (modules, pkgs) = split_modules(kws);
+        if !modules.is_empty() {
+            self.module("remove", &modules, flags, &STRAT_PROMPT)
+                .await?;
+            self.module("disable", &modules, flags, &STRAT_PROMPT)
+                .await?;
+            if pkgs.is_empty() {
+                return Ok(());
+            }
+        }
+        self.r(&pkgs, flags).await
+    }
+
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let flags = self.with_jobs(&self.with_recommends(flags));
+        let flags = self.with_repos(&flags);
+        let (kws, flags) = self.with_arch(Op::S, kws, &flags);
+        let (modules, pkgs) = split_modules(&kws.iter().map(String::as_str).collect_vec());
+        if !modules.is_empty() {
+            self.module("install", &modules, &flags, &STRAT_INSTALL)
+                .await?;
+            if pkgs.is_empty() {
+                return Ok(());
+            }
+        }
         Cmd::with_sudo(&["dnf", "install"])
-            .kws(kws)
-            .flags(flags)
+            .kws(&pkgs)
+            .flags(&flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -212,7 +485,18 @@ impl Pm for Dnf {
     }
 
     /// Sg lists all packages belonging to the GROUP.
+    ///
+    /// The streams and profiles of the modules are listed instead for the
+    /// module specs, eg. `nodejs:18`.
     async fn sg(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let modules = kws.iter().filter_map(|kw| module_name(kw)).collect_vec();
+        if !modules.is_empty() {
+            return Cmd::new(&["dnf", "module", "list"])
+                .kws(&modules)
+                .flags(flags)
+                .pipe(|cmd| self.run(cmd))
+                .await;
+        }
         Cmd::new(if kws.is_empty() {
             &["dnf", "group", "list"]
         } else {
@@ -237,15 +521,23 @@ impl Pm for Dnf {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["dnf", "search"]).kws(kws).flags(flags))
+        let (kws, flags) = self.with_arch(Op::Ss, kws, &self.with_repos(flags));
+        self.run(Cmd::new(&["dnf", "search"]).kws(&kws).flags(&flags))
             .await
     }
 
     /// Su updates outdated packages.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let excludes = self
+            .cfg
+            .ignore
+            .iter()
+            .map(|pkg| format!("--exclude={}", pkg));
+        let flags = self.with_jobs(&self.with_recommends(flags));
+        let flags = self.with_repos(&flags);
         Cmd::with_sudo(&["dnf", "upgrade"])
             .kws(kws)
-            .flags(flags)
+            .flags(&excludes.chain(flags).collect_vec())
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -258,18 +550,41 @@ impl Pm for Dnf {
 
     /// Sw retrieves all packages from the server, but does not install/upgrade
     /// anything.
+    ///
+    /// The modules are rejected, as enabling their streams would change the
+    /// system.
     async fn sw(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let (modules, pkgs) = split_modules(kws);
+        if !modules.is_empty() {
+            return Err(Error::ArgParseError {
+                msg: format!(
+                    "`-Sw` can't download the modules {}, use `-S` to install them instead",
+                    modules.iter().map(|m| format!("`{}`", m)).join(", ")
+                ),
+            });
+        }
+        let dir_flags = self
+            .cfg
+            .ensure_download_dir()?
+            .map(|dir| format!("--downloaddir={}", dir.display()));
         Cmd::with_sudo(&["dnf", "install", "--downloadonly"])
-            .kws(kws)
-            .flags(flags)
+            .kws(&pkgs)
+            .flags(
+                &dir_flags
+                    .into_iter()
+                    .chain(flags.iter().map(|&f| f.into()))
+                    .collect_vec(),
+            )
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
 
     /// Sy refreshes the local package database.
+    ///
+    /// `dnf check-update` is not used here, as it fails with code `100` when
+    /// there is any update available.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.sc(&[], flags).await?;
-        self.run(Cmd::new(&["dnf", "check-update"]).flags(flags))
+        network_bound(self.run(Cmd::with_sudo(&["dnf", "makecache", "--refresh"]).flags(flags)))
             .await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
@@ -283,3 +598,137 @@ impl Pm for Dnf {
         self.s(kws, flags).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pm::{declined, prompted};
+
+    #[test]
+    fn jobs() {
+        let pm = Dnf::new(Config {
+            jobs: Some(8),
+            ..Config::default()
+        });
+        assert_eq!(
+            pm.with_jobs(&["-v"]),
+            ["--setopt=max_parallel_downloads=8", "-v"]
+        );
+        assert_eq!(Dnf::new(Config::default()).with_jobs(&["-v"]), ["-v"]);
+    }
+
+    #[test]
+    fn module_specs() {
+        assert_eq!(module_name("nodejs:18"), Some("nodejs"));
+        assert_eq!(module_name("nodejs:18/minimal"), Some("nodejs"));
+        assert_eq!(module_name("python39:3.9"), Some("python39"));
+        assert_eq!(module_name("nodejs"), None);
+        assert_eq!(module_name("curl-0:7.76.1-14.el9.x86_64"), None);
+        assert_eq!(module_name("./nodejs:18.rpm/"), None);
+        assert_eq!(module_name("https://example.com/foo.rpm"), None);
+        assert_eq!(module_name("nodejs:18/"), None);
+        assert_eq!(module_name("nodejs:"), None);
+        assert_eq!(module_name("node js:18"), None);
+        assert_eq!(module_name("nodejs:18;rm"), None);
+        assert_eq!(module_name("foo*:1"), None);
+        assert_eq!(
+            split_modules(&["curl", "nodejs:18", "vim", "perl:5.32/common"]),
+            (vec!["nodejs:18", "perl:5.32/common"], vec!["curl", "vim"])
+        );
+    }
+
+    #[test]
+    fn simulate_answers_no() {
+        let aborted = |msg: &str| Error::CmdStatusCodeError {
+            code: 1,
+            output: msg.into(),
+            kind: None,
+            raw: false,
+        };
+        for cfg in &[
+            Config {
+                simulate: true,
+                ..Config::default()
+            },
+            Config {
+                simulate: true,
+                assume_no: true,
+                ..Config::default()
+            },
+        ] {
+            let cmd = Cmd::with_sudo(&["dnf", "install"]).kws(&["curl"]);
+            let (cmd, _) = prompted(cfg, cmd, PmMode::default(), &STRAT_INSTALL).unwrap();
+            assert_eq!(cmd.flags, ["--assumeno"]);
+            // The message of the decline is only matched in English.
+            assert_eq!(cmd.envs, [("LC_ALL".to_owned(), "C".to_owned())]);
+
+            let res = declined(cfg, &STRAT_INSTALL, Err(aborted("Operation aborted.\n")));
+            assert_eq!(res.unwrap(), b"Operation aborted.\n");
+            // Any other failure is kept.
+            let res = declined(
+                cfg,
+                &STRAT_INSTALL,
+                Err(aborted("No match for argument: foo\n")),
+            );
+            assert!(matches!(
+                res,
+                Err(Error::CmdStatusCodeError { code: 1, .. })
+            ));
+        }
+
+        // The same failure is kept when the prompt is not answered.
+        let res = declined(
+            &Config::default(),
+            &STRAT_INSTALL,
+            Err(aborted("Operation aborted.\n")),
+        );
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn sw_modules_rejected() {
+        let pm = Dnf::new(Config::default());
+        let err = pm.sw(&["curl", "nodejs:18"], &[]).await.unwrap_err();
+        assert!(matches!(err, Error::ArgParseError { msg } if msg.contains("`nodejs:18`")));
+    }
+
+    #[test]
+    fn search_names() {
+        let out = indoc! {"
+            Last metadata expiration check: 0:12:34 ago on Sat 18 Sep 2021 13:42:05 UTC.
+            ======================== Name Exactly Matched: curl ========================
+            curl.x86_64 : A utility for getting files from remote servers (FTP, HTTP, and others)
+            ====================== Name & Summary Matched: curl ======================
+            curl-minimal.x86_64 : Conservatively configured build of curl for minimal installations
+            python3.11-pycurl.x86_64 : Python interface to libcurl
+            python3.11-pycurl.i686 : Python interface to libcurl
+        "};
+        let pm = Dnf::new(Config::default());
+        assert_eq!(
+            pm.parse_search(out).unwrap(),
+            ["curl", "curl-minimal", "python3.11-pycurl"]
+        );
+    }
+
+    #[test]
+    fn query_packages() {
+        let pm = Dnf::new(Config::default());
+        let out = indoc! {"
+            curl 7.79.1
+            dnf-data 4.9.0
+
+        "};
+        let pkgs = pm.parse_query_output("q", out).unwrap();
+        assert_eq!(
+            pkgs.iter()
+                .map(|pkg| pkg.render("{name}=={version}"))
+                .collect_vec(),
+            ["curl==7.79.1", "dnf-data==4.9.0"]
+        );
+        // The `rpm -qa` output of `q` with keywords is not parsed.
+        assert!(pm
+            .parse_query_output("q", "curl-7.79.1-1.fc35.x86_64\n")
+            .is_none());
+        assert!(pm.parse_query_output("qi", out).is_none());
+    }
+}
@@ -6,7 +6,7 @@ use itertools::Itertools;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{network_bound, NoCacheStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
 use crate::{dispatch::Config, error::Result, exec::Cmd};
 
 macro_rules! docs_self {
@@ -58,6 +58,49 @@ impl Pm for Emerge {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qi,
+            Op::Ql,
+            Op::Qo,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rs,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Si,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sy,
+        ]
+    }
+
+    /// Builds the packages in parallel, rather than downloading them.
+    fn jobs_flags(&self, jobs: u32) -> Option<Vec<String>> {
+        Some(vec![format!("--jobs={}", jobs)])
+    }
+
+    /// Merges into the given root, as in `--root`, which the tools of
+    /// `portage-utils` read the packages under.
+    fn root_flags(&self, exe: &str, root: &str) -> Option<Vec<String>> {
+        match exe {
+            "emerge" => Some(vec![format!("--root={}", root)]),
+            "qlist" | "qfile" => Some(vec!["--root".into(), root.into()]),
+            // `qsearch` only reads the repositories.
+            "qsearch" => Some(vec![]),
+            _ => None,
+        }
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.qs(kws, flags).await
@@ -116,7 +159,7 @@ impl Pm for Emerge {
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["emerge"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_jobs(flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -153,7 +196,7 @@ impl Pm for Emerge {
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["emerge", "-uDN"])
             .kws(if kws.is_empty() { &["@world"] } else { kws })
-            .flags(flags)
+            .flags(&self.with_jobs(flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -167,8 +210,7 @@ impl Pm for Emerge {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::with_sudo(&["emerge", "--sync"]).flags(flags))
-            .await?;
+        network_bound(self.run(Cmd::with_sudo(&["emerge", "--sync"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
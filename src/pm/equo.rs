@@ -0,0 +1,123 @@
+#![doc = docs_self!()]
+
+use async_trait::async_trait;
+use indoc::indoc;
+use once_cell::sync::Lazy;
+use tap::prelude::*;
+
+use super::{network_bound, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use crate::{dispatch::Config, error::Result, exec::Cmd};
+
+macro_rules! docs_self {
+    () => {
+        indoc! {"
+            The [Entropy Package Manager](https://wiki.sabayon.org/index.php?title=En:Entropy) for Sabayon-derived systems.
+        "}
+    };
+}
+
+#[doc = docs_self!()]
+#[derive(Debug)]
+pub(crate) struct Equo {
+    cfg: Config,
+}
+
+static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
+    prompt: PromptStrategy::native_no_confirm(&["--ask=false"]),
+    ..Strategy::default()
+});
+
+impl Equo {
+    #[must_use]
+    #[allow(missing_docs)]
+    pub(crate) fn new(cfg: Config) -> Self {
+        Equo { cfg }
+    }
+}
+
+#[async_trait]
+impl Pm for Equo {
+    /// Gets the name of the package manager.
+    fn name(&self) -> &str {
+        "equo"
+    }
+
+    fn cfg(&self) -> &Config {
+        &self.cfg
+    }
+
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[Op::Q, Op::R, Op::S, Op::Si, Op::Ss, Op::Su, Op::Suy, Op::Sy]
+    }
+
+    /// Q generates a list of installed packages.
+    async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.run(
+            Cmd::new(&["equo", "query", "installed"])
+                .kws(kws)
+                .flags(flags),
+        )
+        .await
+    }
+
+    /// R removes a single package, leaving all of its dependencies installed.
+    async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["equo", "remove"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
+    /// S installs one or more packages by name.
+    async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["equo", "install"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
+    /// Si displays remote package information: name, version, description, etc.
+    async fn si(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&["equo", "query", "info"]).kws(kws).flags(flags))
+            .await
+    }
+
+    /// Ss searches for package(s) by searching the expression in name,
+    /// description, short description.
+    async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&["equo", "search"]).kws(kws).flags(flags))
+            .await
+    }
+
+    /// Su updates outdated packages.
+    async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["equo", "upgrade"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
+            .await
+    }
+
+    /// Suy refreshes the local package database, then updates outdated
+    /// packages.
+    async fn suy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.sy(&[], flags).await?;
+        self.su(kws, flags).await
+    }
+
+    /// Sy refreshes the local package database.
+    async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        network_bound(self.run(Cmd::with_sudo(&["equo", "update"]).flags(flags))).await?;
+        if !kws.is_empty() {
+            self.s(kws, flags).await?;
+        }
+        Ok(())
+    }
+}
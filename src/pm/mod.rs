@@ -17,28 +17,49 @@ mods! {
     brew;
     choco;
     conda;
+    custom;
     dnf;
     emerge;
+    equo;
+    op;
     pip;
     port;
+    pub(crate) registry;
     scoop;
     tlmgr;
     unknown;
     zypper;
 }
 
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
 use async_trait::async_trait;
+use futures::prelude::*;
 use macro_rules_attribute::macro_rules_attribute;
 use tt_call::tt_call;
 
 pub(crate) use self::{
-    apk::Apk, apt::Apt, brew::Brew, choco::Choco, conda::Conda, dnf::Dnf, emerge::Emerge, pip::Pip,
-    port::Port, scoop::Scoop, tlmgr::Tlmgr, unknown::Unknown, zypper::Zypper,
+    apk::Apk, apt::Apt, brew::Brew, choco::Choco, conda::Conda, custom::CustomPm, dnf::Dnf,
+    emerge::Emerge, equo::Equo, pip::Pip, port::Port, scoop::Scoop, tlmgr::Tlmgr, unknown::Unknown,
+    zypper::Zypper,
 };
+pub use self::{op::Op, registry::register};
+pub use crate::json::Package;
 use crate::{
-    dispatch::Config,
-    error::Result,
-    exec::{Cmd, Mode, Output},
+    dispatch::{Config, Elevation, Proxy},
+    elevate::{self, Relay},
+    error::{Error, FailureKind, Result},
+    exec::{self, Capture, Cmd, Mode, Output, StatusCode},
+    history, json,
+    print::{self, print_cmd, print_warning, Event, PROMPT_NATIVE, PROMPT_RUN, PROMPT_WARNING},
+    stat,
 };
 
 /// The list of [`pacman`](https://wiki.archlinux.org/index.php/Pacman) methods supported by [`pacaptr`](crate).
@@ -55,6 +76,9 @@ macro_rules! methods {
                 /// Qc shows the changelog of a package.
                 async fn qc;
 
+                /// Qdt lists orphans, ie. packages installed as dependencies which are no longer required by any other package.
+                async fn qdt;
+
                 /// Qe lists packages installed explicitly (not as dependencies).
                 async fn qe;
 
@@ -148,10 +172,7 @@ macro_rules! methods {
 
 macro_rules! make_op_body {
     ($self:ident, $method:ident) => {{
-        Err(crate::error::Error::OperationUnimplementedError {
-            op: stringify!($method).into(),
-            pm: $self.name().into(),
-        })
+        Err(crate::pm::unimplemented($self, stringify!($method)))
     }};
 }
 
@@ -205,13 +226,384 @@ macro_rules! decor_pm {
 /// - <https://wiki.archlinux.org/index.php/Pacman/Rosetta>
 #[macro_rules_attribute(decor_pm!)]
 #[async_trait]
-pub(crate) trait Pm: Sync {
+pub trait Pm: Sync {
     /// Gets the name of the package manager.
     fn name(&self) -> &str;
 
     /// Gets the config of the package manager.
     fn cfg(&self) -> &Config;
 
+    /// Gets the name of the main executable of the package manager, eg.
+    /// `pip3`, which defaults to its [`name`](Pm::name).
+    fn executable(&self) -> &str {
+        self.name()
+    }
+
+    /// Gets the command printing the version of the package manager, as run
+    /// by `pacaptr -Vv` and `pacaptr doctor`, which defaults to `<executable>
+    /// --version`.
+    fn version_cmd(&self) -> Cmd {
+        Cmd::new(&[self.executable(), "--version"])
+    }
+
+    /// Gets the operations implemented by the package manager, in the order
+    /// of [`Op::ALL`], as printed by `pacaptr --capabilities`.
+    ///
+    /// The nearest ones to an unimplemented operation are suggested in its
+    /// [`Error::OperationUnimplementedError`].
+    ///
+    /// Returns an empty slice by default.
+    fn supported_ops(&self) -> &[Op] {
+        &[]
+    }
+
+    /// Gets the operations which the package manager has no equivalent of, eg.
+    /// `-Sg` without package groups, as opposed to the ones which are merely
+    /// not mapped yet, as told by [`Error::OperationUnimplementedError`].
+    ///
+    /// Returns an empty slice by default.
+    fn lacking_ops(&self) -> &[Op] {
+        &[]
+    }
+
+    /// Checks if the operations of the package manager which might modify the
+    /// system need an elevated shell on Windows.
+    ///
+    /// Returns `false` (by default) for the user-scoped ones (eg. `scoop`),
+    /// otherwise their commands are elevated as selected by
+    /// [`Config::elevation`] when `pacaptr` is not elevated itself.
+    fn requires_elevation(&self) -> bool {
+        false
+    }
+
+    /// Checks if the operations of the package manager which might modify the
+    /// system run their commands through `sudo` on Unix.
+    ///
+    /// Returns `false` (by default) for the user-scoped ones (eg. `brew`),
+    /// otherwise the credentials of `sudo` are kept warm during those
+    /// operations under [`Config::sudo_keepalive`].
+    fn uses_sudo(&self) -> bool {
+        false
+    }
+
+    /// Checks if the package manager can be run as `root`.
+    ///
+    /// Returns `true` by default, otherwise its operations are refused when
+    /// `pacaptr` is run as `root`, unless `--allow-root` is set.
+    fn allows_root(&self) -> bool {
+        true
+    }
+
+    /// Checks if the package manager can apply the patches in `su` and `suy`,
+    /// and list them in `qu`, as set by `--patch`.
+    ///
+    /// Returns `false` by default, in which case `--patch` is ignored with a
+    /// warning.
+    fn supports_patches(&self) -> bool {
+        false
+    }
+
+    /// Hold prevents one or more packages from being upgraded or removed.
+    async fn hold(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, hold)
+    }
+
+    /// Unhold releases one or more packages previously held.
+    async fn unhold(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, unhold)
+    }
+
+    /// Held lists the packages that are currently held.
+    async fn held(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, held)
+    }
+
+    /// Removes the orphans, as listed by `qdt`.
+    ///
+    /// By default, the orphans listed by `qdt` are removed with `r`.
+    async fn remove_orphans(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        let (res, out) = capture(self.qdt(&[], &[])).await;
+        match res {
+            Err(Error::OperationUnimplementedError { .. }) => {
+                return make_op_body!(self, remove_orphans)
+            }
+            res => res?,
+        }
+        let out = String::from_utf8_lossy(&out);
+        let orphans = out
+            .lines()
+            .map(str::trim)
+            .filter(|ln| !ln.is_empty())
+            .collect::<Vec<_>>();
+        if orphans.is_empty() {
+            // The orphans are not even listed in a dry run.
+            let cfg = self.cfg();
+            let msg = if cfg.dry_run || cfg.describe {
+                "The orphans listed by the command above would be removed in a real run"
+            } else {
+                "There are no orphans to remove"
+            };
+            print::print_msg(msg, print::PROMPT_INFO);
+            return Ok(());
+        }
+        self.r(&orphans, flags).await
+    }
+
+    /// Tap adds one or more third-party repositories, eg. Homebrew taps.
+    async fn tap(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, tap)
+    }
+
+    /// Untap removes one or more third-party repositories previously added.
+    async fn untap(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, untap)
+    }
+
+    /// Taps lists the third-party repositories that are currently added.
+    async fn taps(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, taps)
+    }
+
+    /// Bundle installs the packages listed in a bundle file, eg. a Homebrew
+    /// `Brewfile`, given as the only keyword (if any).
+    async fn bundle(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, bundle)
+    }
+
+    /// Bundle dump prints the packages installed in the format of a bundle
+    /// file, to be written by `pacaptr bundle --dump`.
+    async fn bundle_dump(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+        make_op_body!(self, bundle_dump)
+    }
+
+    /// Export prints the packages to be written by `pacaptr export`, which are
+    /// then formatted by [`Pm::format_export`].
+    ///
+    /// By default, this is `qe`, or `q` if the former is unimplemented.
+    async fn export(&self, flags: &[&str]) -> Result<()> {
+        match self.qe(&[], flags).await {
+            Err(Error::OperationUnimplementedError { .. }) => self.q(&[], flags).await,
+            res => res,
+        }
+    }
+
+    /// Classifies the failure of a command which has exited with `code` after
+    /// printing `output`, so that the exit code of [`pacaptr`](crate) is the
+    /// same across package managers.
+    ///
+    /// Returns [`None`] (by default) to pass the exit code through.
+    fn classify(&self, _code: StatusCode, _output: &str) -> Option<FailureKind> {
+        None
+    }
+
+    /// Tells whether the nonzero exit `code` of a command only carries some
+    /// information, in which case the command is taken as successful (unless
+    /// under `--raw-exit-code`).
+    ///
+    /// Returns `false` by default.
+    fn is_informational(&self, _code: StatusCode) -> bool {
+        false
+    }
+
+    /// Formats the package listing `text` printed by [`Pm::export`] as the
+    /// package specs written by `pacaptr export`, one per package, each of
+    /// which can be given to `-S`.
+    ///
+    /// By default, the first word of each line is taken as the package name,
+    /// with any `/suffix` dropped, as in the output of `apt list`.
+    fn format_export(&self, text: &str) -> Vec<String> {
+        text.lines()
+            .filter_map(json::Package::from_line)
+            .map(|pkg| pkg.name.split('/').next().unwrap_or_default().to_owned())
+            .collect()
+    }
+
+    /// Gets the native flags for removing the orphans left behind by `r` along
+    /// with the packages, as set by `--autoremove`.
+    ///
+    /// Returns [`None`] (by default) if the package manager has no such flag,
+    /// in which case the orphans are removed afterwards with
+    /// [`remove_orphans`](Pm::remove_orphans) instead.
+    fn autoremove_flags(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for downloading (or building) `jobs` packages in
+    /// parallel in `s`, `su` and `suy`, as set by `--jobs`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--jobs` is ignored with a warning.
+    fn jobs_flags(&self, _jobs: u32) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for installing the weak dependencies (eg. the
+    /// `Recommends` of `apt`) along with the packages in `s`, `su` and `suy`
+    /// or not, according to `recommends`, as set by `--recommends` or
+    /// `--no-recommends`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case the flag is ignored with a warning.
+    fn recommends_flags(&self, _recommends: bool) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for restricting `s`, `ss`, `su` and `suy` to the
+    /// repositories `repos`, as set by `--repo`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--repo` is ignored with a warning.
+    fn repo_flags(&self, _repos: &[String]) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for using the package indexes `urls` in the
+    /// operation `op` (one of [`Op::S`], [`Op::Ss`] and [`Op::Si`]), as set by
+    /// `--index-url`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--index-url` is ignored with a warning.
+    fn index_url_flags(&self, _op: Op, _urls: &[String]) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for previewing the transactions of `s`, `su`,
+    /// `suy` and `r` with the simulation of the package manager itself, as set
+    /// by `--simulate`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--simulate` is rejected.
+    fn simulate_flags(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for overwriting the conflicting files of other
+    /// packages at the paths matching `glob` in `s`, `su` and `suy`, as set by
+    /// `--overwrite`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--overwrite` is ignored with a warning. The package
+    /// managers which can't restrict it to some paths reject any glob other
+    /// than `*` with [`check_overwrite_all`] instead.
+    fn overwrite_flags(&self, _glob: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags for installing the packages in `s` under the
+    /// virtual package `name`, as set by `--virtual`, which is then removed as
+    /// a whole in `r`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it, in which case `--virtual` is ignored with a warning.
+    fn virtual_flags(&self, _name: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags of the executable `exe` (ie. the package manager
+    /// itself or one of the tools it runs, eg. `rpm` for `dnf`) for managing
+    /// the packages in the alternate root directory `root`, as set by
+    /// `--root`, which are inserted right after `exe` in each of its commands.
+    ///
+    /// Returns [`None`] (by default) if `exe` doesn't support it, in which
+    /// case `--root` is rejected, either right away if `exe` is
+    /// [`Pm::executable`], or when one of its commands is run otherwise.
+    fn root_flags(&self, _exe: &str, _root: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags of the executable `exe` (ie. the package manager
+    /// itself or one of its auxiliary tools) for running behind `proxy`, as
+    /// set by `--proxy` or the `[proxy]` section of the config, which are
+    /// inserted right after `exe` in each of its commands.
+    ///
+    /// The usual environment variables (eg. `https_proxy`) are set anyway, so
+    /// this returns [`None`] (by default) if `exe` honors them.
+    fn proxy_flags(&self, _exe: &str, _proxy: &Proxy) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Gets the native flags and the keyword suffix (eg. `:arm64`) for
+    /// targeting the architecture `arch` in the operation `op` (one of
+    /// [`Op::S`], [`Op::Q`] and [`Op::Ss`]), as set by `--arch`.
+    ///
+    /// Returns [`None`] (by default) if the package manager doesn't support
+    /// it in `op`, in which case `--arch` is ignored with a warning.
+    fn arch_args(&self, _op: Op, _arch: &str) -> Option<(Vec<String>, String)> {
+        None
+    }
+
+    /// Gets the native flags of the executable `exe` (ie. the package manager
+    /// itself or one of the tools it runs) for running the operation `op`
+    /// (eg. [`Op::S`]), which needs the network, from the local cache only, as
+    /// set by `--offline`, which are appended to each of its commands.
+    ///
+    /// Returns [`None`] (by default) if `exe` can't run `op` offline, in which
+    /// case it is rejected under `--offline`, either right away if `exe` is
+    /// [`Pm::executable`], or when one of its commands is run otherwise.
+    fn offline_flags(&self, _exe: &str, _op: Op) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Parses the names of the packages found in the output `text` of `ss`,
+    /// among which the packages to be installed are picked with `--pick`.
+    ///
+    /// Returns [`None`] (by default) if the output can't be parsed.
+    fn parse_search(&self, _text: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Parses the packages listed in the output `text` of the query `op` (ie.
+    /// `q` or `ss`), to be printed with the template of `--print-format`.
+    ///
+    /// By default, the packages found by `ss` are taken from
+    /// [`Pm::parse_search`] without their versions. Returns [`None`] if the
+    /// output can't be parsed, in which case it is printed as is.
+    fn parse_query_output(&self, op: &str, text: &str) -> Option<Vec<Package>> {
+        if op != "ss" {
+            return None;
+        }
+        self.parse_search(text)
+            .map(|names| names.into_iter().map(Package::from).collect())
+    }
+
+    /// Runs the operation `op` (eg. [`Op::Suy`] for `-Suy`) with the keywords
+    /// `kws` and the extra flags `flags`, returning the output of the commands
+    /// run instead of printing it.
+    ///
+    /// The output of each command is `stdout` and `stderr` combined. The
+    /// commands asking for confirmation are not answered on behalf of the
+    /// user unless [`Config::no_confirm`] is set.
+    ///
+    /// # Errors
+    /// Returns an [`Error::CmdStatusCodeError`] holding the output so far
+    /// when a command has failed with a non-zero exit code, and an
+    /// [`Error::OperationUnimplementedError`] when `op` is not supported.
+    /// See [`Error`] for a  list of other possible errors.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use pacaptr::{
+    ///     dispatch::Config,
+    ///     pm::{Op, Pm},
+    /// };
+    ///
+    /// # async fn run() -> Result<(), pacaptr::error::Error> {
+    /// let pm: Box<dyn Pm> = Config {
+    ///     default_pm: Some("apt".into()),
+    ///     ..Config::default()
+    /// }
+    /// .into();
+    /// let out = pm.run_op(Op::Qe, &[], &[]).await?;
+    /// println!("{}", String::from_utf8_lossy(&out));
+    /// # Ok(())
+    /// # }
+    /// ```
+    async fn run_op(&self, op: Op, kws: &[&str], flags: &[&str]) -> Result<Output> {
+        let printer = self.cfg().printer.clone();
+        let (res, out) = print::scoped(printer, capture(op.call(self, kws, flags))).await;
+        res.map(|()| out)
+    }
+
     /// Wraps the [`Pm`] instance in a [`Box`].
     fn boxed<'a>(self) -> Box<dyn Pm + 'a>
     where
@@ -221,6 +613,128 @@ pub(crate) trait Pm: Sync {
     }
 }
 
+/// Merges the output `out` relayed from an elevated command into its result
+/// `res`, printing it through the [`Printer`](print::Printer) unless in
+/// [`PmMode::Mute`], where it is kept in the result to be captured or recorded
+/// instead.
+///
+/// # Errors
+/// Returns the error of `res`, if any, along with `out`.
+fn relayed(out: Output, res: Result<Output>, mode: PmMode) -> Result<Output> {
+    if !matches!(mode, PmMode::Mute) {
+        print::print_output(&out);
+    }
+    match res {
+        Ok(_) => Ok(out),
+        Err(Error::CmdStatusCodeError {
+            code, kind, raw, ..
+        }) => Err(Error::CmdStatusCodeError {
+            code,
+            kind,
+            output: out,
+            raw,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Applies the [`PromptStrategy`] of `strat` to `cmd` to be run in `mode`
+/// according to `cfg`, returning the command along with how it should be
+/// run.
+///
+/// Under `--assume-no`, the command is run with the flags of
+/// [`Strategy::assume_no`] if the package manager has any, or is merely
+/// printed as canceled otherwise, with a warning. So is it under `--simulate`
+/// with [`AssumeNoStrategy::Simulating`], in which case `LC_ALL=C` is set as
+/// well.
+///
+/// Under `--assume-yes`, the command is run with the flags of
+/// [`Strategy::assume_yes`] if the package manager has any, or as under
+/// `--no-confirm` otherwise.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when a custom prompt is required under
+/// `--json`.
+fn prompted(cfg: &Config, mut cmd: Cmd, mode: PmMode, strat: &Strategy) -> Result<(Cmd, Mode)> {
+    let no_confirm = cfg.no_confirm;
+    match &strat.prompt {
+        PromptStrategy::None => Ok((cmd, mode.into())),
+        _ if cfg.assume_no || cfg.simulate && strat.assume_no.is_simulating() => {
+            match &strat.assume_no {
+                AssumeNoStrategy::WithFlags(v) => {
+                    cmd.flags.extend(v.clone());
+                    Ok((cmd, mode.into()))
+                }
+                // The answer is told apart by a message, which has to be in
+                // English.
+                AssumeNoStrategy::Simulating { flags, .. } => {
+                    cmd.flags.extend(flags.clone());
+                    Ok((cmd.env("LC_ALL", "C"), mode.into()))
+                }
+                AssumeNoStrategy::Cancel => {
+                    print_warning(
+                        "Canceled the command asking for confirmation, \
+                        as `--assume-no` has no native equivalent here",
+                        PROMPT_WARNING,
+                    );
+                    Ok((cmd, Mode::PrintCmd))
+                }
+            }
+        }
+        _ if cfg.assume_yes && matches!(strat.assume_yes, AssumeYesStrategy::WithFlags(_)) => {
+            if let AssumeYesStrategy::WithFlags(v) = &strat.assume_yes {
+                cmd.flags.extend(v.clone());
+            }
+            Ok((cmd, mode.into()))
+        }
+        PromptStrategy::CustomPrompt if no_confirm => Ok((cmd, mode.into())),
+        PromptStrategy::CustomPrompt if cfg.json => Err(Error::ArgParseError {
+            msg: "`--json` requires `--no-confirm` for operations asking for confirmation".into(),
+        }),
+        PromptStrategy::CustomPrompt => Ok((cmd, Mode::Prompt)),
+        PromptStrategy::NativeNoConfirm(v) => {
+            if no_confirm {
+                cmd.flags.extend(v.clone());
+            }
+            Ok((cmd, mode.into()))
+        }
+        PromptStrategy::NativeConfirm(v) => {
+            if !no_confirm {
+                cmd.flags.extend(v.clone());
+            }
+            Ok((cmd, mode.into()))
+        }
+        PromptStrategy::AnswerStdin { answer } => {
+            if no_confirm {
+                cmd = cmd.answer(answer);
+            }
+            Ok((cmd, mode.into()))
+        }
+    }
+}
+
+/// Takes the result `res` of a command run with the flags of
+/// [`AssumeNoStrategy::Simulating`] as a success when it fails only because
+/// the prompt is answered with "no".
+fn declined(cfg: &Config, strat: &Strategy, res: Result<Output>) -> Result<Output> {
+    let answered_no =
+        (cfg.assume_no || cfg.simulate) && !matches!(strat.prompt, PromptStrategy::None);
+    match (&strat.assume_no, res) {
+        (
+            AssumeNoStrategy::Simulating { code, msg, .. },
+            Err(Error::CmdStatusCodeError {
+                code: c, output, ..
+            }),
+        ) if answered_no
+            && c == *code
+            && String::from_utf8_lossy(&output).contains(msg.as_str()) =>
+        {
+            Ok(output)
+        }
+        (_, res) => res,
+    }
+}
+
 /// Extra implementation helper functions for [`Pm`],
 /// focusing on the ability to run commands ([`Cmd`]s) in a configured and
 /// [`Pm`]-specific context.
@@ -229,46 +743,179 @@ trait PmHelper: Pm {
     /// Executes a command in the context of the [`Pm`] implementation. Returns
     /// the [`Output`] of this command.
     async fn check_output(&self, mut cmd: Cmd, mode: PmMode, strat: &Strategy) -> Result<Output> {
-        async fn run(cfg: &Config, cmd: &Cmd, mode: PmMode, strat: &Strategy) -> Result<Output> {
+        async fn run(
+            cfg: &Config,
+            cmd: &Cmd,
+            mode: PmMode,
+            strat: &Strategy,
+            relay: Option<&Relay>,
+        ) -> Result<Output> {
             let mut curr_cmd = cmd.clone();
-            let no_confirm = cfg.no_confirm;
+            if let Some(secs) = cfg.timeout {
+                curr_cmd = curr_cmd.timeout(Duration::from_secs(secs));
+            }
+            if let (Some(limit), Capture::Auto(_)) = (cfg.output_limit, curr_cmd.capture) {
+                curr_cmd = curr_cmd.capture(Capture::Auto(limit));
+            }
             if cfg.no_cache {
                 if let NoCacheStrategy::WithFlags(v) = &strat.no_cache {
                     curr_cmd.flags.extend(v.clone());
                 }
             }
-            match &strat.prompt {
-                PromptStrategy::None => curr_cmd.exec(mode.into()).await,
-                PromptStrategy::CustomPrompt if no_confirm => curr_cmd.exec(mode.into()).await,
-                PromptStrategy::CustomPrompt => curr_cmd.exec(Mode::Prompt).await,
-                PromptStrategy::NativeNoConfirm(v) => {
-                    if no_confirm {
-                        curr_cmd.flags.extend(v.clone());
-                    }
-                    curr_cmd.exec(mode.into()).await
-                }
-                PromptStrategy::NativeConfirm(v) => {
-                    if !no_confirm {
-                        curr_cmd.flags.extend(v.clone());
-                    }
-                    curr_cmd.exec(mode.into()).await
-                }
+            let (mut curr_cmd, mode) = prompted(cfg, curr_cmd, mode, strat)?;
+            // The command is relaunched with all of its flags.
+            if let Some(relay) = relay {
+                curr_cmd = relay.wrap(&curr_cmd);
             }
+            declined(cfg, strat, curr_cmd.mode(mode).exec().await)
         }
 
         let cfg = self.cfg();
 
+        // The proxy comes before the environment variables configured for the
+        // package manager, so that the latter take precedence.
+        if !cfg.proxy.is_empty() {
+            cmd.envs.extend(cfg.proxy.envs());
+            let exe = cmd.cmd.first().map_or("", String::as_str);
+            if let Some(flags) = self.proxy_flags(exe, &cfg.proxy) {
+                cmd.cmd.splice(1..1, flags);
+            }
+        }
+        if let Some(pm_cfg) = cfg.pms.get(self.name()) {
+            cmd.envs
+                .extend(pm_cfg.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        if let Some(tx) = &cfg.event_sender {
+            cmd = cmd.event_sender(tx.clone());
+        }
+        if let Some(root) = cfg.root.as_deref() {
+            let exe = cmd.cmd.first().map_or("", String::as_str);
+            // A command not knowing about the root would work on `/` instead.
+            let flags =
+                self.root_flags(exe, root)
+                    .ok_or_else(|| Error::OperationUnimplementedError {
+                        op: "--root".into(),
+                        pm: exe.into(),
+                        alternatives: vec![],
+                        lacking: false,
+                        using: vec![],
+                    })?;
+            cmd.cmd.splice(1..1, flags);
+        }
+        if let Ok(op) = OFFLINE_OP.try_with(|&op| op) {
+            let exe = cmd.cmd.first().map_or("", String::as_str);
+            // A command not knowing about `--offline` might reach the network.
+            let flags = self
+                .offline_flags(exe, op)
+                .ok_or_else(|| Error::OfflineError {
+                    op: op.to_string(),
+                    pm: exe.into(),
+                })?;
+            cmd.flags.extend(flags);
+        }
+        // The output is collected instead of being printed under `--json` or
+        // when capturing, as in the `Mute` mode below.
+        if matches!(mode, PmMode::Mute) || cfg.json || is_capturing() {
+            if let CaptureStrategy::WithFlags(v) = &strat.capture {
+                cmd.flags.extend(v.iter().cloned());
+            }
+        }
+        // The output of a command relaunched through the UAC prompt is relayed
+        // through a file instead, once the command is complete in `run`.
+        let mut relay = None;
+        if cmd.cmd.first().map(String::as_str) == Some(self.executable()) {
+            match ELEVATION.try_with(|&elevation| elevation) {
+                Ok(Elevation::Gsudo) => cmd = elevate::gsudo(cmd),
+                Ok(Elevation::Uac) => relay = Some(Relay::new()?),
+                _ => (),
+            }
+        }
+
+        // Under `pacaptr help`, the commands are described instead of being run.
+        if cfg.describe {
+            if count_described() {
+                return Ok(Output::default());
+            }
+            if !matches!(mode, PmMode::Mute) {
+                print_cmd(&cmd, PROMPT_NATIVE);
+            }
+            return Ok(Output::default());
+        }
+
+        // Under `--json`, the commands run on behalf of the user are muted and
+        // recorded instead.
+        let should_record = cfg.json && !matches!(mode, PmMode::Mute);
+        // When capturing, only the output which would have been printed is kept.
+        let should_capture = is_capturing() && !matches!(mode, PmMode::Mute);
+        let mode = if cfg.json || is_capturing() {
+            PmMode::Mute
+        } else {
+            mode
+        };
+
+        if is_staged() && !matches!(mode, PmMode::Mute) {
+            let label = format!("[{}]", cmd.cmd.join(" "));
+            cmd = cmd.prefix(label);
+        }
+
+        let emit = |event| {
+            if let Some(format) = cfg.events {
+                print::emit(&event, format);
+            }
+        };
+        emit(Event::started(&cmd));
+
         // `--dry-run` should apply to both the main command and the cleanup.
+        let started = SystemTime::now();
         let res = match &strat.dry_run {
-            DryRunStrategy::PrintCmd if cfg.dry_run => cmd.clone().exec(Mode::PrintCmd).await?,
+            DryRunStrategy::PrintCmd if cfg.dry_run && cfg.json => Ok(Output::default()),
+            DryRunStrategy::PrintCmd if cfg.dry_run => {
+                cmd.clone().mode(Mode::PrintCmd).exec().await
+            }
             DryRunStrategy::WithFlags(v) if cfg.dry_run => {
                 cmd.flags.extend(v.clone());
                 // -- A dry run with extra flags does not need `sudo`. --
                 cmd = cmd.sudo(false);
-                run(cfg, &cmd, mode, strat).await?
+                run(cfg, &cmd, mode, strat, relay.as_ref()).await
+            }
+            _ if cfg.retries > 0 && is_network_bound() => {
+                retry(cfg, &cmd, RETRY_DELAY, || {
+                    run(cfg, &cmd, mode, strat, relay.as_ref())
+                })
+                .await
             }
-            _ => run(cfg, &cmd, mode, strat).await?,
+            _ => run(cfg, &cmd, mode, strat, relay.as_ref()).await,
         };
+        let res = match relay {
+            Some(relay) => relayed(relay.take(), res, mode),
+            None => res,
+        };
+        let res = match res {
+            Err(Error::CmdStatusCodeError { code, output, .. })
+                if !cfg.raw_exit_code && self.is_informational(code) =>
+            {
+                Ok(output)
+            }
+            res => res,
+        };
+        if should_record {
+            json::record_cmd(&cmd, &res);
+        }
+        let code = match &strat.dry_run {
+            DryRunStrategy::PrintCmd if cfg.dry_run => None,
+            _ => history::status_code(&res),
+        };
+        let duration = started.elapsed().unwrap_or_default();
+        history::record(cfg, self.name(), &cmd, code, started);
+        stat::record(&cmd, code, duration);
+        emit(Event::finished(&cmd, code, duration));
+        if let Err(e) = &res {
+            emit(Event::error(&cmd, e));
+        }
+        let res = res.map_err(|e| self.classified(e, mode))?;
+        if should_capture {
+            capture_output(&res);
+        }
 
         // Perform the cleanup.
         if cfg.no_cache {
@@ -284,6 +931,125 @@ trait PmHelper: Pm {
         Ok(res)
     }
 
+    /// Classifies the failure `e` of a command run in `mode` with
+    /// [`Pm::classify`], then drops its output if it has already been printed.
+    fn classified(&self, e: Error, mode: PmMode) -> Error {
+        let e = match e {
+            Error::CmdStatusCodeError { code, output, .. } => Error::CmdStatusCodeError {
+                code,
+                kind: (!self.cfg().raw_exit_code)
+                    .then(|| self.classify(code, &String::from_utf8_lossy(&output)))
+                    .flatten(),
+                output,
+                raw: self.cfg().raw_exit_code,
+            },
+            e => e,
+        };
+        match mode {
+            PmMode::Mute => e,
+            _ => e.without_output(),
+        }
+    }
+
+    /// Gets the native flags for the parallel jobs set by `--jobs` (if
+    /// supported), as given by [`Pm::jobs_flags`], followed by `flags`.
+    fn with_jobs(&self, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        let jobs = self.cfg().jobs.and_then(|jobs| self.jobs_flags(jobs));
+        jobs.into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the native flags for the weak dependencies set by `--recommends` or
+    /// `--no-recommends` (if supported), as given by [`Pm::recommends_flags`],
+    /// followed by `flags`.
+    fn with_recommends(&self, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        let recommends = self.cfg().recommends;
+        recommends
+            .and_then(|recommends| self.recommends_flags(recommends))
+            .into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the native flags for the repositories set by `--repo` (if
+    /// supported), as given by [`Pm::repo_flags`], followed by `flags`.
+    fn with_repos(&self, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        let repos = &self.cfg().repos;
+        let repos = (!repos.is_empty())
+            .then(|| self.repo_flags(repos))
+            .flatten();
+        repos
+            .into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the native flags for the package indexes set by `--index-url` (if
+    /// supported in the operation `op`), as given by [`Pm::index_url_flags`],
+    /// followed by `flags`.
+    fn with_index_urls(&self, op: Op, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        let urls = &self.cfg().index_urls;
+        let urls = (!urls.is_empty())
+            .then(|| self.index_url_flags(op, urls))
+            .flatten();
+        urls.into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the native flags for the simulation set by `--simulate` (if
+    /// supported), as given by [`Pm::simulate_flags`], followed by `flags`.
+    fn with_simulate(&self, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        self.cfg()
+            .simulate
+            .then(|| self.simulate_flags())
+            .flatten()
+            .into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the native flags for overwriting the conflicting files set by
+    /// `--overwrite` (if supported), as given by [`Pm::overwrite_flags`],
+    /// followed by `flags`.
+    fn with_overwrite(&self, flags: &[impl AsRef<str> + Sync]) -> Vec<String> {
+        let glob = self.cfg().overwrite.as_deref();
+        glob.and_then(|glob| self.overwrite_flags(glob))
+            .into_iter()
+            .flatten()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect()
+    }
+
+    /// Gets the keywords `kws` and the native flags for the architecture set by
+    /// `--arch` (if supported in the operation `op`), as given by
+    /// [`Pm::arch_args`], followed by `flags`.
+    fn with_arch(
+        &self,
+        op: Op,
+        kws: &[&str],
+        flags: &[impl AsRef<str> + Sync],
+    ) -> (Vec<String>, Vec<String>) {
+        let (arch_flags, suffix) = self
+            .cfg()
+            .arch
+            .as_ref()
+            .and_then(|arch| self.arch_args(op, arch))
+            .unwrap_or_default();
+        let kws = kws.iter().map(|kw| format!("{}{}", kw, suffix)).collect();
+        let flags = arch_flags
+            .into_iter()
+            .chain(flags.iter().map(|f| f.as_ref().into()))
+            .collect();
+        (kws, flags)
+    }
+
     /// Executes a command in the context of the [`Pm`] implementation,
     /// with custom [`PmMode`] and [`Strategy`].
     async fn run_with(&self, cmd: Cmd, mode: PmMode, strat: &Strategy) -> Result<()> {
@@ -296,94 +1062,577 @@ trait PmHelper: Pm {
         self.run_with(cmd, PmMode::default(), &Strategy::default())
             .await
     }
+
+    /// Executes the commands of `runs` (eg. one per keyword) in the context of
+    /// the [`Pm`] implementation with default settings, running at most
+    /// [`Config::jobs`] of them at once.
+    ///
+    /// Each command comes with a message (eg. ``searching `foo` ``) printed
+    /// along with the progress before its output, if there are several
+    /// commands. When the commands are run concurrently, their outputs are
+    /// buffered, and printed along with the commands one after another in the
+    /// order of `runs`.
+    async fn run_each(&self, runs: Vec<(String, Cmd)>) -> Result<()> {
+        let cfg = self.cfg();
+        let total = runs.len();
+        let progress = |i: usize, msg: &str| {
+            if total > 1 && !cfg.describe && !cfg.json && cfg.events.is_none() && !is_capturing() {
+                print::progress(i + 1, total, msg);
+            }
+        };
+        // Nothing is to be printed in a concurrent run when the commands are not
+        // actually run, or when their output is not printed anyway.
+        let jobs = cfg.jobs.map_or(1, |jobs| jobs as usize);
+        if jobs <= 1 || cfg.dry_run || cfg.describe || cfg.json {
+            for (i, (msg, cmd)) in runs.into_iter().enumerate() {
+                progress(i, &msg);
+                self.run(cmd).await?;
+            }
+            return Ok(());
+        }
+        let runs = runs
+            .into_iter()
+            .enumerate()
+            .map(|(i, (msg, cmd))| async move {
+                let out = self
+                    .check_output(cmd.clone(), PmMode::Mute, &Strategy::default())
+                    .await?;
+                Ok((i, msg, cmd, out))
+            });
+        exec::run_grouped(runs, jobs, |(i, msg, cmd, out)| {
+            if is_capturing() {
+                capture_output(&out);
+                return Ok(());
+            }
+            progress(i, &msg);
+            print_cmd(&cmd, PROMPT_RUN);
+            print::print_output(&out);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Decodes the captured output `out` of a command, as in [`exec::decode`],
+    /// failing on invalid UTF-8 only if [`Config::strict_utf8`] is set.
+    fn decode(&self, out: Output) -> Result<String> {
+        exec::decode(out, self.cfg().strict_utf8)
+    }
+
+    /// Prints the lines of `text` matching all of `patterns`, or records them
+    /// as a package listing under `--json`, or captures them as in
+    /// [`capture`].
+    fn grep_print(&self, text: &str, patterns: &[&str]) -> Result<()> {
+        let lines = exec::grep(text, patterns)?;
+        if self.cfg().json {
+            json::record_packages(&lines);
+        } else {
+            let out = lines
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect::<String>();
+            emit_output(out.as_bytes());
+        }
+        Ok(())
+    }
 }
 
 impl<P: Pm> PmHelper for P {}
 
-/// Different ways in which a command shall be dealt with.
-/// This is a [`Pm`] specified version intended to be used along with
-/// [`Strategy`].
-#[derive(Copy, Clone, Debug)]
-enum PmMode {
-    /// Silently collects all the `stdout`/`stderr` combined. Print nothing.
-    Mute,
+/// Makes the [`Error::OperationUnimplementedError`] of the operation
+/// indicated by `method` (eg. `qk`) for `pm`, along with the nearest
+/// operations it supports instead, if any, and whether it lacks the operation
+/// altogether.
+#[must_use]
+pub(crate) fn unimplemented<P: Pm + ?Sized>(pm: &P, method: &str) -> Error {
+    let op = method.parse::<Op>().ok();
+    let alternatives = op.map_or_else(Vec::new, |op| {
+        op.nearest(pm.supported_ops())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    });
+    Error::OperationUnimplementedError {
+        op: method.into(),
+        pm: pm.name().into(),
+        alternatives,
+        lacking: op.map_or(false, |op| pm.lacking_ops().contains(&op)),
+        using: vec![],
+    }
+}
 
-    /// Prints out the command which should be executed, run it and collect its
-    /// `stdout`/`stderr` combined. Potentially dangerous as it destroys the
-    /// colored `stdout`. Use it only if really necessary.
-    CheckAll,
+/// Checks that the glob set by `--overwrite`, if any, is `*`, for `pm` whose
+/// [`Pm::overwrite_flags`] can't restrict the overwriting to some paths.
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when it is not, instead of overwriting
+/// more files than asked for.
+pub(crate) fn check_overwrite_all<P: Pm + ?Sized>(pm: &P) -> Result<()> {
+    match pm.cfg().overwrite.as_deref() {
+        Some(glob) if glob != "*" => Err(Error::ArgParseError {
+            msg: format!(
+                "`{}` can't restrict `--overwrite` to the paths matching `{}`, use `--overwrite '*'` to overwrite all the conflicting files",
+                pm.name(),
+                glob
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
 
-    /// Prints out the command which should be executed, run it and collect its
-    /// `stderr`. This will work with a colored `stdout`.
-    CheckErr,
+/// The operations which mostly talk to the network, and are thus worth
+/// retrying on failure.
+///
+/// `sy` is not among them, since it might install packages after the refresh,
+/// whose command is thus marked as network-bound by each backend instead.
+const NETWORK_BOUND_OPS: &[&str] = &["si", "ss", "sw"];
+
+/// Checks if the operation indicated by `op` (eg. `sy`) mostly talks to the
+/// network.
+#[must_use]
+pub(crate) fn is_network_bound_op(op: &str) -> bool {
+    NETWORK_BOUND_OPS.contains(&op)
 }
 
-impl Default for PmMode {
-    fn default() -> Self {
-        PmMode::CheckErr
-    }
+/// The operations which can't be run without the network, unless the package
+/// manager can run them from its local cache, as given by
+/// [`Pm::offline_flags`].
+const NETWORK_OPS: &[&str] = &["s", "si", "ss", "su", "suy", "sw", "sy"];
+
+/// Checks if the operation indicated by `op` (eg. `suy`) with the keywords
+/// `kws` needs the network, where `u` does only for the packages at a URL.
+#[must_use]
+pub(crate) fn needs_network(op: &str, kws: &[&str]) -> bool {
+    NETWORK_OPS.contains(&op) || (op == "u" && kws.iter().any(|kw| kw.contains("://")))
 }
 
-impl From<PmMode> for Mode {
-    fn from(pm_mode: PmMode) -> Self {
-        match pm_mode {
-            PmMode::Mute => Mode::Mute,
-            PmMode::CheckAll => Mode::CheckAll,
-            PmMode::CheckErr => Mode::CheckErr,
-        }
-    }
+/// Classifies a failure by the first of `patterns` found in its `output`,
+/// where each pattern is paired with its [`FailureKind`].
+#[must_use]
+fn classify_output(output: &str, patterns: &[(&str, FailureKind)]) -> Option<FailureKind> {
+    patterns
+        .iter()
+        .find(|(pat, _)| output.contains(pat))
+        .map(|&(_, kind)| kind)
 }
 
-/// A set of intrinsic properties of a command in the context of a specific
-/// package manager, indicating how it is run.
-#[derive(Clone, Debug, Default)]
-struct Strategy {
-    /// How a dry run is dealt with.
-    dry_run: DryRunStrategy,
+/// The operations which only read the package database, in addition to those
+/// of `-Q`.
+const READ_ONLY_OPS: &[Op] = &[Op::Sg, Op::Si, Op::Sii, Op::Sl, Op::Ss];
 
-    /// How the prompt is dealt with when running the package manager.
-    prompt: PromptStrategy,
+/// Checks if the operation `op` (eg. [`Op::Suy`]) might modify the package
+/// database, and should thus be run under the [`lock`](crate::lock).
+#[must_use]
+pub(crate) fn is_locking_op(op: Op) -> bool {
+    !op.method().starts_with('q') && !READ_ONLY_OPS.contains(&op)
+}
 
-    /// How the cache is cleaned when `no_cache` is set to `true`.
-    no_cache: NoCacheStrategy,
+tokio::task_local! {
+    /// Whether the commands being run are network-bound.
+    static NETWORK_BOUND: bool;
 }
 
-/// How a dry run is dealt with.
-#[derive(Debug, Clone)]
-enum DryRunStrategy {
-    /// Prints the command to be run, and stop.
-    PrintCmd,
-    /// Invokes the corresponding package manager with the flags given.
-    WithFlags(Vec<String>),
+/// Runs `fut` with all of its commands marked as network-bound, so that they
+/// are retried on failure according to [`Config::retries`].
+pub(crate) async fn network_bound<F: Future>(fut: F) -> F::Output {
+    NETWORK_BOUND.scope(true, fut).await
 }
 
-impl DryRunStrategy {
-    /// Invokes the corresponding package manager with the flags given.
-    #[must_use]
-    fn with_flags(flags: &[impl AsRef<str>]) -> Self {
-        Self::WithFlags(flags.iter().map(|s| s.as_ref().into()).collect())
+/// Checks if the commands being run are network-bound.
+#[must_use]
+fn is_network_bound() -> bool {
+    NETWORK_BOUND.try_with(|&b| b).unwrap_or(false)
+}
+
+tokio::task_local! {
+    /// The operation whose commands being run should be kept offline.
+    static OFFLINE_OP: Op;
+}
+
+/// Runs `fut` with the native flags given by [`Pm::offline_flags`] for the
+/// operation `op` (if any) appended to each of its commands, so that they only
+/// use the local cache under `--offline`.
+pub(crate) async fn offline<F: Future>(op: Option<Op>, fut: F) -> F::Output {
+    match op {
+        Some(op) => OFFLINE_OP.scope(op, fut).await,
+        None => fut.await,
     }
 }
 
-impl Default for DryRunStrategy {
-    fn default() -> Self {
-        DryRunStrategy::PrintCmd
+tokio::task_local! {
+    /// How the commands being run are elevated on Windows.
+    static ELEVATION: Elevation;
+}
+
+/// Runs `fut` with each of its commands run by the package manager itself
+/// elevated as given by `elevation`, if any.
+pub(crate) async fn elevated<F: Future>(elevation: Option<Elevation>, fut: F) -> F::Output {
+    match elevation {
+        Some(elevation) => ELEVATION.scope(elevation, fut).await,
+        None => fut.await,
     }
 }
 
-/// How the prompt is dealt with when running the package manager.
-#[derive(Debug, Clone)]
-enum PromptStrategy {
-    /// There is no prompt.
-    None,
-    /// There is no prompt, but a custom prompt is added.
-    CustomPrompt,
-    /// There is a native prompt provided by the package manager
+tokio::task_local! {
+    /// Whether the output of the commands being run is to be prefixed with the
+    /// commands themselves.
+    static STAGED: bool;
+}
+
+/// Runs `fut` with each line of the output of its commands prefixed with the
+/// command it comes from, eg. `[brew update]`, so that the stages of an
+/// operation running several commands can be told apart.
+pub(crate) async fn staged<F: Future>(fut: F) -> F::Output {
+    STAGED.scope(true, fut).await
+}
+
+/// Checks if the output of the commands being run is to be prefixed.
+#[must_use]
+fn is_staged() -> bool {
+    STAGED.try_with(|&b| b).unwrap_or(false)
+}
+
+tokio::task_local! {
+    /// The number of the commands described so far, if they are to be counted.
+    static DESCRIBED: Arc<AtomicUsize>;
+}
+
+/// Runs `fut` on a [`Pm`] with [`Config::describe`] set, returning the number
+/// of commands it would run instead of printing them.
+///
+/// As in `pacaptr help`, the commands depending on a query might not be
+/// exactly the same as in an actual run.
+pub(crate) async fn count_cmds<F: Future>(fut: F) -> usize {
+    let count = Arc::<AtomicUsize>::default();
+    DESCRIBED.scope(Arc::clone(&count), fut).await;
+    count.load(Ordering::SeqCst)
+}
+
+/// Counts a command described under [`count_cmds`], returning whether the
+/// commands are being counted.
+fn count_described() -> bool {
+    DESCRIBED
+        .try_with(|count| count.fetch_add(1, Ordering::SeqCst))
+        .is_ok()
+}
+
+tokio::task_local! {
+    /// The combined output of the commands being run, if it is to be captured.
+    static CAPTURED: Arc<Mutex<Output>>;
+}
+
+/// Runs `fut` with all of its commands muted, returning the result of `fut`
+/// along with the output which would have been printed otherwise.
+///
+/// This includes the combined output of the commands which are not muted
+/// anyway, and the lines printed by [`PmHelper::grep_print`], but not the
+/// messages of [`pacaptr`](crate) itself.
+pub(crate) async fn capture<F: Future>(fut: F) -> (F::Output, Output) {
+    let buf = Arc::default();
+    let res = CAPTURED.scope(Arc::clone(&buf), fut).await;
+    let out = mem::take(&mut *buf.lock().unwrap());
+    (res, out)
+}
+
+/// Checks if the output of the commands being run is to be captured.
+#[must_use]
+fn is_capturing() -> bool {
+    CAPTURED.try_with(|_| ()).is_ok()
+}
+
+/// Appends `out` to the captured output, if the output of the commands being
+/// run is to be captured.
+pub(crate) fn capture_output(out: &[u8]) {
+    let _ = CAPTURED.try_with(|buf| buf.lock().unwrap().extend(out));
+}
+
+/// Prints out the output `out` collected from the commands run, or captures
+/// it again as in [`capture`].
+pub(crate) fn emit_output(out: &[u8]) {
+    if is_capturing() {
+        capture_output(out);
+    } else {
+        print::print_output(out);
+    }
+}
+
+/// Converts the glob `pattern` (eg. `*/bin/*`) into a regex matching the whole
+/// line, where `*` and `?` match any characters (including `/`), and `[...]`
+/// is a character class. A pattern without any of them matches any line
+/// containing it instead.
+#[must_use]
+fn glob_regex(pattern: &str) -> String {
+    if !pattern.contains(|c: char| "*?[".contains(c)) {
+        return regex::escape(pattern);
+    }
+    let mut re = "(?s)^".to_owned();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re += ".*",
+            '?' => re += ".",
+            '[' if chars.clone().any(|c| c == ']') => {
+                re.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    re.push('^');
+                }
+                for c in chars.by_ref().take_while(|&c| c != ']') {
+                    if "\\[&~".contains(c) {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            _ => re += &regex::escape(&c.to_string()),
+        }
+    }
+    re + "$"
+}
+
+/// Gets the lines of `text` (eg. the paths listed by `ql`) matching the glob
+/// `pattern`, as in [`glob_regex`].
+///
+/// # Errors
+/// Returns an [`Error::ArgParseError`] when `pattern` is ill-formed.
+fn filter_paths<'t>(text: &'t str, pattern: &str) -> Result<Vec<&'t str>> {
+    let re = regex::Regex::new(&glob_regex(pattern)).map_err(|_e| Error::ArgParseError {
+        msg: format!("Filter `{}` is ill-formed", pattern),
+    })?;
+    Ok(text
+        .lines()
+        .filter(|line| re.is_match(line.trim()))
+        .collect())
+}
+
+/// Runs `fut` (ie. `ql`) with its output captured, then prints only the lines
+/// matching the glob `pattern`, as set by `--filter`, or captures them as in
+/// [`capture`].
+///
+/// # Errors
+/// Returns the error of `fut`, if any, or an [`Error::ArgParseError`] when
+/// `pattern` is ill-formed.
+pub(crate) async fn filtered<F>(pattern: &str, fut: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let (res, out) = capture(fut).await;
+    let text = String::from_utf8_lossy(&out);
+    let out = filter_paths(&text, pattern)?
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    emit_output(out.as_bytes());
+    res
+}
+
+/// Runs `fut` (ie. `q` or `ss`) with its output captured, then prints each
+/// of the packages parsed from it by [`Pm::parse_query_output`] with
+/// `template`, as set by `--print-format`, or captures them as in
+/// [`capture`]. The output is printed as is if it can't be parsed.
+///
+/// # Errors
+/// Returns the error of `fut`, if any.
+pub(crate) async fn formatted<F>(pm: &dyn Pm, op: &str, template: &str, fut: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let (res, out) = capture(fut).await;
+    let rendered = pm
+        .parse_query_output(op, &String::from_utf8_lossy(&out))
+        .map(|pkgs| {
+            pkgs.iter()
+                .map(|pkg| pkg.render(template) + "\n")
+                .collect::<String>()
+        });
+    emit_output(rendered.as_ref().map_or(&out[..], String::as_bytes));
+    res
+}
+
+/// Runs `fut` (ie. a query) with its output captured, then prints only its
+/// first `max_lines` lines, as set by `--max-lines`, or captures them as in
+/// [`capture`].
+///
+/// # Errors
+/// Returns the error of `fut`, if any.
+pub(crate) async fn truncated<F>(max_lines: usize, fut: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let (res, out) = capture(fut).await;
+    emit_output(&print::truncate_output(&out, max_lines));
+    res
+}
+
+/// Checks if `pm` can remove the orphans left behind by `r`, as set by
+/// `--autoremove`, either with [`Pm::autoremove_flags`] or by listing them
+/// with `qdt`.
+#[must_use]
+pub(crate) fn can_autoremove(pm: &dyn Pm) -> bool {
+    pm.autoremove_flags().is_some() || pm.supported_ops().contains(&Op::Qdt)
+}
+
+/// Removes the packages `kws` with `r`, along with the orphans left behind, as
+/// set by `--autoremove`: either at once with [`Pm::autoremove_flags`], or
+/// afterwards with [`Pm::remove_orphans`].
+///
+/// # Errors
+/// Returns the error of the first command which fails, if any.
+pub(crate) async fn autoremoved(pm: &dyn Pm, kws: &[&str], flags: &[&str]) -> Result<()> {
+    match pm.autoremove_flags() {
+        Some(autoremove) => {
+            let flags = autoremove
+                .iter()
+                .map(String::as_str)
+                .chain(flags.iter().copied());
+            pm.r(kws, &flags.collect::<Vec<_>>()).await
+        }
+        None => {
+            pm.r(kws, flags).await?;
+            pm.remove_orphans(&[], flags).await
+        }
+    }
+}
+
+/// The delay before the first retry of a failed network-bound command, which
+/// doubles after each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs `f` (ie. `cmd`) until it succeeds, retrying at most
+/// [`Config::retries`] times with an exponential backoff starting from `delay`.
+///
+/// Only the failures caused by a non-zero exit code are retried, so that an
+/// interruption stops everything right away.
+async fn retry<F, Fut>(cfg: &Config, cmd: &Cmd, delay: Duration, mut f: F) -> Result<Output>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Output>>,
+{
+    let retries = cfg.retries;
+    let mut res = f().await;
+    for attempt in 1..=retries {
+        let code = match &res {
+            Err(Error::CmdStatusCodeError { code, .. }) => *code,
+            _ => break,
+        };
+        let delay = delay * 2_u32.pow(attempt - 1);
+        if let Some(format) = cfg.events {
+            print::emit(&Event::retry(cmd, code, attempt, retries, delay), format);
+        } else {
+            print_warning(
+                format!(
+                    "Command failed with code {}, retrying in {:?} ({}/{})",
+                    code, delay, attempt, retries
+                ),
+                PROMPT_WARNING,
+            );
+        }
+        tokio::time::sleep(delay).await;
+        res = f().await;
+    }
+    res
+}
+
+/// Different ways in which a command shall be dealt with.
+/// This is a [`Pm`] specified version intended to be used along with
+/// [`Strategy`].
+#[derive(Copy, Clone, Debug)]
+enum PmMode {
+    /// Silently collects all the `stdout`/`stderr` combined. Print nothing.
+    Mute,
+
+    /// Prints out the command which should be executed, run it and collect its
+    /// `stdout`/`stderr` combined. Potentially dangerous as it destroys the
+    /// colored `stdout`. Use it only if really necessary.
+    CheckAll,
+
+    /// Prints out the command which should be executed, run it and collect its
+    /// `stderr`. This will work with a colored `stdout`.
+    CheckErr,
+}
+
+impl Default for PmMode {
+    fn default() -> Self {
+        PmMode::CheckErr
+    }
+}
+
+impl From<PmMode> for Mode {
+    fn from(pm_mode: PmMode) -> Self {
+        match pm_mode {
+            PmMode::Mute => Mode::Mute,
+            PmMode::CheckAll => Mode::CheckAll,
+            PmMode::CheckErr => Mode::CheckErr,
+        }
+    }
+}
+
+/// A set of intrinsic properties of a command in the context of a specific
+/// package manager, indicating how it is run.
+#[derive(Clone, Debug, Default)]
+struct Strategy {
+    /// How a dry run is dealt with.
+    dry_run: DryRunStrategy,
+
+    /// How the prompt is dealt with when running the package manager.
+    prompt: PromptStrategy,
+
+    /// How the cache is cleaned when `no_cache` is set to `true`.
+    no_cache: NoCacheStrategy,
+
+    /// How the prompt is answered with "no" when `assume_no` is set to `true`.
+    assume_no: AssumeNoStrategy,
+
+    /// How the prompt is answered with "yes" when `assume_yes` is set to
+    /// `true`.
+    assume_yes: AssumeYesStrategy,
+
+    /// How the progress output is quieted when the output is collected instead
+    /// of being printed, eg. under `--json`.
+    capture: CaptureStrategy,
+}
+
+/// How a dry run is dealt with.
+#[derive(Debug, Clone)]
+enum DryRunStrategy {
+    /// Prints the command to be run, and stop.
+    PrintCmd,
+    /// Invokes the corresponding package manager with the flags given.
+    WithFlags(Vec<String>),
+}
+
+impl DryRunStrategy {
+    /// Invokes the corresponding package manager with the flags given.
+    #[must_use]
+    fn with_flags(flags: &[impl AsRef<str>]) -> Self {
+        Self::WithFlags(flags.iter().map(|s| s.as_ref().into()).collect())
+    }
+}
+
+impl Default for DryRunStrategy {
+    fn default() -> Self {
+        DryRunStrategy::PrintCmd
+    }
+}
+
+/// How the prompt is dealt with when running the package manager.
+#[derive(Debug, Clone)]
+enum PromptStrategy {
+    /// There is no prompt.
+    None,
+    /// There is no prompt, but a custom prompt is added.
+    CustomPrompt,
+    /// There is a native prompt provided by the package manager
     /// that can be disabled with a flag.
     NativeNoConfirm(Vec<String>),
     /// There is a native prompt provided by the package manager
     /// that can be enabled with a flag.
     NativeConfirm(Vec<String>),
+    /// There is a native prompt provided by the package manager
+    /// that can only be skipped by answering it on `stdin`.
+    AnswerStdin {
+        /// The answer to each question, eg. `y\n`.
+        answer: String,
+    },
 }
 
 impl PromptStrategy {
@@ -400,8 +1649,28 @@ impl PromptStrategy {
     fn native_confirm(confirm: &[impl AsRef<str>]) -> Self {
         Self::NativeConfirm(confirm.iter().map(|s| s.as_ref().into()).collect())
     }
+
+    /// There is a native prompt provided by the package manager
+    /// that can only be skipped by answering it on `stdin`.
+    #[must_use]
+    fn answer_stdin(answer: impl Into<String>) -> Self {
+        Self::AnswerStdin {
+            answer: answer.into(),
+        }
+    }
+
+    /// There is a native prompt provided by the package manager
+    /// that can only be skipped by answering [`DEFAULT_ANSWER`] on `stdin`.
+    #[must_use]
+    fn answer_yes() -> Self {
+        Self::answer_stdin(DEFAULT_ANSWER)
+    }
 }
 
+/// The answer given on `stdin` to the questions of a package manager under
+/// [`PromptStrategy::AnswerStdin`], unless otherwise specified.
+const DEFAULT_ANSWER: &str = "y\n";
+
 impl Default for PromptStrategy {
     fn default() -> Self {
         PromptStrategy::None
@@ -438,3 +1707,1230 @@ impl Default for NoCacheStrategy {
         NoCacheStrategy::None
     }
 }
+
+/// How the progress output (eg. the progress bars) is quieted when the output is
+/// collected instead of being printed, so that it is not corrupted.
+#[derive(Debug, Clone)]
+enum CaptureStrategy {
+    /// Leaves the command untouched.
+    None,
+    /// Invokes the corresponding package manager with the flags given.
+    WithFlags(Vec<String>),
+}
+
+impl CaptureStrategy {
+    /// Invokes the corresponding package manager with the flags given.
+    #[must_use]
+    fn with_flags(flags: &[impl AsRef<str>]) -> Self {
+        Self::WithFlags(flags.iter().map(|s| s.as_ref().into()).collect())
+    }
+}
+
+impl Default for CaptureStrategy {
+    fn default() -> Self {
+        CaptureStrategy::None
+    }
+}
+
+/// How the prompt is answered with "yes" when `assume_yes` is set to `true`.
+#[derive(Debug, Clone)]
+enum AssumeYesStrategy {
+    /// Answers as under `no_confirm`, following the [`PromptStrategy`].
+    NoConfirm,
+    /// Invokes the corresponding package manager with the flags given.
+    WithFlags(Vec<String>),
+}
+
+impl AssumeYesStrategy {
+    /// Invokes the corresponding package manager with the flags given.
+    #[must_use]
+    fn with_flags(flags: &[impl AsRef<str>]) -> Self {
+        Self::WithFlags(flags.iter().map(|s| s.as_ref().into()).collect())
+    }
+}
+
+impl Default for AssumeYesStrategy {
+    fn default() -> Self {
+        AssumeYesStrategy::NoConfirm
+    }
+}
+
+/// How the prompt is answered with "no" when `assume_no` is set to `true`.
+#[derive(Debug, Clone)]
+enum AssumeNoStrategy {
+    /// Prints the command as canceled, without running it.
+    Cancel,
+    /// Invokes the corresponding package manager with the flags given.
+    WithFlags(Vec<String>),
+    /// Invokes the corresponding package manager with the flags given, which
+    /// also serve as the simulation set by `simulate`.
+    ///
+    /// The package manager answering "no" exits with `code`, with `msg` in its
+    /// output (under `LC_ALL=C`), which is taken as a success.
+    Simulating {
+        /// The flags answering "no", eg. `--assumeno`.
+        flags: Vec<String>,
+        /// The exit code of the package manager answering "no".
+        code: StatusCode,
+        /// The message printed by the package manager answering "no".
+        msg: String,
+    },
+}
+
+impl AssumeNoStrategy {
+    /// Invokes the corresponding package manager with the flags given.
+    #[must_use]
+    fn with_flags(flags: &[impl AsRef<str>]) -> Self {
+        Self::WithFlags(flags.iter().map(|s| s.as_ref().into()).collect())
+    }
+
+    /// Invokes the corresponding package manager with the flags given, also
+    /// under `simulate`, taking its exit with `code` and `msg` as a success.
+    #[must_use]
+    fn simulating(flags: &[impl AsRef<str>], code: StatusCode, msg: impl Into<String>) -> Self {
+        Self::Simulating {
+            flags: flags.iter().map(|s| s.as_ref().into()).collect(),
+            code,
+            msg: msg.into(),
+        }
+    }
+
+    /// Checks if the flags given also serve as the simulation.
+    #[must_use]
+    fn is_simulating(&self) -> bool {
+        matches!(self, Self::Simulating { .. })
+    }
+}
+
+impl Default for AssumeNoStrategy {
+    fn default() -> Self {
+        AssumeNoStrategy::Cancel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        iter,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use indoc::indoc;
+    use itertools::Itertools;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        print::{collect_lines, EventFormat},
+        test_util::assert_cmds,
+    };
+
+    /// Runs [`retry`], where `f` fails with `err` on its first `failures`
+    /// calls. Returns the result along with the number of calls.
+    async fn count_retries(
+        retries: u32,
+        failures: u32,
+        err: fn() -> Error,
+    ) -> (Result<Output>, u32) {
+        let calls = AtomicU32::new(0);
+        let cfg = Config {
+            retries,
+            events: Some(EventFormat::Json),
+            ..Config::default()
+        };
+        let cmd = Cmd::new(&["mockpm", "update"]);
+        let res = retry(&cfg, &cmd, Duration::from_millis(1), || {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call < failures {
+                    Err(err())
+                } else {
+                    Ok(Output::default())
+                }
+            }
+        })
+        .await;
+        (res, calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn unclassified_exit_code() {
+        let run = |raw_exit_code| async move {
+            let apt = Apt::new(Config {
+                raw_exit_code,
+                ..Config::default()
+            });
+            let cmd = Cmd::new(&["sh", "-c", "exit 100"]);
+            apt.check_output(cmd, PmMode::Mute, &Strategy::default())
+                .await
+                .unwrap_err()
+                .exit_code()
+        };
+        // The raw code of `apt` would be taken as `NotFound` otherwise.
+        assert_eq!(run(false).await, 1);
+        assert_eq!(run(true).await, 100);
+    }
+
+    fn status_code_error() -> Error {
+        Error::CmdStatusCodeError {
+            code: 100,
+            output: Output::default(),
+            kind: None,
+            raw: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_until_success() {
+        let ((res, calls), events) =
+            print::collect_events(count_retries(3, 2, status_code_error)).await;
+        assert!(res.is_ok());
+        assert_eq!(calls, 3);
+        let attempts = events
+            .iter()
+            .map(|event| match event {
+                Event::Retry {
+                    attempt, delay_ms, ..
+                } => (*attempt, *delay_ms),
+                _ => panic!("unexpected event: {:?}", event),
+            })
+            .collect_vec();
+        assert_eq!(attempts, [(1, 1), (2, 2)]);
+    }
+
+    #[tokio::test]
+    async fn retry_exhausted() {
+        let (res, calls) = count_retries(2, 5, status_code_error).await;
+        assert!(matches!(
+            res,
+            Err(Error::CmdStatusCodeError { code: 100, .. })
+        ));
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn retry_disabled() {
+        let (res, calls) = count_retries(0, 5, status_code_error).await;
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn retry_interrupted() {
+        let (res, calls) = count_retries(3, 5, || Error::CmdInterruptedError).await;
+        assert!(matches!(res, Err(Error::CmdInterruptedError)));
+        assert_eq!(calls, 1);
+    }
+
+    /// A package manager searching for each keyword separately, upgrading in
+    /// several commands, and listing a fixed set of orphans.
+    struct MockPm {
+        cfg: Config,
+    }
+
+    #[async_trait]
+    impl Pm for MockPm {
+        fn name(&self) -> &str {
+            "mockpm"
+        }
+
+        fn cfg(&self) -> &Config {
+            &self.cfg
+        }
+
+        async fn ss(&self, kws: &[&str], _flags: &[&str]) -> Result<()> {
+            // The later searches finish first.
+            let cmds = kws
+                .iter()
+                .enumerate()
+                .map(|(i, kw)| {
+                    let script = format!("sleep 0.{}; echo {}-1; echo {}-2", kws.len() - i, kw, kw);
+                    (
+                        format!("searching `{}`", kw),
+                        Cmd::new(&["sh", "-c", &script]),
+                    )
+                })
+                .collect();
+            self.run_each(cmds).await
+        }
+
+        async fn su(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+            self.run(Cmd::new(&["mockpm", "update"]).flags(flags))
+                .await?;
+            self.run(Cmd::new(&["mockpm", "upgrade"]).flags(flags))
+                .await
+        }
+
+        async fn q(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+            let script = "echo foo; echo bar >&2; exit 3";
+            self.run(Cmd::new(&["sh", "-c", script]).flags(flags)).await
+        }
+
+        async fn qdt(&self, kws: &[&str], _flags: &[&str]) -> Result<()> {
+            self.grep_print("libfoo1\nlibbar2", kws)
+        }
+
+        async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+            self.run(Cmd::new(&["mockpm", "remove"]).kws(kws).flags(flags))
+                .await
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn run_each_grouped() {
+        for &jobs in &[None, Some(3)] {
+            let pm = MockPm {
+                cfg: Config {
+                    jobs,
+                    ..Config::default()
+                },
+            };
+            let (res, out) = capture(pm.ss(&["a", "b", "c"], &[])).await;
+            res.unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&out),
+                "a-1\na-2\nb-1\nb-2\nc-1\nc-2\n"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn count_described_cmds() {
+        let pm = Apt::new(Config {
+            describe: true,
+            ..Config::default()
+        });
+        assert_eq!(count_cmds(pm.s(&["curl"], &[])).await, 1);
+        assert_eq!(count_cmds(pm.suy(&[], &[])).await, 3);
+    }
+
+    #[tokio::test]
+    async fn staged_scope() {
+        assert!(!is_staged());
+        assert!(staged(async { is_staged() }).await);
+        assert!(!is_staged());
+    }
+
+    #[tokio::test]
+    async fn network_bound_scope() {
+        assert!(!is_network_bound());
+        assert!(network_bound(async { is_network_bound() }).await);
+        assert!(!is_network_bound());
+    }
+
+    #[test]
+    fn classify_failures() {
+        use FailureKind::{Aborted, NetworkError, NotFound};
+
+        let cases: &[(&str, StatusCode, &str, Option<FailureKind>)] = &[
+            (
+                "apt",
+                100,
+                "E: Unable to locate package foo",
+                Some(NotFound),
+            ),
+            (
+                "apt",
+                100,
+                "E: Failed to fetch http://deb.debian.org/",
+                Some(NetworkError),
+            ),
+            (
+                "apt",
+                1,
+                "Do you want to continue? [Y/n] n\nAbort.",
+                Some(Aborted),
+            ),
+            (
+                "apt",
+                100,
+                "E: Sub-process /usr/bin/dpkg returned an error",
+                None,
+            ),
+            ("dnf", 1, "No match for argument: foo", Some(NotFound)),
+            (
+                "dnf",
+                1,
+                "Curl error (6): Couldn't resolve host name",
+                Some(NetworkError),
+            ),
+            ("dnf", 1, "Operation aborted.", Some(Aborted)),
+            (
+                "brew",
+                1,
+                "Error: No available formula with the name \"foo\".",
+                Some(NotFound),
+            ),
+            (
+                "brew",
+                1,
+                "curl: (6) Could not resolve host: ghcr.io",
+                Some(NetworkError),
+            ),
+            (
+                "pip",
+                1,
+                "ERROR: No matching distribution found for foo",
+                Some(NotFound),
+            ),
+            (
+                "pip",
+                1,
+                "Failed to establish a new connection",
+                Some(NetworkError),
+            ),
+            ("zypper", 104, "", Some(NotFound)),
+            ("zypper", 105, "", Some(Aborted)),
+            (
+                "zypper",
+                8,
+                "Download (curl) error for 'https://download.opensuse.org/'",
+                Some(NetworkError),
+            ),
+            (
+                "zypper",
+                8,
+                "Problem occurred during or after installation",
+                None,
+            ),
+            (
+                "choco",
+                1,
+                "foo not installed. The package was not found",
+                Some(NotFound),
+            ),
+            (
+                "apk",
+                1,
+                "ERROR: unable to select packages:",
+                Some(NotFound),
+            ),
+            (
+                "apk",
+                1,
+                "temporary error (try again later)",
+                Some(NetworkError),
+            ),
+            (
+                "conda",
+                1,
+                "PackagesNotFoundError: The following packages are not available",
+                Some(NotFound),
+            ),
+            (
+                "conda",
+                1,
+                "CondaHTTPError: HTTP 000 CONNECTION FAILED",
+                Some(NetworkError),
+            ),
+            ("conda", 1, "CondaSystemExit: Exiting.", Some(Aborted)),
+            // The exit code is passed through by default.
+            ("port", 1, "Error: Port foo not found", None),
+        ];
+        for &(name, code, output, kind) in cases {
+            let pm = (registry::find(name).unwrap().constructor)(Config::default());
+            assert_eq!(
+                pm.classify(code, output),
+                kind,
+                "`{}` exiting with {}: {}",
+                name,
+                code,
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn answer_stdin_under_no_confirm() {
+        let strat = Strategy {
+            prompt: PromptStrategy::answer_yes(),
+            ..Strategy::default()
+        };
+        let cmd = || Cmd::new(&["tlmgr", "remove", "foo"]);
+        let (cmd, mode) = prompted(&Config::default(), cmd(), PmMode::default(), &strat).unwrap();
+        assert_eq!(cmd.answer, None);
+        assert!(matches!(mode, Mode::CheckErr));
+
+        let cfg = Config {
+            no_confirm: true,
+            ..Config::default()
+        };
+        let (cmd, _) = prompted(&cfg, cmd(), PmMode::default(), &strat).unwrap();
+        assert_eq!(cmd.answer.as_deref(), Some(DEFAULT_ANSWER));
+    }
+
+    #[test]
+    fn informational_codes() {
+        let cases: &[(&str, StatusCode, bool)] = &[
+            ("zypper", 102, true),
+            ("zypper", 104, false),
+            ("zypper", 106, true),
+            ("apt", 100, false),
+        ];
+        for &(name, code, informational) in cases {
+            let pm = (registry::find(name).unwrap().constructor)(Config::default());
+            assert_eq!(
+                pm.is_informational(code),
+                informational,
+                "`{}` exiting with {}",
+                name,
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn root_flags_spelling() {
+        let cases: &[(&str, &str, Option<&[&str]>)] = &[
+            ("apk", "apk", Some(&["--root", "/mnt"])),
+            ("apk", "rm", None),
+            ("apt", "apt", Some(&["-o", "Dir=/mnt"])),
+            ("apt", "apt-mark", Some(&["-o", "Dir=/mnt"])),
+            ("apt", "dpkg-query", Some(&["--admindir=/mnt/var/lib/dpkg"])),
+            ("apt", "dpkg-deb", Some(&[])),
+            ("dnf", "dnf", Some(&["--installroot=/mnt"])),
+            ("dnf", "rpm", Some(&["--root", "/mnt"])),
+            ("emerge", "emerge", Some(&["--root=/mnt"])),
+            ("emerge", "qlist", Some(&["--root", "/mnt"])),
+            ("emerge", "eclean-dist", None),
+            ("zypper", "zypper", Some(&["--root", "/mnt"])),
+            ("zypper", "rpm", Some(&["--root", "/mnt"])),
+            ("brew", "brew", None),
+            ("scoop", "scoop", None),
+        ];
+        for &(name, exe, flags) in cases {
+            let pm = (registry::find(name).unwrap().constructor)(Config::default());
+            assert_eq!(
+                pm.root_flags(exe, "/mnt").as_deref(),
+                flags
+                    .map(|flags| flags.iter().map(|&f| f.to_owned()).collect::<Vec<_>>())
+                    .as_deref(),
+                "`{}` running `{}`",
+                name,
+                exe
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn root_flags_refused() {
+        let pm = Apk::new(Config {
+            dry_run: true,
+            root: Some("/mnt".into()),
+            ..Config::default()
+        });
+        let (res, stats) = stat::collect(pm.scc(&[], &[])).await;
+        assert!(matches!(
+            res,
+            Err(Error::OperationUnimplementedError { op, pm, .. }) if op == "--root" && pm == "rm"
+        ));
+        assert!(stats.is_empty());
+    }
+
+    #[tokio::test]
+    async fn root_flags_inserted() {
+        let pm = Dnf::new(Config {
+            dry_run: true,
+            root: Some("/mnt".into()),
+            ..Config::default()
+        });
+        let (res, stats) = stat::collect(pm.s(&["curl"], &[])).await;
+        res.unwrap();
+        assert!(stats[0]
+            .cmd
+            .ends_with("dnf --installroot=/mnt install curl"));
+
+        let (res, stats) = stat::collect(pm.ql(&["curl"], &[])).await;
+        res.unwrap();
+        assert!(stats[0].cmd.ends_with("rpm --root /mnt -ql curl"));
+    }
+
+    #[tokio::test]
+    async fn proxy_translated() {
+        let url = "http://proxy:3128";
+        let cfg = Config {
+            dry_run: true,
+            proxy: Proxy {
+                http: Some(url.into()),
+                https: Some(url.into()),
+                no_proxy: Some("localhost".into()),
+            },
+            ..Config::default()
+        };
+        // The auxiliary tools not reaching the network are left untouched.
+        let apt = Apt::new(cfg.clone());
+        assert!(apt.proxy_flags("apt-get", &cfg.proxy).is_some());
+        assert!(apt.proxy_flags("apt-mark", &cfg.proxy).is_none());
+        assert!(Dnf::new(cfg.clone())
+            .proxy_flags("rpm", &cfg.proxy)
+            .is_none());
+
+        let run = async {
+            Apt::new(cfg.clone()).sy(&[], &[]).await?;
+            Dnf::new(cfg.clone()).sy(&[], &[]).await?;
+            Brew::new(cfg.clone()).sy(&[], &[]).await
+        };
+        let (res, stats) = stat::collect(run).await;
+        res.unwrap();
+        let cmds = stats.iter().map(|stat| stat.cmd.as_str()).collect_vec();
+        // The commands might be prefixed with `sudo -S --preserve-env=...`.
+        let envs = format!(
+            "http_proxy={0} HTTP_PROXY={0} https_proxy={0} HTTPS_PROXY={0} \
+             no_proxy=localhost NO_PROXY=localhost ",
+            url
+        );
+        let expected = [
+            format!(
+                "apt -o Acquire::http::Proxy={0} -o Acquire::http::Proxy::localhost=DIRECT \
+                 -o Acquire::https::Proxy={0} -o Acquire::https::Proxy::localhost=DIRECT update",
+                url
+            ),
+            format!("dnf --setopt=proxy={} makecache --refresh", url),
+            "brew update".to_owned(),
+        ];
+        assert_eq!(cmds.len(), expected.len(), "{:?}", cmds);
+        for (cmd, expected) in cmds.iter().zip(&expected) {
+            assert!(cmd.starts_with(&envs), "`{}`", cmd);
+            assert!(cmd.ends_with(expected.as_str()), "`{}`", cmd);
+        }
+    }
+
+    #[test]
+    fn version_cmds() {
+        let cases: &[(Box<dyn Pm>, &[&str])] = &[
+            (Apt::new(Config::default()).boxed(), &["apt", "--version"]),
+            (Dnf::new(Config::default()).boxed(), &["dnf", "--version"]),
+            (Port::new(Config::default()).boxed(), &["port", "version"]),
+            (
+                Scoop::new(Config::default()).boxed(),
+                &["powershell", "scoop", "--version"],
+            ),
+        ];
+        for (pm, argv) in cases {
+            assert_eq!(pm.version_cmd().argv(), *argv, "for `{}`", pm.name());
+        }
+    }
+
+    #[tokio::test]
+    async fn unimplemented_alternatives() {
+        let pm = Equo::new(Config::default());
+        let err = pm.sii(&[], &[]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operation `-Sii` is unimplemented for `equo`, as it is not mapped by `pacaptr` yet \
+             (supported alternatives: `-Si`, `-S`, `-Ss`); \
+             see https://rami3l.github.io/pacaptr/pacaptr/#compatibility-table"
+        );
+        let err = pm.rns(&[], &[]).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::OperationUnimplementedError { alternatives, .. } if alternatives == &["-R"]
+        ));
+        // The operations outside of `Op` get no suggestion.
+        let err = pm.hold(&[], &[]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operation `hold` is unimplemented for `equo`"
+        );
+    }
+
+    #[tokio::test]
+    async fn sy_refresh_only() {
+        let cfg = || Config {
+            describe: true,
+            ..Config::default()
+        };
+        let cases: &[(Box<dyn Pm>, &str)] = &[
+            (Apk::new(cfg()).boxed(), "apk update`"),
+            (Apt::new(cfg()).boxed(), "apt update`"),
+            (Brew::new(cfg()).boxed(), "brew update`"),
+            (Dnf::new(cfg()).boxed(), "dnf makecache --refresh`"),
+            (Emerge::new(cfg()).boxed(), "emerge --sync`"),
+            (Equo::new(cfg()).boxed(), "equo update`"),
+            (Port::new(cfg()).boxed(), "port selfupdate`"),
+            (Zypper::new(cfg()).boxed(), "zypper refresh`"),
+        ];
+        let (_, lines) = collect_lines(async {
+            for (pm, _) in cases {
+                pm.sy(&[], &[]).await.unwrap();
+            }
+        })
+        .await;
+        // The commands might be prefixed with `sudo -S`, but nothing else is run.
+        assert_cmds(&lines, &cases.iter().map(|(_, cmd)| *cmd).collect_vec());
+    }
+
+    #[tokio::test]
+    async fn self_upgrade_sequences() {
+        let cfg = |no_self_upgrade| Config {
+            describe: true,
+            no_self_upgrade,
+            ..Config::default()
+        };
+        let (_, lines) = collect_lines(async {
+            for &no_self_upgrade in &[false, true] {
+                let choco = Choco::new(cfg(no_self_upgrade));
+                let scoop = Scoop::new(cfg(no_self_upgrade));
+                choco.suy(&[], &[]).await.unwrap();
+                choco.su(&["curl"], &[]).await.unwrap();
+                scoop.su(&[], &[]).await.unwrap();
+                scoop.suy(&[], &[]).await.unwrap();
+                scoop.su(&["curl"], &[]).await.unwrap();
+            }
+        })
+        .await;
+        let expected = [
+            "choco upgrade chocolatey`",
+            "choco upgrade all`",
+            "choco upgrade curl`",
+            "powershell scoop update`",
+            "powershell scoop update *`",
+            "powershell scoop update`",
+            "powershell scoop update *`",
+            "powershell scoop update curl`",
+            // Under `no_self_upgrade`.
+            "choco upgrade all`",
+            "choco upgrade curl`",
+            "powershell scoop update *`",
+            "powershell scoop update`",
+            "powershell scoop update *`",
+            "powershell scoop update curl`",
+        ];
+        assert_cmds(&lines, &expected);
+    }
+
+    #[tokio::test]
+    async fn simulate_flags_added() {
+        let cfg = Config {
+            describe: true,
+            simulate: true,
+            ..Config::default()
+        };
+        let zypper = Zypper::new(cfg);
+        let (_, lines) = collect_lines(async {
+            zypper.s(&["curl"], &[]).await.unwrap();
+            zypper.suy(&[], &[]).await.unwrap();
+            zypper.r(&["curl"], &[]).await.unwrap();
+        })
+        .await;
+        // The commands might be prefixed with `sudo -S`.
+        let expected = [
+            "zypper install --dry-run curl`",
+            "zypper dist-upgrade --dry-run`",
+            "zypper remove --dry-run curl`",
+        ];
+        assert_cmds(&lines, &expected);
+    }
+
+    #[tokio::test]
+    async fn overwrite_flags_added() {
+        let cfg = Config {
+            describe: true,
+            overwrite: Some("*".into()),
+            ..Config::default()
+        };
+        let apt = Apt::new(cfg.clone());
+        let dnf = Dnf::new(cfg.clone());
+        let zypper = Zypper::new(cfg);
+        assert!(dnf.overwrite_flags("*").is_none());
+        let (_, lines) = collect_lines(async {
+            apt.s(&["foo"], &[]).await.unwrap();
+            apt.su(&[], &[]).await.unwrap();
+            dnf.s(&["foo"], &[]).await.unwrap();
+            zypper.s(&["foo"], &[]).await.unwrap();
+            zypper.suy(&[], &[]).await.unwrap();
+        })
+        .await;
+        // The commands might be prefixed with `sudo -S`.
+        let expected = [
+            "apt install --reinstall -o Dpkg::Options::=--force-overwrite foo`",
+            "apt upgrade -o Dpkg::Options::=--force-overwrite`",
+            "apt dist-upgrade -o Dpkg::Options::=--force-overwrite`",
+            "dnf install foo`",
+            "zypper install --replacefiles foo`",
+            "zypper dist-upgrade --replacefiles`",
+        ];
+        assert_cmds(&lines, &expected);
+    }
+
+    #[tokio::test]
+    async fn overwrite_glob_rejected() {
+        let cfg = Config {
+            describe: true,
+            overwrite: Some("/usr/share/doc/*".into()),
+            ..Config::default()
+        };
+        let apt = Apt::new(cfg.clone());
+        let zypper = Zypper::new(cfg);
+        let (_, lines) = collect_lines(async {
+            for res in [apt.s(&["foo"], &[]).await, zypper.suy(&[], &[]).await] {
+                assert!(matches!(
+                    res,
+                    Err(Error::ArgParseError { msg }) if msg.contains("/usr/share/doc/*")
+                ));
+            }
+        })
+        .await;
+        assert!(lines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recommends_flags_added() {
+        let cfg = |recommends| Config {
+            describe: true,
+            recommends: Some(recommends),
+            ..Config::default()
+        };
+        let (_, lines) = collect_lines(async {
+            for &recommends in &[false, true] {
+                Apt::new(cfg(recommends)).s(&["curl"], &[]).await.unwrap();
+                Dnf::new(cfg(recommends)).s(&["curl"], &[]).await.unwrap();
+            }
+            Dnf::new(cfg(false)).su(&[], &[]).await.unwrap();
+        })
+        .await;
+        // The commands might be prefixed with `sudo -S`.
+        let expected = [
+            "apt install --reinstall --no-install-recommends curl`",
+            "dnf install --setopt=install_weak_deps=False curl`",
+            "apt install --reinstall --install-recommends curl`",
+            "dnf install --setopt=install_weak_deps=True curl`",
+            "dnf upgrade --setopt=install_weak_deps=False`",
+        ];
+        assert_cmds(&lines, &expected);
+    }
+
+    #[tokio::test]
+    async fn capture_flags_only_captured() {
+        let cfg = Config {
+            describe: true,
+            ..Config::default()
+        };
+        let choco = Choco::new(cfg.clone());
+        let dnf = Dnf::new(cfg);
+        let (_, lines) = collect_lines(async {
+            choco.si(&["curl"], &[]).await.unwrap();
+            capture(choco.si(&["curl"], &[])).await.0.unwrap();
+            capture(dnf.si(&["curl"], &[])).await.0.unwrap();
+        })
+        .await;
+        let expected = [
+            "choco info curl`",
+            "choco info --limit-output --no-progress curl`",
+            "dnf info curl`",
+        ];
+        assert_cmds(&lines, &expected);
+    }
+
+    #[tokio::test]
+    async fn autoremove_after_r() {
+        let cfg = Config {
+            describe: true,
+            no_confirm: true,
+            autoremove: true,
+            ..Config::default()
+        };
+        let pms = [
+            Apt::new(cfg.clone()).boxed(),
+            Brew::new(cfg.clone()).boxed(),
+            Dnf::new(cfg).boxed(),
+        ];
+        let (_, lines) = collect_lines(async {
+            for pm in &pms {
+                assert!(can_autoremove(&**pm));
+                autoremoved(&**pm, &["curl"], &[]).await.unwrap();
+            }
+        })
+        .await;
+        // The commands might be prefixed with `sudo -S`.
+        let expected = [
+            "apt remove --auto-remove curl`",
+            "brew uninstall curl`",
+            "brew autoremove`",
+            "dnf remove curl`",
+            "dnf autoremove`",
+        ];
+        assert_cmds(&lines, &expected);
+        assert!(!can_autoremove(&Scoop::new(Config::default())));
+    }
+
+    #[test]
+    fn network_ops() {
+        for op in &["s", "si", "ss", "su", "suy", "sw", "sy"] {
+            assert!(needs_network(op, &[]), "`{}`", op);
+        }
+        for op in &["q", "qi", "qs", "r", "rns", "sc", "u"] {
+            assert!(!needs_network(op, &["./curl.rpm"]), "`{}`", op);
+        }
+        assert!(needs_network("u", &["https://example.com/curl.rpm"]));
+    }
+
+    #[test]
+    fn offline_flags_spelling() {
+        let cases: &[(&str, &str, Op, Option<&[&str]>)] = &[
+            ("apk", "apk", Op::S, Some(&["--no-network"])),
+            ("apk", "apk", Op::Ss, Some(&[])),
+            ("apk", "apk", Op::Sy, None),
+            ("apk", "rm", Op::S, None),
+            ("apt", "apt", Op::S, Some(&["--no-download"])),
+            ("apt", "apt-get", Op::S, Some(&["--no-download"])),
+            ("apt", "apt", Op::Si, Some(&[])),
+            ("apt", "apt", Op::Suy, None),
+            ("apt", "apt-mark", Op::S, None),
+            ("conda", "conda", Op::Ss, Some(&["--offline"])),
+            ("conda", "conda", Op::Su, None),
+            ("dnf", "dnf", Op::Suy, Some(&["-C"])),
+            ("dnf", "dnf", Op::Sw, None),
+            ("dnf", "rpm", Op::S, None),
+            ("pip", "pip", Op::S, Some(&["--no-index"])),
+            ("brew", "brew", Op::S, None),
+        ];
+        for &(name, exe, op, flags) in cases {
+            let pm = (registry::find(name).unwrap().constructor)(Config::default());
+            assert_eq!(
+                pm.offline_flags(exe, op).as_deref(),
+                flags
+                    .map(|flags| flags.iter().map(|&f| f.to_owned()).collect::<Vec<_>>())
+                    .as_deref(),
+                "`{}` in `{}`",
+                exe,
+                op
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn offline_flags_appended() {
+        let pm = Dnf::new(Config {
+            dry_run: true,
+            ..Config::default()
+        });
+        let (res, stats) = stat::collect(offline(Some(Op::S), pm.s(&["curl"], &[]))).await;
+        res.unwrap();
+        assert!(stats[0].cmd.ends_with("dnf install -C curl"));
+    }
+
+    #[tokio::test]
+    async fn offline_flags_refused() {
+        let pm = Dnf::new(Config {
+            dry_run: true,
+            ..Config::default()
+        });
+        // No operation needing the network runs `rpm`, so one is forced here.
+        let (res, stats) = stat::collect(offline(Some(Op::Ql), pm.ql(&["curl"], &[]))).await;
+        assert!(matches!(
+            res,
+            Err(Error::OfflineError { op, pm }) if op == "-Ql" && pm == "rpm"
+        ));
+        assert!(stats.is_empty());
+    }
+
+    #[test]
+    fn locking_ops() {
+        for &op in &[
+            Op::S,
+            Op::Suy,
+            Op::Sy,
+            Op::Sw,
+            Op::Sccc,
+            Op::R,
+            Op::Rns,
+            Op::U,
+        ] {
+            assert!(is_locking_op(op), "`{}` should be locking", op);
+        }
+        for &op in &[
+            Op::Q,
+            Op::Qi,
+            Op::Qs,
+            Op::Si,
+            Op::Sii,
+            Op::Ss,
+            Op::Sl,
+            Op::Sg,
+        ] {
+            assert!(!is_locking_op(op), "`{}` should not be locking", op);
+        }
+    }
+
+    #[test]
+    fn glob_filter() {
+        let paths = indoc! {"
+            /usr/bin/ls
+            /usr/bin/[
+            /usr/lib/coreutils/libstdbuf.so
+            /usr/share/man/man1/ls.1.gz
+            /usr/share/doc/coreutils/README
+        "};
+        let filter = |pattern: &str| filter_paths(paths, pattern).unwrap();
+        assert_eq!(filter("*/bin/*"), ["/usr/bin/ls", "/usr/bin/["]);
+        assert_eq!(filter("*.gz"), ["/usr/share/man/man1/ls.1.gz"]);
+        assert_eq!(filter("/usr/bin/?s"), ["/usr/bin/ls"]);
+        assert_eq!(
+            filter("*/[!b]*/coreutils/*"),
+            [
+                "/usr/lib/coreutils/libstdbuf.so",
+                "/usr/share/doc/coreutils/README",
+            ]
+        );
+        assert_eq!(filter("/usr/bin/[[]"), ["/usr/bin/["]);
+        assert_eq!(filter("man1"), ["/usr/share/man/man1/ls.1.gz"]);
+        assert_eq!(filter("bin/"), ["/usr/bin/ls", "/usr/bin/["]);
+        assert_eq!(filter("/usr/bin").len(), 2);
+        assert!(filter("*/sbin/*").is_empty());
+        assert!(matches!(
+            filter_paths(paths, "["),
+            Ok(lines) if lines.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn filter_captured() {
+        let (res, out) = capture(filtered("*/bin/*", async {
+            capture_output(b"/usr/bin/curl\n/usr/share/man/man1/curl.1.gz\n");
+            Ok(())
+        }))
+        .await;
+        res.unwrap();
+        assert_eq!(out, b"/usr/bin/curl\n");
+    }
+
+    #[tokio::test]
+    async fn format_captured() {
+        async fn run(pm: &dyn Pm, op: &str, lines: &str) -> Output {
+            let (res, out) = capture(formatted(pm, op, "{name}: {version}", async {
+                capture_output(lines.as_bytes());
+                Ok(())
+            }))
+            .await;
+            res.unwrap();
+            out
+        }
+
+        let dnf = Dnf::new(Config::default());
+        let out = run(&dnf, "q", "curl 7.79.1\nwget 1.21.2\n").await;
+        assert_eq!(out, b"curl: 7.79.1\nwget: 1.21.2\n");
+        // The versions of the packages found by `ss` are not known.
+        let out = run(&dnf, "ss", "curl.x86_64 : A utility for getting files\n").await;
+        assert_eq!(out, b"curl: \n");
+        // The output is kept as is if the backend can't parse it.
+        let apk = Apk::new(Config::default());
+        let out = run(&apk, "q", "curl-7.79.1-r0\n").await;
+        assert_eq!(out, b"curl-7.79.1-r0\n");
+    }
+
+    #[tokio::test]
+    async fn truncate_captured() {
+        let lines = (1..=5).map(|i| format!("line {}\n", i)).join("");
+        let run = |max_lines| {
+            let lines = lines.clone();
+            capture(truncated(max_lines, async move {
+                capture_output(lines.as_bytes());
+                Ok(())
+            }))
+        };
+
+        let (res, out) = run(3).await;
+        res.unwrap();
+        assert_eq!(out, b"line 1\nline 2\nline 3\n... (truncated)\n");
+        // Nothing is truncated until the limit is exceeded.
+        let (res, out) = run(5).await;
+        res.unwrap();
+        assert_eq!(out, lines.as_bytes());
+        let (_, out) = run(10).await;
+        assert_eq!(out, lines.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn capture_scope() {
+        assert!(!is_capturing());
+        let (capturing, out) = capture(async {
+            capture_output(b"foo");
+            is_capturing()
+        })
+        .await;
+        assert!(capturing);
+        assert_eq!(out, b"foo");
+        assert!(!is_capturing());
+        capture_output(b"bar");
+    }
+
+    #[tokio::test]
+    async fn stat_mockpm() {
+        let pm = MockPm {
+            cfg: Config {
+                dry_run: true,
+                ..Config::default()
+            },
+        };
+        let (res, stats) = stat::collect(pm.su(&[], &["--yes"])).await;
+        let cmds = stats
+            .iter()
+            .map(|s| (s.cmd.as_str(), s.code))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cmds,
+            [
+                ("mockpm update --yes", None),
+                ("mockpm upgrade --yes", None)
+            ]
+        );
+        let summary = stat::render(&stats, &res);
+        assert_eq!(summary.len(), 4);
+        assert!(summary[0].starts_with("Command               Status"));
+        assert!(summary[1].starts_with("mockpm update --yes        -"));
+        assert!(summary[3].starts_with("2 commands run in "));
+        assert!(summary[3].ends_with(", succeeded"));
+    }
+
+    #[tokio::test]
+    async fn register_custom_pm() {
+        let pm_for = |name: &str| -> Box<dyn Pm> {
+            Config {
+                default_pm: Some(name.into()),
+                ..Config::default()
+            }
+            .into()
+        };
+        register("pacaptr-custom", |cfg| MockPm { cfg }.boxed());
+        let pm = pm_for("pacaptr-custom");
+        assert_eq!(pm.name(), "mockpm");
+        let out = pm.run_op(Op::Qdt, &["foo"], &[]).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&out), "libfoo1\n");
+
+        assert_eq!(
+            pm_for("pacaptr-unregistered").name(),
+            "unknown package manager: pacaptr-unregistered"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn run_op_mockpm() {
+        let pm = MockPm {
+            cfg: Config::default(),
+        };
+        let out = pm.run_op(Op::Ss, &["a", "b"], &[]).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&out), "a-1\na-2\nb-1\nb-2\n");
+        let out = pm.run_op(Op::Qdt, &["foo"], &[]).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&out), "libfoo1\n");
+
+        // The output is kept along with the exit code on failure, although
+        // `stdout` and `stderr` might come in any order.
+        let err = pm.run_op(Op::Q, &[], &[]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CmdStatusCodeError { code: 3, output, .. }
+                if String::from_utf8_lossy(&output).lines().sorted().eq(["bar", "foo"])
+        ));
+
+        let err = pm.run_op(Op::Sg, &[], &[]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::OperationUnimplementedError { op, .. } if op == "sg"
+        ));
+    }
+
+    /// Runs the operation `op` on [`MockPm`] under `--events json`, returning
+    /// the events emitted as JSON, without the durations.
+    async fn mockpm_events(op: Op, kws: &[&str]) -> Vec<serde_json::Value> {
+        let pm = MockPm {
+            cfg: Config {
+                events: Some(EventFormat::Json),
+                ..Config::default()
+            },
+        };
+        let (_, events) = print::collect_events(pm.run_op(op, kws, &[])).await;
+        events
+            .iter()
+            .map(|event| {
+                let mut event = serde_json::to_value(event).unwrap();
+                event.as_object_mut().unwrap().remove("duration_ms");
+                event
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn events_success() {
+        let script = "sleep 0.1; echo a-1; echo a-2";
+        assert_eq!(
+            mockpm_events(Op::Ss, &["a"]).await,
+            [
+                json!({ "event": "command-started", "cmd": ["sh", "-c", script] }),
+                json!({ "event": "command-finished", "cmd": ["sh", "-c", script], "code": 0 }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn events_failure() {
+        let cmd = ["sh", "-c", "echo foo; echo bar >&2; exit 3"];
+        assert_eq!(
+            mockpm_events(Op::Q, &[]).await,
+            [
+                json!({ "event": "command-started", "cmd": cmd }),
+                json!({ "event": "command-finished", "cmd": cmd, "code": 3 }),
+                json!({
+                    "event": "error",
+                    "cmd": cmd,
+                    "msg": "Subprocess exited with code 3",
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn mockpm_event_sender() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pm = MockPm {
+            cfg: Config {
+                event_sender: Some(tx),
+                ..Config::default()
+            },
+        };
+        pm.run_op(Op::Q, &[], &[]).await.unwrap_err();
+        drop(pm);
+        let events = iter::from_fn(|| rx.try_recv().ok()).collect_vec();
+
+        let argv = ["sh", "-c", "echo foo; echo bar >&2; exit 3"];
+        assert!(matches!(
+            &events[..],
+            [
+                Event::CommandStarted { cmd: started },
+                Event::OutputLine { .. },
+                Event::OutputLine { .. },
+                Event::CommandFinished { code: Some(3), .. },
+            ] if started == &argv
+        ));
+        // `stdout` and `stderr` might come in any order.
+        let lines = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::OutputLine { line, .. } => Some(line.as_str()),
+                _ => None,
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(lines, ["bar", "foo"]);
+    }
+
+    #[tokio::test]
+    async fn remove_orphans_default() {
+        let pm = MockPm {
+            cfg: Config {
+                dry_run: true,
+                ..Config::default()
+            },
+        };
+        let (res, stats) = stat::collect(pm.remove_orphans(&[], &["--yes"])).await;
+        res.unwrap();
+        let cmds = stats.iter().map(|s| s.cmd.as_str()).collect::<Vec<_>>();
+        assert_eq!(cmds, ["mockpm remove --yes libfoo1 libbar2"]);
+    }
+}
@@ -0,0 +1,228 @@
+//! The `pacman` operations supported by [`pacaptr`](crate), as run by
+//! [`Pm::run_op`].
+
+use std::{cmp::Reverse, fmt, str::FromStr};
+
+use futures::future::BoxFuture;
+use itertools::Itertools;
+
+use super::Pm;
+use crate::error::{Error, Result};
+
+macro_rules! ops {(
+    $( $( #[$meta:meta] )* $op:ident => $method:ident, )*
+) => {
+    /// A `pacman` operation, eg. [`Op::Suy`] for `-Suy`, standing for the
+    /// [`Pm`] method of the same name, eg. [`Pm::suy`].
+    ///
+    /// An [`Op`] can be parsed from its `pacman` spelling, with or without
+    /// the leading `-`, and with the flags in any order, eg. `-Syu`.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum Op {
+        $( $( #[$meta] )* $op, )*
+    }
+
+    impl Op {
+        /// All the operations, in the order of the [`Pm`] methods.
+        pub const ALL: &'static [Op] = &[ $( Op::$op, )* ];
+
+        /// Gets the name of the [`Pm`] method standing for this operation,
+        /// eg. `suy`.
+        #[must_use]
+        pub fn method(self) -> &'static str {
+            match self {
+                $( Op::$op => stringify!($method), )*
+            }
+        }
+
+        /// Calls the [`Pm`] method standing for this operation on `pm`.
+        pub(crate) fn call<'a, P: Pm + ?Sized>(
+            self,
+            pm: &'a P,
+            kws: &'a [&str],
+            flags: &'a [&str],
+        ) -> BoxFuture<'a, Result<()>> {
+            match self {
+                $( Op::$op => pm.$method(kws, flags), )*
+            }
+        }
+    }
+};}
+
+ops! {
+    /// `-Q`, as in [`Pm::q`].
+    Q => q,
+    /// `-Qc`, as in [`Pm::qc`].
+    Qc => qc,
+    /// `-Qdt`, as in [`Pm::qdt`].
+    Qdt => qdt,
+    /// `-Qe`, as in [`Pm::qe`].
+    Qe => qe,
+    /// `-Qi`, as in [`Pm::qi`].
+    Qi => qi,
+    /// `-Qk`, as in [`Pm::qk`].
+    Qk => qk,
+    /// `-Ql`, as in [`Pm::ql`].
+    Ql => ql,
+    /// `-Qm`, as in [`Pm::qm`].
+    Qm => qm,
+    /// `-Qo`, as in [`Pm::qo`].
+    Qo => qo,
+    /// `-Qp`, as in [`Pm::qp`].
+    Qp => qp,
+    /// `-Qs`, as in [`Pm::qs`].
+    Qs => qs,
+    /// `-Qu`, as in [`Pm::qu`].
+    Qu => qu,
+    /// `-R`, as in [`Pm::r`].
+    R => r,
+    /// `-Rn`, as in [`Pm::rn`].
+    Rn => rn,
+    /// `-Rns`, as in [`Pm::rns`].
+    Rns => rns,
+    /// `-Rs`, as in [`Pm::rs`].
+    Rs => rs,
+    /// `-Rss`, as in [`Pm::rss`].
+    Rss => rss,
+    /// `-S`, as in [`Pm::s`].
+    S => s,
+    /// `-Sc`, as in [`Pm::sc`].
+    Sc => sc,
+    /// `-Scc`, as in [`Pm::scc`].
+    Scc => scc,
+    /// `-Sccc`, as in [`Pm::sccc`].
+    Sccc => sccc,
+    /// `-Sg`, as in [`Pm::sg`].
+    Sg => sg,
+    /// `-Si`, as in [`Pm::si`].
+    Si => si,
+    /// `-Sii`, as in [`Pm::sii`].
+    Sii => sii,
+    /// `-Sl`, as in [`Pm::sl`].
+    Sl => sl,
+    /// `-Ss`, as in [`Pm::ss`].
+    Ss => ss,
+    /// `-Su`, as in [`Pm::su`].
+    Su => su,
+    /// `-Suy`, as in [`Pm::suy`].
+    Suy => suy,
+    /// `-Sw`, as in [`Pm::sw`].
+    Sw => sw,
+    /// `-Sy`, as in [`Pm::sy`].
+    Sy => sy,
+    /// `-U`, as in [`Pm::u`].
+    U => u,
+}
+
+impl Op {
+    /// The maximum number of operations suggested by [`Op::nearest`].
+    const MAX_NEAREST: usize = 3;
+
+    /// Finds the operations among `supported` nearest to this one, that is,
+    /// the ones of the same kind (eg. `-S*` for `-Sii`), sharing the longest
+    /// prefix with it first, then the shortest ones, eg. `[-Si, -S, -Sc]` for
+    /// `-Sii`.
+    #[must_use]
+    pub fn nearest(self, supported: &[Op]) -> Vec<Op> {
+        let method = self.method();
+        let common_prefix = |op: &Op| {
+            op.method()
+                .chars()
+                .zip(method.chars())
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        supported
+            .iter()
+            .copied()
+            .filter(|&op| op != self && common_prefix(&op) > 0)
+            .sorted_by_key(|op| (Reverse(common_prefix(op)), op.method().len()))
+            .take(Self::MAX_NEAREST)
+            .collect()
+    }
+}
+
+impl FromStr for Op {
+    type Err = Error;
+
+    /// Parses an operation from its `pacman` spelling, eg. `-Syu`, or from
+    /// the name of the corresponding [`Pm`] method, eg. `suy`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chars = s.trim_start_matches('-').chars();
+        let method = chars
+            .next()
+            .into_iter()
+            .chain(chars.sorted_unstable())
+            .collect::<String>()
+            .to_lowercase();
+        Op::ALL
+            .iter()
+            .copied()
+            .find(|op| op.method() == method)
+            .ok_or_else(|| Error::ArgParseError {
+                msg: format!("Invalid flag combination `-{}`", s.trim_start_matches('-')),
+            })
+    }
+}
+
+impl fmt::Display for Op {
+    /// Formats the operation in its `pacman` spelling, eg. `-Suy`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chars = self.method().chars();
+        let op = chars.next().unwrap_or_default().to_ascii_uppercase();
+        write!(f, "-{}{}", op, chars.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tt_call::tt_call;
+
+    use super::*;
+    use crate::methods;
+
+    #[test]
+    fn ops_match_methods() {
+        macro_rules! method_names {(
+            methods = [{ $(
+                $( #[$meta:meta] )*
+                async fn $method:ident;
+            )* }]
+        ) => {
+            [ $( stringify!($method), )* ]
+        };}
+
+        let methods = tt_call! {
+            macro = [{ methods }]
+            ~~> method_names
+        };
+        assert_eq!(Op::ALL.iter().map(|op| op.method()).collect_vec(), methods);
+    }
+
+    #[test]
+    fn op_spellings() {
+        assert_eq!("-Syu".parse::<Op>().unwrap(), Op::Suy);
+        assert_eq!("Suy".parse::<Op>().unwrap(), Op::Suy);
+        assert_eq!("suy".parse::<Op>().unwrap(), Op::Suy);
+        assert_eq!("-Qtd".parse::<Op>().unwrap(), Op::Qdt);
+        assert_eq!(Op::Qdt.to_string(), "-Qdt");
+        assert!(Op::ALL
+            .iter()
+            .all(|&op| op.to_string().parse::<Op>().unwrap() == op));
+
+        let err = "-Sx".parse::<Op>().unwrap_err();
+        assert!(
+            matches!(err, Error::ArgParseError { msg } if msg == "Invalid flag combination `-Sx`")
+        );
+    }
+
+    #[test]
+    fn nearest_ops() {
+        let supported = &[Op::Q, Op::Qi, Op::R, Op::S, Op::Sc, Op::Si, Op::Ss, Op::Suy];
+        assert_eq!(Op::Sii.nearest(supported), [Op::Si, Op::S, Op::Sc]);
+        assert_eq!(Op::Qk.nearest(supported), [Op::Q, Op::Qi]);
+        assert_eq!(Op::Rns.nearest(supported), [Op::R]);
+        assert_eq!(Op::Si.nearest(supported), [Op::S, Op::Sc, Op::Ss]);
+        assert!(Op::U.nearest(supported).is_empty());
+    }
+}
@@ -2,14 +2,17 @@
 
 use async_trait::async_trait;
 use indoc::indoc;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 use tap::prelude::*;
 
-use super::{Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{classify_output, Op, Package, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
 use crate::{
     dispatch::Config,
-    error::{Error, Result},
-    exec::{self, Cmd},
+    error::{Error, FailureKind, Result},
+    exec::{Cmd, StatusCode},
+    json,
     print::{self, PROMPT_RUN},
 };
 
@@ -37,6 +40,48 @@ static STRAT_UNINSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] = &[
+    ("No matching distribution found", FailureKind::NotFound),
+    (
+        "Could not find a version that satisfies",
+        FailureKind::NotFound,
+    ),
+    (
+        "Failed to establish a new connection",
+        FailureKind::NetworkError,
+    ),
+    ("Could not fetch URL", FailureKind::NetworkError),
+    ("Operation cancelled by user", FailureKind::Aborted),
+];
+
+/// An entry of `pip list --format=json`.
+#[derive(Debug, Deserialize)]
+struct ListEntry {
+    name: String,
+    version: String,
+}
+
+/// Takes the packages listed by `pip list --format=json`, skipping the
+/// warnings around the listing itself.
+///
+/// # Errors
+/// Returns an [`Error::JsonError`] if the listing is ill-formed.
+fn parse_list_json(text: &str) -> Result<Vec<Package>> {
+    let listing = match text.lines().find(|ln| ln.starts_with('[')) {
+        Some(listing) => listing,
+        None => return Ok(vec![]),
+    };
+    let pkgs = serde_json::from_str::<Vec<ListEntry>>(listing)?
+        .into_iter()
+        .map(|entry| Package {
+            name: entry.name,
+            version: Some(entry.version),
+        })
+        .collect();
+    Ok(pkgs)
+}
+
 impl Pip {
     /// Returns the command used to invoke [`Pip`], eg. `pip`, `pip3`.
     #[must_use]
@@ -63,10 +108,73 @@ impl Pm for Pip {
         "pip"
     }
 
+    fn executable(&self) -> &str {
+        self.cmd()
+    }
+
     fn cfg(&self) -> &Config {
         &self.cfg
     }
 
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qi,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::S,
+            Op::Sc,
+            Op::Su,
+            Op::Sw,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Qc, Op::Rn, Op::Rns, Op::Sg, Op::Sy]
+    }
+
+    fn classify(&self, _code: StatusCode, output: &str) -> Option<FailureKind> {
+        classify_output(output, FAILURE_PATTERNS)
+    }
+
+    /// Uses the first index instead of the default one, and the others as
+    /// extra ones, in `s` only, as `pip search` is gone.
+    fn index_url_flags(&self, op: Op, urls: &[String]) -> Option<Vec<String>> {
+        if op != Op::S {
+            return None;
+        }
+        let (first, rest) = urls.split_first()?;
+        let mut flags = vec!["--index-url".to_owned(), first.clone()];
+        flags.extend(
+            rest.iter()
+                .flat_map(|url| ["--extra-index-url".to_owned(), url.clone()]),
+        );
+        Some(flags)
+    }
+
+    /// Installs the packages from the local archives given with
+    /// `--find-links` only, ignoring the package index.
+    fn offline_flags(&self, exe: &str, op: Op) -> Option<Vec<String>> {
+        (exe == self.executable() && op == Op::S).then(|| vec!["--no-index".into()])
+    }
+
+    /// Keeps the requirements printed by `pip freeze` as they are, eg.
+    /// `wheel==0.37.0`, skipping the comments.
+    fn format_export(&self, text: &str) -> Vec<String> {
+        text.lines()
+            .map(str::trim)
+            .filter(|ln| !ln.is_empty() && !ln.starts_with('#'))
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Export prints the installed packages as a requirements file.
+    async fn export(&self, flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&[self.cmd(), "freeze"] as _).flags(flags))
+            .await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
@@ -89,23 +197,35 @@ impl Pm for Pip {
     // matching ALL of those terms are returned.
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         let cmd = Cmd::new(&[self.cmd(), "list"] as _).flags(flags);
-        if !self.cfg.dry_run {
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        exec::grep_print(&String::from_utf8(out_bytes)?, kws)?;
+        self.grep_print(&self.decode(out_bytes)?, kws)?;
         Ok(())
     }
 
     /// Qu lists packages which have an update available.
     async fn qu(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&[self.cmd(), "list", "--outdated"] as _)
+        if !self.cfg.json {
+            return Cmd::new(&[self.cmd(), "list", "--outdated"] as _)
+                .kws(kws)
+                .flags(flags)
+                .pipe(|cmd| self.run(cmd))
+                .await;
+        }
+        // Under `--json`, the packages are reported as parsed from the JSON
+        // listing of `pip` itself.
+        let out = Cmd::new(&[self.cmd(), "list", "--outdated", "--format=json"] as _)
             .kws(kws)
             .flags(flags)
-            .pipe(|cmd| self.run(cmd))
-            .await
+            .pipe(|cmd| self.check_output(cmd, PmMode::Mute, &Strategy::default()))
+            .await?
+            .pipe(|out| self.decode(out))?;
+        json::record_parsed(parse_list_json(&out)?);
+        Ok(())
     }
 
     /// R removes a single package, leaving all of its dependencies installed.
@@ -121,7 +241,7 @@ impl Pm for Pip {
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::new(&[self.cmd(), "install"] as _)
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_index_urls(Op::S, flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
     }
@@ -139,6 +259,9 @@ impl Pm for Pip {
             return Err(Error::OperationUnimplementedError {
                 op: "su".into(),
                 pm: self.name().into(),
+                alternatives: vec![],
+                lacking: false,
+                using: vec![],
             });
         }
         Cmd::new(&[self.cmd(), "install", "--upgrade"] as _)
@@ -151,10 +274,41 @@ impl Pm for Pip {
     /// Sw retrieves all packages from the server, but does not install/upgrade
     /// anything.
     async fn sw(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let dir_flags = match self.cfg.ensure_download_dir()? {
+            Some(dir) => vec!["-d".into(), dir.display().to_string()],
+            None => vec![],
+        };
         Cmd::new(&[self.cmd(), "download"] as _)
             .kws(kws)
-            .flags(flags)
+            .flags(
+                &dir_flags
+                    .into_iter()
+                    .chain(flags.iter().map(|&f| f.into()))
+                    .collect_vec(),
+            )
             .pipe(|cmd| self.run(cmd))
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_json_packages() {
+        let out = indoc! {r#"
+            [{"name": "pip", "version": "21.2.4", "latest_version": "21.3", "latest_filetype": "wheel"}]
+            WARNING: You are using pip version 21.2.4; however, version 21.3 is available.
+        "#};
+        assert_eq!(
+            parse_list_json(out).unwrap(),
+            [Package {
+                name: "pip".into(),
+                version: Some("21.2.4".into()),
+            }]
+        );
+        assert!(parse_list_json("").unwrap().is_empty());
+        assert!(parse_list_json("[{]").is_err());
+    }
+}
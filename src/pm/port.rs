@@ -5,7 +5,7 @@ use indoc::indoc;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{network_bound, NoCacheStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
 use crate::{dispatch::Config, error::Result, exec::Cmd};
 
 macro_rules! docs_self {
@@ -39,6 +39,23 @@ impl Port {
     pub(crate) fn new(cfg: Config) -> Self {
         Port { cfg }
     }
+
+    /// Builds a [`Cmd`] where variant tokens (eg. `+bar`, `-bar`) passed as
+    /// extra flags are moved after the keywords.
+    ///
+    /// `port` requires variants to follow the port name, as in `port install foo
+    /// +bar -baz`, but negative variants can only be passed after `--`, ending up
+    /// in `flags`.
+    fn with_variants(cmd: Cmd, kws: &[&str], flags: &[&str]) -> Cmd {
+        // Port options are all single letters, eg. `-v`.
+        let is_variant = |flag: &&str| {
+            flag.starts_with('+')
+                || (flag.len() > 2 && flag.starts_with('-') && !flag.starts_with("--"))
+        };
+        let (variants, flags): (Vec<&str>, Vec<&str>) = flags.iter().copied().partition(is_variant);
+        cmd.kws(&kws.iter().chain(&variants).collect::<Vec<_>>())
+            .flags(&flags)
+    }
 }
 
 #[async_trait]
@@ -48,10 +65,46 @@ impl Pm for Port {
         "port"
     }
 
+    /// `port` takes its version as a subcommand rather than a flag.
+    fn version_cmd(&self) -> Cmd {
+        Cmd::new(&["port", "version"])
+    }
+
     fn cfg(&self) -> &Config {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qc,
+            Op::Qi,
+            Op::Ql,
+            Op::Qo,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rss,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Si,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sy,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Sg]
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.run(Cmd::new(&["port", "installed"]).kws(kws).flags(flags))
@@ -118,8 +171,7 @@ impl Pm for Port {
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["port", "install"])
-            .kws(kws)
-            .flags(flags)
+            .pipe(|cmd| Self::with_variants(cmd, kws, flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -186,8 +238,7 @@ impl Pm for Port {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["port", "selfupdate"]).flags(flags))
-            .await?;
+        network_bound(self.run(Cmd::with_sudo(&["port", "selfupdate"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
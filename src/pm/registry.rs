@@ -0,0 +1,516 @@
+//! The registry of all the package managers supported by
+//! [`pacaptr`](crate).
+//!
+//! Adding a new backend should only require a new entry in [`REGISTRY`]
+//! (along with its implementation in `src/pm/`).
+//!
+//! Backends outside of [`pacaptr`](crate) can be added at runtime with
+//! [`register`] instead.
+
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use once_cell::sync::Lazy;
+
+use super::{
+    scoop, Apk, Apt, Brew, Choco, Conda, Dnf, Emerge, Equo, Op, Pip, Pm, Port, Scoop, Tlmgr,
+    Unknown, Zypper,
+};
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+    exec::{self, is_exe},
+};
+
+/// An entry of a supported package manager in the [`REGISTRY`].
+#[derive(Debug)]
+pub(crate) struct PmEntry {
+    /// The canonical name of the package manager, eg. `brew`.
+    pub name: &'static str,
+
+    /// Other names under which the package manager can be selected, eg.
+    /// `pip3`.
+    pub aliases: &'static [&'static str],
+
+    /// The `(os, path)` pairs used in automatic detection, where `os` is
+    /// compared against [`env::consts::OS`].
+    ///
+    /// An empty `path` means that the executable is looked up in `$PATH` only,
+    /// and an empty slice means that the package manager is never detected
+    /// automatically.
+    pub detect_paths: &'static [(&'static str, &'static str)],
+
+    /// Finds the executable of the package manager in the places which can't
+    /// be listed in [`detect_paths`](PmEntry::detect_paths), eg. under an
+    /// environment variable, as a fallback in automatic detection.
+    pub locate: Option<fn() -> Option<PathBuf>>,
+
+    /// Generates the [`Pm`] instance, feeding it with the current [`Config`].
+    pub constructor: fn(Config) -> Box<dyn Pm>,
+}
+
+impl PmEntry {
+    /// Checks if `name` refers to this package manager.
+    #[must_use]
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// Checks if this package manager can be found on the current system.
+    #[must_use]
+    pub(crate) fn is_detected(&self) -> bool {
+        self.detect_paths
+            .iter()
+            .any(|&(os, path)| os == env::consts::OS && is_exe(self.name, path))
+            || (self.is_candidate() && self.locate.map_or(false, |locate| locate().is_some()))
+    }
+
+    /// Checks if this package manager is probed in automatic detection on the
+    /// current system.
+    #[must_use]
+    pub(crate) fn is_candidate(&self) -> bool {
+        self.detect_paths
+            .iter()
+            .any(|&(os, _)| os == env::consts::OS)
+    }
+
+    /// Checks if this package manager is supported on the current system, that
+    /// is, if it is either probed in automatic detection, or never detected
+    /// automatically (eg. `pip`).
+    #[must_use]
+    pub(crate) fn is_supported_here(&self) -> bool {
+        self.detect_paths.is_empty() || self.is_candidate()
+    }
+}
+
+/// Makes an [`Error::PmNotFoundError`] after probing the package managers
+/// `tried`, listing those supported on the current system.
+#[must_use]
+pub(crate) fn pm_not_found(tried: Vec<String>) -> Error {
+    let supported = REGISTRY
+        .iter()
+        .filter(|entry| entry.is_supported_here())
+        .map(|entry| entry.name.into())
+        .collect();
+    Error::PmNotFoundError { tried, supported }
+}
+
+/// All the supported package managers, in order of precedence in automatic
+/// detection.
+pub(crate) static REGISTRY: &[PmEntry] = &[
+    // Scoop
+    PmEntry {
+        name: "scoop",
+        aliases: &[],
+        detect_paths: &[("windows", "")],
+        locate: Some(scoop::find_shim),
+        constructor: |cfg| Scoop::new(cfg).boxed(),
+    },
+    // Chocolatey
+    PmEntry {
+        name: "choco",
+        aliases: &[],
+        detect_paths: &[("windows", "")],
+        locate: None,
+        constructor: |cfg| Choco::new(cfg).boxed(),
+    },
+    // Homebrew/Linuxbrew
+    PmEntry {
+        name: "brew",
+        aliases: &[],
+        detect_paths: &[("macos", "/usr/local/bin/brew")],
+        locate: None,
+        constructor: |cfg| Brew::new(cfg).boxed(),
+    },
+    // Macports
+    PmEntry {
+        name: "port",
+        aliases: &[],
+        detect_paths: &[("macos", "/opt/local/bin/port")],
+        locate: None,
+        constructor: |cfg| {
+            if cfg!(target_os = "macos") {
+                Port::new(cfg).boxed()
+            } else {
+                Unknown::new("port", vec!["port".into()]).boxed()
+            }
+        },
+    },
+    // Apk for Alpine
+    PmEntry {
+        name: "apk",
+        aliases: &[],
+        detect_paths: &[("linux", "/sbin/apk")],
+        locate: None,
+        constructor: |cfg| Apk::new(cfg).boxed(),
+    },
+    // Apt for Debian/Ubuntu/Termux (new versions)
+    PmEntry {
+        name: "apt",
+        aliases: &[],
+        detect_paths: &[
+            ("macos", "/opt/procursus/bin/apt"),
+            ("ios", "/usr/bin/apt"),
+            ("linux", "/usr/bin/apt"),
+        ],
+        locate: None,
+        constructor: |cfg| Apt::new(cfg).boxed(),
+    },
+    // Portage for Gentoo
+    PmEntry {
+        name: "emerge",
+        aliases: &[],
+        detect_paths: &[("linux", "/usr/bin/emerge")],
+        locate: None,
+        constructor: |cfg| Emerge::new(cfg).boxed(),
+    },
+    // Dnf for RedHat
+    PmEntry {
+        name: "dnf",
+        aliases: &[],
+        detect_paths: &[("linux", "/usr/bin/dnf")],
+        locate: None,
+        constructor: |cfg| Dnf::new(cfg).boxed(),
+    },
+    // Zypper for SUSE
+    PmEntry {
+        name: "zypper",
+        aliases: &[],
+        detect_paths: &[("linux", "/usr/bin/zypper")],
+        locate: None,
+        constructor: |cfg| Zypper::new(cfg).boxed(),
+    },
+    // Entropy for Sabayon
+    PmEntry {
+        name: "equo",
+        aliases: &[],
+        detect_paths: &[("linux", "/usr/bin/equo")],
+        locate: None,
+        constructor: |cfg| Equo::new(cfg).boxed(),
+    },
+    // -- External Package Managers --
+
+    // Conda
+    PmEntry {
+        name: "conda",
+        aliases: &["mamba", "micromamba"],
+        detect_paths: &[],
+        locate: None,
+        constructor: |cfg| Conda::new(cfg).boxed(),
+    },
+    // Pip
+    PmEntry {
+        name: "pip",
+        aliases: &["pip3"],
+        detect_paths: &[],
+        locate: None,
+        constructor: |cfg| Pip::new(cfg).boxed(),
+    },
+    // Tlmgr
+    PmEntry {
+        name: "tlmgr",
+        aliases: &[],
+        detect_paths: &[],
+        locate: None,
+        constructor: |cfg| Tlmgr::new(cfg).boxed(),
+    },
+];
+
+/// Generates a [`Pm`] instance registered with [`register`], feeding it with
+/// the current [`Config`].
+type Constructor = dyn Fn(Config) -> Box<dyn Pm> + Send + Sync;
+
+/// The package managers registered at runtime with [`register`], by name.
+static CUSTOM: Lazy<RwLock<HashMap<String, Arc<Constructor>>>> = Lazy::new(RwLock::default);
+
+/// Registers the package manager `name` outside of [`pacaptr`](crate), eg. a
+/// backend of your own, so that it can be selected by `--using` or
+/// `default_pm` just like the built-in ones, which it shadows if they share
+/// the same name.
+///
+/// When `name` is selected, `constructor` is called with the current
+/// [`Config`] to generate the [`Pm`] instance, eg.
+/// `|cfg| Box::new(MyPm::new(cfg))`. Registering the same `name` again
+/// replaces the previous `constructor`.
+///
+/// # Thread Safety
+/// The registry is guarded by an [`RwLock`], so this can be called from any
+/// thread, although it is meant to be done once at startup, before any
+/// [`Pm`] is generated. `constructor` is called without holding the lock,
+/// so it might be called from several threads at once, but it might also call
+/// [`register`] itself.
+///
+/// # Panics
+/// Panics if another thread has panicked while holding the lock.
+///
+/// # Examples
+/// ```
+/// use async_trait::async_trait;
+/// use pacaptr::{
+///     dispatch::Config,
+///     pm::{self, Pm},
+/// };
+///
+/// struct MyPm {
+///     cfg: Config,
+/// }
+///
+/// #[async_trait]
+/// impl Pm for MyPm {
+///     fn name(&self) -> &str {
+///         "mypm"
+///     }
+///
+///     fn cfg(&self) -> &Config {
+///         &self.cfg
+///     }
+/// }
+///
+/// pm::register("mypm", |cfg| Box::new(MyPm { cfg }));
+/// let pm: Box<dyn Pm> = Config {
+///     default_pm: Some("mypm".into()),
+///     ..Config::default()
+/// }
+/// .into();
+/// assert_eq!(pm.name(), "mypm");
+/// ```
+pub fn register(
+    name: impl Into<String>,
+    constructor: impl Fn(Config) -> Box<dyn Pm> + Send + Sync + 'static,
+) {
+    CUSTOM
+        .write()
+        .unwrap()
+        .insert(name.into(), Arc::new(constructor));
+}
+
+/// Finds the constructor of the package manager `name` registered with
+/// [`register`].
+#[must_use]
+pub(crate) fn find_custom(name: &str) -> Option<Arc<Constructor>> {
+    CUSTOM.read().unwrap().get(name).cloned()
+}
+
+/// Finds the [`REGISTRY`] entry referred to by `name`.
+#[must_use]
+pub(crate) fn find(name: &str) -> Option<&'static PmEntry> {
+    REGISTRY.iter().find(|entry| entry.matches(name))
+}
+
+/// Checks that the executable `exe` of the package manager of `entry` can be
+/// found, either in `$PATH` or, if it is the default one, in one of the
+/// detection paths.
+///
+/// # Errors
+/// Returns an [`Error::ExeNotFoundError`] when `exe` is not found.
+fn check_exe(entry: &PmEntry, exe: &str) -> Result<()> {
+    let found = is_exe(exe, "") || (exe == entry.name && entry.is_detected());
+    if found {
+        return Ok(());
+    }
+    Err(Error::ExeNotFoundError {
+        pm: entry.name.into(),
+        exe: exe.into(),
+        detected: REGISTRY
+            .iter()
+            .filter(|entry| entry.is_detected())
+            .map(|entry| entry.name.into())
+            .collect(),
+    })
+}
+
+/// Finds the path of the executable of `pm`, either in `$PATH` or, if it is
+/// the default one, in one of the detection paths, as in [`preflight`].
+#[must_use]
+pub(crate) fn exe_path(pm: &dyn Pm) -> Option<PathBuf> {
+    let exe = pm.executable();
+    exec::find_exe(exe, "").or_else(|| {
+        let entry = find(pm.name()).filter(|entry| entry.name == exe)?;
+        entry
+            .detect_paths
+            .iter()
+            .filter(|&&(os, _)| os == env::consts::OS)
+            .find_map(|&(_, path)| exec::find_exe("", path))
+            .or_else(|| entry.locate.and_then(|locate| locate()))
+    })
+}
+
+/// Fills in the package managers among `candidates` (eg. the ones detected on
+/// this system) which support the operation of `err`, if it is an
+/// [`Error::OperationUnimplementedError`], so that they are suggested with
+/// `--using` instead.
+///
+/// The candidates are only looked into when needed.
+#[must_use]
+pub(crate) fn suggest_using<'a>(
+    mut err: Error,
+    candidates: impl IntoIterator<Item = &'a PmEntry>,
+) -> Error {
+    if let Error::OperationUnimplementedError { op, pm, using, .. } = &mut err {
+        if let Ok(op) = op.parse::<Op>() {
+            *using = candidates
+                .into_iter()
+                .filter(|entry| !entry.matches(pm))
+                .filter(|entry| {
+                    (entry.constructor)(Config::default())
+                        .supported_ops()
+                        .contains(&op)
+                })
+                .map(|entry| entry.name.into())
+                .collect();
+        }
+    }
+    err
+}
+
+/// Checks that the executable of `pm` can be found before running any
+/// command, so that a missing package manager is reported as such instead of
+/// as a failure to spawn a subprocess.
+///
+/// Package managers outside of the [`REGISTRY`] are not checked.
+///
+/// # Errors
+/// Returns an [`Error::ExeNotFoundError`] when the executable is not found.
+pub(crate) fn preflight(pm: &dyn Pm) -> Result<()> {
+    match find(pm.name()) {
+        Some(entry) => check_exe(entry, pm.executable()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs, path::Path};
+
+    use itertools::Itertools;
+    use regex::Regex;
+
+    use super::*;
+
+    #[test]
+    fn preflight_missing_exe() {
+        let entry = find("apt").unwrap();
+        let err = check_exe(entry, "pacaptr-missing-exe").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ExeNotFoundError { pm, exe, .. } if pm == "apt" && exe == "pacaptr-missing-exe"
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("Executable `pacaptr-missing-exe` of `apt` is not found"));
+        assert_ne!(err.exit_code(), 0);
+    }
+
+    #[test]
+    fn preflight_hint() {
+        let err = |detected: &[&str]| Error::ExeNotFoundError {
+            pm: "dnf".into(),
+            exe: "dnf".into(),
+            detected: detected.iter().map(|&s| s.into()).collect(),
+        };
+        assert_eq!(
+            err(&["apt", "brew"]).to_string(),
+            "Executable `dnf` of `dnf` is not found \
+             (detected on this system: `apt`, `brew`; try `--using` with one of them)"
+        );
+        assert_eq!(
+            err(&[]).to_string(),
+            "Executable `dnf` of `dnf` is not found, \
+             and no other package manager is detected on this system"
+        );
+    }
+
+    #[test]
+    fn registry_matches_impls() {
+        let excluded_names = ["custom", "mod", "op", "registry", "unknown"];
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/pm");
+        let impls: BTreeSet<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "rs"))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+            .filter(|name| !excluded_names.contains(&name.as_str()))
+            .collect();
+        let names: BTreeSet<String> = REGISTRY.iter().map(|entry| entry.name.into()).collect();
+        assert_eq!(impls, names);
+    }
+
+    #[test]
+    fn supported_ops_match_impls() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/pm");
+        for entry in REGISTRY {
+            let src = fs::read_to_string(dir.join(entry.name).with_extension("rs")).unwrap();
+            // The same scan as the one generating the compatibility table in
+            // `pacaptr-macros`, without the Unicode classes which are not enabled
+            // here.
+            let impld = Op::ALL
+                .iter()
+                .copied()
+                .filter(|op| {
+                    Regex::new(&format!(r"(?-u)fn\s+{}\s*\(", op.method()))
+                        .unwrap()
+                        .is_match(&src)
+                })
+                .collect_vec();
+            // `port` is replaced by `Unknown` outside of macOS.
+            let pm = match entry.name {
+                "port" => Port::new(Config::default()).boxed(),
+                _ => (entry.constructor)(Config::default()),
+            };
+            assert_eq!(pm.supported_ops(), impld, "`{}`", entry.name);
+            assert!(
+                pm.lacking_ops().iter().all(|op| !impld.contains(op)),
+                "`{}`",
+                entry.name
+            );
+        }
+    }
+
+    #[test]
+    fn unimplemented_suggestions() {
+        let candidates = ["apt", "brew", "pip"].iter().filter_map(|&name| find(name));
+        let err = suggest_using(
+            crate::pm::unimplemented(&Pip::new(Config::default()), "qc"),
+            candidates.clone(),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Operation `-Qc` is unimplemented for `pip`, which has no equivalent of it \
+             (supported alternatives: `-Q`, `-Qi`, `-Qs`); \
+             try `--using apt` or `--using brew` instead; \
+             see https://rami3l.github.io/pacaptr/pacaptr/#compatibility-table"
+        );
+
+        let err = suggest_using(
+            crate::pm::unimplemented(&Tlmgr::new(Config::default()), "sii"),
+            candidates.clone(),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Operation `-Sii` is unimplemented for `tlmgr`, as it is not mapped by `pacaptr` yet \
+             (supported alternatives: `-Si`, `-S`, `-Sl`); \
+             try `--using apt` or `--using brew` instead; \
+             see https://rami3l.github.io/pacaptr/pacaptr/#compatibility-table"
+        );
+
+        // Nobody else is suggested for the operations outside of `Op`.
+        let err = suggest_using(
+            crate::pm::unimplemented(&Pip::new(Config::default()), "hold"),
+            candidates,
+        );
+        assert_eq!(
+            err.to_string(),
+            "Operation `hold` is unimplemented for `pip`"
+        );
+    }
+
+    #[test]
+    fn find_by_alias() {
+        assert_eq!(find("pip3").map(|entry| entry.name), Some("pip"));
+        assert!(find("pacman").is_none());
+    }
+}
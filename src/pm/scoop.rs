@@ -1,15 +1,25 @@
 #![doc = docs_self!()]
 
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
 use async_trait::async_trait;
 use indoc::indoc;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{
+    network_bound, NoCacheStrategy, Op, Package, Pm, PmHelper, PmMode, PromptStrategy, Strategy,
+};
 use crate::{
     dispatch::Config,
     error::Result,
-    exec::{self, Cmd},
+    exec::{is_exe, Cmd},
+    json,
     print::{self, PROMPT_RUN},
 };
 
@@ -25,6 +35,10 @@ macro_rules! docs_self {
 #[derive(Debug)]
 pub(crate) struct Scoop {
     cfg: Config,
+
+    /// The PowerShell shim of `scoop` run in its place when it is not found in
+    /// `$PATH`, eg. in a non-interactive shell, as given by [`find_shim`].
+    shim: Option<PathBuf>,
 }
 
 static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
@@ -38,26 +52,95 @@ static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+/// Finds the PowerShell shim of `scoop` (`shims\scoop.ps1`) under the first of
+/// the install roots where it exists, as given by `var` reading the
+/// environment: `$SCOOP`, `%USERPROFILE%\scoop`, then the global
+/// `$SCOOP_GLOBAL` or `C:\ProgramData\scoop` (on Windows).
+#[must_use]
+fn find_shim_with(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let roots = [
+        var("SCOOP").map(PathBuf::from),
+        var("USERPROFILE").map(|home| Path::new(&home).join("scoop")),
+        var("SCOOP_GLOBAL").map(PathBuf::from),
+        cfg!(windows).then(|| PathBuf::from(r"C:\ProgramData\scoop")),
+    ];
+    roots
+        .iter()
+        .flatten()
+        .map(|root| root.join("shims").join("scoop.ps1"))
+        .find(|shim| shim.is_file())
+}
+
+/// Finds the PowerShell shim of `scoop` in the current environment, as in
+/// [`find_shim_with`].
+#[must_use]
+pub(super) fn find_shim() -> Option<PathBuf> {
+    find_shim_with(|key| env::var_os(key))
+}
+
 impl Scoop {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
-        Scoop { cfg }
+        // This is decided once and for all, since probing `$PATH` is not free.
+        let shim = if is_exe("scoop", "") {
+            None
+        } else {
+            find_shim()
+        };
+        Scoop { cfg, shim }
+    }
+
+    /// Makes the command running `scoop` with `args` in PowerShell, either by
+    /// name, or with its shim when it is not found in `$PATH`.
+    #[must_use]
+    fn cmd(&self, args: &[&str]) -> Cmd {
+        let shim = self.shim.as_ref().map(|shim| shim.display().to_string());
+        let head = match &shim {
+            Some(shim) => vec!["powershell", "-NoProfile", "-File", shim.as_str()],
+            None => vec!["powershell", "scoop"],
+        };
+        Cmd::new(&head.into_iter().chain(args.iter().copied()).collect_vec())
     }
 
-    async fn search_regex(&self, cmd: &[&str], kws: &[&str], flags: &[&str]) -> Result<()> {
-        let cmd = Cmd::new(cmd).flags(flags);
-        if !self.cfg.dry_run {
+    /// Updates the packages given by `kws`, or all of them if empty.
+    async fn update(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.cmd(&["update"])
+            .kws(if kws.is_empty() { &["*"] } else { kws })
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
+            .await
+    }
+
+    async fn search_regex(&self, cmd: Cmd, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = cmd.flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
             print::print_cmd(&cmd, PROMPT_RUN);
         }
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        exec::grep_print(&String::from_utf8(out_bytes)?, kws)?;
+        self.grep_print(&self.decode(out_bytes)?, kws)?;
         Ok(())
     }
 }
 
+/// Takes the packages described by `scoop info` from their `Name` and
+/// `Version` fields.
+#[must_use]
+fn parse_info(text: &str) -> Vec<Package> {
+    let mut pkgs: Vec<Package> = vec![];
+    for (key, val) in text.lines().filter_map(|ln| ln.split_once(':')) {
+        let val = val.trim();
+        match (key.trim(), pkgs.last_mut()) {
+            ("Name", _) => pkgs.push(Package::from(val.to_owned())),
+            ("Version", Some(pkg)) => pkg.version = Some(val.into()),
+            _ => (),
+        }
+    }
+    pkgs
+}
+
 // Windows is so special! It's better not to "sudo" automatically.
 #[async_trait]
 impl Pm for Scoop {
@@ -66,15 +149,63 @@ impl Pm for Scoop {
         "scoop"
     }
 
+    /// `scoop` is a script run by `powershell`, as are its other commands.
+    fn version_cmd(&self) -> Cmd {
+        self.cmd(&["--version"])
+    }
+
     fn cfg(&self) -> &Config {
         &self.cfg
     }
 
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qi,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rn,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Si,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sy,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Sg]
+    }
+
+    /// Takes the app names from the output of `scoop search`, either listed as
+    /// `name (version)` under each `'bucket' bucket:` header, or as the first
+    /// column of a table in newer versions.
+    fn parse_search(&self, text: &str) -> Option<Vec<String>> {
+        text.lines()
+            .map(str::trim)
+            .filter(|ln| {
+                !(ln.is_empty()
+                    || ln.ends_with("bucket:")
+                    || ln.starts_with("Results from")
+                    || ln.starts_with("Name ")
+                    || ln.starts_with("----")
+                    || ln.starts_with("WARN"))
+            })
+            .filter_map(|ln| ln.split_whitespace().next())
+            .map(Into::into)
+            .unique()
+            .collect_vec()
+            .pipe(Some)
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
-            self.run(Cmd::new(&["powershell", "scoop", "list"]).flags(flags))
-                .await
+            self.run(self.cmd(&["list"]).flags(flags)).await
         } else {
             self.qs(kws, flags).await
         }
@@ -82,7 +213,19 @@ impl Pm for Scoop {
 
     /// Qi displays local package information: name, version, description, etc.
     async fn qi(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.si(kws, flags).await
+        if !self.cfg.json {
+            return self.si(kws, flags).await;
+        }
+        // Under `--json`, the packages are reported as parsed from `scoop info`.
+        let out = self
+            .cmd(&["info"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.check_output(cmd, PmMode::Mute, &Strategy::default()))
+            .await?
+            .pipe(|out| self.decode(out))?;
+        json::record_parsed(parse_info(&out));
+        Ok(())
     }
 
     /// Qs searches locally installed package for names or descriptions.
@@ -90,13 +233,12 @@ impl Pm for Scoop {
     // when including multiple search terms, only packages with descriptions
     // matching ALL of those terms are returned.
     async fn qs(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.search_regex(&["powershell", "scoop", "list"], kws, flags)
-            .await
+        self.search_regex(self.cmd(&["list"]), kws, flags).await
     }
 
     /// Qu lists packages which have an update available.
     async fn qu(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "status"])
+        self.cmd(&["status"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run(cmd))
@@ -105,7 +247,7 @@ impl Pm for Scoop {
 
     /// R removes a single package, leaving all of its dependencies installed.
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "uninstall"])
+        self.cmd(&["uninstall"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
@@ -115,7 +257,7 @@ impl Pm for Scoop {
     /// Rn removes a package and skips the generation of configuration backup
     /// files.
     async fn rn(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "uninstall", "--purge"])
+        self.cmd(&["uninstall", "--purge"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
@@ -124,7 +266,7 @@ impl Pm for Scoop {
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "install"])
+        self.cmd(&["install"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
@@ -134,7 +276,7 @@ impl Pm for Scoop {
     /// Sc removes all the cached packages that are not currently installed, and
     /// the unused sync database.
     async fn sc(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "cache", "rm"])
+        self.cmd(&["cache", "rm"])
             .kws(if kws.is_empty() { &["*"] } else { kws })
             .flags(flags)
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
@@ -148,7 +290,7 @@ impl Pm for Scoop {
 
     /// Si displays remote package information: name, version, description, etc.
     async fn si(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "info"])
+        self.cmd(&["info"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run(cmd))
@@ -158,7 +300,7 @@ impl Pm for Scoop {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "search"])
+        self.cmd(&["search"])
             .kws(kws)
             .flags(flags)
             .pipe(|cmd| self.run(cmd))
@@ -167,8 +309,7 @@ impl Pm for Scoop {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.run(Cmd::new(&["powershell", "scoop", "update"]).flags(flags))
-            .await?;
+        network_bound(self.run(self.cmd(&["update"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
@@ -176,18 +317,117 @@ impl Pm for Scoop {
     }
 
     /// Su updates outdated packages.
+    ///
+    /// When updating all of them, `scoop` itself (along with the buckets) is
+    /// updated first, unless `no_self_upgrade` is set.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        Cmd::new(&["powershell", "scoop", "update"])
-            .kws(if kws.is_empty() { &["*"] } else { kws })
-            .flags(flags)
-            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
-            .await
+        if kws.is_empty() && !self.cfg.no_self_upgrade {
+            self.sy(&[], flags).await?;
+        }
+        self.update(kws, flags).await
     }
 
     /// Suy refreshes the local package database, then updates outdated
     /// packages.
     async fn suy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        // `scoop update` refreshes the buckets and updates `scoop` itself at
+        // once, so it is run only once, even under `no_self_upgrade`.
         self.sy(&[], flags).await?;
-        self.su(kws, flags).await
+        self.update(kws, flags).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn shim_found() {
+        let dir = TempDir::new().unwrap();
+        let (custom, home) = (dir.path().join("custom"), dir.path().join("home"));
+        let shim = |root: &Path| root.join("shims").join("scoop.ps1");
+        let var = |key: &str| match key {
+            "SCOOP" => Some(custom.clone().into()),
+            "USERPROFILE" => Some(home.clone().into()),
+            _ => None,
+        };
+        assert_eq!(find_shim_with(var), None);
+        for root in &[&home.join("scoop"), &custom] {
+            fs::create_dir_all(shim(root).parent().unwrap()).unwrap();
+            fs::write(shim(root), "").unwrap();
+        }
+        // `$SCOOP` takes precedence over the user profile.
+        assert_eq!(find_shim_with(var), Some(shim(&custom)));
+        fs::remove_dir_all(&custom).unwrap();
+        assert_eq!(find_shim_with(var), Some(shim(&home.join("scoop"))));
+    }
+
+    #[test]
+    fn shim_invoked() {
+        let pm = Scoop {
+            cfg: Config::default(),
+            shim: Some(PathBuf::from("scoop.ps1")),
+        };
+        assert_eq!(
+            pm.cmd(&["install"]).cmd,
+            ["powershell", "-NoProfile", "-File", "scoop.ps1", "install"]
+        );
+        let pm = Scoop { shim: None, ..pm };
+        assert_eq!(pm.cmd(&["install"]).cmd, ["powershell", "scoop", "install"]);
+    }
+
+    #[test]
+    fn search_names() {
+        let pm = Scoop::new(Config::default());
+        let out = indoc! {"
+            'main' bucket:
+                curl (7.79.1)
+                curlie (1.6.7)
+
+            'extras' bucket:
+                curl (7.79.1) --> includes 'curl.exe'
+        "};
+        assert_eq!(pm.parse_search(out).unwrap(), ["curl", "curlie"]);
+
+        let out = indoc! {"
+            Results from local buckets...
+
+            Name   Version Source Binaries
+            ----   ------- ------ --------
+            curl   8.0.1_7 main
+            curlie 1.7.1   main
+        "};
+        assert_eq!(pm.parse_search(out).unwrap(), ["curl", "curlie"]);
+    }
+
+    #[test]
+    fn info_packages() {
+        let out = indoc! {"
+            Name        : curl
+            Description : Command line tool and library for transferring data with URLs
+            Version     : 7.79.1_1
+            Website     : https://curl.se/
+
+            Name        : wget
+            Version     : 1.21.2
+        "};
+        assert_eq!(
+            parse_info(out),
+            [
+                Package {
+                    name: "curl".into(),
+                    version: Some("7.79.1_1".into()),
+                },
+                Package {
+                    name: "wget".into(),
+                    version: Some("1.21.2".into()),
+                },
+            ]
+        );
+        assert!(parse_info("Couldn't find manifest for 'foo'.").is_empty());
     }
 }
@@ -5,8 +5,12 @@ use indoc::indoc;
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{DryRunStrategy, Pm, PmHelper, PmMode, Strategy};
-use crate::{dispatch::Config, error::Result, exec::Cmd};
+use super::{DryRunStrategy, Op, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+    exec::Cmd,
+};
 
 macro_rules! docs_self {
     () => {
@@ -27,12 +31,47 @@ static STRAT_CHECK_DRY: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+// `tlmgr remove` might ask for confirmation (eg. with `--all`), but the only way
+// to skip it is `--force`, which does more than that.
+static STRAT_REMOVE: Lazy<Strategy> = Lazy::new(|| Strategy {
+    dry_run: DryRunStrategy::with_flags(&["--dry-run"]),
+    prompt: PromptStrategy::answer_yes(),
+    ..Strategy::default()
+});
+
 impl Tlmgr {
     #[must_use]
     #[allow(missing_docs)]
     pub(crate) fn new(cfg: Config) -> Self {
         Tlmgr { cfg }
     }
+
+    /// Checks that the repositories set by `--repo`, if any, are URLs of the
+    /// form `scheme://location`.
+    ///
+    /// # Errors
+    /// Returns an [`Error::ArgParseError`] when one of them is not.
+    fn check_repos(&self) -> Result<()> {
+        match self.cfg.repos.iter().find(|url| !is_url(url)) {
+            Some(url) => Err(Error::ArgParseError {
+                msg: format!("Repository `{}` is not a valid URL", url),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks if `url` is of the form `scheme://location`.
+#[must_use]
+fn is_url(url: &str) -> bool {
+    url.split_once("://").map_or(false, |(scheme, location)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+            && !location.is_empty()
+            && !location.contains(char::is_whitespace)
+    })
 }
 
 #[async_trait]
@@ -46,6 +85,36 @@ impl Pm for Tlmgr {
         &self.cfg
     }
 
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qi,
+            Op::Qk,
+            Op::Ql,
+            Op::R,
+            Op::S,
+            Op::Si,
+            Op::Sl,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::U,
+        ]
+    }
+
+    fn lacking_ops(&self) -> &[Op] {
+        &[Op::Qc, Op::Rn, Op::Rns, Op::Sy]
+    }
+
+    /// Uses the given CTAN mirror instead of the default one, of which there
+    /// can only be one.
+    fn repo_flags(&self, repos: &[String]) -> Option<Vec<String>> {
+        match repos {
+            [url] => Some(vec!["--repository".into(), url.clone()]),
+            _ => None,
+        }
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.qi(kws, flags).await
@@ -80,15 +149,16 @@ impl Pm for Tlmgr {
         Cmd::new(&["tlmgr", "remove"])
             .kws(kws)
             .flags(flags)
-            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_CHECK_DRY))
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_REMOVE))
             .await
     }
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.check_repos()?;
         Cmd::new(&["tlmgr", "install"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_repos(flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_CHECK_DRY))
             .await
     }
@@ -108,22 +178,24 @@ impl Pm for Tlmgr {
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.check_repos()?;
         Cmd::new(&["tlmgr", "search", "--global"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_repos(flags))
             .pipe(|cmd| self.run(cmd))
             .await
     }
 
     /// Su updates outdated packages.
     async fn su(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.check_repos()?;
         Cmd::new(if kws.is_empty() {
             &["tlmgr", "update", "--self", "--all"]
         } else {
             &["tlmgr", "update", "--self"]
         })
         .kws(kws)
-        .flags(flags)
+        .flags(&self.with_repos(flags))
         .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_CHECK_DRY))
         .await
     }
@@ -144,3 +216,42 @@ impl Pm for Tlmgr {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_url() {
+        for url in &[
+            "https://mirror.ctan.org/systems/texlive/tlnet",
+            "ftp://ftp.example.com/tlnet",
+            "file:///opt/tlnet",
+        ] {
+            assert!(is_url(url), "`{}`", url);
+        }
+        for url in &[
+            "mirror.ctan.org",
+            "://mirror",
+            "https://",
+            "1http://x",
+            "https://a b",
+        ] {
+            assert!(!is_url(url), "`{}`", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn repo_rejected() {
+        let pm = Tlmgr::new(Config {
+            dry_run: true,
+            repos: vec!["mirror.ctan.org".into()],
+            ..Config::default()
+        });
+        let err = pm.s(&["biber"], &[]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ArgParseError { msg } if msg == "Repository `mirror.ctan.org` is not a valid URL"
+        ));
+    }
+}
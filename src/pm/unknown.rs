@@ -2,14 +2,20 @@
 
 use async_trait::async_trait;
 use indoc::indoc;
+use tt_call::tt_call;
 
-use super::Pm;
-use crate::dispatch::Config;
+use super::{registry, Pm};
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+    methods,
+};
 
 macro_rules! docs_self {
     () => {
         indoc! {"
-            An empty mapping for unidentified package managers.
+            A placeholder for unidentified package managers, failing every
+            operation with [`Error::PmNotFoundError`].
         "}
     };
 }
@@ -18,28 +24,80 @@ macro_rules! docs_self {
 #[derive(Debug)]
 pub(crate) struct Unknown {
     name: String,
+    tried: Vec<String>,
     cfg: Config,
 }
 
 impl Unknown {
     #[must_use]
-    /// Creates a new [`Unknown`] package manager with the given name.
-    pub(crate) fn new(name: &str) -> Self {
+    /// Creates a new [`Unknown`] package manager with the given name, and the
+    /// names of the package managers `tried` before falling back to it.
+    pub(crate) fn new(name: &str, tried: Vec<String>) -> Self {
         Unknown {
             name: format!("unknown package manager: {}", name),
+            tried,
             cfg: Config::default(),
         }
     }
 }
 
-#[async_trait]
-impl Pm for Unknown {
-    /// Gets the name of the package manager.
-    fn name(&self) -> &str {
-        &self.name
+macro_rules! impl_pm_unknown {(
+    methods = [{ $(
+        $( #[$meta:meta] )*
+        async fn $method:ident;
+    )* }]
+) => {
+    #[async_trait]
+    impl Pm for Unknown {
+        /// Gets the name of the package manager.
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn cfg(&self) -> &Config {
+            &self.cfg
+        }
+
+        $(
+            $( #[$meta] )*
+            async fn $method(&self, _kws: &[&str], _flags: &[&str]) -> Result<()> {
+                Err(registry::pm_not_found(self.tried.clone()))
+            }
+        )*
     }
+};}
+
+tt_call! {
+    macro = [{ methods }]
+    ~~> impl_pm_unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unknown_not_found() {
+        let tried = vec!["apk".to_owned(), "apt".to_owned()];
+        let err = Unknown::new("unknown", tried.clone())
+            .s(&["curl"], &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 127);
+        let msg = err.to_string();
+        assert!(msg.starts_with(
+            "No supported package manager is found (tried: `apk`, `apt`); \
+             select one of those supported on "
+        ));
+        assert!(msg.contains(" with `--backend <name>` or `default_pm` in the config: "));
+        assert!(matches!(err, Error::PmNotFoundError { tried: t, .. } if t == tried));
 
-    fn cfg(&self) -> &Config {
-        &self.cfg
+        let err = Unknown::new("pacman", vec![])
+            .suy(&[], &[])
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("No supported package manager is found (tried: none); "));
     }
 }
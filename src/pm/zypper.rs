@@ -2,14 +2,19 @@
 
 use async_trait::async_trait;
 use indoc::indoc;
+use itertools::{chain, Itertools};
 use once_cell::sync::Lazy;
 use tap::prelude::*;
 
-use super::{DryRunStrategy, NoCacheStrategy, Pm, PmHelper, PmMode, PromptStrategy, Strategy};
+use super::{
+    check_overwrite_all, classify_output, network_bound, DryRunStrategy, NoCacheStrategy, Op, Pm,
+    PmHelper, PmMode, PromptStrategy, Strategy,
+};
 use crate::{
     dispatch::Config,
-    error::Result,
-    exec::{self, Cmd},
+    error::{FailureKind, Result},
+    exec::{Cmd, StatusCode},
+    print::{self, PROMPT_RUN},
 };
 
 macro_rules! docs_self {
@@ -37,12 +42,49 @@ static STRAT_PROMPT: Lazy<Strategy> = Lazy::new(|| Strategy {
     ..Strategy::default()
 });
 
+static STRAT_PROMPT_CUSTOM: Lazy<Strategy> = Lazy::new(|| Strategy {
+    prompt: PromptStrategy::CustomPrompt,
+    ..Strategy::default()
+});
+
+/// `zypper patch` has no flag of its own to skip the prompt, so
+/// `--non-interactive` is added in [`Zypper::patch`] instead.
+static STRAT_PATCH: Lazy<Strategy> = Lazy::new(|| Strategy {
+    prompt: PromptStrategy::native_no_confirm(&[] as &[&str]),
+    dry_run: DryRunStrategy::with_flags(&["--dry-run"]),
+    ..Strategy::default()
+});
+
 static STRAT_INSTALL: Lazy<Strategy> = Lazy::new(|| Strategy {
     prompt: PromptStrategy::native_no_confirm(&["-y"]),
     no_cache: NoCacheStrategy::Scc,
     dry_run: DryRunStrategy::with_flags(&["--dry-run"]),
+    ..Strategy::default()
 });
 
+/// The messages identifying the kinds of failures, as in [`Pm::classify`].
+static FAILURE_PATTERNS: &[(&str, FailureKind)] =
+    &[("Download (curl) error", FailureKind::NetworkError)];
+
+/// Parses the package names from the table printed by `zypper packages`,
+/// eg. `foo` from `i  | @System | foo | 1.0-1 | x86_64`.
+#[must_use]
+fn parse_packages(text: &str) -> Vec<&str> {
+    let mut rows = text
+        .lines()
+        .filter(|ln| ln.contains('|'))
+        .map(|ln| ln.split('|').map(str::trim).collect_vec());
+    let name = rows
+        .next()
+        .and_then(|header| header.iter().position(|&col| col == "Name"));
+    name.map_or_else(Vec::new, |i| {
+        rows.filter_map(|row| row.get(i).copied())
+            .filter(|name| !name.is_empty())
+            .unique()
+            .collect()
+    })
+}
+
 impl Zypper {
     #[must_use]
     #[allow(missing_docs)]
@@ -54,6 +96,21 @@ impl Zypper {
         self.run_with(cmd, PmMode::default(), &STRAT_CHECK_DRY)
             .await
     }
+
+    /// Applies the needed patches with `zypper patch`, refreshing the
+    /// repositories first if `refresh` is set.
+    async fn patch(&self, refresh: bool, flags: &[&str]) -> Result<()> {
+        // The global options must come before the command.
+        let global = chain!(
+            self.cfg.no_confirm.then(|| "--non-interactive"),
+            (!refresh).then(|| "--no-refresh"),
+        );
+        check_overwrite_all(self)?;
+        Cmd::with_sudo(&chain!(["zypper"], global, ["patch"]).collect_vec())
+            .flags(&self.with_simulate(&self.with_overwrite(&self.with_repos(flags))))
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PATCH))
+            .await
+    }
 }
 
 #[async_trait]
@@ -67,6 +124,115 @@ impl Pm for Zypper {
         &self.cfg
     }
 
+    /// Runs the commands modifying the system through `sudo`.
+    fn uses_sudo(&self) -> bool {
+        true
+    }
+
+    fn supported_ops(&self) -> &[Op] {
+        &[
+            Op::Q,
+            Op::Qc,
+            Op::Qdt,
+            Op::Qi,
+            Op::Ql,
+            Op::Qm,
+            Op::Qo,
+            Op::Qp,
+            Op::Qs,
+            Op::Qu,
+            Op::R,
+            Op::Rss,
+            Op::S,
+            Op::Sc,
+            Op::Scc,
+            Op::Sg,
+            Op::Si,
+            Op::Sl,
+            Op::Ss,
+            Op::Su,
+            Op::Suy,
+            Op::Sw,
+            Op::Sy,
+            Op::U,
+        ]
+    }
+
+    /// Applies the patches with `zypper patch`, and lists them with `zypper
+    /// list-patches`.
+    fn supports_patches(&self) -> bool {
+        true
+    }
+
+    /// Classifies a failure by the exit codes documented in `zypper(8)`, or
+    /// else by its output.
+    fn classify(&self, code: StatusCode, output: &str) -> Option<FailureKind> {
+        match code {
+            // `ZYPPER_EXIT_INF_CAP_NOT_FOUND`
+            104 => Some(FailureKind::NotFound),
+            // `ZYPPER_EXIT_ON_SIGNAL`
+            105 => Some(FailureKind::Aborted),
+            _ => classify_output(output, FAILURE_PATTERNS),
+        }
+    }
+
+    /// Takes the pending reboot (`ZYPPER_EXIT_INF_REBOOT_NEEDED`) and the
+    /// skipped repositories (`ZYPPER_EXIT_INF_REPOS_SKIPPED`) as successes, as
+    /// they are only reported once the command has run.
+    fn is_informational(&self, code: StatusCode) -> bool {
+        matches!(code, 102 | 106)
+    }
+
+    /// Works only with the given repositories.
+    fn repo_flags(&self, repos: &[String]) -> Option<Vec<String>> {
+        Some(
+            repos
+                .iter()
+                .flat_map(|repo| vec!["--repo".into(), repo.clone()])
+                .collect(),
+        )
+    }
+
+    /// Runs the commands without making any changes.
+    fn simulate_flags(&self) -> Option<Vec<String>> {
+        Some(vec!["--dry-run".into()])
+    }
+
+    /// Replaces the conflicting files, which can't be restricted to some
+    /// paths, so only the glob `*` is accepted.
+    fn overwrite_flags(&self, _glob: &str) -> Option<Vec<String>> {
+        Some(vec!["--replacefiles".into()])
+    }
+
+    /// Operates on a different root directory, eg. of a chroot, which `rpm`
+    /// reads the database under.
+    fn root_flags(&self, exe: &str, root: &str) -> Option<Vec<String>> {
+        matches!(exe, "zypper" | "rpm").then(|| vec!["--root".into(), root.into()])
+    }
+
+    /// Hold prevents one or more packages from being upgraded or removed.
+    async fn hold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["zypper", "addlock"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM))
+            .await
+    }
+
+    /// Unhold releases one or more packages previously held.
+    async fn unhold(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        Cmd::with_sudo(&["zypper", "removelock"])
+            .kws(kws)
+            .flags(flags)
+            .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT_CUSTOM))
+            .await
+    }
+
+    /// Held lists the packages that are currently held.
+    async fn held(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        self.run(Cmd::new(&["zypper", "locks"]).flags(flags)).await
+    }
+
     /// Q generates a list of installed packages.
     async fn q(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         if kws.is_empty() {
@@ -88,6 +254,20 @@ impl Pm for Zypper {
             .await
     }
 
+    /// Qdt lists orphans, ie. packages installed as dependencies which are no
+    /// longer required by any other package.
+    async fn qdt(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        let cmd = Cmd::new(&["zypper", "packages", "--orphaned"]).flags(flags);
+        if !(self.cfg.dry_run || self.cfg.json) {
+            print::print_cmd(&cmd, PROMPT_RUN);
+        }
+        let out_bytes = self
+            .check_output(cmd, PmMode::Mute, &Strategy::default())
+            .await?;
+        let orphans = parse_packages(&self.decode(out_bytes)?).join("\n");
+        self.grep_print(&orphans, kws)
+    }
+
     /// Qi displays local package information: name, version, description, etc.
     async fn qi(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         self.si(kws, flags).await
@@ -106,9 +286,9 @@ impl Pm for Zypper {
         let out_bytes = self
             .check_output(cmd, PmMode::Mute, &Strategy::default())
             .await?;
-        let out = String::from_utf8(out_bytes)?;
+        let out = self.decode(out_bytes)?;
 
-        exec::grep_print(&out, &["System Packages"])?;
+        self.grep_print(&out, &["System Packages"])?;
         Ok(())
     }
 
@@ -139,6 +319,11 @@ impl Pm for Zypper {
 
     /// Qu lists packages which have an update available.
     async fn qu(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        if self.cfg.patch {
+            return self
+                .run(Cmd::new(&["zypper", "list-patches"]).kws(kws).flags(flags))
+                .await;
+        }
         self.check_dry(Cmd::new(&["zypper", "list-updates"]).kws(kws).flags(flags))
             .await
     }
@@ -147,7 +332,7 @@ impl Pm for Zypper {
     async fn r(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
         Cmd::with_sudo(&["zypper", "remove"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_simulate(flags))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_PROMPT))
             .await
     }
@@ -164,9 +349,10 @@ impl Pm for Zypper {
 
     /// S installs one or more packages by name.
     async fn s(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
+        check_overwrite_all(self)?;
         Cmd::with_sudo(&["zypper", "install"])
             .kws(kws)
-            .flags(flags)
+            .flags(&self.with_simulate(&self.with_overwrite(&self.with_repos(flags))))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -223,21 +409,29 @@ impl Pm for Zypper {
         let out = self
             .check_output(cmd, PmMode::Mute, &STRAT_CHECK_DRY)
             .await?
-            .pipe(String::from_utf8)?;
-        exec::grep_print(&out, kws)
+            .pipe(|out| self.decode(out))?;
+        self.grep_print(&out, kws)
     }
 
     /// Ss searches for package(s) by searching the expression in name,
     /// description, short description.
     async fn ss(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.check_dry(Cmd::new(&["zypper", "search"]).kws(kws).flags(flags))
-            .await
+        self.check_dry(
+            Cmd::new(&["zypper", "search"])
+                .kws(kws)
+                .flags(&self.with_repos(flags)),
+        )
+        .await
     }
 
     /// Su updates outdated packages.
     async fn su(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        if self.cfg.patch {
+            return self.patch(false, flags).await;
+        }
+        check_overwrite_all(self)?;
         Cmd::with_sudo(&["zypper", "--no-refresh", "dist-upgrade"])
-            .flags(flags)
+            .flags(&self.with_simulate(&self.with_overwrite(&self.with_repos(flags))))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -245,8 +439,12 @@ impl Pm for Zypper {
     /// Suy refreshes the local package database, then updates outdated
     /// packages.
     async fn suy(&self, _kws: &[&str], flags: &[&str]) -> Result<()> {
+        if self.cfg.patch {
+            return self.patch(true, flags).await;
+        }
+        check_overwrite_all(self)?;
         Cmd::with_sudo(&["zypper", "dist-upgrade"])
-            .flags(flags)
+            .flags(&self.with_simulate(&self.with_overwrite(&self.with_repos(flags))))
             .pipe(|cmd| self.run_with(cmd, PmMode::default(), &STRAT_INSTALL))
             .await
     }
@@ -263,8 +461,7 @@ impl Pm for Zypper {
 
     /// Sy refreshes the local package database.
     async fn sy(&self, kws: &[&str], flags: &[&str]) -> Result<()> {
-        self.check_dry(Cmd::with_sudo(&["zypper", "refresh"]).flags(flags))
-            .await?;
+        network_bound(self.check_dry(Cmd::with_sudo(&["zypper", "refresh"]).flags(flags))).await?;
         if !kws.is_empty() {
             self.s(kws, flags).await?;
         }
@@ -277,3 +474,23 @@ impl Pm for Zypper {
         self.s(kws, flags).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orphaned_packages() {
+        let out = indoc! {"
+            Loading repository data...
+            Reading installed packages...
+            S  | Repository | Name    | Version | Arch
+            ---+------------+---------+---------+-------
+            i  | @System    | libfoo1 | 1.0-1.1 | x86_64
+            i  | @System    | libfoo1 | 1.0-1.1 | i586
+            i+ | @System    | bar     | 2.3-4.5 | noarch
+        "};
+        assert_eq!(parse_packages(out), ["libfoo1", "bar"]);
+        assert!(parse_packages("No packages found.").is_empty());
+    }
+}
@@ -1,19 +1,322 @@
 //! Output messages and prompts.
+//!
+//! The messages of [`pacaptr`](crate) itself go through a [`Printer`], which
+//! writes them to `stdout` and `stderr` by default, or elsewhere as set by
+//! [`Config::printer`](crate::dispatch::Config::printer).
 
 #![allow(missing_docs, clippy::module_name_repetitions)]
 
+use std::{
+    borrow::Cow,
+    env, fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use clap::ArgEnum;
 use colored::Colorize;
+use itertools::{chain, Itertools};
+use once_cell::sync::Lazy;
+use serde::Serialize;
 
-use crate::exec::Cmd;
+use crate::exec::{self, Cmd, OutputStream, StatusCode};
 
 pub(crate) static PROMPT_CANCELED: &str = "Canceled";
 pub(crate) static PROMPT_PENDING: &str = "Pending";
 pub(crate) static PROMPT_RUN: &str = "Running";
 pub(crate) static PROMPT_INFO: &str = "Info";
+pub(crate) static PROMPT_NATIVE: &str = "Native";
+pub(crate) static PROMPT_WARNING: &str = "Warning";
 pub static PROMPT_ERROR: &str = "Error";
 
-/// The right indentation to be applied on prompt prefixes.
-static PROMPT_INDENT: usize = 9;
+/// The question asked before running a command which needs a confirmation.
+pub(crate) static QUESTION_PROCEED: &str = "Proceed";
+
+/// The translations of the prompts above, by language, as chosen with
+/// `$PACAPTR_LANG`.
+///
+/// The prompts themselves are the keys, so that those missing from a table
+/// are printed in English.
+static TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "es",
+        &[
+            (PROMPT_CANCELED, "Cancelado"),
+            (PROMPT_PENDING, "Pendiente"),
+            (PROMPT_RUN, "Ejecutando"),
+            (PROMPT_NATIVE, "Nativo"),
+            (PROMPT_WARNING, "Aviso"),
+            (QUESTION_PROCEED, "Continuar"),
+        ],
+    ),
+    (
+        "fr",
+        &[
+            (PROMPT_CANCELED, "Annulé"),
+            (PROMPT_PENDING, "En attente"),
+            (PROMPT_RUN, "Exécution"),
+            (PROMPT_NATIVE, "Natif"),
+            (PROMPT_WARNING, "Attention"),
+            (PROMPT_ERROR, "Erreur"),
+            (QUESTION_PROCEED, "Continuer"),
+        ],
+    ),
+];
+
+/// Gets the table of translations for the language `lang`, eg. `fr` or
+/// `fr_FR.UTF-8`, which is empty for English or any unknown language.
+#[must_use]
+fn translations(lang: Option<&str>) -> &'static [(&'static str, &'static str)] {
+    let lang = lang
+        .and_then(|lang| lang.split(&['_', '-', '.'][..]).next())
+        .unwrap_or_default()
+        .to_lowercase();
+    TRANSLATIONS
+        .iter()
+        .find(|&&(name, _)| name == lang)
+        .map_or(&[], |&(_, table)| table)
+}
+
+/// Translates `key` with `table`, or leaves it in English if it is missing.
+#[must_use]
+fn translate<'a>(table: &[(&str, &'a str)], key: &'a str) -> &'a str {
+    table
+        .iter()
+        .find(|&&(k, _)| k == key)
+        .map_or(key, |&(_, translated)| translated)
+}
+
+/// The table of translations in use, as chosen with `$PACAPTR_LANG`.
+static LANG: Lazy<&[(&str, &str)]> =
+    Lazy::new(|| translations(env::var("PACAPTR_LANG").ok().as_deref()));
+
+/// Translates the prompt `key` into the language in use.
+#[must_use]
+fn localize(key: &str) -> &str {
+    translate(&LANG, key)
+}
+
+/// Where the messages of [`pacaptr`](crate) itself are printed, eg. the
+/// commands echoed after their prompts, the warnings and the errors.
+///
+/// Each method gets a single rendered line, without the line break, except
+/// for [`question`](Printer::question), which the answer should follow on
+/// the same line.
+pub trait Printer: fmt::Debug + Send + Sync {
+    /// Prints a line meant for `stdout`, eg. a command after its prompt.
+    fn out(&self, line: &str);
+
+    /// Prints a line meant for `stderr`, eg. a warning or an error.
+    fn err(&self, line: &str);
+
+    /// Prints a question meant for `stdout`, eg. `Proceed? [YES/All/No/^C]`.
+    fn question(&self, question: &str);
+}
+
+/// The default [`Printer`], writing to `stdout` and `stderr`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdPrinter;
+
+impl Printer for StdPrinter {
+    fn out(&self, line: &str) {
+        println!("{}", line);
+    }
+
+    fn err(&self, line: &str) {
+        eprintln!("{}", line);
+    }
+
+    fn question(&self, question: &str) {
+        print!("{}", question);
+    }
+}
+
+/// A [`Printer`] keeping the lines printed in memory, eg. to check the
+/// messages of an operation in a test.
+///
+/// The lines of `stdout` and `stderr` are kept together in the order they
+/// are printed.
+#[derive(Debug, Default)]
+pub struct BufPrinter {
+    lines: Mutex<Vec<String>>,
+}
+
+impl BufPrinter {
+    /// Gets the lines printed so far.
+    ///
+    /// # Panics
+    /// Panics if another thread has panicked while printing.
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    /// Keeps `line` as printed.
+    fn push(&self, line: &str) {
+        self.lines.lock().unwrap().push(line.into());
+    }
+}
+
+impl Printer for BufPrinter {
+    fn out(&self, line: &str) {
+        self.push(line);
+    }
+
+    fn err(&self, line: &str) {
+        self.push(line);
+    }
+
+    fn question(&self, question: &str) {
+        self.push(question);
+    }
+}
+
+tokio::task_local! {
+    /// The [`Printer`] in use, if not the [`StdPrinter`].
+    static PRINTER: Arc<dyn Printer>;
+}
+
+/// Runs `fut` with all of its messages printed by `printer`, or by the
+/// current [`Printer`] if it is [`None`].
+pub(crate) async fn scoped<F: Future>(printer: Option<Arc<dyn Printer>>, fut: F) -> F::Output {
+    match printer {
+        Some(printer) => PRINTER.scope(printer, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Gets the current [`Printer`].
+#[must_use]
+pub(crate) fn printer() -> Arc<dyn Printer> {
+    PRINTER
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::new(StdPrinter))
+}
+
+/// The minimum right indentation to be applied on prompt prefixes, which fits
+/// all the prompts in English.
+const MIN_PROMPT_INDENT: usize = 9;
+
+/// Gets the right indentation fitting all the prompts translated with `table`.
+#[must_use]
+fn prompt_indent(table: &[(&str, &str)]) -> usize {
+    table
+        .iter()
+        .map(|&(_, translated)| translated.chars().count())
+        .fold(MIN_PROMPT_INDENT, usize::max)
+}
+
+/// The right indentation to be applied on prompt prefixes in the language in
+/// use.
+static PROMPT_INDENT: Lazy<usize> = Lazy::new(|| prompt_indent(&LANG));
+
+/// The prompts after which the command itself is the result, eg. in a dry run.
+static PROMPTS_RESULT: &[&str] = &[PROMPT_CANCELED, PROMPT_NATIVE];
+
+/// The amount of messages printed by [`pacaptr`](crate) itself, in addition to
+/// the output of the commands it runs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Verbosity {
+    /// Prints nothing but errors, and the commands in a dry run.
+    Quiet,
+
+    /// Prints the commands along with their prompts, and warnings.
+    Normal,
+
+    /// Also prints every command executed as it is, along with its duration
+    /// and exit status.
+    Verbose,
+
+    /// Also prints the [`Config`](crate::dispatch::Config) in use, and how the
+    /// package manager has been detected.
+    Debug,
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Verbosity::Normal
+    }
+}
+
+/// The current [`Verbosity`].
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the current [`Verbosity`] for all the messages to be printed.
+pub(crate) fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Checks if the messages of the given [`Verbosity`] should be printed.
+#[must_use]
+pub(crate) fn shows(verbosity: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+/// When to use colors in the messages printed by [`pacaptr`](crate) itself, as
+/// given by `--color`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ArgEnum)]
+pub(crate) enum ColorMode {
+    /// Uses colors when printing to a terminal, unless told otherwise by
+    /// `$NO_COLOR` or `$CLICOLOR_FORCE`.
+    Auto,
+
+    /// Always uses colors.
+    Always,
+
+    /// Never uses colors.
+    Never,
+}
+
+impl Default for ColorMode {
+    fn default() -> Self {
+        ColorMode::Auto
+    }
+}
+
+/// Decides if colors should be used under `mode`, given the values of
+/// `$NO_COLOR` and `$CLICOLOR_FORCE`, and whether the messages are printed to
+/// a terminal as indicated by `is_tty`.
+///
+/// See: <https://no-color.org> and <https://bixense.com/clicolors>
+#[must_use]
+fn decide_color(
+    mode: ColorMode,
+    no_color: Option<&str>,
+    clicolor_force: Option<&str>,
+    is_tty: bool,
+) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto if no_color.map_or(false, |v| !v.is_empty()) => false,
+        ColorMode::Auto if clicolor_force.map_or(false, |v| !v.is_empty() && v != "0") => true,
+        ColorMode::Auto => is_tty,
+    }
+}
+
+/// Sets whether all the messages to be printed, including the errors and the
+/// commands in a dry run, should be colored under `mode`.
+pub(crate) fn set_color(mode: ColorMode) {
+    let is_tty = atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stderr);
+    colored::control::set_override(decide_color(
+        mode,
+        env::var("NO_COLOR").ok().as_deref(),
+        env::var("CLICOLOR_FORCE").ok().as_deref(),
+        is_tty,
+    ));
+}
+
+/// Checks if the messages to be printed are colored, as decided by
+/// [`set_color`].
+#[must_use]
+pub(crate) fn uses_color() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
 
 macro_rules! prompt_format {
     () => {
@@ -40,41 +343,524 @@ macro_rules! question_format {
 }
 
 /// Prints out the command after the given prompt.
+///
+/// Under [`Verbosity::Quiet`], only the commands that are the result
+/// themselves are printed, without any prompt, as well as the commands waiting
+/// for a confirmation.
 pub(crate) fn print_cmd(cmd: &Cmd, prompt: &str) {
-    println!(
+    if !shows(Verbosity::Normal) {
+        if PROMPTS_RESULT.contains(&prompt) {
+            printer().out(&cmd.to_string());
+        }
+        // A question about a pending command is meaningless without the latter.
+        if prompt != PROMPT_PENDING {
+            return;
+        }
+    }
+    printer().out(&render_cmd(cmd, prompt));
+}
+
+/// Renders the command after the given prompt.
+#[must_use]
+fn render_cmd(cmd: &Cmd, prompt: &str) -> String {
+    format!(
         cmd_format!(),
-        prompt.green().bold(),
+        localize(prompt).green().bold(),
         cmd,
-        indent = PROMPT_INDENT
-    );
+        indent = *PROMPT_INDENT
+    )
+}
+
+/// Prints out the output `out` of the commands run, which has been collected
+/// instead of being printed as it came, eg. to be filtered or cached.
+pub(crate) fn print_output(out: &[u8]) {
+    let printer = printer();
+    for line in String::from_utf8_lossy(out).lines() {
+        printer.out(line);
+    }
 }
 
 /// Prints out a message after the given prompt.
 pub(crate) fn print_msg(msg: &str, prompt: &str) {
-    println!(
+    if !shows(Verbosity::Normal) {
+        return;
+    }
+    printer().out(&format!(
         msg_format!(),
-        prompt.green().bold(),
+        localize(prompt).green().bold(),
         msg,
-        indent = PROMPT_INDENT
-    );
+        indent = *PROMPT_INDENT
+    ));
 }
 
 /// Prints out an error after the given prompt.
 pub fn print_err(err: impl std::fmt::Display, prompt: &str) {
-    eprintln!(
+    printer().err(&render_err(err, prompt));
+}
+
+/// Renders the error after the given prompt.
+#[must_use]
+fn render_err(err: impl std::fmt::Display, prompt: &str) -> String {
+    format!(
         msg_format!(),
-        prompt.bright_red().bold(),
+        localize(prompt).bright_red().bold(),
         format!("{:#}", err),
-        indent = PROMPT_INDENT
+        indent = *PROMPT_INDENT
+    )
+}
+
+/// The number of lines of output printed along with an error, unless under
+/// [`Verbosity::Verbose`].
+pub const OUTPUT_TAIL_LINES: usize = 20;
+
+/// Renders the last `max_lines` lines of `output` (or all of them if
+/// [`None`]), indented to be printed beneath an error.
+#[must_use]
+fn render_output(output: &[u8], max_lines: Option<usize>) -> String {
+    let output = String::from_utf8_lossy(output);
+    let lines = output.trim().lines().map(str::trim_end).collect::<Vec<_>>();
+    let skipped = max_lines.map_or(0, |max| lines.len().saturating_sub(max));
+    let header =
+        (skipped > 0).then(|| format!("... ({} more lines, use `--verbose` to show all)", skipped));
+    let indent = " ".repeat(*PROMPT_INDENT + 1);
+    header
+        .iter()
+        .map(String::as_str)
+        .chain(lines[skipped..].iter().copied())
+        .map(|line| [indent.as_str(), line, "\n"].concat())
+        .collect()
+}
+
+/// The notice appended to the output truncated by [`truncate_output`].
+pub(crate) const TRUNCATED_NOTICE: &str = "... (truncated)";
+
+/// Keeps only the first `max_lines` lines of `output`, as set by
+/// `--max-lines`, followed by [`TRUNCATED_NOTICE`] if anything is left out.
+#[must_use]
+pub(crate) fn truncate_output(output: &[u8], max_lines: usize) -> Cow<'_, [u8]> {
+    let cut = match max_lines {
+        0 => 0,
+        n => match output.iter().positions(|&b| b == b'\n').nth(n - 1) {
+            Some(i) => i + 1,
+            None => return Cow::Borrowed(output),
+        },
+    };
+    if cut == output.len() {
+        return Cow::Borrowed(output);
+    }
+    let mut truncated = output[..cut].to_vec();
+    truncated.extend_from_slice(TRUNCATED_NOTICE.as_bytes());
+    truncated.push(b'\n');
+    Cow::Owned(truncated)
+}
+
+/// Prints out the output of a failed command beneath its error, keeping only
+/// the last [`OUTPUT_TAIL_LINES`] lines unless under [`Verbosity::Verbose`].
+pub fn print_err_output(output: &[u8]) {
+    let max_lines = (!shows(Verbosity::Verbose)).then(|| OUTPUT_TAIL_LINES);
+    let printer = printer();
+    for line in render_output(output, max_lines).lines() {
+        printer.err(line);
+    }
+}
+
+/// Prints out a warning after the given prompt.
+pub(crate) fn print_warning(warning: impl std::fmt::Display, prompt: &str) {
+    if !shows(Verbosity::Normal) {
+        return;
+    }
+    printer().err(&format!(
+        msg_format!(),
+        localize(prompt).yellow().bold(),
+        format!("{:#}", warning),
+        indent = *PROMPT_INDENT
+    ));
+}
+
+/// Prints out a message for troubleshooting after the given prompt to
+/// `stderr`, if the given [`Verbosity`] is reached.
+pub(crate) fn print_info(msg: impl std::fmt::Display, prompt: &str, verbosity: Verbosity) {
+    if !shows(verbosity) {
+        return;
+    }
+    printer().err(&format!(
+        msg_format!(),
+        localize(prompt).blue().bold(),
+        msg,
+        indent = *PROMPT_INDENT
+    ));
+}
+
+/// Renders the progress of a step out of `total` after a counter (eg.
+/// `[2/5] installing foo`), which is highlighted if `styled` is set.
+#[must_use]
+fn render_progress(current: usize, total: usize, msg: &str, styled: bool) -> String {
+    let counter = format!(
+        "[{:>width$}/{}]",
+        current,
+        total,
+        width = total.to_string().len()
     );
+    let counter = if styled {
+        counter.blue().bold()
+    } else {
+        counter.normal()
+    };
+    format!(msg_format!(), counter, msg, indent = *PROMPT_INDENT)
+}
+
+/// Prints out the progress of a step out of `total` to `stderr` (eg.
+/// `[2/5] installing foo`), with the counter highlighted if colors are in use.
+pub(crate) fn progress(current: usize, total: usize, msg: impl std::fmt::Display) {
+    if !shows(Verbosity::Normal) {
+        return;
+    }
+    printer().err(&render_progress(
+        current,
+        total,
+        &msg.to_string(),
+        uses_color(),
+    ));
 }
 
 /// Prints out a question after the given prompt.
 pub(crate) fn print_question(question: &str, options: &str) {
-    print!(
+    printer().question(&format!(
         question_format!(),
-        question.yellow(),
+        localize(question).yellow(),
         options.underline(),
-        indent = PROMPT_INDENT
-    );
+        indent = *PROMPT_INDENT
+    ));
+}
+
+/// The format of the events emitted on `stderr` for automation, as given by
+/// `--events`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ArgEnum)]
+pub enum EventFormat {
+    /// One JSON object per line.
+    Json,
+}
+
+/// A significant step of an operation, emitted on `stderr` under `--events`
+/// so that other tools can follow the progress of [`pacaptr`](crate), or sent
+/// to the [`event_sender`](field@crate::exec::Cmd::event_sender) of a command
+/// as it runs.
+///
+/// The output lines and the prompts are only sent to the latter.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A command is about to be run.
+    CommandStarted { cmd: Vec<String> },
+
+    /// A command has printed a line, without the line break.
+    ///
+    /// Only the streams collected under the [`Mode`](crate::exec::Mode) of
+    /// the command are reported, eg. only `stderr` under
+    /// [`Mode::CheckErr`](crate::exec::Mode::CheckErr), since the other ones
+    /// go directly to the terminal.
+    OutputLine { stream: OutputStream, line: String },
+
+    /// The user is about to be asked whether a command should be run, as in
+    /// [`Mode::Prompt`](crate::exec::Mode::Prompt).
+    PromptRequested { cmd: Vec<String> },
+
+    /// A command has finished, where `code` is [`None`] if it has not exited
+    /// normally, or has not been run at all in a dry run.
+    CommandFinished {
+        cmd: Vec<String>,
+        code: Option<StatusCode>,
+        duration_ms: u128,
+    },
+
+    /// A failed command is about to be retried.
+    Retry {
+        cmd: Vec<String>,
+        code: StatusCode,
+        attempt: u32,
+        retries: u32,
+        delay_ms: u128,
+    },
+
+    /// A command has failed.
+    Error { cmd: Vec<String>, msg: String },
+}
+
+impl Event {
+    /// Makes an [`Event::CommandStarted`] for `cmd`.
+    #[must_use]
+    pub(crate) fn started(cmd: &Cmd) -> Self {
+        Event::CommandStarted {
+            cmd: event_cmd(cmd),
+        }
+    }
+
+    /// Makes an [`Event::CommandFinished`] for `cmd`.
+    #[must_use]
+    pub(crate) fn finished(cmd: &Cmd, code: Option<StatusCode>, duration: Duration) -> Self {
+        Event::CommandFinished {
+            cmd: event_cmd(cmd),
+            code,
+            duration_ms: duration.as_millis(),
+        }
+    }
+
+    /// Makes an [`Event::Retry`] for `cmd`, which has failed with `code`.
+    #[must_use]
+    pub(crate) fn retry(
+        cmd: &Cmd,
+        code: StatusCode,
+        attempt: u32,
+        retries: u32,
+        delay: Duration,
+    ) -> Self {
+        Event::Retry {
+            cmd: event_cmd(cmd),
+            code,
+            attempt,
+            retries,
+            delay_ms: delay.as_millis(),
+        }
+    }
+
+    /// Makes an [`Event::Error`] for `cmd`, which has failed with `err`.
+    #[must_use]
+    pub(crate) fn error(cmd: &Cmd, err: impl std::fmt::Display) -> Self {
+        Event::Error {
+            cmd: event_cmd(cmd),
+            msg: err.to_string(),
+        }
+    }
+
+    /// Makes an [`Event::PromptRequested`] for `cmd`.
+    #[must_use]
+    pub(crate) fn prompt_requested(cmd: &Cmd) -> Self {
+        Event::PromptRequested {
+            cmd: event_cmd(cmd),
+        }
+    }
+}
+
+/// Splits `cmd` into words as shown in an [`Event`], without the `sudo` part,
+/// and with the credentials redacted as in [`Cmd::redacted`].
+#[must_use]
+pub(crate) fn event_cmd(cmd: &Cmd) -> Vec<String> {
+    chain!(&cmd.cmd, &cmd.flags, &cmd.kws)
+        .map(|arg| exec::redact(arg).into_owned())
+        .collect()
+}
+
+/// Renders the event in the given format.
+#[must_use]
+fn render_event(event: &Event, format: EventFormat) -> String {
+    match format {
+        EventFormat::Json => serde_json::to_string(event).expect("events should be serializable"),
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    /// The events emitted so far, if they are to be collected instead of being
+    /// printed.
+    static EVENTS: std::sync::Arc<std::sync::Mutex<Vec<Event>>>;
+}
+
+/// Runs `fut` with all of its events collected instead of being printed,
+/// returning the result of `fut` along with the events.
+#[cfg(test)]
+pub(crate) async fn collect_events<F: std::future::Future>(fut: F) -> (F::Output, Vec<Event>) {
+    let events = std::sync::Arc::default();
+    let res = EVENTS.scope(std::sync::Arc::clone(&events), fut).await;
+    let events = events.lock().unwrap().drain(..).collect();
+    (res, events)
+}
+
+/// Runs `fut` with all of its messages collected without colors instead of
+/// being printed, returning the result of `fut` along with the lines.
+#[cfg(test)]
+pub(crate) async fn collect_lines<F: Future>(fut: F) -> (F::Output, Vec<String>) {
+    let printer = Arc::new(BufPrinter::default());
+    let _lock = crate::test_util::COLOR_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    colored::control::set_override(false);
+    let res = scoped(Some(printer.clone()), fut).await;
+    colored::control::unset_override();
+    (res, printer.lines())
+}
+
+/// Emits the event in the given format to `stderr`.
+pub(crate) fn emit(event: &Event, format: EventFormat) {
+    #[cfg(test)]
+    if let Ok(()) = EVENTS.try_with(|events| events.lock().unwrap().push(event.clone())) {
+        return;
+    }
+    printer().err(&render_event(event, format));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        exec::{Cmd, Mode},
+        test_util::COLOR_LOCK,
+    };
+
+    #[test]
+    fn color_decision() {
+        use ColorMode::{Always, Auto, Never};
+
+        assert!(decide_color(Always, Some("1"), None, false));
+        assert!(!decide_color(Never, None, Some("1"), true));
+        assert!(decide_color(Auto, None, None, true));
+        assert!(!decide_color(Auto, None, None, false));
+        assert!(!decide_color(Auto, Some("1"), None, true));
+        // An empty `$NO_COLOR` is ignored.
+        assert!(decide_color(Auto, Some(""), None, true));
+        assert!(decide_color(Auto, None, Some("1"), false));
+        assert!(!decide_color(Auto, None, Some("0"), false));
+        assert!(!decide_color(Auto, Some("1"), Some("1"), true));
+    }
+
+    #[test]
+    fn color_modes() {
+        let _lock = COLOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let has_ansi = |s: &str| s.contains('\u{1b}');
+        let cmd = Cmd::new(&["apt", "install", "curl"]);
+        let render_all = || {
+            [
+                render_cmd(&cmd, PROMPT_CANCELED),
+                render_err("Failed to install `curl`", PROMPT_ERROR),
+                render_progress(2, 5, "installing foo", uses_color()),
+            ]
+        };
+
+        set_color(ColorMode::Always);
+        assert!(uses_color());
+        assert!(render_all().iter().all(|s| has_ansi(s)));
+
+        set_color(ColorMode::Never);
+        assert!(!uses_color());
+        assert_eq!(
+            render_all(),
+            [
+                " Canceled `apt install curl`",
+                "    Error Failed to install `curl`",
+                "    [2/5] installing foo",
+            ]
+        );
+        colored::control::unset_override();
+    }
+
+    #[tokio::test]
+    async fn buf_printer() {
+        let _lock = COLOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        set_color(ColorMode::Never);
+        let printer = Arc::new(BufPrinter::default());
+        scoped(Some(printer.clone()), async {
+            print_cmd(&Cmd::new(&["apt", "install", "curl"]), PROMPT_CANCELED);
+            print_warning("Something is off", PROMPT_WARNING);
+            print_err("Failed to install `curl`", PROMPT_ERROR);
+            print_err_output(b"E: Unable to locate package curl\n");
+        })
+        .await;
+        colored::control::unset_override();
+        assert_eq!(
+            printer.lines(),
+            [
+                " Canceled `apt install curl`",
+                "  Warning Something is off",
+                "    Error Failed to install `curl`",
+                "          E: Unable to locate package curl",
+            ]
+        );
+    }
+
+    #[test]
+    fn localized_prompts() {
+        let fr = translations(Some("fr_FR.UTF-8"));
+        assert_eq!(translate(fr, PROMPT_CANCELED), "Annulé");
+        assert_eq!(translate(fr, QUESTION_PROCEED), "Continuer");
+        // The prompts missing from a table are left in English.
+        assert_eq!(translate(fr, PROMPT_INFO), "Info");
+        assert_eq!(translate(translations(Some("es")), PROMPT_ERROR), "Error");
+        assert!(translations(Some("en_US")).is_empty());
+        assert!(translations(None).is_empty());
+
+        let de = &[(PROMPT_CANCELED, "Abgebrochen")];
+        assert_eq!(translate(de, PROMPT_CANCELED), "Abgebrochen");
+        assert_eq!(translate(de, PROMPT_RUN), "Running");
+
+        let _lock = COLOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        set_color(ColorMode::Never);
+        let cmd = Cmd::new(&["apt", "install", "curl"]);
+        assert_eq!(
+            render_cmd(&cmd, translate(fr, PROMPT_PENDING)),
+            "En attente `apt install curl`"
+        );
+        assert_eq!(
+            render_cmd(&cmd, translate(fr, PROMPT_CANCELED)),
+            "   Annulé `apt install curl`"
+        );
+        colored::control::unset_override();
+
+        // The prompts are indented to fit the longest one, eg. `En attente`.
+        assert_eq!(prompt_indent(fr), "En attente".len());
+        assert_eq!(prompt_indent(translations(Some("es"))), "Ejecutando".len());
+        assert_eq!(prompt_indent(&[]), MIN_PROMPT_INDENT);
+    }
+
+    #[test]
+    fn render_output_tail() {
+        let output = (1..=25)
+            .map(|i| format!("line {}  \n", i))
+            .collect::<String>();
+        assert_eq!(
+            render_output(format!("\n{}\n", output).as_bytes(), Some(3)),
+            concat!(
+                "          ... (22 more lines, use `--verbose` to show all)\n",
+                "          line 23\n",
+                "          line 24\n",
+                "          line 25\n",
+            )
+        );
+        assert_eq!(render_output(output.as_bytes(), None).lines().count(), 25);
+        assert_eq!(render_output(b"", Some(3)), "");
+    }
+
+    #[test]
+    fn render_progress_counter() {
+        assert_eq!(
+            render_progress(2, 5, "installing foo", false),
+            "    [2/5] installing foo"
+        );
+        // The counter is aligned for every step.
+        assert_eq!(
+            render_progress(3, 10, "installing bar", false),
+            "  [ 3/10] installing bar"
+        );
+        assert_eq!(
+            render_progress(10, 10, "installing baz", false),
+            "  [10/10] installing baz"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn render_failed_cmd_output() {
+        let script = "echo 'Resolving host...'; sleep 0.1; \
+                      echo 'Could not resolve host: example.com' >&2; exit 6";
+        let err = Cmd::new(&["sh", "-c", script])
+            .mode(Mode::Mute)
+            .exec()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            render_output(err.output().unwrap(), Some(OUTPUT_TAIL_LINES)),
+            concat!(
+                "          Resolving host...\n",
+                "          Could not resolve host: example.com\n",
+            )
+        );
+    }
 }
@@ -0,0 +1,305 @@
+//! The on-disk cache of the results of `-Ss` and `-Si`, enabled by
+//! [`Config::search_cache_ttl`].
+//!
+//! The results are cached per package manager and query, under
+//! `$HOME/.cache/pacaptr/search/<pm>/`, and those of a package manager are
+//! dropped as soon as its database is refreshed (eg. in `-Sy`).
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::prelude::*;
+use itertools::chain;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    dispatch::Config,
+    error::Result,
+    pm::{self, Pm},
+    print::{print_info, print_warning, Verbosity, PROMPT_INFO, PROMPT_WARNING},
+};
+
+/// The results of a query, as stored in the cache.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    /// The query whose results are cached, checked against that being looked
+    /// up in case of a collision in the name of the entry.
+    key: Vec<String>,
+
+    /// When the results were cached, in seconds since the Unix epoch.
+    timestamp: u64,
+
+    /// What has been printed by the query.
+    output: String,
+}
+
+/// Gets the directory of the search cache.
+#[must_use]
+pub(crate) fn dir() -> PathBuf {
+    Config::cache_dir().join("search")
+}
+
+/// Checks if the results of the operation indicated by `op` (eg. `ss`) can be
+/// cached.
+#[must_use]
+pub(crate) fn is_cached_op(op: &str) -> bool {
+    op == "ss" || op == "si"
+}
+
+/// Checks if the operation indicated by `op` (eg. `suy`) refreshes the
+/// package database, and thus invalidates the cache.
+#[must_use]
+pub(crate) fn is_invalidating_op(op: &str) -> bool {
+    op.starts_with('s') && op.contains('y')
+}
+
+/// Gets the key identifying the query `method` (eg. `ss`) with the keywords
+/// `kws` and the flags `flags`, along with the options of `cfg` changing its
+/// results, eg. `--index-url`.
+#[must_use]
+pub(crate) fn key(cfg: &Config, method: &str, kws: &[&str], flags: &[&str]) -> Vec<String> {
+    // `--tap` is not among them, since it never reaches the cache.
+    let opts = chain!(
+        cfg.repos.iter().map(|repo| format!("--repo={}", repo)),
+        cfg.index_urls
+            .iter()
+            .map(|url| format!("--index-url={}", url)),
+        cfg.arch.iter().map(|arch| format!("--arch={}", arch)),
+        cfg.cask.then(|| "--cask".to_owned()),
+    );
+    chain!([method], kws.iter().copied(), ["--"], flags.iter().copied())
+        .map(Into::into)
+        .chain(opts)
+        .collect()
+}
+
+/// Gets the path of the cache entry for the query `key` on the package
+/// manager `pm`.
+///
+/// The entry is named after the SHA-256 of `key` (as a JSON array), which,
+/// unlike the [`Hash`](std::hash::Hash) of the standard library, is stable
+/// across the builds of [`pacaptr`](crate).
+#[must_use]
+fn entry_path(dir: &Path, pm: &str, key: &[&str]) -> PathBuf {
+    let key = serde_json::to_string(key).unwrap_or_default();
+    let digest = Sha256::digest(key.as_bytes());
+    dir.join(pm).join(format!("{:x}.json", digest))
+}
+
+/// Gets the seconds since the Unix epoch at `time`.
+#[must_use]
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Looks up the results of the query `key` on the package manager `pm`,
+/// returning them along with their age if they are not older than `ttl` at
+/// `now`.
+#[must_use]
+fn lookup(
+    dir: &Path,
+    pm: &str,
+    key: &[&str],
+    ttl: Duration,
+    now: SystemTime,
+) -> Option<(String, Duration)> {
+    let content = fs::read_to_string(entry_path(dir, pm, key)).ok()?;
+    let entry = serde_json::from_str::<Entry>(&content).ok()?;
+    if entry.key != key {
+        return None;
+    }
+    let age = Duration::from_secs(secs(now).checked_sub(entry.timestamp)?);
+    (age <= ttl).then(|| (entry.output, age))
+}
+
+/// Stores the results `output` of the query `key` on the package manager
+/// `pm` as cached at `now`.
+fn store(dir: &Path, pm: &str, key: &[&str], output: &str, now: SystemTime) -> Result<()> {
+    let path = entry_path(dir, pm, key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let entry = Entry {
+        key: key.iter().map(|&s| s.into()).collect(),
+        timestamp: secs(now),
+        output: output.into(),
+    };
+    fs::write(path, serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Removes all the cached results of the package manager `pm`.
+///
+/// # Errors
+/// Returns an [`io::Error`] when the cache can't be removed.
+pub(crate) fn invalidate(dir: &Path, pm: &str) -> io::Result<()> {
+    clean(&dir.join(pm))
+}
+
+/// Removes all the cached results.
+///
+/// # Errors
+/// Returns an [`io::Error`] when the cache can't be removed.
+pub(crate) fn clean(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// Runs the query `run` of `pm` (eg. `-Ss curl`, as identified by `key`), or
+/// prints its results from the cache instead if they are not older than
+/// `ttl`.
+///
+/// # Errors
+/// Returns the error of `run` if it fails, in which case nothing is cached.
+pub(crate) async fn cached<F>(pm: &dyn Pm, key: &[&str], ttl: Duration, run: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let dir = dir();
+    if let Some((output, age)) = lookup(&dir, pm.name(), key, ttl, SystemTime::now()) {
+        print_info(
+            format!("Showing the results cached {}s ago", age.as_secs()),
+            PROMPT_INFO,
+            Verbosity::Normal,
+        );
+        pm::emit_output(output.as_bytes());
+        return Ok(());
+    }
+
+    let started = SystemTime::now();
+    let (res, output) = pm::capture(run).await;
+    pm::emit_output(&output);
+    let output = String::from_utf8_lossy(&output);
+    res?;
+    if let Err(e) = store(&dir, pm.name(), key, &output, started) {
+        print_warning(
+            format!("Failed to cache the results: {}", e),
+            PROMPT_WARNING,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Makes a scratch directory, removed when dropped, with the path of a
+    /// cache inside.
+    fn temp_dir() -> (TempDir, PathBuf) {
+        let root = TempDir::new().unwrap();
+        let dir = root.path().join("search");
+        (root, dir)
+    }
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn cache_hit_miss() {
+        let (_root, dir) = temp_dir();
+        let now = UNIX_EPOCH + Duration::from_secs(1_631_972_525);
+        let key = ["ss", "curl"];
+        assert_eq!(lookup(&dir, "apt", &key, TTL, now), None);
+
+        store(&dir, "apt", &key, "curl/stable 7.74.0\n", now).unwrap();
+        assert_eq!(
+            lookup(&dir, "apt", &key, TTL, now),
+            Some(("curl/stable 7.74.0\n".into(), Duration::from_secs(0)))
+        );
+        // The entries are distinct per package manager and query.
+        assert_eq!(lookup(&dir, "apt", &["ss", "wget"], TTL, now), None);
+        assert_eq!(lookup(&dir, "dnf", &key, TTL, now), None);
+        assert_eq!(lookup(&dir, "apt", &["ss", "curl", "-v"], TTL, now), None);
+
+        // The entries are named after a stable hash of the query.
+        assert_eq!(
+            entry_path(&dir, "apt", &key),
+            dir.join("apt")
+                .join("bf529485c8158a7690310af6df077f9aee8ed1cc3bf5772b836bd659d8052149.json")
+        );
+
+        invalidate(&dir, "apt").unwrap();
+        assert_eq!(lookup(&dir, "apt", &key, TTL, now), None);
+        clean(&dir).unwrap();
+        clean(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_key_checked() {
+        let (_root, dir) = temp_dir();
+        let now = UNIX_EPOCH + Duration::from_secs(1_631_972_525);
+        let key = ["ss", "curl"];
+        store(&dir, "apt", &key, "curl/stable 7.74.0\n", now).unwrap();
+        // An entry whose name collides with that of another query is ignored.
+        fs::copy(
+            entry_path(&dir, "apt", &key),
+            entry_path(&dir, "apt", &["ss", "wget"]),
+        )
+        .unwrap();
+        assert_eq!(lookup(&dir, "apt", &["ss", "wget"], TTL, now), None);
+        assert!(lookup(&dir, "apt", &key, TTL, now).is_some());
+        clean(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_key_options() {
+        let (_root, dir) = temp_dir();
+        let now = UNIX_EPOCH + Duration::from_secs(1_631_972_525);
+        let cfg = |index_urls: &[&str]| Config {
+            index_urls: index_urls.iter().map(|&url| url.into()).collect(),
+            ..Config::default()
+        };
+        let key = |cfg| key(&cfg, "ss", &["requests"], &[]);
+        let private = key(cfg(&["https://pypi.example.com/simple"]));
+        let private = private.iter().map(String::as_str).collect::<Vec<_>>();
+        store(&dir, "pip", &private, "requests (9.9.9)\n", now).unwrap();
+        assert!(lookup(&dir, "pip", &private, TTL, now).is_some());
+        for other in &[
+            key(cfg(&[])),
+            key(cfg(&["https://mirror.example.com/simple"])),
+        ] {
+            let other = other.iter().map(String::as_str).collect::<Vec<_>>();
+            assert_eq!(lookup(&dir, "pip", &other, TTL, now), None);
+        }
+        clean(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_ttl() {
+        let (_root, dir) = temp_dir();
+        let then = UNIX_EPOCH + Duration::from_secs(1_631_972_525);
+        let key = ["si", "curl"];
+        store(&dir, "apt", &key, "Package: curl\n", then).unwrap();
+
+        let lookup_after = |secs| lookup(&dir, "apt", &key, TTL, then + Duration::from_secs(secs));
+        assert_eq!(lookup_after(30).unwrap().1, Duration::from_secs(30));
+        assert!(lookup_after(60).is_some());
+        assert_eq!(lookup_after(61), None);
+        // An entry from the future is never trusted.
+        assert_eq!(
+            lookup(&dir, "apt", &key, TTL, then - Duration::from_secs(1)),
+            None
+        );
+        clean(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_ops() {
+        assert!(is_cached_op("ss"));
+        assert!(is_cached_op("si"));
+        assert!(!is_cached_op("sii"));
+        assert!(is_invalidating_op("sy"));
+        assert!(is_invalidating_op("suy"));
+        assert!(is_invalidating_op("syy"));
+        assert!(!is_invalidating_op("su"));
+        assert!(!is_invalidating_op("qi"));
+    }
+}
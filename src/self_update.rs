@@ -0,0 +1,453 @@
+//! Keeping [`pacaptr`](crate) itself up to date when it has been installed
+//! from a GitHub release, where no package manager would notice its new
+//! versions.
+//!
+//! With `check_self_update` set in the config, the latest release is looked up
+//! at most once a day, and announced in a single line after a successful
+//! operation if it is newer. `pacaptr self-update` then downloads the archive
+//! built for the current platform, verifies its checksum, and replaces the
+//! current executable with the one inside.
+
+mod platforms;
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{task::JoinHandle, time};
+
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+    exec::{Cmd, Mode},
+    print::{print_info, print_msg, Verbosity, PROMPT_INFO},
+};
+
+/// The GitHub repository the releases of [`pacaptr`](crate) are published to.
+const REPO: &str = "rami3l/pacaptr";
+
+/// How often the latest release is looked up under `check_self_update`.
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the lookup of the latest release may take under
+/// `check_self_update`, so that it never holds the operation up.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Gets the platform of the release archive built for the current target, eg.
+/// `linux-amd64`.
+///
+/// On macOS, the universal binary is preferred over the ones built for each
+/// architecture, since it keeps working after being migrated to another Mac.
+#[must_use]
+fn platform() -> Option<&'static str> {
+    if cfg!(all(windows, target_arch = "x86_64")) {
+        Some(platforms::WINDOWS_AMD64)
+    } else if cfg!(target_os = "macos") {
+        Some(platforms::MACOS_UNIVERSAL)
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some(platforms::LINUX_AMD64)
+    } else {
+        None
+    }
+}
+
+/// Gets the name of the release archive built for `platform`, eg.
+/// `pacaptr-linux-amd64.tar.gz`.
+#[must_use]
+fn archive(platform: &str) -> String {
+    format!("{}-{}.tar.gz", clap::crate_name!(), platform)
+}
+
+/// Gets the name of the executable inside the release archives, eg.
+/// `pacaptr.exe` on Windows.
+#[must_use]
+fn artifact() -> String {
+    format!("{}{}", clap::crate_name!(), env::consts::EXE_SUFFIX)
+}
+
+/// Parses a version such as `v0.13.2` into its numeric components.
+#[must_use]
+fn parse_version(ver: &str) -> Option<Vec<u64>> {
+    ver.trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect()
+}
+
+/// Checks if the release tagged `latest` (eg. `v0.14.0`) is newer than the
+/// `current` version (eg. `0.13.2`).
+///
+/// The versions which can't be parsed, eg. those of pre-releases, are never
+/// considered newer.
+#[must_use]
+fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Parses the tag of the latest release from the response `body` of the
+/// GitHub API.
+#[must_use]
+fn parse_latest_tag(body: &[u8]) -> Option<String> {
+    let release: serde_json::Value = serde_json::from_slice(body).ok()?;
+    release.get("tag_name")?.as_str().map(Into::into)
+}
+
+/// Parses the hex digest from the `.sha256` file published along with each
+/// release archive, eg. `<digest> *pacaptr-linux-amd64.tar.gz`.
+#[must_use]
+fn parse_checksum(text: &str) -> Option<&str> {
+    text.split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Computes the SHA-256 digest of `data` in hex, as published along with each
+/// release archive.
+#[must_use]
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Gets the path of the file recording when the latest release was last
+/// looked up.
+#[must_use]
+fn state_path() -> PathBuf {
+    Config::cache_dir().join("self-update-check")
+}
+
+/// Checks if the latest release should be looked up at `now`, that is, if it
+/// hasn't been in the last [`CHECK_INTERVAL`] according to the file at
+/// `state`, in which case `now` is recorded there.
+fn is_due(state: &Path, now: SystemTime) -> bool {
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let last = fs::read_to_string(state)
+        .ok()
+        .and_then(|last| last.trim().parse::<u64>().ok());
+    if matches!(last, Some(last) if secs(now).saturating_sub(last) < CHECK_INTERVAL.as_secs()) {
+        return false;
+    }
+    if let Some(dir) = state.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(state, secs(now).to_string());
+    true
+}
+
+/// Downloads `url` with `curl`, returning its contents, or writing them to
+/// `output` if given.
+async fn fetch(url: &str, output: Option<&Path>, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    let flags = output
+        .map(|output| vec!["-o".to_owned(), output.to_string_lossy().into_owned()])
+        .unwrap_or_default();
+    let mut cmd = Cmd::new(&["curl", "-fsSL"])
+        .flags(&flags)
+        .kws(&[url])
+        .mode(Mode::Mute);
+    if let Some(timeout) = timeout {
+        cmd = cmd.timeout(timeout);
+    }
+    cmd.exec().await
+}
+
+/// Looks up the tag of the latest release, eg. `v0.14.0`.
+async fn latest_tag(timeout: Option<Duration>) -> Result<String> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", REPO);
+    let body = fetch(&url, None, timeout).await?;
+    parse_latest_tag(&body).ok_or_else(|| Error::SelfUpdateError {
+        msg: "the latest release can't be found".into(),
+    })
+}
+
+/// The lookup of the latest release running in the background, resolving to
+/// its tag if it is newer than the current version.
+pub(crate) type Check = JoinHandle<Option<String>>;
+
+/// Spawns the lookup of the latest release, if `check_self_update` is set
+/// and it hasn't been done in the last [`CHECK_INTERVAL`].
+#[must_use]
+pub(crate) fn spawn_check(cfg: &Config) -> Option<Check> {
+    if !cfg.check_self_update || !is_due(&state_path(), SystemTime::now()) {
+        return None;
+    }
+    Some(tokio::spawn(async {
+        let tag = latest_tag(Some(CHECK_TIMEOUT)).await.ok()?;
+        is_newer(&tag, clap::crate_version!()).then(|| tag)
+    }))
+}
+
+/// Announces the new release found by `check`, if any, waiting for it for at
+/// most [`CHECK_TIMEOUT`].
+///
+/// Any failure of the lookup (eg. without the network) is ignored, so that it
+/// never affects the operation.
+pub(crate) async fn notify(check: Check) {
+    if let Ok(Ok(Some(tag))) = time::timeout(CHECK_TIMEOUT, check).await {
+        print_info(
+            format!(
+                "`pacaptr` {} is available (current: v{}), \
+                 run `pacaptr self-update` or see https://github.com/{}/releases",
+                tag,
+                clap::crate_version!(),
+                REPO
+            ),
+            PROMPT_INFO,
+            Verbosity::Normal,
+        );
+    }
+}
+
+/// Appends `suffix` to the file name of `path`, eg. `pacaptr.exe.old`.
+#[must_use]
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Replaces the executable `exe` with `new`, which is first copied next to
+/// the former and then renamed over it, so that `exe` is never left
+/// half-written.
+///
+/// A running executable can't be replaced on Windows, but it can still be
+/// renamed, so with `move_aside` it is moved to `<exe>.old` first (which is
+/// removed by the next update), and moved back if `new` can't be put in place.
+fn replace_exe(new: &Path, exe: &Path, move_aside: bool) -> io::Result<()> {
+    let staged = with_suffix(exe, ".new");
+    // The permissions of `new` (eg. the executable bit) are copied as well.
+    fs::copy(new, &staged)?;
+    let cleanup = |e| {
+        let _ = fs::remove_file(&staged);
+        e
+    };
+    if !move_aside {
+        return fs::rename(&staged, exe).map_err(cleanup);
+    }
+    let old = with_suffix(exe, ".old");
+    let _ = fs::remove_file(&old);
+    fs::rename(exe, &old).map_err(cleanup)?;
+    fs::rename(&staged, exe).map_err(|e| {
+        let _ = fs::rename(&old, exe);
+        cleanup(e)
+    })
+}
+
+/// Gets the package manager managing the executable at `exe` (eg. installed
+/// with Homebrew in its `Cellar`), along with the command updating it, if any.
+///
+/// Such an executable must be left to its package manager, which would
+/// otherwise keep recording the old version, or even put it back.
+#[must_use]
+fn managed_by(exe: &Path) -> Option<(&'static str, &'static str)> {
+    let dirs: Vec<String> = exe
+        .parent()?
+        .iter()
+        .map(|dir| dir.to_string_lossy().to_lowercase())
+        .collect();
+    let has = |dir: &str| dirs.iter().any(|d| d == dir);
+    if has("cellar") || has("homebrew") || has("linuxbrew") {
+        Some(("Homebrew", "brew upgrade pacaptr"))
+    } else if has("scoop") {
+        Some(("Scoop", "scoop update pacaptr"))
+    } else if has("chocolatey") {
+        Some(("Chocolatey", "choco upgrade pacaptr"))
+    } else if dirs.ends_with(&[".cargo".into(), "bin".into()]) {
+        Some(("Cargo", "cargo install pacaptr"))
+    } else {
+        None
+    }
+}
+
+/// Replaces the current executable with that of the latest release, as run by
+/// `pacaptr self-update`.
+///
+/// # Errors
+/// Returns an [`Error::SelfUpdateError`] when the current executable is
+/// managed by a package manager, when no release is built for the current
+/// platform or when the downloaded archive doesn't match its checksum,
+/// the error of `curl` or `tar` if any, or an [`Error::IoError`] when the
+/// current executable can't be replaced.
+pub(crate) async fn self_update() -> Result<()> {
+    // The executable might be reached through a symlink, eg. from Homebrew's
+    // `bin` into its `Cellar`.
+    let exe = env::current_exe()?;
+    let exe = fs::canonicalize(&exe).unwrap_or(exe);
+    if let Some((pm, hint)) = managed_by(&exe) {
+        return Err(Error::SelfUpdateError {
+            msg: format!(
+                "`{}` is managed by {}, run `{}` instead",
+                exe.display(),
+                pm,
+                hint
+            ),
+        });
+    }
+    let platform = platform().ok_or_else(|| Error::SelfUpdateError {
+        msg: format!(
+            "no release is built for `{}-{}`",
+            env::consts::OS,
+            env::consts::ARCH
+        ),
+    })?;
+    let tag = latest_tag(None).await?;
+    let current = clap::crate_version!();
+    if !is_newer(&tag, current) {
+        print_msg(
+            &format!("`pacaptr` v{} is already the latest version", current),
+            PROMPT_INFO,
+        );
+        return Ok(());
+    }
+
+    let archive = archive(platform);
+    let url = format!(
+        "https://github.com/{}/releases/download/{}/{}",
+        REPO, tag, archive
+    );
+    let dir = Config::cache_dir().join("self-update");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(&archive);
+    print_msg(&format!("Downloading {}", url), PROMPT_INFO);
+    fetch(&url, Some(&path), None).await?;
+    let checksum = fetch(&format!("{}.sha256", url), None, None).await?;
+    let checksum = String::from_utf8_lossy(&checksum);
+    let expected = parse_checksum(&checksum).ok_or_else(|| Error::SelfUpdateError {
+        msg: format!("the checksum of `{}` can't be parsed", archive),
+    })?;
+    if !sha256_hex(&fs::read(&path)?).eq_ignore_ascii_case(expected) {
+        return Err(Error::SelfUpdateError {
+            msg: format!("`{}` doesn't match its checksum", archive),
+        });
+    }
+
+    Cmd::new(&["tar", "xzf"])
+        .kws(&[&*path.to_string_lossy(), "-C", &*dir.to_string_lossy()])
+        .mode(Mode::Mute)
+        .exec()
+        .await?;
+    replace_exe(&dir.join(artifact()), &exe, cfg!(windows))?;
+    let _ = fs::remove_dir_all(&dir);
+    print_msg(
+        &format!("Updated `pacaptr` from v{} to {}", current, tag),
+        PROMPT_INFO,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn versions_compared() {
+        assert!(is_newer("v0.14.0", "0.13.2"));
+        assert!(is_newer("v0.13.10", "0.13.2"));
+        assert!(is_newer("1.0.0", "0.13.2"));
+        assert!(!is_newer("v0.13.2", "0.13.2"));
+        assert!(!is_newer("v0.13.1", "0.13.2"));
+        // Pre-releases are never announced.
+        assert!(!is_newer("v0.14.0-beta.1", "0.13.2"));
+    }
+
+    #[test]
+    fn release_parsed() {
+        let body = br#"{"tag_name":"v0.14.0","name":"v0.14.0","assets":[]}"#;
+        assert_eq!(parse_latest_tag(body).as_deref(), Some("v0.14.0"));
+        assert_eq!(parse_latest_tag(br#"{"message":"Not Found"}"#), None);
+        assert_eq!(parse_latest_tag(b"<html>"), None);
+
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let text = format!("{} *pacaptr-linux-amd64.tar.gz\n", digest);
+        assert_eq!(parse_checksum(&text), Some(digest));
+        assert_eq!(parse_checksum("Not Found"), None);
+    }
+
+    #[test]
+    fn sha256_digests() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Spanning several blocks.
+        assert_eq!(
+            sha256_hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn managed_exes() {
+        let hint = |exe: &str| managed_by(Path::new(exe)).map(|(_, hint)| hint);
+        assert_eq!(
+            hint("/usr/local/Cellar/pacaptr/0.13.2/bin/pacaptr"),
+            Some("brew upgrade pacaptr")
+        );
+        assert_eq!(
+            hint("/home/linuxbrew/.linuxbrew/bin/pacaptr"),
+            Some("brew upgrade pacaptr")
+        );
+        assert_eq!(
+            hint("C:/Users/me/scoop/apps/pacaptr/current/pacaptr.exe"),
+            Some("scoop update pacaptr")
+        );
+        assert_eq!(
+            hint("C:/ProgramData/chocolatey/bin/pacaptr.exe"),
+            Some("choco upgrade pacaptr")
+        );
+        assert_eq!(
+            hint("/home/me/.cargo/bin/pacaptr"),
+            Some("cargo install pacaptr")
+        );
+        assert_eq!(hint("/usr/local/bin/pacaptr"), None);
+        assert_eq!(hint("/home/me/.cargo/pacaptr/pacaptr"), None);
+        // Only the directories count, not the executable itself.
+        assert_eq!(hint("/opt/scoop"), None);
+    }
+
+    #[test]
+    fn check_once_a_day() {
+        let dir = TempDir::new().unwrap();
+        let state = dir.path().join("self-update-check");
+        let now = SystemTime::now();
+        assert!(is_due(&state, now));
+        assert!(!is_due(&state, now + Duration::from_secs(60 * 60)));
+        assert!(is_due(&state, now + CHECK_INTERVAL));
+        // A broken state file is overwritten.
+        fs::write(&state, "garbage").unwrap();
+        assert!(is_due(&state, now));
+    }
+
+    #[test]
+    fn exe_replaced() {
+        let dir = TempDir::new().unwrap();
+        let new = dir.path().join("new");
+        let exe = dir.path().join("pacaptr");
+        let old = with_suffix(&exe, ".old");
+        for &move_aside in &[false, true] {
+            fs::write(&new, "v2").unwrap();
+            fs::write(&exe, "v1").unwrap();
+            replace_exe(&new, &exe, move_aside).unwrap();
+            assert_eq!(fs::read_to_string(&exe).unwrap(), "v2");
+            assert!(!with_suffix(&exe, ".new").exists());
+            assert_eq!(old.exists(), move_aside);
+        }
+        assert_eq!(fs::read_to_string(&old).unwrap(), "v1");
+
+        // The current executable is kept if the new one can't be copied.
+        fs::remove_file(&new).unwrap();
+        assert!(replace_exe(&new, &exe, true).is_err());
+        assert_eq!(fs::read_to_string(&exe).unwrap(), "v2");
+    }
+}
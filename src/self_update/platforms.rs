@@ -0,0 +1,23 @@
+//! The platforms the release archives are built for, as in
+//! `pacaptr-linux-amd64.tar.gz`.
+//!
+//! This module is shared with `xtask`, which builds and uploads the archives,
+//! while `pacaptr self-update` looks them up. Not every platform is looked up
+//! by both.
+
+#![allow(dead_code)]
+
+/// Windows on x86-64.
+pub const WINDOWS_AMD64: &str = "windows-amd64";
+
+/// macOS on x86-64.
+pub const MACOS_AMD64: &str = "macos-amd64";
+
+/// macOS on Apple silicon.
+pub const MACOS_AARCH64: &str = "macos-aarch64";
+
+/// macOS on both x86-64 and Apple silicon, as a universal binary.
+pub const MACOS_UNIVERSAL: &str = "macos-universal";
+
+/// Linux on x86-64.
+pub const LINUX_AMD64: &str = "linux-amd64";
@@ -0,0 +1,166 @@
+//! The summary of the commands run by an operation, printed after it
+//! finishes (or fails) when [`Config::stats`](crate::dispatch::Config::stats)
+//! is set, eg. with `--stat`.
+//!
+//! The commands are collected in a task-local while the operation runs, so
+//! that the backends don't need to be aware of it.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::prelude::*;
+use itertools::Itertools;
+
+use crate::{
+    error::Result,
+    exec::{Cmd, StatusCode},
+    print::{self, Verbosity},
+};
+
+/// A command run by an operation, as shown in the summary.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Stat {
+    /// The command, as printed in the shell.
+    pub cmd: String,
+
+    /// The exit code of the command, if it has exited normally.
+    pub code: Option<StatusCode>,
+
+    /// How long the command has run.
+    pub duration: Duration,
+}
+
+tokio::task_local! {
+    /// The commands run so far, if they are to be collected.
+    static STATS: Arc<Mutex<Vec<Stat>>>;
+}
+
+/// Runs `fut` with all of its commands collected, returning the result of
+/// `fut` along with a [`Stat`] per command.
+pub(crate) async fn collect<F: Future>(fut: F) -> (F::Output, Vec<Stat>) {
+    let stats = Arc::default();
+    let res = STATS.scope(Arc::clone(&stats), fut).await;
+    let stats = stats.lock().unwrap().drain(..).collect();
+    (res, stats)
+}
+
+/// Records the run of `cmd` for `duration`, if the commands being run are to
+/// be collected, where `code` is [`None`] if it has not exited normally, or
+/// has not been run at all in a dry run.
+pub(crate) fn record(cmd: &Cmd, code: Option<StatusCode>, duration: Duration) {
+    let _ = STATS.try_with(|stats| {
+        stats.lock().unwrap().push(Stat {
+            cmd: cmd.to_string(),
+            code,
+            duration,
+        });
+    });
+}
+
+/// Renders the summary of the commands run as `stats`, as a table followed by
+/// the total duration and the final status `res`.
+#[must_use]
+pub(crate) fn render(stats: &[Stat], res: &Result<()>) -> Vec<String> {
+    let secs = |d: Duration| format!("{:.2}s", d.as_secs_f64());
+    let rows = stats
+        .iter()
+        .map(|stat| {
+            let code = stat
+                .code
+                .map_or_else(|| "-".into(), |code| code.to_string());
+            (stat.cmd.as_str(), code, secs(stat.duration))
+        })
+        .collect_vec();
+    let width = rows
+        .iter()
+        .map(|(cmd, _, _)| cmd.chars().count())
+        .chain(Some("Command".len()))
+        .max()
+        .unwrap_or_default();
+
+    let header = format!(
+        "{:<width$}  {:>6}  {:>8}",
+        "Command",
+        "Status",
+        "Time",
+        width = width
+    );
+    let total = secs(stats.iter().map(|stat| stat.duration).sum());
+    let outcome = match res {
+        Ok(()) => "succeeded".into(),
+        Err(e) => format!("failed: {}", e),
+    };
+    let footer = format!(
+        "{} command{} run in {}, {}",
+        stats.len(),
+        if stats.len() == 1 { "" } else { "s" },
+        total,
+        outcome
+    );
+    let rows = rows.into_iter().map(|(cmd, code, time)| {
+        format!("{:<width$}  {:>6}  {:>8}", cmd, code, time, width = width)
+    });
+    Some(header)
+        .into_iter()
+        .chain(rows)
+        .chain(Some(footer))
+        .collect()
+}
+
+/// Prints the summary of the commands run as `stats` to `stderr`, as in
+/// [`render`].
+pub(crate) fn print(stats: &[Stat], res: &Result<()>) {
+    if !print::shows(Verbosity::Normal) {
+        return;
+    }
+    let printer = print::printer();
+    for line in render(stats, res) {
+        printer.err(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, exec::Output};
+
+    fn stat(cmd: &str, code: Option<StatusCode>, millis: u64) -> Stat {
+        Stat {
+            cmd: cmd.into(),
+            code,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn stat_render() {
+        let stats = [
+            stat("sudo apt update", Some(0), 1200),
+            stat("sudo apt upgrade", Some(100), 35_100),
+        ];
+        let err = Error::CmdStatusCodeError {
+            code: 100,
+            output: Output::default(),
+            kind: None,
+            raw: false,
+        };
+        assert_eq!(
+            render(&stats, &Err(err)),
+            [
+                "Command           Status      Time",
+                "sudo apt update        0     1.20s",
+                "sudo apt upgrade     100    35.10s",
+                "2 commands run in 36.30s, failed: Subprocess exited with code 100",
+            ]
+        );
+        assert_eq!(
+            render(&[], &Ok(())),
+            [
+                "Command  Status      Time",
+                "0 commands run in 0.00s, succeeded"
+            ]
+        );
+    }
+}
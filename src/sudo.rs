@@ -0,0 +1,150 @@
+//! Keeping the credentials of `sudo` warm during a long operation, as enabled
+//! by `sudo_keepalive` in the config, so that the password prompt can't appear
+//! between two elevated commands.
+//!
+//! The credentials are validated once with `sudo -v` before the operation, and
+//! the timestamp of `sudo` is then refreshed in the background until the
+//! operation completes.
+
+use std::{process::Stdio, time::Duration};
+
+use futures::prelude::*;
+use tokio::{
+    process::Command,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+use crate::{
+    dispatch::Config,
+    error::{Error, Result},
+};
+
+/// The interval between two refreshes of the timestamp of `sudo`, which is
+/// well below its default timeout of 5 minutes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The background task keeping the credentials of `sudo` warm, cancelled when
+/// dropped.
+#[must_use]
+#[derive(Debug)]
+pub(crate) struct Keepalive {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Decides whether the credentials of `sudo` are to be kept warm for the
+/// commands of a package manager, given whether it [`uses_sudo`] and whether
+/// [`pacaptr`](crate) is run as `root`.
+///
+/// [`uses_sudo`]: crate::pm::Pm::uses_sudo
+#[must_use]
+pub(crate) fn decide(cfg: &Config, uses_sudo: bool, root: bool) -> bool {
+    cfg.sudo_keepalive && uses_sudo && !root
+}
+
+/// Spawns the task calling `refresh` every `interval`, starting after the
+/// first `interval`, until the returned [`Keepalive`] is dropped.
+fn spawn<F, Fut>(interval: Duration, mut refresh: F) -> Keepalive
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let task = tokio::spawn(async move {
+        let mut ticks = time::interval_at(Instant::now() + interval, interval);
+        loop {
+            ticks.tick().await;
+            refresh().await;
+        }
+    });
+    Keepalive { task }
+}
+
+/// Runs `sudo -v` with the extra arguments `args`, returning whether it has
+/// succeeded.
+async fn validate(args: &[&str], stdin: Stdio) -> Result<bool> {
+    let status = Command::new("sudo")
+        .arg("-v")
+        .args(args)
+        .stdin(stdin)
+        .kill_on_drop(true)
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+/// Validates the credentials of `sudo` (asking for the password if needed),
+/// then keeps them warm until the returned [`Keepalive`] is dropped.
+///
+/// # Errors
+/// Returns an [`Error::SudoPasswordError`] when the password is needed but
+/// `stdin` is not a terminal, or when the validation fails, or an
+/// [`Error::IoError`] when `sudo` can't be run.
+pub(crate) async fn keep_alive() -> Result<Keepalive> {
+    // Without a terminal, the password prompt would hang forever.
+    let validated = if atty::is(atty::Stream::Stdin) {
+        validate(&[], Stdio::inherit()).await?
+    } else {
+        validate(&["-n"], Stdio::null()).await?
+    };
+    if !validated {
+        return Err(Error::SudoPasswordError);
+    }
+    Ok(spawn(REFRESH_INTERVAL, || async {
+        // A failed refresh is noticed by the next elevated command anyway.
+        let _ = validate(&["-n"], Stdio::null()).await;
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::pm::{Apt, Brew, Pm};
+
+    #[test]
+    fn keepalive_decision() {
+        let cfg = Config {
+            sudo_keepalive: true,
+            ..Config::default()
+        };
+        assert!(decide(&cfg, Apt::new(cfg.clone()).uses_sudo(), false));
+        // `brew` never runs `sudo`, and neither does `root` need to.
+        assert!(!decide(&cfg, Brew::new(cfg.clone()).uses_sudo(), false));
+        assert!(!decide(&cfg, true, true));
+        assert!(!decide(&Config::default(), true, false));
+    }
+
+    #[tokio::test]
+    async fn keepalive_refreshes_until_dropped() {
+        let interval = Duration::from_millis(20);
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let keepalive = spawn(interval, {
+            let ticks = Arc::clone(&ticks);
+            move || {
+                ticks.fetch_add(1, Ordering::SeqCst);
+                future::ready(())
+            }
+        });
+        // Nothing is refreshed before the first `interval`.
+        assert_eq!(ticks.load(Ordering::SeqCst), 0);
+        time::sleep(interval * 10).await;
+        assert!(ticks.load(Ordering::SeqCst) >= 2);
+
+        drop(keepalive);
+        // Let the aborted task be dropped, in case a tick was in flight.
+        time::sleep(interval).await;
+        let dropped = ticks.load(Ordering::SeqCst);
+        time::sleep(interval * 5).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), dropped);
+    }
+}
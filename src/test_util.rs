@@ -0,0 +1,19 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+/// The lock to be held by the tests depending on whether colors are in use,
+/// which is a global setting.
+pub(crate) static COLOR_LOCK: Lazy<Mutex<()>> = Lazy::new(Default::default);
+
+/// Asserts that each of the collected `lines` ends with the corresponding one
+/// of `cmds`, whatever comes before the command, eg. the prompt or `sudo -S`.
+#[track_caller]
+pub(crate) fn assert_cmds(lines: &[String], cmds: &[&str]) {
+    assert_eq!(lines.len(), cmds.len(), "{:?}", lines);
+    for (line, cmd) in lines.iter().zip(cmds) {
+        assert!(line.ends_with(cmd), "`{}`", line);
+    }
+}
@@ -12,6 +12,17 @@ fn apk_fail() {
     "## }
 }
 
+#[test]
+fn apk_qdt() {
+    test_dsl! { r##"
+        in orphans --dry-run
+        ou apk info`$
+        in orphans --remove --dry-run
+        ou apk info`$
+        ou would be removed in a real run
+    "## }
+}
+
 #[test]
 fn apk_q() {
     test_dsl! { r##"
@@ -74,3 +85,13 @@ fn apk_ss() {
         ou wget-.*-r
     "## }
 }
+
+#[test]
+fn apk_virtual() {
+    test_dsl! { r##"
+        in -S --virtual .build-deps gcc make --dry-run
+        ou apk add --virtual .build-deps gcc make`$
+        in -R --virtual .build-deps --dry-run
+        ou apk del .build-deps`$
+    "## }
+}
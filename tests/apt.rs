@@ -12,6 +12,123 @@ fn apt_fail() {
     "## }
 }
 
+#[test]
+fn apt_hold() {
+    test_dsl! { r##"
+        in -S --hold wget --dry-run
+        ou apt-mark hold wget
+    "## }
+}
+
+#[test]
+fn apt_s_as_root() {
+    // Eg. in a container without `sudo`, the commands are run as is.
+    if !is_root::is_root() {
+        return;
+    }
+    test_dsl! { r##"
+        in -S curl --dry-run --quiet --using apt
+        ou ^apt install --reinstall curl$
+    "## }
+}
+
+#[test]
+#[ignore]
+fn apt_r_s_as_root() {
+    // The commands are actually run without `sudo`.
+    if !is_root::is_root() {
+        return;
+    }
+    test_dsl! { r##"
+        in -Sy --using apt
+
+        in -S fish --yes --using apt
+        in ! which fish
+        ou /bin/fish
+
+        in -R fish --yes --using apt
+        ou ^Removing fish
+    "## }
+}
+
+#[test]
+fn apt_suy_quiet() {
+    test_dsl! { r##"
+        in -Suy --dry-run --quiet
+        ou ^(sudo -S )?apt update$
+        ou ^(sudo -S )?apt upgrade$
+    "## }
+}
+
+#[test]
+fn apt_su_ignore() {
+    test_dsl! { r##"
+        in -Su --ignore wget,curl --dry-run
+        ou apt upgrade -o Dir::Etc::Preferences=\S+pacaptr-ignore-XXXXXX\.pref`$
+    "## }
+}
+
+#[test]
+fn apt_sw_download_dir() {
+    test_dsl! { r##"
+        in -Sw --download-dir /tmp/x curl --dry-run
+        ou apt install --download-only -o Dir::Cache::archives=/tmp/x curl
+    "## }
+}
+
+#[test]
+fn apt_arch() {
+    test_dsl! { r##"
+        in -S curl --arch arm64 --dry-run
+        ou apt install --reinstall curl:arm64`$
+        in -Q curl --arch arm64 --dry-run
+        ou apt list curl:arm64`$
+    "## }
+}
+
+#[test]
+fn apt_unhold() {
+    test_dsl! { r##"
+        in -R --unhold wget --dry-run
+        ou apt-mark unhold wget
+    "## }
+}
+
+#[test]
+fn apt_held() {
+    test_dsl! { r##"
+        in -Q --hold --dry-run
+        ou apt-mark showhold
+    "## }
+}
+
+#[test]
+fn apt_qdt() {
+    test_dsl! { r##"
+        in -Qdt --dry-run
+        ou apt-get autoremove --dry-run`$
+    "## }
+}
+
+#[test]
+fn apt_r_orphans() {
+    test_dsl! { r##"
+        in -R --orphans --dry-run
+        ou apt autoremove`$
+    "## }
+}
+
+#[test]
+fn apt_chain() {
+    test_dsl! { r##"
+        in -S curl --arch arm64 --dry-run --quiet -- -- -Q wget
+        ou ^(sudo -S )?apt install --reinstall curl:arm64$
+        ou ^apt list wget:arm64$
+        in -S curl --dry-run --quiet -- -U
+        ou ^(sudo -S )?apt install --reinstall -U curl$
+    "## }
+}
+
 #[test]
 fn apt_q() {
     test_dsl! { r##"
@@ -12,6 +12,48 @@ fn brew_fail() {
     "## }
 }
 
+#[test]
+fn brew_cask() {
+    test_dsl! { r##"
+        in --using brew --cask -S firefox --dry-run
+        ou brew install --cask firefox
+        in --using brew --cask -R firefox --dry-run
+        ou brew uninstall --cask firefox
+        in --using brew --cask -Su --dry-run
+        ou brew upgrade --cask
+    "## }
+}
+
+#[test]
+fn brew_tap() {
+    test_dsl! { r##"
+        in --using brew -S --tap homebrew/cask-fonts --dry-run
+        ou brew tap homebrew/cask-fonts`$
+        in --using brew -R --tap homebrew/cask-fonts --dry-run
+        ou brew untap homebrew/cask-fonts`$
+        in --using brew -Q --tap --dry-run
+        ou brew tap`$
+    "## }
+}
+
+#[test]
+fn brew_bundle() {
+    test_dsl! { r##"
+        in --using brew bundle --file Brewfile --dry-run
+        ou brew bundle --file=Brewfile`$
+        in --using brew bundle --dump --file Brewfile --dry-run
+        ou brew bundle dump --file=-`$
+    "## }
+}
+
+#[test]
+fn brew_tapped_formula() {
+    test_dsl! { r##"
+        in --using brew -S homebrew/cask-fonts/font-fira-code --dry-run
+        ou brew reinstall homebrew/cask-fonts/font-fira-code`$
+    "## }
+}
+
 #[test]
 fn brew_q() {
     test_dsl! { r##"
@@ -32,6 +32,14 @@ fn choco_qi() {
     "## }
 }
 
+#[test]
+fn choco_qs() {
+    test_dsl! { r##"
+        in -Qs choco
+        ou chocolatey
+    "## }
+}
+
 #[test]
 #[ignore]
 fn choco_r_s() {
@@ -0,0 +1,23 @@
+mod common;
+use common::*;
+
+#[test]
+fn completions_bash() {
+    test_dsl! { r##"
+        in completions bash
+        ou --using
+        ou --dry-run
+        ou --sync
+    "## }
+}
+
+#[test]
+fn completions_zsh() {
+    test_dsl! { r##"
+        in completions zsh
+        ou --using
+        ou --no-confirm
+        ou apt
+        ou pip3
+    "## }
+}
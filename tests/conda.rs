@@ -58,3 +58,37 @@ fn conda_ss() {
         ou pkgs/main
     "## }
 }
+
+#[test]
+fn conda_s_mamba() {
+    test_dsl! { r##"
+        in --using mamba -S sympy --dry-run
+        ou mamba install sympy
+    "## }
+}
+
+#[test]
+fn conda_r_micromamba() {
+    test_dsl! { r##"
+        in --using micromamba -R sympy --dry-run
+        ou micromamba remove sympy
+    "## }
+}
+
+#[test]
+fn conda_export() {
+    test_dsl! { r##"
+        in --using conda export --dry-run
+        ou conda env export
+    "## }
+}
+
+#[test]
+fn conda_index_url() {
+    test_dsl! { r##"
+        in --using conda -S sympy --index-url conda-forge --dry-run
+        ou conda install -c conda-forge sympy`$
+        in --using conda -Si sympy --index-url conda-forge --index-url bioconda --dry-run
+        ou conda search --info -c conda-forge -c bioconda sympy`$
+    "## }
+}
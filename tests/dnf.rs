@@ -12,6 +12,44 @@ fn dnf_fail() {
     "## }
 }
 
+#[test]
+fn dnf_s_as_root() {
+    // Eg. in a container without `sudo`, the commands are run as is.
+    if !is_root::is_root() {
+        return;
+    }
+    test_dsl! { r##"
+        in -S curl --dry-run --quiet --using dnf
+        ou ^dnf install curl$
+    "## }
+}
+
+#[test]
+fn dnf_su_ignore() {
+    test_dsl! { r##"
+        in -Su --ignore wget --ignore curl --dry-run
+        ou dnf upgrade --exclude=wget --exclude=curl`$
+    "## }
+}
+
+#[test]
+fn dnf_sw_download_dir() {
+    test_dsl! { r##"
+        in -Sw --download-dir /tmp/x curl --dry-run
+        ou dnf install --downloadonly --downloaddir=/tmp/x curl
+    "## }
+}
+
+#[test]
+fn dnf_qdt() {
+    test_dsl! { r##"
+        in -Qdt --dry-run
+        ou dnf repoquery --unneeded`$
+        in -R --orphans --dry-run
+        ou dnf autoremove`$
+    "## }
+}
+
 #[test]
 fn dnf_q() {
     test_dsl! { r##"
@@ -141,3 +179,56 @@ fn dnf_ss() {
         ou A utility for retrieving files using the HTTP or FTP protocols
     "## }
 }
+
+#[test]
+fn dnf_module() {
+    test_dsl! { r##"
+        in -S nodejs:18 --dry-run
+        ou dnf module install nodejs:18`$
+        in -S nodejs:18/minimal curl --dry-run
+        ou dnf module install nodejs:18/minimal`$
+        ou dnf install curl`$
+        in -R nodejs:18 --dry-run
+        ou dnf module remove nodejs:18`$
+        in -Rn nodejs:18 --dry-run
+        ou dnf module remove nodejs:18`$
+        ou dnf module disable nodejs:18`$
+        in -Rn nodejs:18 curl --dry-run
+        ou dnf module disable nodejs:18`$
+        ou dnf remove curl`$
+        in -Sg nodejs:18 --dry-run
+        ou dnf module list nodejs`$
+    "## }
+}
+
+#[test]
+fn dnf_jobs() {
+    test_dsl! { r##"
+        in -S curl --jobs 8 --dry-run
+        ou dnf install --setopt=max_parallel_downloads=8 curl`$
+        in -Su --jobs 8 --dry-run
+        ou dnf upgrade --setopt=max_parallel_downloads=8`$
+    "## }
+}
+
+#[test]
+fn dnf_repo() {
+    test_dsl! { r##"
+        in -S curl --repo epel --dry-run
+        ou dnf install --repo=epel curl`$
+        in -Ss curl --repo epel --repo crb --dry-run
+        ou dnf search --repo=epel --repo=crb curl`$
+        in -Su --repo epel --jobs 8 --dry-run
+        ou dnf upgrade --repo=epel --setopt=max_parallel_downloads=8`$
+    "## }
+}
+
+#[test]
+fn dnf_arch() {
+    test_dsl! { r##"
+        in -S curl --arch aarch64 --dry-run
+        ou dnf install --forcearch=aarch64 curl`$
+        in -Ss curl --arch aarch64 --dry-run
+        ou dnf search --forcearch=aarch64 curl`$
+    "## }
+}
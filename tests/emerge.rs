@@ -75,3 +75,11 @@ fn emerge_ss() {
         ou net-misc/wget: Network utility to retrieve files from the WWW
     "## }
 }
+
+#[test]
+fn emerge_jobs() {
+    test_dsl! { r##"
+        in -S wget --jobs 4 --dry-run
+        ou emerge --jobs=4 wget`$
+    "## }
+}
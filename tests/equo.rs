@@ -0,0 +1,42 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+use common::*;
+
+#[test]
+fn equo_s() {
+    test_dsl! { r##"
+        in -S wget --using equo --dry-run
+        ou equo install wget
+    "## }
+}
+
+#[test]
+fn equo_r() {
+    test_dsl! { r##"
+        in -R wget --using equo --dry-run
+        ou equo remove wget
+    "## }
+}
+
+#[test]
+fn equo_suy() {
+    test_dsl! { r##"
+        in -Su --using equo --dry-run
+        ou equo upgrade
+        in -Sy --using equo --dry-run
+        ou equo update
+    "## }
+}
+
+#[test]
+fn equo_q() {
+    test_dsl! { r##"
+        in -Q wget --using equo --dry-run
+        ou equo query installed wget
+        in -Si wget --using equo --dry-run
+        ou equo query info wget
+        in -Ss wget --using equo --dry-run
+        ou equo search wget
+    "## }
+}
@@ -0,0 +1,21 @@
+mod common;
+use common::*;
+
+#[test]
+fn help_s() {
+    test_dsl! { r##"
+        in help S --using apt
+        ou S installs one or more packages by name
+        ou apt install
+    "## }
+}
+
+#[test]
+fn help_suy() {
+    test_dsl! { r##"
+        in help Syu --using apt
+        ou Suy refreshes the local package database, then updates outdated packages
+        ou apt update
+        ou apt upgrade
+    "## }
+}
@@ -10,6 +10,15 @@ fn pip_fail() {
     "## }
 }
 
+#[test]
+#[cfg(unix)]
+fn pip_sw_download_dir() {
+    test_dsl! { r##"
+        in --using pip -Sw --download-dir /tmp/x curl --dry-run
+        ou pip download -d /tmp/x curl
+    "## }
+}
+
 #[test]
 fn pip_q() {
     test_dsl! { r##"
@@ -28,6 +37,14 @@ fn pip_qi() {
     "## }
 }
 
+#[test]
+fn pip_export() {
+    test_dsl! { r##"
+        in --using pip export --dry-run
+        ou pip freeze
+    "## }
+}
+
 #[test]
 fn pip_qs() {
     test_dsl! { r##"
@@ -48,3 +65,13 @@ fn pip_r_s() {
         ou Successfully uninstalled
     "## }
 }
+
+#[test]
+fn pip_index_url() {
+    test_dsl! { r##"
+        in --using pip -S requests --index-url https://pypi.example.com/simple --dry-run
+        ou pip install --index-url https://pypi.example.com/simple requests`$
+        in --using pip -S requests --index-url https://a.example.com --index-url https://b.example.com --dry-run
+        ou pip install --index-url https://a.example.com --extra-index-url https://b.example.com requests`$
+    "## }
+}
@@ -14,6 +14,24 @@ fn port_fail() {
     "## }
 }
 
+#[test]
+fn port_s_variants() {
+    test_dsl! { r##"
+        in --using port -S foo +bar --dry-run
+        ou port install foo \+bar`$
+        in --using port -S foo +bar --dry-run -- -baz
+        ou port install foo \+bar -baz
+    "## }
+}
+
+#[test]
+fn port_suy() {
+    test_dsl! { r##"
+        in --using port -Suy --dry-run
+        ou (?s)port selfupdate.*port upgrade outdated
+    "## }
+}
+
 #[test]
 fn port_q() {
     test_dsl! { r##"
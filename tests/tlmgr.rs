@@ -0,0 +1,12 @@
+mod common;
+use common::*;
+
+#[test]
+fn tlmgr_repo() {
+    test_dsl! { r##"
+        in help S --using tlmgr --repo https://mirror.ctan.org/systems/texlive/tlnet
+        ou tlmgr install --repository https://mirror.ctan.org/systems/texlive/tlnet
+        in help Su --using tlmgr --repo https://mirror.ctan.org/systems/texlive/tlnet
+        ou tlmgr update --self --all --repository https://mirror.ctan.org/systems/texlive/tlnet
+    "## }
+}
@@ -12,6 +12,40 @@ fn zypper_fail() {
     "## }
 }
 
+#[test]
+fn zypper_qdt() {
+    test_dsl! { r##"
+        in -Qdt --dry-run
+        ou zypper packages --orphaned`$
+    "## }
+}
+
+#[test]
+fn zypper_hold() {
+    test_dsl! { r##"
+        in -S --hold wget --dry-run
+        ou zypper addlock wget
+    "## }
+}
+
+#[test]
+fn zypper_unhold() {
+    test_dsl! { r##"
+        in -R --unhold wget --dry-run
+        ou zypper removelock wget
+    "## }
+}
+
+#[test]
+fn zypper_repo() {
+    test_dsl! { r##"
+        in -S wget --repo oss --dry-run
+        ou zypper install --repo oss --dry-run wget`$
+        in -Suy --repo oss --repo update --dry-run
+        ou zypper dist-upgrade --repo oss --repo update --dry-run`$
+    "## }
+}
+
 #[test]
 fn zypper_q() {
     test_dsl! { r##"
@@ -125,3 +159,15 @@ fn zypper_ss() {
         ou A Tool for Mirroring FTP and HTTP
     "## }
 }
+
+#[test]
+fn zypper_patch() {
+    test_dsl! { r##"
+        in --patch -Su --dry-run
+        ou zypper --no-refresh patch --dry-run`$
+        in --patch -Suy --yes --dry-run
+        ou zypper --non-interactive patch --dry-run`$
+        in --patch -Qu --dry-run
+        ou zypper list-patches`$
+    "## }
+}
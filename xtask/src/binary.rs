@@ -4,6 +4,10 @@ use xshell::{cmd, write_file};
 
 use crate::dispatch::{get_ver_from_env, names::CORE};
 
+// The platforms are shared with `pacaptr self-update`.
+#[path = "../../src/self_update/platforms.rs"]
+mod platforms;
+
 pub struct Binary<'s> {
     pub artifact: &'s str,
     pub platform: &'s str,
@@ -87,25 +91,25 @@ impl<'s> BinaryBuilder<'s> {
 
 pub const WIN_X64: Binary = Binary {
     artifact: formatcp!("{}.exe", CORE),
-    platform: "windows-amd64",
+    platform: platforms::WINDOWS_AMD64,
 };
 
 pub const MAC_X64: Binary = Binary {
     artifact: CORE,
-    platform: "macos-amd64",
+    platform: platforms::MACOS_AMD64,
 };
 
 pub const MAC_ARM: Binary = Binary {
     artifact: CORE,
-    platform: "macos-aarch64",
+    platform: platforms::MACOS_AARCH64,
 };
 
 pub const MAC_UNIV: Binary = Binary {
     artifact: CORE,
-    platform: "macos-universal",
+    platform: platforms::MACOS_UNIVERSAL,
 };
 
 pub const LINUX_X64: Binary = Binary {
     artifact: CORE,
-    platform: "linux-amd64",
+    platform: platforms::LINUX_AMD64,
 };
@@ -0,0 +1,18 @@
+use anyhow::Result;
+use xshell::{cmd, write_file};
+
+use super::{names::*, Runner};
+
+#[derive(Debug)]
+pub struct Man {}
+
+impl Runner for Man {
+    fn run(self) -> Result<()> {
+        println!(":: Generating man page...");
+        let page = cmd!("cargo run --quiet -- --generate-man").read()?;
+        let path = format!("dist/{}.1", CORE);
+        write_file(&path, page + "\n")?;
+        println!(":: Man page written to `{}`", path);
+        Ok(())
+    }
+}
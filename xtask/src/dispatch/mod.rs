@@ -1,5 +1,6 @@
 pub mod bump_choco;
 pub mod bump_tap;
+pub mod man;
 pub mod publish;
 
 use std::env;
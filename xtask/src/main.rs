@@ -4,7 +4,9 @@ mod dispatch;
 use anyhow::Result;
 use pico_args::Arguments;
 
-use crate::dispatch::{bump_choco::BumpChoco, bump_tap::BumpTap, publish::Publish, Runner};
+use crate::dispatch::{
+    bump_choco::BumpChoco, bump_tap::BumpTap, man::Man, publish::Publish, Runner,
+};
 
 const BANNER: &str = r#"
                             __
@@ -22,6 +24,7 @@ USAGE:
 SUBCOMMANDS:
     bump-choco
     bump-tap
+    man
     publish
 "#;
 
@@ -39,6 +42,13 @@ USAGE:
     cargo xtask bump-tap
 "#;
 
+const MAN_HELP: &str = r#"
+cargo xtask man
+Generate the man page into `dist/`.
+USAGE:
+    cargo xtask man
+"#;
+
 const BUMP_CHOCO_HELP: &str = r#"
 cargo xtask bump-choco
 Bump chocolatey package version.
@@ -78,6 +88,15 @@ fn main() -> Result<()> {
             BumpChoco {}.run()
         }
 
+        "man" => {
+            if args.contains(["-h", "--help"]) {
+                eprintln!("{}", MAN_HELP);
+                return Ok(());
+            }
+
+            Man {}.run()
+        }
+
         _ => {
             println!("{}", BANNER);
             eprintln!("{}", XTASK_HELP);